@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+
+# Builds a fully static boxxy binary against musl. The `pretty` feature is
+# disabled because bat links against oniguruma.
+set -euo pipefail
+
+target="${TARGET:-x86_64-unknown-linux-musl}"
+
+rustup target add "${target}"
+cargo build --profile dist --target "${target}" --no-default-features
+file "target/${target}/dist/boxxy"
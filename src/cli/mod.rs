@@ -0,0 +1,752 @@
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser, Subcommand};
+use color_eyre::Result;
+
+use crate::config::preset::Preset;
+use crate::config::VersionCheck;
+use crate::enclosure::agent::SshAgent;
+use crate::enclosure::enforce::EnforceAction;
+use crate::enclosure::rule::RuleMode;
+use crate::hook::Shell;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Parser)]
+#[command(
+    name = "boxxy",
+    display_name = "boxxy",
+    about = "Put bad programs in a box with only their files.",
+    long_about = "boxxy forces bad programs to put their files somewhere else via Linux user namespaces.",
+    version = VERSION,
+    subcommand_negates_reqs = true,
+)]
+pub struct Args {
+    #[arg(
+        short = 'i',
+        long = "immutable",
+        default_value = "false",
+        help = "Make the root filesystem immutable."
+    )]
+    pub immutable_root: bool,
+
+    #[arg(
+        trailing_var_arg = true,
+        name = "COMMAND TO RUN",
+        help = "The command to run, ex. `ls -lah` or `aws configure`. Defaults to your $SHELL, so that `boxxy` on its own gives you a shell where all your rules apply."
+    )]
+    pub command_with_args: Vec<String>,
+
+    #[arg(
+        short = 'l',
+        long = "log-level",
+        value_name = "LEVEL",
+        default_value = "info",
+        help = "The log level, ex. `debug` or `warn`. RUST_LOG takes precedence."
+    )]
+    pub log_level: String,
+
+    #[arg(
+        long = "force-colour",
+        default_value = "false",
+        help = "Force colour output even when not writing to a terminal. Without it, NO_COLOR, CLICOLOR, and CLICOLOR_FORCE are respected."
+    )]
+    pub force_colour: bool,
+
+    #[arg(
+        short = 't',
+        long = "trace",
+        default_value = "false",
+        help = "Enable tracing of I/O-related syscalls and generate a report of files/directories the program touched."
+    )]
+    pub trace: bool,
+
+    #[arg(
+        long = "trace-enforce",
+        value_name = "SYSCALLS",
+        value_delimiter = ',',
+        value_parser = crate::enclosure::enforce::parse_syscall,
+        requires = "trace",
+        help = "Stop the traced processes from making any of these comma-separated syscalls, ex. `ptrace,mount`. See --trace-enforce-action."
+    )]
+    pub trace_enforce: Vec<String>,
+
+    #[arg(
+        long = "trace-enforce-action",
+        value_name = "eperm|kill",
+        default_value = "eperm",
+        help = "What to do when a traced process makes a --trace-enforce syscall: fail it with EPERM, or kill the process."
+    )]
+    pub trace_enforce_action: EnforceAction,
+
+    #[arg(
+        long = "watch",
+        default_value = "false",
+        conflicts_with = "trace",
+        help = "Watch file opens with fanotify instead of tracing syscalls, and generate the same report as --trace. Much cheaper, but doesn't see stats or other metadata lookups."
+    )]
+    pub watch: bool,
+
+    #[arg(
+        long = "expand-report",
+        default_value = "false",
+        help = "List every path in the --trace or --watch report, instead of rolling up directories with many entries into one `dir/ (N files)` line."
+    )]
+    pub expand_report: bool,
+
+    #[arg(
+        short = 'd',
+        long = "dotenv",
+        default_value = "false",
+        help = "Load environment variables from the .env file in the current directory and apply them to the boxxed program."
+    )]
+    pub dotenv: bool,
+
+    #[arg(
+        long = "daemon",
+        default_value = "false",
+        help = "Fork to the background and run as a daemon."
+    )]
+    pub daemon: bool,
+
+    #[arg(
+        long = "no-config",
+        default_value = "false",
+        help = "Disable loading config files entirely.",
+        action = ArgAction::SetTrue
+    )]
+    pub no_config: bool,
+
+    #[arg(
+        long = "no-community",
+        default_value = "false",
+        help = "Don't load the community rules fetched by `boxxy rules sync`."
+    )]
+    pub no_community: bool,
+
+    #[arg(
+        short = 'r',
+        long = "rule",
+        value_name = "RULE",
+        help = "Pass rules via CLI. -r/--rule `/remount/this:/to/this:<file/directory>`. Escape `:` and `\\` in paths as `\\:` and `\\\\`.",
+        action = ArgAction::Append
+    )]
+    pub arg_rules: Vec<String>,
+
+    #[arg(
+        long = "rule-yaml",
+        value_name = "YAML",
+        help = "Pass a rule, or a list of rules, as YAML, ex. `--rule-yaml '{target: \"~/a:b\", rewrite: ~/c, mode: file}'`.",
+        action = ArgAction::Append
+    )]
+    pub arg_rules_yaml: Vec<String>,
+
+    #[arg(
+        long = "isolate-project-caches",
+        default_value = "false",
+        help = "Shadow global package manager caches (cargo, npm, pip, gradle) with per-project directories, based on the project files in the current directory."
+    )]
+    pub isolate_project_caches: bool,
+
+    #[arg(
+        long = "preset",
+        value_name = "cargo-strict|npm-strict|pip-strict",
+        help = "Build the project in the current directory in a ready-made sandbox: the home directory is read-only except for the project and the tool's caches, which are isolated per project, and credentials like ~/.ssh are hidden."
+    )]
+    pub preset: Option<Preset>,
+
+    #[arg(
+        long = "and-then",
+        value_name = "COMMAND",
+        help = "A shell command to run in the same container after the command exits successfully. Can be given multiple times.",
+        action = ArgAction::Append
+    )]
+    pub and_then: Vec<String>,
+
+    #[arg(
+        long = "appimage",
+        default_value = "false",
+        help = "Run an AppImage in the box. It mounts itself with FUSE when boxxy runs as root, and is extracted on the fly otherwise; boxxy refuses to run AppImages that can't be."
+    )]
+    pub appimage: bool,
+
+    #[arg(
+        long = "private-tmpdir",
+        default_value = "false",
+        help = "Point TMPDIR at a temporary directory that only this box uses, and delete it when the box exits."
+    )]
+    pub private_tmpdir: bool,
+
+    #[arg(
+        long = "self-test",
+        default_value = "false",
+        help = "After setting up the container, check from inside it that the rules are in effect, and refuse to run the command if they aren't."
+    )]
+    pub self_test: bool,
+
+    #[arg(
+        long = "verbose-mounts",
+        default_value = "false",
+        help = "Before running the command, print a table of the mounts that the rules were applied with: each rule's source, destination, flags, and strategy."
+    )]
+    pub verbose_mounts: bool,
+
+    #[arg(
+        long = "show-mount-order",
+        default_value = "false",
+        help = "Before running the command, print the order the rules are mounted in, and the priority and target depth they were sorted by."
+    )]
+    pub show_mount_order: bool,
+
+    #[arg(
+        long = "dry-run",
+        default_value = "false",
+        help = "Print the rules that match the command, the mounts they'd be applied with, the files and directories that would be created for them, and the env vars they'd set, then exit without running anything."
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long = "profile-setup",
+        value_name = "PATH",
+        help = "Write how long each step of setting up the box took, ex. loading configs, matching rules, mapping uids, and each mount, to PATH as a Chrome trace. Open it in Perfetto or chrome://tracing to see it as a flame graph."
+    )]
+    pub profile_setup: Option<PathBuf>,
+
+    #[arg(
+        long = "tag-mounts",
+        default_value = "false",
+        help = "Make boxxy's tmpfs mounts with a source naming the box instead of `tmpfs`, so they can be told apart in /proc/self/mountinfo. See `boxxy explain-mountinfo`."
+    )]
+    pub tag_mounts: bool,
+
+    #[arg(
+        long = "root-tmpfs",
+        default_value = "false",
+        help = "Build the container root on a tmpfs, so that the files boxxy creates for it, including the --private-tmpdir, never touch the disk and are gone when the box exits. Faster on slow disks."
+    )]
+    pub root_tmpfs: bool,
+
+    #[arg(
+        long = "ro-home",
+        default_value = "false",
+        help = "Make your home directory read-only in the box, then apply the rules on top, so the command can read your configs but only write where a rule rewrites a path."
+    )]
+    pub ro_home: bool,
+
+    #[arg(
+        long = "shell-init",
+        default_value = "false",
+        help = "When no command is given, start your shell with an rc snippet for the box: it loads your usual rc file, adds the box's name to the prompt, and runs the config's `shell_init` lines. Supports bash, zsh, fish, and shells that read $ENV."
+    )]
+    pub shell_init: bool,
+
+    #[arg(
+        long = "ssh-agent",
+        value_name = "allow|deny|proxy",
+        default_value = "allow",
+        help = "Whether the command can use your SSH agent: as usual, not at all, or through a proxy that lists your keys but asks on the terminal before signing anything with them. Without a terminal, signing is allowed and logged."
+    )]
+    pub ssh_agent: SshAgent,
+
+    #[arg(
+        long = "strict-secrets",
+        default_value = "false",
+        help = "Hide the gpg-agent's sockets, the D-Bus session bus that the Secret Service keyring is reached over, and the GNOME keyring and KWallet from the command, unless a rule that applies to it sets `allow_secrets: true`."
+    )]
+    pub strict_secrets: bool,
+
+    #[arg(
+        long = "name-from-command",
+        default_value = "false",
+        help = "Name the container after a hash of the command and the current directory instead of a random name, so reruns get the same name."
+    )]
+    pub name_from_command: bool,
+
+    #[arg(
+        long = "follow-wrappers",
+        default_value = "false",
+        help = "Also match rules' `only` against the program that a wrapper runs: the program after `env`, `nohup`, `timeout`, etc., the program a shell script execs, or a script's interpreter."
+    )]
+    pub follow_wrappers: bool,
+
+    #[arg(
+        long = "expose-boxxy-config",
+        default_value = "false",
+        help = "Let the boxxed program write to boxxy's own config files and state. By default they're read-only in the box, so it can't change the rules future boxes run with."
+    )]
+    pub expose_boxxy_config: bool,
+
+    #[arg(
+        long = "shell",
+        default_value = "false",
+        help = "Run the command as a shell script with `sh -c`, joining its words with spaces. `-` reads the script from stdin, ex. `echo 'npm ci && npm test' | boxxy --shell -`."
+    )]
+    pub shell: bool,
+
+    #[arg(
+        long = "profile",
+        value_name = "NAME",
+        help = "Also apply the rules of this profile from the config's `profiles`."
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long = "assume-yes",
+        default_value = "false",
+        help = "Don't ask before hiding data in a rule's target behind its empty rewrite. Without a terminal, boxxy only warns about it."
+    )]
+    pub assume_yes: bool,
+
+    #[arg(
+        long = "workspace",
+        value_name = "NAME",
+        help = "Also apply the rules of this workspace from the config's `workspaces`, instead of the workspaces whose `context` has the current directory in it."
+    )]
+    pub workspace: Option<String>,
+
+    #[arg(
+        long = "only-tags",
+        value_name = "TAGS",
+        value_delimiter = ',',
+        help = "Only apply rules with one of these comma-separated tags."
+    )]
+    pub only_tags: Vec<String>,
+
+    #[arg(
+        long = "skip-tags",
+        value_name = "TAGS",
+        value_delimiter = ',',
+        help = "Don't apply rules with any of these comma-separated tags."
+    )]
+    pub skip_tags: Vec<String>,
+
+    #[arg(
+        long = "log-tags",
+        value_name = "TAGS",
+        value_delimiter = ',',
+        help = "Log how rules with one of these comma-separated tags are set up at the info level, instead of debug."
+    )]
+    pub log_tags: Vec<String>,
+
+    #[arg(
+        long = "warn-unreachable",
+        default_value = "false",
+        help = "Warn about rules that can never apply, like rules whose `only` programs don't exist. See `boxxy config lint`."
+    )]
+    pub warn_unreachable: bool,
+
+    #[arg(
+        long = "stack-size",
+        value_name = "SIZE",
+        value_parser = crate::enclosure::fs::parse_size,
+        help = "The stack size of the boxxed process, ex. `64m`. Defaults to the stack rlimit, or 8m if it's unlimited."
+    )]
+    pub stack_size: Option<u64>,
+
+    #[arg(
+        long = "control-socket",
+        default_value = "false",
+        help = "Expose a socket at $BOXXY_CONTROL_SOCKET that the boxxed program can use to add rules, report the paths it uses, or say that it's ready. See `boxxy control`."
+    )]
+    pub control_socket: bool,
+
+    #[arg(
+        long = "offline",
+        default_value = "false",
+        help = "Don't fetch `include_url` rule files, only use the copies cached by earlier runs."
+    )]
+    pub offline: bool,
+
+    #[arg(
+        long = "version-check",
+        value_name = "error|warn|ignore",
+        default_value = "error",
+        help = "What to do when a config file sets `min_boxxy_version` to a newer version than this one."
+    )]
+    pub version_check: VersionCheck,
+
+    #[command(subcommand)]
+    pub command: Option<BoxxySubcommand>,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigSubcommand {
+    #[command(
+        name = "lint",
+        about = "List rules that can never apply: their `only` programs don't exist, their `context` directories don't exist, or they need `when_env` variables that aren't set. Exits with 1 if there are any."
+    )]
+    Lint,
+    #[command(
+        name = "check",
+        about = "Check every config file that applies here, and the files they include, without running anything: that they parse, only use known keys, that each rule's paths expand and its rewrite's parent exists, and that no two rules shadow the same target. Exits with 1 if there are problems."
+    )]
+    Check,
+    #[command(
+        name = "migrate",
+        about = "Rewrite config files written for an older config version in the current layout, keeping the old file next to it with a .bak suffix. Comments and anchors aren't kept."
+    )]
+    Migrate {
+        #[arg(
+            help = "The config file to migrate. Defaults to every config file that applies here."
+        )]
+        path: Option<PathBuf>,
+    },
+    #[command(
+        name = "trust",
+        about = "Trust a project's config file as it is now, so that its rules' `before`/`after` commands, which run outside the box, are used. Editing the file takes its trust away."
+    )]
+    Trust {
+        #[arg(
+            help = "The config file to trust. Defaults to the config files in the current directory."
+        )]
+        path: Option<PathBuf>,
+    },
+    #[command(
+        name = "generate-xdg",
+        about = "Print a config that moves the stray dotfiles and dotdirs in your homedir that boxxy knows about into their XDG locations, setting the env vars the apps honor where there are any."
+    )]
+    GenerateXdg,
+    #[command(
+        name = "add-rule",
+        about = "Add a rule to the end of the default config file, keeping its comments."
+    )]
+    AddRule {
+        #[arg(long = "name", help = "The name of the rule.")]
+        name: String,
+        #[arg(long = "target", help = "The path to shadow, ex. `~/.foo`.")]
+        target: String,
+        #[arg(
+            long = "rewrite",
+            help = "The path to shadow the target with, ex. `~/.config/foo`. Defaults to a directory named after the rule in boxxy's store."
+        )]
+        rewrite: Option<String>,
+        #[arg(
+            long = "mode",
+            value_name = "file|directory|tmpfs|expose-ro|mask|copy",
+            default_value = "directory",
+            help = "Whether the target is a file or a directory, a directory to shadow with a tmpfs, a path to expose read-only as it is, a path to hide entirely, or a path to copy the rewrite to and sync back on exit."
+        )]
+        mode: RuleMode,
+        #[arg(
+            long = "only",
+            value_name = "PROGRAM",
+            help = "Only apply the rule to this program. Can be given multiple times.",
+            action = ArgAction::Append
+        )]
+        only: Vec<String>,
+        #[arg(
+            long = "context",
+            value_name = "DIR",
+            help = "Only apply the rule in this directory. Can be given multiple times.",
+            action = ArgAction::Append
+        )]
+        context: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RulesSubcommand {
+    #[command(
+        name = "sync",
+        about = "Fetch the curated community rules from boxxy's repo into ~/.config/boxxy/community, checked against the checksums published with them to catch broken downloads. They're loaded before your own config files, unless --no-community is given."
+    )]
+    Sync,
+    #[command(
+        name = "list",
+        about = "List the rules from every config that applies here that would apply to a command, and why: which `only` entry matches it, and whether its arguments, the environment, and the current directory match the rule."
+    )]
+    List {
+        #[arg(
+            long = "all",
+            default_value = "false",
+            help = "Also list the rules that wouldn't apply, and why not."
+        )]
+        all: bool,
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "The command, with its arguments."
+        )]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ControlSubcommand {
+    #[command(name = "ready", about = "Tell boxxy that the boxxed program is ready.")]
+    Ready,
+    #[command(
+        name = "report",
+        about = "Report paths that the boxxed program uses. They're written to boxxy-usage.txt when the box exits."
+    )]
+    Report {
+        #[arg(required = true, help = "The paths to report.")]
+        paths: Vec<PathBuf>,
+    },
+    #[command(
+        name = "mount",
+        about = "Apply another of the rules in boxxy's config to the running box."
+    )]
+    Mount {
+        #[arg(
+            value_name = "NAME",
+            help = "The name of the rule. Deny, omit, mask, copy, and faultmode rules aren't supported."
+        )]
+        rule: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BoxxySubcommand {
+    #[command(
+        name = "config",
+        about = "View the config file.",
+        subcommand_negates_reqs = true,
+        aliases = &["cfg", "conf", "c"]
+    )]
+    Config {
+        #[command(subcommand)]
+        command: Option<ConfigSubcommand>,
+    },
+    #[command(
+        name = "rules",
+        about = "Manage the community rules.",
+        subcommand_negates_reqs = true
+    )]
+    Rules {
+        #[command(subcommand)]
+        command: RulesSubcommand,
+    },
+    #[command(
+        name = "scan",
+        about = "Scan your homedir for applications that may benefit from boxxy.",
+        subcommand_negates_reqs = true,
+        aliases = &["s"]
+    )]
+    Scan {
+        #[arg(
+            long = "update-db",
+            default_value = "false",
+            help = "Fetch the latest app database from boxxy's repo instead of scanning, check it against the checksums published with it to catch broken downloads, and use it over the one built into boxxy from now on."
+        )]
+        update_db: bool,
+    },
+    #[command(
+        name = "gc",
+        about = "Delete orphaned or stale per-project cache directories.",
+        subcommand_negates_reqs = true
+    )]
+    Gc {
+        #[arg(
+            long = "days",
+            default_value = "30",
+            help = "Consider entries unused for at least this many days stale."
+        )]
+        days: u64,
+        #[arg(
+            short = 'y',
+            long = "yes",
+            default_value = "false",
+            help = "Delete without asking for confirmation."
+        )]
+        yes: bool,
+    },
+    #[command(
+        name = "stats",
+        about = "Show which binaries you box and which rules apply most, from a stats file that never leaves this machine.",
+        subcommand_negates_reqs = true
+    )]
+    Stats {
+        #[arg(
+            long = "enable",
+            default_value = "false",
+            conflicts_with = "disable",
+            help = "Start recording usage stats."
+        )]
+        enable: bool,
+        #[arg(
+            long = "disable",
+            default_value = "false",
+            help = "Stop recording usage stats and delete the stats file."
+        )]
+        disable: bool,
+    },
+    #[command(
+        name = "du",
+        about = "Show how much disk space the rewrite location of each rule uses.",
+        subcommand_negates_reqs = true
+    )]
+    Du {
+        #[arg(
+            long = "children",
+            default_value = "3",
+            help = "How many of the largest children of each location to show."
+        )]
+        children: usize,
+    },
+    #[command(
+        name = "migrate",
+        about = "Copy the data in a rule's target into its empty rewrite, so the boxxed program keeps it.",
+        subcommand_negates_reqs = true
+    )]
+    Migrate {
+        #[arg(help = "The name of the rule.")]
+        rule: String,
+        #[arg(
+            help = "The program to render the rule's templates for. Defaults to the first program in its `only`."
+        )]
+        program: Option<String>,
+    },
+    #[command(
+        name = "hook",
+        about = "Print a shell hook that suggests running programs with rules in boxxy. Add `eval \"$(boxxy hook zsh)\"` to your shell's rc file.",
+        subcommand_negates_reqs = true
+    )]
+    Hook {
+        #[arg(value_name = "bash|zsh", help = "The shell to print the hook for.")]
+        shell: Shell,
+    },
+    #[command(
+        name = "batch",
+        about = "Run every line of a file as a shell command, one after another, in the same container.",
+        subcommand_negates_reqs = true
+    )]
+    Batch {
+        #[arg(
+            help = "File with one shell command per line. Empty lines and lines starting with # are skipped."
+        )]
+        file: PathBuf,
+    },
+    #[command(
+        name = "serve",
+        about = "Set up a box once and run commands sent to it with `boxxy run-in`.",
+        subcommand_negates_reqs = true
+    )]
+    Serve {
+        #[arg(help = "The name to serve the box under.")]
+        name: String,
+        #[arg(help = "The program to match rules against. Defaults to `sh`.")]
+        program: Option<String>,
+    },
+    #[command(
+        name = "check",
+        about = "Exit 0 if any rule would apply to a command run in the current directory, 1 if none would, and 2 if the config can't be loaded. Prints nothing, and never fetches `include_url`s, so that shell prompts and wrapper scripts can call it.",
+        subcommand_negates_reqs = true
+    )]
+    Check {
+        #[arg(help = "The program to match rules against.")]
+        binary: String,
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "The program's arguments, for rules that match on them."
+        )]
+        args: Vec<String>,
+    },
+    #[command(
+        name = "verify",
+        about = "Check that each rule is in effect, comparing its target on the host with the target in the box.",
+        subcommand_negates_reqs = true
+    )]
+    Verify {
+        #[arg(help = "The program to match rules against. Defaults to `sh`.")]
+        program: Option<String>,
+    },
+    #[command(
+        name = "man",
+        about = "Print the boxxy(1) man page, or write it and boxxy-rules(5) to a directory.",
+        subcommand_negates_reqs = true
+    )]
+    Man {
+        #[arg(
+            long = "rules",
+            default_value = "false",
+            help = "Print boxxy-rules(5), which describes the config file, instead."
+        )]
+        rules: bool,
+        #[arg(
+            long = "out-dir",
+            value_name = "DIR",
+            help = "Write boxxy.1 and boxxy-rules.5 into this directory instead of printing."
+        )]
+        out_dir: Option<PathBuf>,
+    },
+    #[command(
+        name = "top",
+        about = "Show the processes in a running box, the files they have open under shadowed targets, and what they opened recently.",
+        subcommand_negates_reqs = true
+    )]
+    Top {
+        #[arg(help = "The name of the box. Can be left out if only one box is running.")]
+        name: Option<String>,
+        #[arg(
+            long = "once",
+            default_value = "false",
+            help = "Print the view once instead of refreshing it."
+        )]
+        once: bool,
+        #[arg(
+            long = "interval",
+            value_name = "SECONDS",
+            default_value = "1",
+            help = "How often to refresh the view."
+        )]
+        interval: u64,
+    },
+    #[command(
+        name = "run-in",
+        about = "Run a command in a box started with `boxxy serve`.",
+        subcommand_negates_reqs = true
+    )]
+    RunIn {
+        #[arg(help = "The name of the box.")]
+        name: String,
+        #[arg(trailing_var_arg = true, required = true, help = "The command to run.")]
+        command: Vec<String>,
+    },
+    #[command(
+        name = "test-rules",
+        about = "Check which rules apply to each command in a file of test cases, for testing shared configs in CI. Exits with 1 if any case fails.",
+        subcommand_negates_reqs = true
+    )]
+    TestRules {
+        #[arg(
+            help = "YAML file with a list of `cases`, each with a `command`, optionally a `cwd` and `env`, and the names of the `rules` that should apply."
+        )]
+        cases: PathBuf,
+    },
+    #[command(
+        name = "explain-mountinfo",
+        about = "List the mounts in /proc/self/mountinfo, or another process's, that boxxy made, and the rules they're for.",
+        subcommand_negates_reqs = true
+    )]
+    ExplainMountinfo {
+        #[arg(
+            long = "pid",
+            help = "Read this process's mountinfo instead, ex. a program running in a box."
+        )]
+        pid: Option<u32>,
+    },
+    #[command(
+        name = "control",
+        about = "Talk to the box this is running in, over the socket that --control-socket exposes.",
+        subcommand_negates_reqs = true
+    )]
+    Control {
+        #[command(subcommand)]
+        command: ControlSubcommand,
+    },
+}
+
+/// Ask the user a yes/no question on stdin. Defaults to no.
+pub fn confirm(question: &str) -> Result<bool> {
+    use std::io::Write;
+
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
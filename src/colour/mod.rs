@@ -0,0 +1,100 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+pub use owo_colors::Stream;
+
+static CHOICE: OnceLock<ColourChoice> = OnceLock::new();
+
+/// Whether to colour output, decided once at startup from `--force-colour`
+/// and the environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourChoice {
+    Always,
+    Never,
+    /// Colour each stream only if it's a terminal.
+    Auto,
+}
+
+impl ColourChoice {
+    /// Follows https://no-color.org and https://bixense.com/clicolors: the
+    /// flag wins, then `NO_COLOR`, then `CLICOLOR_FORCE`, then `CLICOLOR=0`.
+    fn detect<F: Fn(&str) -> Option<String>>(force_colour: bool, var: F) -> Self {
+        let set = |name| var(name).filter(|value| !value.is_empty());
+        if force_colour {
+            ColourChoice::Always
+        } else if set("NO_COLOR").is_some() {
+            ColourChoice::Never
+        } else if set("CLICOLOR_FORCE").is_some_and(|value| value != "0") {
+            ColourChoice::Always
+        } else if set("CLICOLOR").is_some_and(|value| value == "0") {
+            ColourChoice::Never
+        } else {
+            ColourChoice::Auto
+        }
+    }
+}
+
+/// Decide whether to colour output. Must be called before anything is
+/// logged.
+pub fn init(force_colour: bool) {
+    let choice =
+        *CHOICE.get_or_init(|| ColourChoice::detect(force_colour, |name| std::env::var(name).ok()));
+
+    // owo-colors does its own per-stream detection, which only needs
+    // correcting when the choice doesn't depend on the stream.
+    match choice {
+        ColourChoice::Always => owo_colors::set_override(true),
+        ColourChoice::Never => owo_colors::set_override(false),
+        ColourChoice::Auto => {}
+    }
+}
+
+/// Whether output written to `stream` should be coloured.
+pub fn enabled(stream: Stream) -> bool {
+    match CHOICE.get().copied().unwrap_or(ColourChoice::Auto) {
+        ColourChoice::Always => true,
+        ColourChoice::Never => false,
+        ColourChoice::Auto => {
+            let is_terminal = match stream {
+                Stream::Stdout => std::io::stdout().is_terminal(),
+                Stream::Stderr => std::io::stderr().is_terminal(),
+            };
+            is_terminal && std::env::var("TERM").map_or(true, |term| term != "dumb")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(force_colour: bool, vars: &[(&str, &str)]) -> ColourChoice {
+        ColourChoice::detect(force_colour, |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn test_detect_colour_choice() {
+        assert_eq!(detect(false, &[]), ColourChoice::Auto);
+        assert_eq!(detect(false, &[("NO_COLOR", "1")]), ColourChoice::Never);
+        assert_eq!(detect(false, &[("NO_COLOR", "")]), ColourChoice::Auto);
+        assert_eq!(detect(true, &[("NO_COLOR", "1")]), ColourChoice::Always);
+        assert_eq!(
+            detect(false, &[("CLICOLOR_FORCE", "1"), ("CLICOLOR", "0")]),
+            ColourChoice::Always
+        );
+        assert_eq!(
+            detect(false, &[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")]),
+            ColourChoice::Never
+        );
+        assert_eq!(
+            detect(false, &[("CLICOLOR_FORCE", "0")]),
+            ColourChoice::Auto
+        );
+        assert_eq!(detect(false, &[("CLICOLOR", "0")]), ColourChoice::Never);
+        assert_eq!(detect(false, &[("CLICOLOR", "1")]), ColourChoice::Auto);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::enclosure::fs::FsDriver;
+use crate::enclosure::rule::BoxxyRules;
+
+/// A file of cases for `boxxy test-rules`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cases {
+    pub cases: Vec<Case>,
+}
+
+/// A command, and the names of the rules that should apply to it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The program, then its arguments.
+    pub command: Vec<String>,
+    /// The directory the command is run from. Relative paths are relative
+    /// to the cases file. Defaults to the cases file's directory.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// The environment the command is run with, for `when_env`. Nothing
+    /// else in the environment is seen, so that cases don't depend on where
+    /// they're run.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// The names of every rule that should apply, in any order.
+    pub rules: Vec<String>,
+}
+
+/// How a case failed: the rules that should have applied but didn't, and
+/// the ones that applied but shouldn't have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub missing: Vec<String>,
+    pub unexpected: Vec<String>,
+}
+
+impl Case {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.command.join(" "))
+    }
+
+    /// Check the case against `rules`. `dir` is the cases file's directory.
+    pub fn run(&self, rules: &BoxxyRules, dir: &Path, fs: &FsDriver) -> Result<Option<Failure>> {
+        let Some((program, args)) = self.command.split_first() else {
+            return Err(eyre!("{}: `command` is empty", self.name()));
+        };
+        let cwd = match &self.cwd {
+            Some(cwd) => dir.join(shellexpand::tilde(cwd).as_ref()),
+            None => dir.to_path_buf(),
+        };
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        let applied: BTreeSet<String> = rules
+            .get_applicable_rules_in(
+                OsStr::new(program),
+                &args,
+                &cwd,
+                |key| self.env.get(key).cloned(),
+                fs,
+            )?
+            .into_iter()
+            .map(|rule| rule.name)
+            .collect();
+        let expected: BTreeSet<String> = self.rules.iter().cloned().collect();
+        if applied == expected {
+            return Ok(None);
+        }
+
+        Ok(Some(Failure {
+            missing: expected.difference(&applied).cloned().collect(),
+            unexpected: applied.difference(&expected).cloned().collect(),
+        }))
+    }
+}
+
+/// Load the cases in `path`.
+pub fn load(path: &Path) -> Result<Cases> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| eyre!("could not read {}: {err}", path.display()))?;
+    serde_yaml::from_str(&contents)
+        .map_err(|err| eyre!("invalid cases in {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclosure::rule::Rule;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_cases() -> Result<()> {
+        let dir = Scratch::new("cases")?;
+        std::fs::create_dir_all(dir.join("work"))?;
+        let rules = BoxxyRules {
+            rules: vec![
+                Rule::builder("aws", "~/.aws", "~/.config/aws")
+                    .with_only("aws")
+                    .build(),
+                Rule::builder("work aws", "~/.aws", "~/work/aws")
+                    .with_only("aws")
+                    .with_context(dir.join("work").to_string_lossy())
+                    .build(),
+                Rule::builder("profiled", "~/.p", "~/p")
+                    .with_when_env("PROFILE", Some("work"))
+                    .build(),
+            ],
+            ..Default::default()
+        };
+        let cases: Cases = serde_yaml::from_str(
+            r#"
+cases:
+- command: ["aws", "s3", "ls"]
+  rules: ["aws"]
+- name: "at work"
+  command: ["aws"]
+  cwd: "work"
+  env:
+    PROFILE: "work"
+  rules: ["aws", "work aws", "profiled"]
+- command: ["git"]
+  cwd: "work"
+  rules: ["aws", "profiled"]
+"#,
+        )?;
+        let fs = FsDriver::new();
+        assert_eq!(cases.cases[0].run(&rules, &dir, &fs)?, None);
+        assert_eq!(cases.cases[1].name(), "at work");
+        assert_eq!(cases.cases[1].run(&rules, &dir, &fs)?, None);
+        assert_eq!(
+            cases.cases[2].run(&rules, &dir, &fs)?,
+            Some(Failure {
+                missing: vec!["aws".to_string(), "profiled".to_string()],
+                unexpected: vec![],
+            })
+        );
+        assert!(serde_yaml::from_str::<Cases>("cases:\n- command: [a]\n  rule: [b]\n").is_err());
+
+        Ok(())
+    }
+}
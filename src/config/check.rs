@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, Deserialize, Visitor};
+
+use super::{parse_json_rules, parse_rules_in, resolve_include, VersionCheck};
+use crate::enclosure::fs::FsDriver;
+use crate::enclosure::rule::{BoxxyRules, Rule, Workspace};
+
+/// Something wrong with a config file, and the line it's on, when it can be
+/// found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.path.display(), self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// A rule and where it came from, for finding rules with the same target.
+struct Located {
+    rule: Rule,
+    path: PathBuf,
+    line: Option<usize>,
+    target: PathBuf,
+}
+
+/// Check `paths`, and the files they include, without running anything:
+/// that they parse, that they only use keys boxxy knows, that every rule's
+/// paths expand and its rewrite's parent exists, and that no two rules that
+/// can apply to the same program shadow the same target.
+pub fn check(paths: &[PathBuf], fs: &FsDriver) -> Vec<Problem> {
+    let mut files: Vec<PathBuf> = paths.to_vec();
+    let mut problems = vec![];
+    let mut scopes: BTreeMap<String, Vec<Located>> = BTreeMap::new();
+    let mut next = 0;
+    while let Some(path) = files.get(next).cloned() {
+        next += 1;
+        let path = &path;
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                problems.push(Problem {
+                    path: path.clone(),
+                    line: None,
+                    message: format!("could not read: {err}"),
+                });
+                continue;
+            }
+        };
+        let problem = |line: Option<usize>, message: String| Problem {
+            path: path.clone(),
+            line,
+            message,
+        };
+
+        let json = path
+            .extension()
+            .is_some_and(|extension| extension == "json");
+        let documents: Vec<serde_yaml::Value> = if json {
+            match serde_json::from_str(&contents) {
+                Ok(document) => vec![document],
+                Err(err) => {
+                    problems.push(problem(Some(err.line()), err.to_string()));
+                    continue;
+                }
+            }
+        } else {
+            let mut documents = vec![];
+            for document in serde_yaml::Deserializer::from_str(&contents) {
+                match serde_yaml::Value::deserialize(document) {
+                    Ok(document) => documents.push(document),
+                    Err(err) => {
+                        let line = err.location().map(|location| location.line());
+                        problems.push(problem(line, err.to_string()));
+                    }
+                }
+            }
+            documents
+        };
+        for document in &documents {
+            for (key, line) in unknown_keys(document, &contents) {
+                problems.push(problem(line, format!("unknown key `{key}`")));
+            }
+        }
+
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let parsed = if json {
+            parse_json_rules(&contents, VersionCheck::Warn, canonical.parent())
+        } else {
+            parse_rules_in(&contents, VersionCheck::Warn, canonical.parent())
+        };
+        let rules = match parsed {
+            Ok((rules, includes)) => {
+                let dir = path.parent().unwrap_or(Path::new("/"));
+                for pattern in includes {
+                    match resolve_include(&pattern, dir) {
+                        Ok(included) => {
+                            for included in included {
+                                if !files.contains(&included) {
+                                    files.push(included);
+                                }
+                            }
+                        }
+                        Err(err) => problems.push(problem(
+                            line_of(&contents, &pattern, 0),
+                            format!("bad include: {err}"),
+                        )),
+                    }
+                }
+                rules
+            }
+            Err(err) => {
+                // serde_yaml can't say where in the file a value it couldn't
+                // deserialize is, so point at the rule or value it names.
+                let message = err.to_string();
+                let line = message
+                    .split_once(':')
+                    .and_then(|(name, _)| line_of(&contents, name, 0))
+                    .or_else(|| {
+                        let quoted = message.split('`').nth(1)?;
+                        line_of(&contents, quoted, 0)
+                    });
+                problems.push(problem(line, message));
+                continue;
+            }
+        };
+
+        for (scope, rules) in scoped_rules(rules) {
+            for rule in rules {
+                let line = line_of(&contents, &rule.name, 0);
+                let rendered = match render(&rule) {
+                    Ok(rendered) => rendered,
+                    Err(err) => {
+                        problems.push(problem(line, err));
+                        continue;
+                    }
+                };
+                let target = match fs.fully_expand_path(&rendered.target) {
+                    Ok(target) => target,
+                    Err(err) => {
+                        problems.push(problem(
+                            line,
+                            format!("{}: could not expand target: {err}", rule.name),
+                        ));
+                        continue;
+                    }
+                };
+                // Rules without a rewrite get one in the store, which is
+                // created when it's first used.
+                if rendered.has_rewrite() && !rendered.rewrite.is_empty() {
+                    match fs.fully_expand_path(&rendered.rewrite) {
+                        Ok(rewrite) => {
+                            if let Some(parent) = rewrite.parent().filter(|parent| !parent.exists())
+                            {
+                                problems.push(problem(
+                                    line,
+                                    format!(
+                                        "{}: the parent of the rewrite, {}, doesn't exist",
+                                        rule.name,
+                                        parent.display()
+                                    ),
+                                ));
+                            }
+                        }
+                        Err(err) => problems.push(problem(
+                            line,
+                            format!("{}: could not expand rewrite: {err}", rule.name),
+                        )),
+                    }
+                }
+                // Disabled rules are still checked, but never shadow anything.
+                if !rule.is_enabled() {
+                    continue;
+                }
+                scopes.entry(scope.clone()).or_default().push(Located {
+                    rule,
+                    path: path.clone(),
+                    line,
+                    target,
+                });
+            }
+        }
+    }
+
+    for rules in scopes.values() {
+        for (i, first) in rules.iter().enumerate() {
+            for second in &rules[i + 1..] {
+                if first.target == second.target && share_programs(&first.rule, &second.rule) {
+                    problems.push(Problem {
+                        path: second.path.clone(),
+                        line: second.line,
+                        message: format!(
+                            "{}: target {} is also shadowed by rule '{}' in {}{}",
+                            second.rule.name,
+                            second.target.display(),
+                            first.rule.name,
+                            first.path.display(),
+                            first
+                                .line
+                                .map(|line| format!(":{line}"))
+                                .unwrap_or_default()
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// The rules in a config, grouped by where they can apply together: the
+/// top-level rules, and each profile and workspace.
+fn scoped_rules(rules: BoxxyRules) -> Vec<(String, Vec<Rule>)> {
+    let mut scoped = vec![("rules".to_string(), rules.rules)];
+    for (name, rules) in rules.profiles {
+        scoped.push((format!("profiles.{name}"), rules));
+    }
+    for (name, workspace) in rules.workspaces {
+        scoped.push((format!("workspaces.{name}"), workspace.rules));
+    }
+    scoped
+}
+
+/// Render a rule's templates, for the first program in its `only` if they
+/// need one.
+fn render(rule: &Rule) -> Result<Rule, String> {
+    rule.render_templates(None)
+        .or_else(|err| match rule.only.first() {
+            Some(binary) => rule.render_templates(Some(OsStr::new(binary))),
+            None => Err(err),
+        })
+        .map_err(|err| format!("{}: could not render templates: {err}", rule.name))
+}
+
+/// Whether two rules can apply to the same program.
+pub(super) fn share_programs(first: &Rule, second: &Rule) -> bool {
+    first.only.is_empty()
+        || second.only.is_empty()
+        || first.only.iter().any(|binary| second.only.contains(binary))
+}
+
+/// Keys in a config document that boxxy doesn't know, and the lines they're
+/// probably on.
+fn unknown_keys(document: &serde_yaml::Value, contents: &str) -> Vec<(String, Option<usize>)> {
+    let mut unknown = vec![];
+    let Some(mapping) = document.as_mapping() else {
+        return unknown;
+    };
+    let mut top_level = field_names::<BoxxyRules>().to_vec();
+    top_level.extend(["include", "include_url", "min_boxxy_version"]);
+    unknown_in(mapping, &top_level, contents, 0, &mut unknown);
+
+    let mut rules: Vec<&serde_yaml::Value> = vec![];
+    rules.extend(
+        document
+            .get("rules")
+            .and_then(|rules| rules.as_sequence())
+            .into_iter()
+            .flatten(),
+    );
+    if let Some(profiles) = document
+        .get("profiles")
+        .and_then(|profiles| profiles.as_mapping())
+    {
+        rules.extend(
+            profiles
+                .values()
+                .filter_map(|rules| rules.as_sequence())
+                .flatten(),
+        );
+    }
+    if let Some(workspaces) = document
+        .get("workspaces")
+        .and_then(|workspaces| workspaces.as_mapping())
+    {
+        for workspace in workspaces
+            .values()
+            .filter_map(|workspace| workspace.as_mapping())
+        {
+            unknown_in(
+                workspace,
+                field_names::<Workspace>(),
+                contents,
+                0,
+                &mut unknown,
+            );
+            rules.extend(
+                workspace
+                    .get("rules")
+                    .and_then(|rules| rules.as_sequence())
+                    .into_iter()
+                    .flatten(),
+            );
+        }
+    }
+
+    let mut rule_keys = field_names::<Rule>().to_vec();
+    rule_keys.push("extends");
+    for rule in rules.iter().filter_map(|rule| rule.as_mapping()) {
+        let start = rule
+            .get("name")
+            .and_then(|name| name.as_str())
+            .and_then(|name| line_of(contents, name, 0))
+            .unwrap_or(1);
+        unknown_in(rule, &rule_keys, contents, start - 1, &mut unknown);
+    }
+
+    unknown
+}
+
+fn unknown_in(
+    mapping: &serde_yaml::Mapping,
+    known: &[&str],
+    contents: &str,
+    from_line: usize,
+    unknown: &mut Vec<(String, Option<usize>)>,
+) {
+    for key in mapping.keys().filter_map(|key| key.as_str()) {
+        if !known.contains(&key) {
+            unknown.push((key.to_string(), line_of(contents, key, from_line)));
+        }
+    }
+}
+
+/// The 1-based number of the first line at or after `from_line` (0-based)
+/// that contains `needle`.
+fn line_of(contents: &str, needle: &str, from_line: usize) -> Option<usize> {
+    contents
+        .lines()
+        .enumerate()
+        .skip(from_line)
+        .find(|(_, line)| line.contains(needle))
+        .map(|(number, _)| number + 1)
+}
+
+/// The names of the fields that `T` deserializes from, as serde sees them.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// A deserializer that only records the fields of the struct it's asked to
+/// deserialize.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> de::Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only looking at field names"))
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_check() -> color_eyre::Result<()> {
+        let dir = Scratch::new("check")?;
+        let config = dir.join("boxxy.yaml");
+        std::fs::write(
+            &config,
+            format!(
+                r#"rules:
+- name: aws
+  target: /boxxy/a
+  rewrite: {dir}/aws
+  onyl: [aws]
+- name: aws again
+  target: /boxxy/a
+  rewrite: {dir}/missing/aws
+- name: only npm
+  target: /boxxy/a
+  rewrite: {dir}/npm
+  only: [npm]
+- name: disabled npm
+  extends: only npm
+  enabled: false
+profiles:
+  other:
+  - name: aws elsewhere
+    target: /boxxy/a
+    rewrite: {dir}/aws
+unknown: true
+"#,
+                dir = dir.display()
+            ),
+        )?;
+        let problems = check(std::slice::from_ref(&config), &FsDriver::new());
+        std::fs::write(&config, "rules:\n- name: bad\n  target: /a\n  mode: nope\n")?;
+        let invalid = check(std::slice::from_ref(&config), &FsDriver::new());
+
+        let problems: Vec<(Option<usize>, &str)> = problems
+            .iter()
+            .map(|problem| (problem.line, problem.message.as_str()))
+            .collect();
+        assert_eq!(problems.len(), 6, "{problems:?}");
+        assert_eq!(problems[0], (Some(21), "unknown key `unknown`"));
+        assert_eq!(problems[1], (Some(5), "unknown key `onyl`"));
+        assert_eq!(problems[2].0, Some(6));
+        assert!(problems[2].1.ends_with("missing, doesn't exist"));
+        assert!(problems[3]
+            .1
+            .starts_with("aws again: target /boxxy/a is also shadowed by rule 'aws'"));
+        assert!(problems[4].1.contains("by rule 'aws' in"));
+        assert!(problems[5].1.contains("by rule 'aws again' in"));
+
+        assert_eq!(invalid.len(), 1, "{invalid:?}");
+        assert!(invalid[0].message.contains("nope"));
+        assert_eq!(invalid[0].line, Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_field_names() {
+        assert!(field_names::<Rule>().contains(&"copy_xattrs"));
+        assert_eq!(field_names::<Workspace>(), ["context", "rules"]);
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use super::{parse_rules, remote, VersionCheck};
+use crate::enclosure::fs::append_all;
+
+const RULES_FILE: &str = "community-rules.yaml";
+
+/// Where the rules fetched by `boxxy rules sync` are kept.
+pub fn rules_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().ok_or_else(|| eyre!("could not find config directory"))?;
+    Ok(append_all(
+        &config_dir,
+        vec!["boxxy", "community", "rules.yaml"],
+    ))
+}
+
+/// Fetch the curated rules from boxxy's repo, check that they downloaded
+/// intact, and save them to [`rules_path`]. Returns how many
+/// rules there are.
+pub fn sync() -> Result<usize> {
+    let contents = remote::get_repo_files(&[RULES_FILE])?.remove(0);
+    let (rules, _) = parse_rules(&contents, VersionCheck::Error)
+        .map_err(|err| eyre!("{RULES_FILE} isn't a valid config: {err}"))?;
+    remote::write_atomically(&rules_path()?, &contents)?;
+    Ok(rules.rules.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_community_rules_parse() -> Result<()> {
+        let (rules, includes) = parse_rules(
+            include_str!("../../data/community-rules.yaml"),
+            VersionCheck::Error,
+        )?;
+        assert!(includes.is_empty());
+        assert!(!rules.rules.is_empty());
+        for rule in &rules.rules {
+            assert!(
+                !rule.only.is_empty(),
+                "{} should only apply to its program",
+                rule.name
+            );
+            assert!(
+                rule.has_any_tag(&["community".to_string()]),
+                "{}",
+                rule.name
+            );
+        }
+        Ok(())
+    }
+}
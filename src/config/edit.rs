@@ -0,0 +1,162 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use super::{parse_rules, VersionCheck};
+use crate::enclosure::rule::Rule;
+
+/// Add `rule` to the end of the top-level `rules` list in the YAML config
+/// `contents`, editing the text rather than re-serializing the whole config,
+/// so that comments and formatting are kept.
+pub fn add_rule(contents: &str, rule: &Rule) -> Result<String> {
+    rule.validate()?;
+    let (existing, _) = parse_rules(contents, VersionCheck::Ignore)?;
+    if existing
+        .rules
+        .iter()
+        .any(|existing| existing.name == rule.name)
+    {
+        return Err(eyre!("there's already a rule named {}", rule.name));
+    }
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut output = String::new();
+    match lines.iter().position(|line| is_key(line, "rules")) {
+        Some(start) => {
+            if lines[start].trim_end() != "rules:" {
+                return Err(eyre!(
+                    "can only add to a `rules:` list written one rule per item, not `{}`",
+                    lines[start].trim()
+                ));
+            }
+            // The list ends at the next top-level key or document, not
+            // counting the blank lines and comments before it.
+            let mut end = start + 1;
+            let mut indent = None;
+            for (i, line) in lines.iter().enumerate().skip(start + 1) {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                if line.starts_with(|c: char| !c.is_whitespace() && c != '-') {
+                    break;
+                }
+                if indent.is_none() && trimmed.starts_with('-') {
+                    indent = Some(line.len() - trimmed.len());
+                }
+                end = i + 1;
+            }
+
+            for line in &lines[..end] {
+                output.push_str(line);
+                output.push('\n');
+            }
+            output.push_str(&rule_item(rule, indent.unwrap_or(0))?);
+            for line in &lines[end..] {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        None => {
+            output.push_str(contents);
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str("rules:\n");
+            output.push_str(&rule_item(rule, 0)?);
+        }
+    }
+
+    // Make sure that the edit didn't break the config.
+    let (edited, _) = parse_rules(&output, VersionCheck::Ignore)
+        .map_err(|err| eyre!("adding the rule would break the config: {err}"))?;
+    if edited.rules.len() != existing.rules.len() + 1 {
+        return Err(eyre!("could not find where to add the rule in the config"));
+    }
+
+    Ok(output)
+}
+
+fn is_key(line: &str, key: &str) -> bool {
+    line.strip_prefix(key)
+        .is_some_and(|rest| rest.trim_start().starts_with(':'))
+}
+
+/// `rule` as a YAML list item, leaving out fields that are empty.
+fn rule_item(rule: &Rule, indent: usize) -> Result<String> {
+    let mut value = serde_yaml::to_value(rule)?;
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.retain(|_, value| match value {
+            serde_yaml::Value::String(string) => !string.is_empty(),
+            serde_yaml::Value::Sequence(sequence) => !sequence.is_empty(),
+            serde_yaml::Value::Mapping(mapping) => !mapping.is_empty(),
+            _ => true,
+        });
+    }
+
+    let mut item = String::new();
+    for (i, line) in serde_yaml::to_string(&value)?.lines().enumerate() {
+        item.push_str(&" ".repeat(indent));
+        item.push_str(if i == 0 { "- " } else { "  " });
+        item.push_str(line);
+        item.push('\n');
+    }
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclosure::rule::RuleMode;
+
+    #[test]
+    fn test_add_rule() -> Result<()> {
+        let rule = Rule::builder("foo", "~/.foo", "~/.config/foo")
+            .with_mode(RuleMode::File)
+            .with_only("foo")
+            .build();
+
+        let config = r#"# my rules
+rules:
+  # aws
+  - name: aws
+    target: ~/.aws
+    rewrite: ~/.config/aws
+
+# shared
+profiles: {}
+"#;
+        let edited = add_rule(config, &rule)?;
+        assert_eq!(
+            edited,
+            r#"# my rules
+rules:
+  # aws
+  - name: aws
+    target: ~/.aws
+    rewrite: ~/.config/aws
+  - name: foo
+    target: ~/.foo
+    rewrite: ~/.config/foo
+    mode: file
+    only:
+    - foo
+
+# shared
+profiles: {}
+"#
+        );
+
+        let edited = add_rule("", &rule)?;
+        assert!(edited.starts_with("rules:\n- name: foo\n"));
+        assert_eq!(parse_rules(&edited, VersionCheck::Ignore)?.0.rules.len(), 1);
+
+        assert!(add_rule(&edited, &rule).is_err());
+        let stored = Rule::builder("bar", "~/.bar", "").build();
+        assert_eq!(
+            add_rule("", &stored)?,
+            "rules:\n- name: bar\n  target: ~/.bar\n  mode: directory\n"
+        );
+        assert!(add_rule("rules: []\n", &rule).is_err());
+        Ok(())
+    }
+}
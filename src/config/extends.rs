@@ -0,0 +1,161 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_yaml::{Mapping, Value};
+
+/// Fill in the rules in a config document that `extends` another rule, by
+/// name, with the fields of that rule that they don't set themselves. The
+/// rule being extended has to be in the same document, in `rules`, a profile,
+/// or a workspace. Its `name` and `enabled` aren't inherited, so a disabled
+/// rule can be used as a template.
+pub fn resolve_extends(document: &mut Value) -> Result<()> {
+    let rules: Vec<Mapping> = rules_mut(document)
+        .into_iter()
+        .filter_map(|rule| rule.as_mapping().cloned())
+        .collect();
+
+    for rule in rules_mut(document) {
+        let Some(mapping) = rule.as_mapping() else {
+            continue;
+        };
+        if mapping.contains_key("extends") {
+            *rule = Value::Mapping(resolve(mapping, &rules, &mut vec![])?);
+        }
+    }
+
+    Ok(())
+}
+
+/// `rule` with the fields of the rules it extends filled in. `extending` is
+/// the names of the rules being resolved, to catch cycles.
+fn resolve(rule: &Mapping, rules: &[Mapping], extending: &mut Vec<String>) -> Result<Mapping> {
+    let name = rule
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("?")
+        .to_string();
+    let Some(base) = rule.get("extends") else {
+        return Ok(rule.clone());
+    };
+    let base = base
+        .as_str()
+        .ok_or_else(|| eyre!("{name}: extends must be the name of a rule"))?;
+    if extending.contains(&name) {
+        return Err(eyre!("{name}: rule extends itself"));
+    }
+    let base_rule = rules
+        .iter()
+        .find(|rule| rule.get("name").and_then(Value::as_str) == Some(base))
+        .ok_or_else(|| eyre!("{name}: extends unknown rule {base}"))?;
+
+    extending.push(name);
+    let mut resolved = resolve(base_rule, rules, extending)?;
+    extending.pop();
+
+    resolved.remove("name");
+    resolved.remove("enabled");
+    for (key, value) in rule {
+        if key.as_str() != Some("extends") {
+            resolved.insert(key.clone(), value.clone());
+        }
+    }
+    Ok(resolved)
+}
+
+/// Every rule in a document, wherever it is.
+pub(super) fn rules_mut(document: &mut Value) -> Vec<&mut Value> {
+    let mut rules = vec![];
+    let Some(document) = document.as_mapping_mut() else {
+        return rules;
+    };
+    for (key, value) in document.iter_mut() {
+        match key.as_str() {
+            Some("rules") => rules.extend(value.as_sequence_mut().into_iter().flatten()),
+            Some("profiles") => rules.extend(
+                value
+                    .as_mapping_mut()
+                    .into_iter()
+                    .flat_map(|profiles| profiles.values_mut())
+                    .filter_map(Value::as_sequence_mut)
+                    .flatten(),
+            ),
+            Some("workspaces") => rules.extend(
+                value
+                    .as_mapping_mut()
+                    .into_iter()
+                    .flat_map(|workspaces| workspaces.values_mut())
+                    .filter_map(|workspace| workspace.get_mut("rules"))
+                    .filter_map(Value::as_sequence_mut)
+                    .flatten(),
+            ),
+            _ => {}
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(config: &str) -> Result<Value> {
+        let mut document: Value = serde_yaml::from_str(config)?;
+        resolve_extends(&mut document)?;
+        Ok(document)
+    }
+
+    #[test]
+    fn test_resolve_extends() -> Result<()> {
+        let document = resolved(
+            r#"
+rules:
+- name: base
+  target: ~/.foo
+  rewrite: ~/.config/foo
+  only: [foo]
+  enabled: false
+- name: bar
+  extends: base
+  only: [bar]
+- name: baz
+  extends: bar
+  mode: file
+profiles:
+  work:
+  - name: work-foo
+    extends: base
+"#,
+        )?;
+        let expected: Value = serde_yaml::from_str(
+            r#"
+rules:
+- name: base
+  target: ~/.foo
+  rewrite: ~/.config/foo
+  only: [foo]
+  enabled: false
+- target: ~/.foo
+  rewrite: ~/.config/foo
+  name: bar
+  only: [bar]
+- target: ~/.foo
+  rewrite: ~/.config/foo
+  only: [bar]
+  name: baz
+  mode: file
+profiles:
+  work:
+  - target: ~/.foo
+    rewrite: ~/.config/foo
+    only: [foo]
+    name: work-foo
+"#,
+        )?;
+        assert_eq!(document, expected);
+
+        assert!(resolved("rules: [{name: a, extends: missing}]").is_err());
+        assert!(resolved("rules: [{name: a, extends: b}, {name: b, extends: a}]").is_err());
+        assert!(resolved("rules: [{name: a, extends: a}]").is_err());
+        assert!(resolved("rules: [{name: a, extends: [b]}]").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use serde_yaml::Value;
+
+use super::extends::rules_mut;
+use crate::enclosure::fs::expand_tilde;
+use crate::enclosure::template::is_template;
+
+/// Resolve the rules in a config document whose `rewrite` is a list of
+/// candidates, ex. for configs that live in different places on different
+/// distros. See [`resolve_rule`].
+pub fn resolve_rewrites<F: Fn(&Path) -> bool>(document: &mut Value, exists: &F) -> Result<()> {
+    for rule in rules_mut(document) {
+        resolve_rule(rule, exists)?;
+    }
+    Ok(())
+}
+
+/// If `rule`'s `rewrite` is a list, replace it with the first candidate that
+/// exists after `~` and `~user` are expanded, or else the last one, which is created like
+/// any other missing rewrite. Templates aren't rendered until a command is
+/// run, so candidates can't use them.
+pub fn resolve_rule<F: Fn(&Path) -> bool>(rule: &mut Value, exists: &F) -> Result<()> {
+    let name = rule
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("?")
+        .to_string();
+    let Some(Value::Sequence(candidates)) = rule.get("rewrite") else {
+        return Ok(());
+    };
+    let candidates = candidates
+        .iter()
+        .map(|candidate| candidate.as_str())
+        .collect::<Option<Vec<&str>>>()
+        .ok_or_else(|| eyre!("{name}: rewrite must be a path or a list of paths"))?;
+    if let Some(templated) = candidates.iter().find(|candidate| is_template(candidate)) {
+        return Err(eyre!(
+            "{name}: rewrite candidate `{templated}` uses a template, but candidates are checked before templates are rendered"
+        ));
+    }
+    let chosen = candidates
+        .iter()
+        .find(|candidate| exists(Path::new(&expand_tilde(candidate))))
+        .or(candidates.last())
+        .ok_or_else(|| eyre!("{name}: rewrite is an empty list"))?
+        .to_string();
+    debug!("{name}: using rewrite {chosen}");
+    if let Some(rule) = rule.as_mapping_mut() {
+        rule.insert("rewrite".into(), chosen.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_candidates() -> Result<()> {
+        let mut document: Value = serde_yaml::from_str(
+            r#"
+rules:
+- name: "found"
+  target: "~/.a"
+  rewrite: ["/etc/missing", "/etc/found", "/etc/also-found"]
+- name: "created"
+  target: "~/.b"
+  rewrite: ["/etc/missing", "~/fallback"]
+- name: "single"
+  target: "~/.c"
+  rewrite: "/etc/single"
+profiles:
+  work:
+  - name: "profiled"
+    target: "~/.d"
+    rewrite: ["/etc/found"]
+"#,
+        )?;
+        let exists = |path: &Path| path.ends_with("found");
+        resolve_rewrites(&mut document, &exists)?;
+        let rewrite = |rule: &Value| rule["rewrite"].as_str().unwrap().to_string();
+        assert_eq!(rewrite(&document["rules"][0]), "/etc/found");
+        assert_eq!(rewrite(&document["rules"][1]), "~/fallback");
+        assert_eq!(rewrite(&document["rules"][2]), "/etc/single");
+        assert_eq!(rewrite(&document["profiles"]["work"][0]), "/etc/found");
+
+        let mut empty: Value = serde_yaml::from_str("name: e\ntarget: ~/.e\nrewrite: []\n")?;
+        assert!(resolve_rule(&mut empty, &exists).is_err());
+        let mut numbers: Value = serde_yaml::from_str("name: n\ntarget: ~/.n\nrewrite: [1]\n")?;
+        assert!(resolve_rule(&mut numbers, &exists).is_err());
+        let mut templated: Value = serde_yaml::from_str(
+            "name: t\ntarget: ~/.t\nrewrite: [\"{{ xdg_config }}/t\", ~/.t-fallback]\n",
+        )?;
+        let err = resolve_rule(&mut templated, &exists).unwrap_err();
+        assert!(err.to_string().contains("uses a template"), "{err}");
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_yaml::Value;
+
+/// Replace `${VAR}` and `${VAR:-default}` in every string in a config
+/// document with the value of the environment variable, except in rule
+/// names. `$${` is a literal `${`.
+pub fn interpolate_document<F: Fn(&str) -> Option<String>>(
+    value: &mut Value,
+    var: &F,
+) -> Result<()> {
+    walk(value, "", var)
+}
+
+fn walk<F: Fn(&str) -> Option<String>>(value: &mut Value, path: &str, var: &F) -> Result<()> {
+    match value {
+        Value::String(string) => {
+            if !is_rule_name(path) {
+                *string = interpolate(string, var).map_err(|err| eyre!("{path}: {err}"))?;
+            }
+        }
+        Value::Sequence(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                walk(value, &format!("{path}[{i}]"), var)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let key = key.as_str().unwrap_or("?");
+                let path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{path}.{key}")
+                };
+                walk(value, &path, var)?;
+            }
+        }
+        Value::Tagged(tagged) => walk(&mut tagged.value, path, var)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+
+    Ok(())
+}
+
+fn is_rule_name(path: &str) -> bool {
+    let rule = if let Some(profile) = path.strip_prefix("profiles.") {
+        profile.split_once('[').map(|(_, rule)| rule)
+    } else if let Some(workspace) = path.strip_prefix("workspaces.") {
+        workspace.split_once(".rules[").map(|(_, rule)| rule)
+    } else {
+        path.strip_prefix("rules[")
+    };
+    rule.and_then(|rest| rest.split_once("]."))
+        .is_some_and(|(_, field)| field == "name")
+}
+
+/// Interpolate environment variables into a single string.
+pub fn interpolate<F: Fn(&str) -> Option<String>>(input: &str, var: &F) -> Result<String> {
+    let mut output = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        output.push_str(&rest[..start]);
+        let end = closing_brace(&rest[start + 2..])
+            .ok_or_else(|| eyre!("unterminated `${{` in `{input}`"))?
+            + start
+            + 2;
+        let expression = &rest[start + 2..end];
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(eyre!(
+                "invalid environment variable name `{name}` in `{input}`"
+            ));
+        }
+
+        match (var(name).filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => output.push_str(&value),
+            (None, Some(default)) => output.push_str(&interpolate(default, var)?),
+            (None, None) => {
+                return Err(eyre!(
+                    "environment variable `{name}` isn't set, use `${{{name}:-default}}` to give it a default"
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// The index of the `}` that closes a `${`, skipping over any `${...}`
+/// nested in a default.
+fn closing_brace(after_open: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = after_open.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '$' if chars.peek().is_some_and(|(_, next)| *next == '{') => {
+                chars.next();
+                depth += 1;
+            }
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Option<String> {
+        match name {
+            "ROOT" => Some("/mnt/fast".into()),
+            "EMPTY" => Some("".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() -> Result<()> {
+        assert_eq!(interpolate("~/.aws", &var)?, "~/.aws");
+        assert_eq!(interpolate("${ROOT}/aws", &var)?, "/mnt/fast/aws");
+        assert_eq!(
+            interpolate("${MISSING:-~/x}/${ROOT}", &var)?,
+            "~/x//mnt/fast"
+        );
+        assert_eq!(interpolate("${EMPTY:-fallback}", &var)?, "fallback");
+        assert_eq!(interpolate("${ROOT:-}", &var)?, "/mnt/fast");
+        assert_eq!(
+            interpolate("${MISSING:-${ROOT}/share}/aws", &var)?,
+            "/mnt/fast/share/aws"
+        );
+        assert_eq!(
+            interpolate("${MISSING:-${NOPE:-x}}${ROOT}", &var)?,
+            "x/mnt/fast"
+        );
+        assert!(interpolate("${MISSING:-${NOPE}}", &var).is_err());
+        assert!(interpolate("${MISSING:-${ROOT}", &var).is_err());
+        assert_eq!(
+            interpolate("$${ROOT} and $HOME", &var)?,
+            "${ROOT} and $HOME"
+        );
+        assert!(interpolate("${MISSING}", &var).is_err());
+        assert!(interpolate("${ROOT", &var).is_err());
+        assert!(interpolate("${}", &var).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolate_document_skips_rule_names() -> Result<()> {
+        let mut document: Value = serde_yaml::from_str(
+            "rules:\n- name: ${ROOT}\n  target: ${ROOT}\n  env:\n    name: ${ROOT}\n",
+        )?;
+        interpolate_document(&mut document, &var)?;
+        let rule = &document["rules"][0];
+        assert_eq!(rule["name"], "${ROOT}");
+        assert_eq!(rule["target"], "/mnt/fast");
+        assert_eq!(rule["env"]["name"], "/mnt/fast");
+
+        let mut document: Value = serde_yaml::from_str("rules:\n- rewrite: ${NOPE}\n")?;
+        let err = interpolate_document(&mut document, &var).unwrap_err();
+        assert!(err.to_string().starts_with("rules[0].rewrite: "));
+        Ok(())
+    }
+}
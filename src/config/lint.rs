@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use crate::enclosure::rule::{is_glob, Rule};
+
+/// Why `rule` can never apply on this machine, as things are now: programs
+/// in its `only` that aren't in `PATH` or on disk, `context` directories that
+/// don't exist, and `when_env` variables that it needs set but, as seen
+/// through `var`, aren't.
+pub fn unreachable_reasons<F: Fn(&str) -> Option<String>>(rule: &Rule, var: F) -> Vec<String> {
+    let mut reasons = vec![];
+    if !rule.only.is_empty() && rule.only.iter().all(|program| !program_exists(program)) {
+        reasons.push(format!(
+            "none of the programs in `only` are in PATH or on disk: {}",
+            rule.only.join(", ")
+        ));
+    }
+
+    if !rule.context.is_empty()
+        && rule.context_git.is_empty()
+        && rule
+            .context
+            .iter()
+            .all(|dir| !Path::new(shellexpand::tilde(dir).as_ref()).is_dir())
+    {
+        reasons.push(format!(
+            "none of the directories in `context` exist: {}",
+            rule.context.join(", ")
+        ));
+    }
+
+    let mut unset: Vec<&str> = rule
+        .when_env
+        .iter()
+        .filter(|(key, value)| value.is_some() && var(key).is_none())
+        .map(|(key, _)| key.as_str())
+        .collect();
+    unset.sort();
+    for key in unset {
+        reasons.push(format!("`when_env` needs ${key} to be set, but it isn't"));
+    }
+
+    reasons
+}
+
+/// Whether an `only` entry matches anything in PATH or on disk.
+fn program_exists(program: &str) -> bool {
+    if !is_glob(program) {
+        return which::which(program).is_ok();
+    }
+    let patterns: Vec<String> = if program.contains('/') {
+        vec![shellexpand::tilde(program).to_string()]
+    } else {
+        std::env::var_os("PATH")
+            .map(|path| {
+                std::env::split_paths(&path)
+                    .map(|dir| dir.join(program).to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    patterns
+        .iter()
+        .any(|pattern| glob::glob(pattern).is_ok_and(|mut paths| paths.any(|path| path.is_ok())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreachable_reasons() {
+        let var = |key: &str| (key == "SET").then(|| "1".to_string());
+        let reachable = Rule::builder("ok", "~/.a", "~/.b")
+            .with_only("sh")
+            .with_only("boxxy-no-such-program")
+            .with_only("s?")
+            .with_context("/")
+            .with_when_env("SET", Some("1"))
+            .with_when_env("UNSET", None)
+            .build();
+        assert!(unreachable_reasons(&reachable, var).is_empty());
+
+        let unreachable = Rule::builder("bad", "~/.a", "~/.b")
+            .with_only("boxxy-no-such-program")
+            .with_only("/boxxy/no/such/program")
+            .with_only("/boxxy/no/*/program")
+            .with_context("/boxxy/no/such/dir")
+            .with_when_env("UNSET", Some("*"))
+            .build();
+        assert_eq!(unreachable_reasons(&unreachable, var).len(), 3);
+
+        let glob = Rule::builder("glob", "~/.a", "~/.b")
+            .with_only("/bin/s*")
+            .build();
+        assert!(unreachable_reasons(&glob, var).is_empty());
+    }
+}
@@ -1,19 +1,114 @@
-use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use log::*;
+use serde::Deserialize;
 
-use crate::enclosure::rule::{BoxxyRules, Rule};
+use crate::enclosure::agent::SshAgent;
+use crate::enclosure::enforce::Enforcement;
+use crate::enclosure::fs::FsDriver;
+use crate::enclosure::limits::Umask;
+use crate::enclosure::rule::{context_contains, BoxxyRules, Rule};
+use crate::store::Project;
+use trust::Trust;
+
+pub mod cases;
+mod check;
+pub mod community;
+mod edit;
+mod extends;
+mod fallback;
+mod interpolate;
+mod lint;
+mod precedence;
+pub mod preset;
+mod relative;
+pub mod remote;
+pub mod schema;
+pub mod trust;
+mod wrapper;
+
+pub use check::{check, Problem};
+pub use edit::add_rule;
+pub use lint::unreachable_reasons;
 
 pub struct BoxxyConfig {
     pub rules: BoxxyRules,
     pub immutable_root: bool,
     pub trace: bool,
+    /// Whether to serve a control socket for the boxxed program.
+    pub control_socket: bool,
+    /// Syscalls that `trace` doesn't let the command make.
+    pub trace_enforce: Option<Enforcement>,
+    /// Record the files the command opens with fanotify, like a cheaper
+    /// `trace`.
+    pub watch: bool,
+    /// Whether to list every path in the trace report instead of rolling up
+    /// big directories.
+    pub expand_report: bool,
     pub dotenv: bool,
     pub daemon: bool,
     pub command: Command,
+    /// Shell commands to run, in order, in the same container once `command`
+    /// exits successfully.
+    pub and_then: Vec<String>,
+    /// If set, serve `boxxy run-in` requests under this name instead of
+    /// running `command`.
+    pub serve: Option<String>,
+    /// Whether the command is an AppImage that should be allowed to run.
+    pub appimage: bool,
+    /// Whether to give the command its own `TMPDIR` that's deleted when the
+    /// box exits.
+    pub private_tmpdir: bool,
+    /// Whether to check that the rules are in effect before running the
+    /// command.
+    pub self_test: bool,
+    /// Whether to print the mounts that the rules were applied with before
+    /// running the command.
+    pub verbose_mounts: bool,
+    /// Whether to print the order the rules are mounted in before running
+    /// the command.
+    pub show_mount_order: bool,
+    /// If set, print what running `command` would do instead of running it.
+    pub dry_run: bool,
+    /// Whether to make boxxy's tmpfs mounts with a source naming the box.
+    pub tag_mounts: bool,
+    /// Whether to build the container root on a tmpfs instead of on disk.
+    pub root_tmpfs: bool,
+    /// Whether `$HOME` is read-only in the box, except for rule rewrites.
+    pub ro_home: bool,
+    /// Whether to start the default shell with an rc snippet for the box.
+    pub shell_init: bool,
+    /// Whether the command can use the SSH agent.
+    pub ssh_agent: SshAgent,
+    /// Whether to hide the gpg-agent and the keyrings unless a rule allows
+    /// them.
+    pub strict_secrets: bool,
+    /// If set, report on whether each rule is in effect instead of running
+    /// `command`.
+    pub verify: bool,
+    /// Whether to name the container after the command and working
+    /// directory instead of randomly.
+    pub name_from_command: bool,
+    /// Whether to also match rules against the programs that wrappers run.
+    pub follow_wrappers: bool,
+    /// Whether to leave boxxy's own config and state writable in the box.
+    pub expose_boxxy_config: bool,
+    /// Whether to run without asking when a rule would hide data in its
+    /// target behind an empty rewrite.
+    pub assume_yes: bool,
+    /// Tags of the rules whose setup is logged at the info level.
+    pub log_tags: Vec<String>,
+    /// The stack size of the container process, overriding the stack
+    /// rlimit.
+    pub stack_size: Option<u64>,
 }
 
 impl BoxxyConfig {
@@ -41,37 +136,125 @@ impl BoxxyConfig {
         }
     }
 
+    /// The names config files are looked for under, in the order they're
+    /// loaded when a directory has more than one: the YAML config, then the
+    /// JSON one.
+    pub fn config_file_names() -> Result<[&'static str; 2]> {
+        if Self::debug_mode()? {
+            Ok(["boxxy-dev.yaml", "boxxy-dev.json"])
+        } else {
+            Ok(["boxxy.yaml", "boxxy.json"])
+        }
+    }
+
+    /// boxxy's own files that a boxxed program could use to change the rules
+    /// of future boxes: the config dir, every config file that applies in
+    /// the current directory, and the state that `boxxy top` reads. Only
+    /// paths that exist are returned.
+    pub fn protected_paths() -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        if let Some(config_dir) = dirs::config_dir() {
+            paths.push(crate::enclosure::fs::append_all(&config_dir, vec!["boxxy"]));
+        }
+        for config in Self::rule_paths()? {
+            paths.extend(Self::included_paths(&config).unwrap_or_default());
+            paths.push(config);
+        }
+        paths.push(crate::enclosure::fs::append_all(
+            &Self::runtime_dir()?,
+            vec!["boxes"],
+        ));
+
+        let mut protected: Vec<PathBuf> = vec![];
+        for path in paths.iter().filter_map(|path| path.canonicalize().ok()) {
+            if !protected.iter().any(|parent| path.starts_with(parent)) {
+                protected.push(path);
+            }
+        }
+
+        Ok(protected)
+    }
+
+    /// Directory for sockets and other per-session state,
+    /// `$XDG_RUNTIME_DIR/boxxy` or `/tmp/boxxy-<uid>` if that isn't set.
+    pub fn runtime_dir() -> Result<PathBuf> {
+        let Some(runtime_dir) = dirs::runtime_dir() else {
+            // Anyone can create this before us, so it has to be checked.
+            let dir = PathBuf::from(format!("/tmp/boxxy-{}", nix::unistd::getuid()));
+            match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+            check_private_dir(&dir)?;
+            return Ok(dir);
+        };
+        let dir = crate::enclosure::fs::append_all(&runtime_dir, vec!["boxxy"]);
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(dir)
+    }
+
+    /// The config files that apply in the current directory: the ones in
+    /// boxxy's config directory, then the ones in the current directory and
+    /// each of its parents, nearest first.
     pub fn rule_paths() -> Result<Vec<PathBuf>> {
-        let config_file_name = Self::default_config_file_name()?;
+        let mut config_paths = Self::global_rule_paths()?;
+        config_paths.extend(Self::project_rule_paths()?);
+        Ok(config_paths)
+    }
 
-        let default_config_file = {
+    /// `rule_paths`, from the lowest precedence to the highest: the ones in
+    /// boxxy's config directory, then the ones from the root of the
+    /// filesystem down to the current directory.
+    pub fn rule_paths_by_precedence() -> Result<Vec<PathBuf>> {
+        let mut config_paths = Self::global_rule_paths()?;
+        config_paths.extend(Self::project_rule_paths()?.into_iter().rev());
+        Ok(config_paths)
+    }
+
+    pub(super) fn global_rule_paths() -> Result<Vec<PathBuf>> {
+        let config_file_names = Self::config_file_names()?;
+
+        let config_dir = {
             let config_dir = dirs::config_dir().unwrap();
-            let config_path =
-                crate::enclosure::fs::append_all(&config_dir, vec!["boxxy", config_file_name]);
+            let config_dir = crate::enclosure::fs::append_all(&config_dir, vec!["boxxy"]);
 
-            std::fs::create_dir_all(config_path.parent().unwrap())?;
+            std::fs::create_dir_all(&config_dir)?;
 
-            config_path
+            config_dir
         };
 
         let mut config_paths = vec![];
-        if default_config_file.exists() {
-            config_paths.push(default_config_file);
+        for config_file_name in config_file_names {
+            let config_path = crate::enclosure::fs::append_all(&config_dir, vec![config_file_name]);
+            if config_path.exists() {
+                config_paths.push(config_path);
+            }
         }
 
-        // Search up the tree for a `config_file_name` file
+        Ok(config_paths)
+    }
+
+    fn project_rule_paths() -> Result<Vec<PathBuf>> {
+        let config_file_names = Self::config_file_names()?;
+        let mut config_paths = vec![];
+
+        // Search up the tree for config files
         let mut current_dir = std::env::current_dir()?;
         debug!(
             "searching for boxxy config starting at {}",
             current_dir.display()
         );
         loop {
-            let config_path =
-                crate::enclosure::fs::append_all(&current_dir, vec![config_file_name]);
-            debug!("checking for: {}", config_path.display());
-            if config_path.exists() {
-                debug!("found boxxy config file at {}", config_path.display());
-                config_paths.push(config_path);
+            for config_file_name in config_file_names {
+                let config_path =
+                    crate::enclosure::fs::append_all(&current_dir, vec![config_file_name]);
+                debug!("checking for: {}", config_path.display());
+                if config_path.exists() {
+                    debug!("found boxxy config file at {}", config_path.display());
+                    config_paths.push(config_path);
+                }
             }
 
             if let Some(parent) = current_dir.parent() {
@@ -89,76 +272,340 @@ impl BoxxyConfig {
         Ok(config_paths)
     }
 
-    pub fn load_rules_from_path(path: &Path) -> Result<BoxxyRules> {
-        let config = config::Config::builder()
-            .add_source(config::File::new(
-                &path.to_string_lossy(),
-                config::FileFormat::Yaml,
-            ))
-            .build()?;
+    /// Load the rules in a config file, and in the files it includes.
+    /// Keys that run commands on the host are dropped unless the file is
+    /// trusted; see [`trust`]. Files it includes are trusted as far as it is.
+    pub fn load_rules_from_path(path: &Path, version_check: VersionCheck) -> Result<BoxxyRules> {
+        let _span = tracing::info_span!("load config", path = %path.display()).entered();
+        let trust = trust::trust_of(path, &std::fs::read_to_string(path)?)?;
+        Self::load_rules_with_includes(path, version_check, trust, &mut vec![], &mut vec![])
+    }
 
-        let rules = config.try_deserialize::<BoxxyRules>()?;
+    /// Every file that a config file includes, directly or not.
+    pub fn included_paths(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut loaded = vec![];
+        Self::load_rules_with_includes(
+            path,
+            VersionCheck::Ignore,
+            Trust::Own,
+            &mut vec![],
+            &mut loaded,
+        )?;
+        loaded.retain(|loaded| loaded.as_path() != path);
+        Ok(loaded)
+    }
 
-        Ok(rules)
+    fn load_rules_with_includes(
+        path: &Path,
+        version_check: VersionCheck,
+        trust: Trust,
+        including: &mut Vec<PathBuf>,
+        loaded: &mut Vec<PathBuf>,
+    ) -> Result<BoxxyRules> {
+        let canonical = path.canonicalize()?;
+        if including.contains(&canonical) {
+            return Err(eyre!("{} includes itself", path.display()));
+        }
+        let contents = std::fs::read_to_string(path)?;
+        let parsed = if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            parse_json_rules(&contents, version_check, canonical.parent())
+        } else {
+            parse_rules_in(&contents, version_check, canonical.parent())
+        };
+        let (mut rules, includes) =
+            parsed.map_err(|err| eyre!("invalid config in {}: {err}", path.display()))?;
+        trust::restrict(&mut rules, trust, path)?;
+        loaded.push(path.to_path_buf());
+
+        including.push(canonical);
+        let mut configs = vec![rules];
+        for pattern in includes {
+            let trust = if remote::is_url(&pattern) {
+                Trust::Remote
+            } else {
+                trust
+            };
+            for included in resolve_include(&pattern, path.parent().unwrap_or(Path::new("/")))? {
+                debug!("{} includes {}", path.display(), included.display());
+                configs.push(
+                    Self::load_rules_with_includes(
+                        &included,
+                        version_check,
+                        trust,
+                        including,
+                        loaded,
+                    )
+                    .map_err(|err| {
+                        if trust == Trust::Remote {
+                            eyre!("in include_url {pattern}: {err}")
+                        } else {
+                            err
+                        }
+                    })?,
+                );
+            }
+        }
+        including.pop();
+
+        Ok(Self::merge(configs))
     }
 
     pub fn load_rules_from_cli_flag(rules: &[String]) -> Result<BoxxyRules> {
         let rules = rules
             .iter()
             .map(|s| {
-                let parts: Vec<&str> = s.split(':').collect();
+                let parts = split_cli_rule(s);
                 match parts.as_slice() {
-                    [src, dest] => Rule {
-                        name: format!("cli-loaded rule: {src} -> {dest}"),
-                        target: src.to_string(),
-                        rewrite: dest.to_string(),
-                        mode: crate::enclosure::rule::RuleMode::File,
-                        context: vec![],
-                        only: vec![],
-                        env: HashMap::new(),
-                    },
-
-                    [src, dest, mode] => Rule {
-                        name: format!("cli-loaded rule: {src} -> {dest} ({mode})"),
-                        target: src.to_string(),
-                        rewrite: dest.to_string(),
-                        mode: mode.parse().unwrap(),
-                        context: vec![],
-                        only: vec![],
-                        env: HashMap::new(),
-                    },
-
-                    _ => panic!("invalid format for cli rule: {s}"),
+                    [src, dest] => Ok(Rule::builder(
+                        format!("cli-loaded rule: {src} -> {dest}"),
+                        src,
+                        dest,
+                    )
+                    .with_mode(crate::enclosure::rule::RuleMode::File)
+                    .build()),
+
+                    [src, dest, mode] => Ok(Rule::builder(
+                        if dest.is_empty() {
+                            format!("cli-loaded rule: {mode} at {src}")
+                        } else {
+                            format!("cli-loaded rule: {src} -> {dest} ({mode})")
+                        },
+                        src,
+                        dest,
+                    )
+                    .with_mode(mode.parse().map_err(|err| eyre!("{err} in cli rule: {s}"))?)
+                    .build()),
+
+                    _ => Err(eyre!(
+                        "invalid format for cli rule: {s}, expected `target:rewrite[:mode]` (escape `:` in paths as `\\:`)"
+                    )),
                 }
             })
-            .collect();
-        Ok(BoxxyRules { rules })
+            .collect::<Result<_>>()?;
+        Ok(BoxxyRules {
+            rules,
+            ..Default::default()
+        })
+    }
+
+    /// Load rules passed as YAML with `--rule-yaml`. Each snippet is either a
+    /// single rule or a list of them, and rules without a name are named
+    /// after their target and rewrite.
+    pub fn load_rules_from_cli_yaml(snippets: &[String]) -> Result<BoxxyRules> {
+        let mut rules = vec![];
+        for snippet in snippets {
+            let value: serde_yaml::Value = serde_yaml::from_str(snippet)
+                .map_err(|err| eyre!("invalid yaml in --rule-yaml: {err}"))?;
+            let values = match value {
+                serde_yaml::Value::Sequence(values) => values,
+                value => vec![value],
+            };
+
+            for mut value in values {
+                fallback::resolve_rule(&mut value, &|path| path.exists())
+                    .map_err(|err| eyre!("invalid rule in --rule-yaml: {err}"))?;
+                if let serde_yaml::Value::Mapping(mapping) = &mut value {
+                    if !mapping.contains_key("name") {
+                        let field = |key: &str| {
+                            mapping
+                                .get(key)
+                                .and_then(|value| value.as_str())
+                                .unwrap_or_default()
+                                .to_string()
+                        };
+                        let name =
+                            if mapping.get("deny").and_then(|deny| deny.as_bool()) == Some(true) {
+                                format!("cli-loaded rule: deny {}", field("target"))
+                            } else if field("mode") == "tmpfs" {
+                                format!("cli-loaded rule: tmpfs at {}", field("target"))
+                            } else if field("mode") == "expose-ro" {
+                                format!("cli-loaded rule: expose {} read-only", field("target"))
+                            } else if field("mode") == "mask" {
+                                format!("cli-loaded rule: mask {}", field("target"))
+                            } else if field("mode") == "copy" {
+                                format!(
+                                    "cli-loaded rule: copy {} to {}",
+                                    field("rewrite"),
+                                    field("target")
+                                )
+                            } else if field("rewrite").is_empty() {
+                                format!("cli-loaded rule: {}", field("target"))
+                            } else {
+                                format!(
+                                    "cli-loaded rule: {} -> {}",
+                                    field("target"),
+                                    field("rewrite")
+                                )
+                            };
+                        mapping.insert("name".into(), name.into());
+                    }
+                }
+                let rule: Rule = serde_yaml::from_value(value)
+                    .map_err(|err| eyre!("invalid rule in --rule-yaml: {err}"))?;
+                rule.validate()
+                    .map_err(|err| eyre!("invalid rule in --rule-yaml: {err}"))?;
+                rules.push(rule);
+            }
+        }
+
+        Ok(BoxxyRules {
+            rules,
+            ..Default::default()
+        })
+    }
+
+    pub fn load_project_cache_rules() -> Result<BoxxyRules> {
+        let cwd = std::env::current_dir()?;
+        match Project::detect(&cwd) {
+            Some(project) => {
+                info!(
+                    "isolating {} caches for project {}",
+                    project
+                        .kinds
+                        .iter()
+                        .map(|kind| kind.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    project.root.display()
+                );
+                let store_dir = project.store_dir()?;
+                project.mark_used(&store_dir)?;
+                Ok(BoxxyRules {
+                    rules: project.cache_rules(&store_dir)?,
+                    ..Default::default()
+                })
+            }
+            None => {
+                warn!(
+                    "--isolate-project-caches: no cargo, npm, pip, or gradle project found in {}",
+                    cwd.display()
+                );
+                Ok(BoxxyRules::default())
+            }
+        }
     }
 
     pub fn merge(configs: Vec<BoxxyRules>) -> BoxxyRules {
-        let mut merged = BoxxyRules { rules: vec![] };
+        let mut merged = BoxxyRules::default();
         for config in configs {
             merged.rules.extend(config.rules);
+            merged.container_init.extend(config.container_init);
+            merged.shell_init.extend(config.shell_init);
+            merged.rlimits.merge(config.rlimits);
+            if config.store_root.is_some() {
+                merged.store_root = config.store_root;
+            }
+            if config.containers_root.is_some() {
+                merged.containers_root = config.containers_root;
+            }
+            // A later config, ex. a project's, can only tighten the umask.
+            merged.umask = Umask::tightest(merged.umask.into_iter().chain(config.umask));
+            merged.tag_mounts |= config.tag_mounts;
+            merged.strict_secrets |= config.strict_secrets;
+            merged.ro_home |= config.ro_home;
+            for (name, rules) in config.profiles {
+                merged.profiles.entry(name).or_default().extend(rules);
+            }
+            for (name, workspace) in config.workspaces {
+                let merged = merged.workspaces.entry(name).or_default();
+                merged.context.extend(workspace.context);
+                merged.rules.extend(workspace.rules);
+            }
         }
 
         merged
     }
 
-    pub fn load_config(args: crate::Args) -> Result<Self> {
-        // Load rules
-        let rules = {
-            let mut rules = vec![];
-            if !args.no_config {
-                debug!("loading rules (not asked not to!)");
-                for config in BoxxyConfig::rule_paths()? {
-                    info!("loading rules from {}", config.display());
-                    rules.push(BoxxyConfig::load_rules_from_path(&config)?);
-                }
+    /// Load and merge the rules from all config files and CLI flags.
+    pub fn load_rules(args: &crate::Args) -> Result<BoxxyRules> {
+        let _span = tracing::info_span!("load rules").entered();
+        // Lowest precedence first.
+        let mut configs = vec![];
+        if !args.no_config {
+            debug!("loading rules (not asked not to!)");
+            let community = community::rules_path()?;
+            if !args.no_community && community.exists() {
+                debug!("loading community rules from {}", community.display());
+                configs.push((
+                    community.display().to_string(),
+                    BoxxyConfig::load_rules_from_path(&community, args.version_check)?,
+                ));
             }
-            rules.push(BoxxyConfig::load_rules_from_cli_flag(&args.arg_rules)?);
-            BoxxyConfig::merge(rules)
-        };
+            for config in BoxxyConfig::rule_paths_by_precedence()? {
+                info!("loading rules from {}", config.display());
+                configs.push((
+                    config.display().to_string(),
+                    BoxxyConfig::load_rules_from_path(&config, args.version_check)?,
+                ));
+            }
+        }
+        if args.isolate_project_caches {
+            configs.push((
+                "--isolate-project-caches".to_string(),
+                BoxxyConfig::load_project_cache_rules()?,
+            ));
+        }
+        if let Some(preset) = args.preset {
+            configs.push((
+                format!("--preset {}", preset.name()),
+                preset.rules(&std::env::current_dir()?)?,
+            ));
+        }
+        // Rules given explicitly on the command line win over the ones that
+        // flags generate.
+        configs.push((
+            "--rule".to_string(),
+            BoxxyConfig::load_rules_from_cli_flag(&args.arg_rules)?,
+        ));
+        configs.push((
+            "--rule-yaml".to_string(),
+            BoxxyConfig::load_rules_from_cli_yaml(&args.arg_rules_yaml)?,
+        ));
+        for overridden in precedence::drop_overridden(&mut configs) {
+            warn!("{overridden}");
+        }
+        let rules = configs.into_iter().map(|(_, rules)| rules).collect();
+        let mut rules = BoxxyConfig::merge(rules);
+        select_profile(&mut rules, args.profile.as_deref())?;
+        select_workspaces(
+            &mut rules,
+            args.workspace.as_deref(),
+            &std::env::current_dir()?,
+            &FsDriver::new(),
+        )?;
+        fill_default_rewrites(&mut rules)?;
+        select_enabled(&mut rules);
+        select_tags(&mut rules, &args.only_tags, &args.skip_tags);
         info!("loaded {} total rule(s)", rules.rules.len());
+        if args.warn_unreachable {
+            for rule in &rules.rules {
+                for reason in unreachable_reasons(rule, |key| std::env::var(key).ok()) {
+                    warn!("{}: rule can never apply, {reason}", rule.name);
+                }
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Put containers under the `containers_root` in `rules`, if it sets one.
+    pub fn configure_containers_root(rules: &BoxxyRules) {
+        if let Some(containers_root) = &rules.containers_root {
+            crate::enclosure::fs::configure_containers_root(PathBuf::from(
+                shellexpand::tilde(containers_root).to_string(),
+            ));
+        }
+    }
+
+    pub fn load_config(args: crate::Args) -> Result<Self> {
+        let rules = BoxxyConfig::load_rules(&args)?;
+        let tag_mounts = args.tag_mounts || rules.tag_mounts;
+        let strict_secrets = args.strict_secrets || rules.strict_secrets;
+        let ro_home = args.ro_home || rules.ro_home;
+        Self::configure_containers_root(&rules);
 
         let (cmd, cmd_args) = (&args.command_with_args[0], &args.command_with_args[1..]);
 
@@ -185,9 +632,1003 @@ impl BoxxyConfig {
             rules,
             immutable_root: args.immutable_root,
             trace: args.trace,
+            trace_enforce: if args.trace_enforce.is_empty() {
+                None
+            } else {
+                Some(
+                    Enforcement::new(&args.trace_enforce, args.trace_enforce_action)
+                        .map_err(|err| eyre!(err))?,
+                )
+            },
+            watch: args.watch,
+            expand_report: args.expand_report,
+            control_socket: args.control_socket,
             dotenv: args.dotenv,
             daemon: args.daemon,
             command,
+            and_then: args.and_then,
+            verify: matches!(args.command, Some(crate::BoxxySubcommand::Verify { .. })),
+            serve: match args.command {
+                Some(crate::BoxxySubcommand::Serve { name, .. }) => Some(name),
+                _ => None,
+            },
+            appimage: args.appimage,
+            private_tmpdir: args.private_tmpdir,
+            self_test: args.self_test,
+            verbose_mounts: args.verbose_mounts,
+            show_mount_order: args.show_mount_order,
+            dry_run: args.dry_run,
+            tag_mounts,
+            root_tmpfs: args.root_tmpfs,
+            ro_home,
+            shell_init: args.shell_init,
+            ssh_agent: args.ssh_agent,
+            strict_secrets,
+            name_from_command: args.name_from_command,
+            follow_wrappers: args.follow_wrappers,
+            expose_boxxy_config: args.expose_boxxy_config,
+            assume_yes: args.assume_yes,
+            log_tags: args.log_tags,
+            stack_size: args.stack_size,
         })
     }
+
+    /// The level to log how `rule` is set up at: info for rules with one of
+    /// the `--log-tags`, debug for the rest.
+    pub fn log_level_for(&self, rule: &Rule) -> Level {
+        if rule.has_any_tag(&self.log_tags) {
+            Level::Info
+        } else {
+            Level::Debug
+        }
+    }
+
+    /// A container name that's the same every time this command is run from
+    /// this directory: the program's file name and a hash of the command
+    /// line and working directory.
+    pub fn name_from_command(&self) -> String {
+        let program = Path::new(self.command.get_program());
+        let mut bytes = vec![];
+        for part in std::iter::once(self.command.get_program())
+            .chain(self.command.get_args())
+            .chain(self.and_then.iter().map(OsStr::new))
+        {
+            bytes.extend_from_slice(part.as_bytes());
+            bytes.push(0);
+        }
+        if let Ok(cwd) = std::env::current_dir() {
+            bytes.extend_from_slice(cwd.as_os_str().as_bytes());
+        }
+
+        format!(
+            "{}-{:016x}",
+            program
+                .file_name()
+                .unwrap_or(program.as_os_str())
+                .to_string_lossy(),
+            crate::enclosure::template::fnv1a(&bytes)
+        )
+    }
+
+    /// The programs that rules are matched against, with their arguments:
+    /// the command itself, and the first program of every shell command run
+    /// in the container. With `follow_wrappers`, the programs that any of
+    /// those wrap too.
+    pub fn programs_for_matching(&self) -> Vec<(OsString, Vec<OsString>)> {
+        let mut programs = vec![];
+        let mut add = |program: &OsStr, args: &[&OsStr]| {
+            let owned = |args: &[&OsStr]| args.iter().map(|arg| arg.to_os_string()).collect();
+            programs.push((program.to_os_string(), owned(args)));
+            if self.follow_wrappers {
+                if let Some(wrapped) = wrapper::wrapped_program(program, args) {
+                    debug!("{program:?} wraps {wrapped:?}");
+                    // A wrapped program's arguments are the ones after it,
+                    // while an interpreter's are the script's.
+                    let args = match args.iter().position(|arg| *arg == wrapped) {
+                        Some(at) => &args[at + 1..],
+                        None => args,
+                    };
+                    programs.push((wrapped, owned(args)));
+                }
+            }
+        };
+
+        let args: Vec<&OsStr> = self.command.get_args().collect();
+        add(self.command.get_program(), &args);
+
+        let mut scripts = vec![];
+        if let [flag, script] = args.as_slice() {
+            if *flag == "-c" && Path::new(self.command.get_program()).ends_with("sh") {
+                scripts.push(script.to_string_lossy().to_string());
+            }
+        }
+        scripts.extend(self.and_then.iter().cloned());
+        for script in &scripts {
+            let words: Vec<&OsStr> = shell_words(script).into_iter().map(OsStr::new).collect();
+            if let [program, args @ ..] = words.as_slice() {
+                add(program, args);
+            }
+        }
+
+        programs
+    }
+}
+
+/// Parse a config file into its rules and the files it `include`s. It may
+/// hold several YAML documents, each with its own `rules` and `include`, and
+/// may use anchors, aliases, and `<<` merge keys to share parts of rules.
+/// Empty documents are skipped. `${VAR}` is replaced with the value of the
+/// environment variable everywhere but in rule names.
+fn parse_rules(contents: &str, version_check: VersionCheck) -> Result<(BoxxyRules, Vec<String>)> {
+    parse_rules_in(contents, version_check, None)
+}
+
+/// Like [`parse_rules`], for a config file in `dir`, which relative `target`s
+/// and `rewrite`s are resolved against.
+fn parse_rules_in(
+    contents: &str,
+    version_check: VersionCheck,
+    dir: Option<&Path>,
+) -> Result<(BoxxyRules, Vec<String>)> {
+    let mut rules = vec![];
+    let mut includes = vec![];
+    for document in serde_yaml::Deserializer::from_str(contents) {
+        let value = serde_yaml::Value::deserialize(document)?;
+        if value.is_null() {
+            continue;
+        }
+        rules.push(parse_document(value, version_check, dir, &mut includes)?);
+    }
+
+    Ok((BoxxyConfig::merge(rules), includes))
+}
+
+/// Parse a JSON config file, which holds a single document with the same
+/// keys as a YAML one, into its rules and the files it `include`s.
+fn parse_json_rules(
+    contents: &str,
+    version_check: VersionCheck,
+    dir: Option<&Path>,
+) -> Result<(BoxxyRules, Vec<String>)> {
+    let value: serde_yaml::Value = serde_json::from_str(contents)?;
+    let mut includes = vec![];
+    let rules = parse_document(value, version_check, dir, &mut includes)?;
+
+    Ok((rules, includes))
+}
+
+fn parse_document(
+    mut value: serde_yaml::Value,
+    version_check: VersionCheck,
+    dir: Option<&Path>,
+    includes: &mut Vec<String>,
+) -> Result<BoxxyRules> {
+    if let Some(required) = value.get("min_boxxy_version") {
+        let required = match required {
+            serde_yaml::Value::String(required) => required.clone(),
+            serde_yaml::Value::Number(required) => required.to_string(),
+            _ => return Err(eyre!("min_boxxy_version must be a version, ex. `0.9.0`")),
+        };
+        check_min_version(&required, crate::cli::VERSION, version_check)?;
+    }
+    for change in schema::upgrade(&mut value, version_check)? {
+        warn!("{change}; run `boxxy config migrate` to update the config file");
+    }
+    value.apply_merge()?;
+    interpolate::interpolate_document(&mut value, &|name| std::env::var(name).ok())?;
+    extends::resolve_extends(&mut value)?;
+    if let Some(dir) = dir {
+        relative::resolve_relative_paths(&mut value, dir);
+    }
+    fallback::resolve_rewrites(&mut value, &|path| path.exists())?;
+    if let Some(include) = value.as_mapping_mut().and_then(|map| map.remove("include")) {
+        includes.extend(
+            string_or_list(include)
+                .ok_or_else(|| eyre!("include must be a path or a list of paths"))?,
+        );
+    }
+    if let Some(include) = value
+        .as_mapping_mut()
+        .and_then(|map| map.remove("include_url"))
+    {
+        let urls = string_or_list(include)
+            .ok_or_else(|| eyre!("include_url must be a URL or a list of URLs"))?;
+        if let Some(url) = urls.iter().find(|url| !remote::is_url(url)) {
+            return Err(eyre!("include_url must be an https:// URL, not {url}"));
+        }
+        includes.extend(urls);
+    }
+    let document = serde_yaml::from_value::<BoxxyRules>(value)?;
+    for rule in document
+        .rules
+        .iter()
+        .chain(document.profiles.values().flatten())
+        .chain(
+            document
+                .workspaces
+                .values()
+                .flat_map(|workspace| &workspace.rules),
+        )
+    {
+        rule.validate()?;
+    }
+
+    Ok(document)
+}
+
+fn string_or_list(value: serde_yaml::Value) -> Option<Vec<String>> {
+    match value {
+        serde_yaml::Value::String(string) => Some(vec![string]),
+        serde_yaml::Value::Sequence(values) => values
+            .into_iter()
+            .map(|value| match value {
+                serde_yaml::Value::String(string) => Some(string),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// The files an `include` refers to, relative to the including file's
+/// directory, in sorted order. A pattern with glob characters can match
+/// nothing, but a plain path must exist. An `include_url` resolves to its
+/// cached copy.
+fn resolve_include(pattern: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    if remote::is_url(pattern) {
+        return Ok(vec![remote::fetch(pattern)?]);
+    }
+    let expanded = shellexpand::tilde(pattern).to_string();
+    let full = dir.join(&expanded);
+    if !expanded.contains(['*', '?', '[']) {
+        if !full.exists() {
+            return Err(eyre!("included file {} doesn't exist", full.display()));
+        }
+        return Ok(vec![full]);
+    }
+
+    let mut paths = vec![];
+    for path in glob::glob(&full.to_string_lossy())
+        .map_err(|err| eyre!("invalid include pattern {pattern}: {err}"))?
+    {
+        let path = path?;
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Add the rules of `profile`, if any, to the rules that always apply, and
+/// drop the other profiles.
+fn select_profile(rules: &mut BoxxyRules, profile: Option<&str>) -> Result<()> {
+    if let Some(profile) = profile {
+        let Some(profile_rules) = rules.profiles.remove(profile) else {
+            return Err(eyre!(
+                "no profile named {profile}, the config defines: {}",
+                rules
+                    .profiles
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        };
+        info!("using profile {profile}");
+        rules.rules.extend(profile_rules);
+    }
+    rules.profiles.clear();
+
+    Ok(())
+}
+
+/// Add the rules of `workspace`, if one is picked, or else of every
+/// workspace with a `context` directory that `pwd` is in, to the rules that
+/// always apply, and drop the other workspaces.
+fn select_workspaces(
+    rules: &mut BoxxyRules,
+    workspace: Option<&str>,
+    pwd: &Path,
+    fs: &FsDriver,
+) -> Result<()> {
+    let workspaces = std::mem::take(&mut rules.workspaces);
+    if let Some(workspace) = workspace {
+        let Some(selected) = workspaces.get(workspace) else {
+            return Err(eyre!(
+                "no workspace named {workspace}, the config defines: {}",
+                workspaces.keys().cloned().collect::<Vec<_>>().join(", ")
+            ));
+        };
+        info!("using workspace {workspace}");
+        rules.rules.extend(selected.rules.iter().cloned());
+        return Ok(());
+    }
+
+    for (name, workspace) in workspaces {
+        for dir in &workspace.context {
+            if context_contains(dir, pwd, fs)? {
+                info!("using workspace {name}, {} is in {dir}", pwd.display());
+                rules.rules.extend(workspace.rules);
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Give the rules that need a rewrite but don't set one a directory in the
+/// store: `store_root`, or `~/.local/share/boxxy/store` by default. Rule
+/// names that only differ in the characters that can't be in a path get the
+/// same directory, so that's an error rather than them sharing data.
+fn fill_default_rewrites(rules: &mut BoxxyRules) -> Result<()> {
+    let store_root = match &rules.store_root {
+        Some(store_root) => store_root.clone(),
+        None => {
+            let data_dir =
+                dirs::data_dir().ok_or_else(|| eyre!("could not find data directory"))?;
+            crate::enclosure::fs::append_all(&data_dir, vec!["boxxy", "store"])
+                .to_string_lossy()
+                .to_string()
+        }
+    };
+    let mut named = std::collections::HashMap::new();
+    for rule in &mut rules.rules {
+        if rule.has_rewrite() && rule.rewrite.is_empty() {
+            rule.rewrite = rule.default_rewrite(&store_root);
+            debug!("{}: no rewrite, using {}", rule.name, rule.rewrite);
+            if !rule.is_enabled() {
+                continue;
+            }
+            if let Some(other) = named.insert(rule.rewrite.clone(), rule.name.clone()) {
+                if other != rule.name {
+                    return Err(eyre!(
+                        "rules '{other}' and '{}' would both store their data in {}; give one of them a `rewrite` or a different name",
+                        rule.name,
+                        rule.rewrite
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drop the rules that are turned off with `enabled: false`.
+fn select_enabled(rules: &mut BoxxyRules) {
+    rules.rules.retain(|rule| {
+        if !rule.is_enabled() {
+            debug!("{}: skipped, it's disabled", rule.name);
+        }
+        rule.is_enabled()
+    });
+}
+
+/// Keep only the rules with one of `only_tags`, if any are given, and drop
+/// the rules with any of `skip_tags`.
+fn select_tags(rules: &mut BoxxyRules, only_tags: &[String], skip_tags: &[String]) {
+    rules.rules.retain(|rule| {
+        let keep =
+            (only_tags.is_empty() || rule.has_any_tag(only_tags)) && !rule.has_any_tag(skip_tags);
+        if !keep {
+            debug!("{}: skipped by its tags", rule.name);
+        }
+        keep
+    });
+}
+
+/// What to do when a config needs a newer boxxy than this one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionCheck {
+    Error,
+    Warn,
+    Ignore,
+}
+
+impl FromStr for VersionCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(VersionCheck::Error),
+            "warn" => Ok(VersionCheck::Warn),
+            "ignore" => Ok(VersionCheck::Ignore),
+            _ => Err(format!("invalid version check: {s}")),
+        }
+    }
+}
+
+/// Compare `required` with the running boxxy's `version`. Configs that use
+/// newer rule fields would otherwise be misread, since unknown keys are
+/// silently dropped.
+fn check_min_version(required: &str, version: &str, version_check: VersionCheck) -> Result<()> {
+    if parse_version(required)? <= parse_version(version)? {
+        return Ok(());
+    }
+
+    let message =
+        format!("this config needs boxxy {required} or newer, but this is boxxy {version}");
+    match version_check {
+        VersionCheck::Error => Err(eyre!(
+            "{message}; upgrade boxxy, or pass `--version-check warn` to use it anyway"
+        )),
+        VersionCheck::Warn => {
+            warn!("{message}, some rules may not work as intended");
+            Ok(())
+        }
+        VersionCheck::Ignore => Ok(()),
+    }
+}
+
+/// Parse `major[.minor[.patch]]`, ignoring any `-prerelease` or `+build`
+/// suffix.
+fn parse_version(version: &str) -> Result<(u64, u64, u64)> {
+    let invalid = || eyre!("invalid version `{version}`, expected ex. `0.9.0`");
+    let core = version
+        .trim()
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default();
+    let mut parts = [0; 3];
+    for (i, part) in core.split('.').enumerate() {
+        *parts.get_mut(i).ok_or_else(invalid)? = part.parse().map_err(|_| invalid())?;
+    }
+
+    Ok((parts[0], parts[1], parts[2]))
+}
+
+/// Split a `-r` rule on `:`. `\:` is a literal `:` and `\\` a literal `\`,
+/// so that paths containing colons can be used.
+fn split_cli_rule(rule: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = rule.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ (':' | '\\')) => parts.last_mut().unwrap().push(escaped),
+                Some(other) => {
+                    let part = parts.last_mut().unwrap();
+                    part.push('\\');
+                    part.push(other);
+                }
+                None => parts.last_mut().unwrap().push('\\'),
+            },
+            ':' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+
+    parts
+}
+
+/// The shell to run when no command is given: `$SHELL`, or `sh`.
+pub fn default_shell() -> String {
+    std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "sh".to_string())
+}
+
+/// The `sh -c` command line for `--shell`. The words of `command` are joined
+/// with spaces, like `"$*"`, so quoting is up to the shell. A lone `-` reads
+/// the whole script from `stdin` instead.
+pub fn shell_command(command: &[String], mut stdin: impl Read) -> Result<Vec<String>> {
+    let script = if command == ["-"] {
+        let mut script = String::new();
+        stdin
+            .read_to_string(&mut script)
+            .map_err(|err| eyre!("could not read a shell script from stdin: {err}"))?;
+        script
+    } else {
+        command.join(" ")
+    };
+    if script.trim().is_empty() {
+        return Err(eyre!("--shell: no commands to run"));
+    }
+
+    Ok(vec!["sh".into(), "-c".into(), script])
+}
+
+/// The program a simple shell command line runs and its arguments, split on
+/// whitespace, ignoring leading `KEY=value` assignments.
+pub fn shell_words(line: &str) -> Vec<&str> {
+    line.split_whitespace()
+        .skip_while(|word| word.contains('='))
+        .collect()
+}
+
+/// Check that `dir` is a directory, not a symlink, that's owned by this user
+/// and that nobody else could have written to, and make it `0700` if it
+/// isn't already, as older versions of boxxy didn't.
+fn check_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != nix::unistd::getuid().as_raw()
+        || metadata.mode() & 0o022 != 0
+    {
+        return Err(eyre!(
+            "refusing to use {}: it must be a directory that's owned by you, and that only you can write to",
+            dir.display()
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::enclosure::rule::RuleMode;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_check_private_dir() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Scratch::new("private")?;
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        check_private_dir(&dir)?;
+        // Made by an older boxxy.
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))?;
+        check_private_dir(&dir)?;
+        assert_eq!(std::fs::metadata(&dir)?.mode() & 0o777, 0o700);
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777))?;
+        assert!(check_private_dir(&dir).is_err());
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        let link = dir.with_extension("link");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&dir, &link)?;
+        assert!(check_private_dir(&link).is_err());
+
+        std::fs::remove_file(link)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_cli_rule() {
+        assert_eq!(split_cli_rule("~/a:~/b"), vec!["~/a", "~/b"]);
+        assert_eq!(
+            split_cli_rule("/with space/ünï:/c\\:d:file"),
+            vec!["/with space/ünï", "/c:d", "file"]
+        );
+        assert_eq!(split_cli_rule("/a\\\\:/b"), vec!["/a\\", "/b"]);
+        assert_eq!(split_cli_rule("/a\\b:/c\\"), vec!["/a\\b", "/c\\"]);
+    }
+
+    #[test]
+    fn test_load_rules_from_cli_flag() -> Result<()> {
+        let rules =
+            BoxxyConfig::load_rules_from_cli_flag(&["/tmp/a\\:b:/tmp/c d:directory".into()])?;
+        assert_eq!(rules.rules[0].target, "/tmp/a:b");
+        assert_eq!(rules.rules[0].rewrite, "/tmp/c d");
+        assert_eq!(rules.rules[0].mode, RuleMode::Directory);
+
+        assert!(BoxxyConfig::load_rules_from_cli_flag(&["/a:/b:nope".into()]).is_err());
+        assert!(BoxxyConfig::load_rules_from_cli_flag(&["/a".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rules_with_anchors_and_documents() -> Result<()> {
+        let (rules, _) = parse_rules(
+            r#"
+rules:
+- &aws
+  name: aws
+  target: ~/.aws
+  rewrite: ~/.config/aws
+  only: [aws]
+- <<: *aws
+  name: aws in a project
+  context: [~/Projects/work]
+  rewrite: ~/Projects/work/.aws
+---
+---
+rules:
+- name: kube
+  target: ~/.kube
+  rewrite: ~/.config/kube
+"#,
+            VersionCheck::Error,
+        )?;
+        assert_eq!(rules.rules.len(), 3);
+        assert_eq!(rules.rules[1].target, "~/.aws");
+        assert_eq!(rules.rules[1].rewrite, "~/Projects/work/.aws");
+        assert_eq!(rules.rules[1].only, vec!["aws"]);
+        assert_eq!(rules.rules[2].name, "kube");
+
+        assert!(parse_rules("", VersionCheck::Error)?.0.rules.is_empty());
+        assert!(parse_rules("rules: [{name: broken}]", VersionCheck::Error).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_name_from_command() -> Result<()> {
+        use clap::Parser;
+
+        let name = |command: &[&str]| -> Result<String> {
+            let args = crate::Args::try_parse_from(
+                ["boxxy", "--no-config", "--name-from-command"]
+                    .iter()
+                    .chain(command),
+            )?;
+            Ok(BoxxyConfig::load_config(args)?.name_from_command())
+        };
+
+        let ls = name(&["ls", "-la"])?;
+        assert!(ls.starts_with("ls-"));
+        assert_eq!(ls, name(&["ls", "-la"])?);
+        assert_ne!(ls, name(&["ls", "-l", "a"])?);
+        assert_ne!(ls, name(&["ls"])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell_command() -> Result<()> {
+        let words = |words: &[&str]| {
+            words
+                .iter()
+                .map(|word| word.to_string())
+                .collect::<Vec<_>>()
+        };
+        let run = |command: Vec<String>| -> Result<String> {
+            let output = Command::new(&command[0]).args(&command[1..]).output()?;
+            Ok(String::from_utf8(output.stdout)?)
+        };
+
+        assert_eq!(
+            shell_command(&words(&["npm ci", "&&", "npm test"]), std::io::empty())?,
+            words(&["sh", "-c", "npm ci && npm test"])
+        );
+
+        // The script is passed to the shell as is, so its quoting is kept.
+        let script = "printf '%s|' \"a  b\" 'c$HOME'\nprintf 'd'\n";
+        let command = shell_command(&words(&["-"]), script.as_bytes())?;
+        assert_eq!(command, words(&["sh", "-c", script]));
+        assert_eq!(run(command)?, "a  b|c$HOME|d");
+        let command = shell_command(&words(&["echo", "'a  b'", "c  d"]), std::io::empty())?;
+        assert_eq!(run(command)?, "a  b c d\n");
+
+        assert!(shell_command(&words(&["-"]), " \n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_boxxy_version() -> Result<()> {
+        assert_eq!(parse_version("0.8.4")?, (0, 8, 4));
+        assert_eq!(parse_version("1.2")?, (1, 2, 0));
+        assert_eq!(parse_version("v1.0.0-rc.1")?, (1, 0, 0));
+        assert!(parse_version("1.x").is_err());
+        assert!(parse_version("1.2.3.4").is_err());
+
+        assert!(check_min_version("0.8", "0.8.4", VersionCheck::Error).is_ok());
+        assert!(check_min_version("0.9.0", "0.8.4", VersionCheck::Error).is_err());
+        assert!(check_min_version("0.9.0", "0.8.4", VersionCheck::Warn).is_ok());
+
+        let config = "min_boxxy_version: 999.0.0\nrules: []\n";
+        assert!(parse_rules(config, VersionCheck::Error).is_err());
+        assert!(parse_rules(config, VersionCheck::Ignore).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rules_from_path_interpolates_env_vars() -> Result<()> {
+        let home = std::env::var("HOME")?;
+        let dir = Scratch::new("env")?;
+        let path = dir.join("boxxy.yaml");
+        std::fs::write(
+            &path,
+            r#"
+rules:
+- name: ${HOME}
+  target: ~/.aws
+  rewrite: ${XDG_DATA_HOME_FOR_BOXXY_TESTS:-${HOME}/.local/share}/aws
+  context: ["${HOME}/work"]
+  env:
+    AWS_CONFIG_FILE: ${HOME}/.config/aws/config
+"#,
+        )?;
+        let rules = BoxxyConfig::load_rules_from_path(&path, VersionCheck::Error)?;
+
+        let rule = &rules.rules[0];
+        assert_eq!(rule.name, "${HOME}");
+        assert_eq!(rule.rewrite, format!("{home}/.local/share/aws"));
+        assert_eq!(rule.context, vec![format!("{home}/work")]);
+        assert_eq!(
+            rule.env["AWS_CONFIG_FILE"],
+            format!("{home}/.config/aws/config")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rules_from_path_with_includes() -> Result<()> {
+        let dir = Scratch::new("include")?;
+        std::fs::create_dir_all(dir.join("rules.d"))?;
+        let rule = |name: &str| format!("rules: [{{name: {name}, target: /a, rewrite: /b}}]\n");
+        std::fs::write(
+            dir.join("boxxy.yaml"),
+            format!("include: [rules.d/*.yaml, extra.yaml]\n{}", rule("main")),
+        )?;
+        std::fs::write(dir.join("rules.d/2-npm.yaml"), rule("npm"))?;
+        std::fs::write(
+            dir.join("rules.d/1-aws.yaml"),
+            format!(
+                "include: {}\n{}",
+                dir.join("boxxy.yaml").display(),
+                rule("aws")
+            ),
+        )?;
+        std::fs::write(dir.join("rules.d/notes.txt"), "not a config")?;
+        std::fs::write(dir.join("extra.yaml"), rule("extra"))?;
+
+        let cycle = BoxxyConfig::load_rules_from_path(&dir.join("boxxy.yaml"), VersionCheck::Error);
+        std::fs::write(dir.join("rules.d/1-aws.yaml"), rule("aws"))?;
+        let rules = BoxxyConfig::load_rules_from_path(&dir.join("boxxy.yaml"), VersionCheck::Error);
+        let included = BoxxyConfig::included_paths(&dir.join("boxxy.yaml"));
+        std::fs::write(dir.join("boxxy.yaml"), "include: missing.yaml\n")?;
+        let missing =
+            BoxxyConfig::load_rules_from_path(&dir.join("boxxy.yaml"), VersionCheck::Error);
+
+        assert!(cycle.is_err());
+        let names: Vec<String> = rules?.rules.into_iter().map(|rule| rule.name).collect();
+        assert_eq!(names, ["main", "aws", "npm", "extra"]);
+        assert_eq!(
+            included?,
+            [
+                dir.join("rules.d/1-aws.yaml"),
+                dir.join("rules.d/2-npm.yaml"),
+                dir.join("extra.yaml"),
+            ]
+        );
+        assert!(missing.is_err());
+
+        assert!(parse_rules(
+            "include_url: http://example.com/boxxy.yaml",
+            VersionCheck::Error
+        )
+        .is_err());
+        assert!(parse_rules("include_url: [{}]", VersionCheck::Error).is_err());
+        let (_, includes) = parse_rules(
+            "include: a.yaml\ninclude_url: [https://example.com/boxxy.yaml]",
+            VersionCheck::Error,
+        )?;
+        assert_eq!(includes, ["a.yaml", "https://example.com/boxxy.yaml"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rules_from_json() -> Result<()> {
+        let dir = Scratch::new("json")?;
+        std::fs::write(
+            dir.join("boxxy.json"),
+            r#"{"include": "extra.yaml", "rules": [{"name": "json", "target": "/a", "rewrite": "/b", "only": ["aws"]}]}"#,
+        )?;
+        std::fs::write(
+            dir.join("extra.yaml"),
+            "rules: [{name: yaml, target: /c, rewrite: /d}]\n",
+        )?;
+        std::fs::write(dir.join("invalid.json"), "rules: []\n")?;
+
+        let rules = BoxxyConfig::load_rules_from_path(&dir.join("boxxy.json"), VersionCheck::Error);
+        let invalid =
+            BoxxyConfig::load_rules_from_path(&dir.join("invalid.json"), VersionCheck::Error);
+
+        let rules = rules?.rules;
+        assert_eq!(rules[0].name, "json");
+        assert_eq!(rules[0].only, ["aws"]);
+        assert_eq!(rules[1].name, "yaml");
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles() -> Result<()> {
+        let config = r#"
+rules:
+- {name: always, target: /a, rewrite: /b}
+profiles:
+  paranoid:
+  - {name: "${HOME}", target: ~/.ssh, deny: true}
+---
+profiles:
+  paranoid:
+  - {name: tmp, target: /tmp, mode: tmpfs}
+  dev: []
+"#;
+        let names = |rules: &BoxxyRules| -> Vec<String> {
+            rules.rules.iter().map(|rule| rule.name.clone()).collect()
+        };
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        assert_eq!(rules.profiles.len(), 2);
+        select_profile(&mut rules, Some("paranoid"))?;
+        assert_eq!(names(&rules), ["always", "${HOME}", "tmp"]);
+        assert!(rules.profiles.is_empty());
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_profile(&mut rules, None)?;
+        assert_eq!(names(&rules), ["always"]);
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        assert!(select_profile(&mut rules, Some("missing")).is_err());
+        assert!(parse_rules("profiles: {bad: [{name: bad}]}", VersionCheck::Error).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_workspaces() -> Result<()> {
+        let config = r#"
+rules:
+- {name: always, target: /a, rewrite: /b}
+workspaces:
+  work:
+    context: [/tmp]
+    rules:
+    - {name: "${HOME}", target: /c, rewrite: /d}
+  personal:
+    rules:
+    - {name: personal, target: /e, rewrite: /f}
+"#;
+        let names = |rules: &BoxxyRules| -> Vec<String> {
+            rules.rules.iter().map(|rule| rule.name.clone()).collect()
+        };
+        let fs = FsDriver::new();
+        let tmp = Path::new("/tmp").canonicalize()?;
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_workspaces(&mut rules, None, &tmp, &fs)?;
+        assert_eq!(names(&rules), ["always", "${HOME}"]);
+        assert!(rules.workspaces.is_empty());
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_workspaces(&mut rules, None, Path::new("/"), &fs)?;
+        assert_eq!(names(&rules), ["always"]);
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_workspaces(&mut rules, Some("personal"), &tmp, &fs)?;
+        assert_eq!(names(&rules), ["always", "personal"]);
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        assert!(select_workspaces(&mut rules, Some("missing"), &tmp, &fs).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_default_rewrites() -> Result<()> {
+        let config = r#"
+store_root: /ignored
+rules:
+- {name: aws cli, target: ~/.aws}
+- {name: explicit, target: /c, rewrite: /d}
+- {name: tmp, target: /e, mode: tmpfs}
+---
+store_root: ~/store/
+"#;
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        fill_default_rewrites(&mut rules)?;
+        let rewrites: Vec<&str> = rules
+            .rules
+            .iter()
+            .map(|rule| rule.rewrite.as_str())
+            .collect();
+        assert_eq!(rewrites, ["~/store/aws-cli", "/d", ""]);
+
+        let config = r#"
+store_root: ~/store
+rules:
+- {name: aws cli, target: ~/.aws}
+- {name: aws/cli, target: ~/.config/aws}
+"#;
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        assert!(fill_default_rewrites(&mut rules).is_err());
+
+        let config = r#"
+store_root: ~/store
+rules:
+- {name: aws cli, target: ~/.aws}
+- {name: aws/cli, target: ~/.config/aws, rewrite: ~/store/aws-config}
+- {name: aws.cli, target: ~/.config/aws-old, enabled: false}
+"#;
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        fill_default_rewrites(&mut rules)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_umask() -> Result<()> {
+        let umask = |config: &str| -> Result<BoxxyRules> {
+            Ok(parse_rules(config, VersionCheck::Error)?.0)
+        };
+        let merged = BoxxyConfig::merge(vec![
+            umask("umask: \"077\"")?,
+            umask("umask: \"000\"")?,
+            umask("rules: []")?,
+            umask("umask: \"022\"")?,
+        ]);
+        assert_eq!(merged.umask, Some(Umask(0o077)));
+        let merged = BoxxyConfig::merge(vec![umask("umask: \"022\"")?, umask("umask: \"007\"")?]);
+        assert_eq!(merged.umask, Some(Umask(0o027)));
+        assert_eq!(BoxxyConfig::merge(vec![umask("rules: []")?]).umask, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_enabled() -> Result<()> {
+        let config = r#"
+rules:
+- {name: on, target: /a, rewrite: /b}
+- {name: explicitly on, target: /c, rewrite: /d, enabled: true}
+- {name: off, target: /e, rewrite: /f, enabled: false}
+"#;
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_enabled(&mut rules);
+        let names: Vec<&str> = rules.rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["on", "explicitly on"]);
+
+        assert!(parse_rules(
+            "rules: [{name: off, target: /a, size: 1g, enabled: false}]",
+            VersionCheck::Error
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_tags() -> Result<()> {
+        let config = r#"
+rules:
+- {name: aws, target: /a, rewrite: /b, tags: [aws, secrets]}
+- {name: ssh, target: /c, rewrite: /d, tags: [secrets]}
+- {name: npm, target: /e, rewrite: /f}
+"#;
+        let names = |rules: &BoxxyRules| -> Vec<String> {
+            rules.rules.iter().map(|rule| rule.name.clone()).collect()
+        };
+        let tags =
+            |tags: &[&str]| -> Vec<String> { tags.iter().map(|tag| tag.to_string()).collect() };
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_tags(&mut rules, &tags(&["secrets"]), &[]);
+        assert_eq!(names(&rules), ["aws", "ssh"]);
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_tags(&mut rules, &[], &tags(&["aws"]));
+        assert_eq!(names(&rules), ["ssh", "npm"]);
+
+        let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+        select_tags(&mut rules, &tags(&["secrets"]), &tags(&["aws"]));
+        assert_eq!(names(&rules), ["ssh"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rules_from_cli_yaml() -> Result<()> {
+        let rules = BoxxyConfig::load_rules_from_cli_yaml(&[
+            "{target: \"~/a:b\", rewrite: \"~/日本\", mode: file}".into(),
+            "[{name: named, target: /x, rewrite: /y}, {target: /z, rewrite: /w}]".into(),
+        ])?;
+        assert_eq!(rules.rules.len(), 3);
+        assert_eq!(rules.rules[0].target, "~/a:b");
+        assert_eq!(rules.rules[0].rewrite, "~/日本");
+        assert_eq!(rules.rules[0].mode, RuleMode::File);
+        assert_eq!(rules.rules[1].name, "named");
+        assert_eq!(rules.rules[2].name, "cli-loaded rule: /z -> /w");
+
+        let rules = BoxxyConfig::load_rules_from_cli_yaml(&["{target: /a}".into()])?;
+        assert_eq!(rules.rules[0].name, "cli-loaded rule: /a");
+        assert!(BoxxyConfig::load_rules_from_cli_yaml(&["{rewrite: /a}".into()]).is_err());
+        let rules = BoxxyConfig::load_rules_from_cli_yaml(&["{target: /a, deny: true}".into()])?;
+        assert_eq!(rules.rules[0].name, "cli-loaded rule: deny /a");
+        let rules = BoxxyConfig::load_rules_from_cli_yaml(&["{target: /a, mode: tmpfs}".into()])?;
+        assert_eq!(rules.rules[0].name, "cli-loaded rule: tmpfs at /a");
+        Ok(())
+    }
 }
@@ -0,0 +1,130 @@
+use super::check::share_programs;
+use crate::enclosure::fs::expand_tilde;
+use crate::enclosure::rule::{BoxxyRules, Rule};
+
+/// Drop the rules that a config later in `configs` overrides: rules that
+/// shadow the same target as one of its rules with a different rewrite, for
+/// the same programs. `configs` are named by where they came from, from the
+/// lowest precedence to the highest. Rules are only compared with the rules
+/// in the same list, ex. the same profile. Returns what was overridden.
+pub fn drop_overridden(configs: &mut [(String, BoxxyRules)]) -> Vec<String> {
+    let mut overridden = vec![];
+    for higher in (1..configs.len()).rev() {
+        let (lower, rest) = configs.split_at_mut(higher);
+        let (source, rules) = &rest[0];
+        for (lower_source, lower_rules) in lower.iter_mut() {
+            let mut drop = |scope: &str, kept: &[Rule], candidates: &mut Vec<Rule>| {
+                candidates.retain(|candidate| {
+                    let Some(winner) = kept.iter().find(|rule| overrides(rule, candidate)) else {
+                        return true;
+                    };
+                    overridden.push(format!(
+                        "rule '{}' from {lower_source}{scope} is overridden by rule '{}' from {source}, which shadows {} with {} instead of {}",
+                        candidate.name,
+                        winner.name,
+                        candidate.target,
+                        describe_rewrite(winner),
+                        describe_rewrite(candidate),
+                    ));
+                    false
+                });
+            };
+            drop("", &rules.rules, &mut lower_rules.rules);
+            for (name, profile) in &rules.profiles {
+                if let Some(candidates) = lower_rules.profiles.get_mut(name) {
+                    drop(&format!(" (profile {name})"), profile, candidates);
+                }
+            }
+            for (name, workspace) in &rules.workspaces {
+                if let Some(candidates) = lower_rules.workspaces.get_mut(name) {
+                    drop(
+                        &format!(" (workspace {name})"),
+                        &workspace.rules,
+                        &mut candidates.rules,
+                    );
+                }
+            }
+        }
+    }
+
+    overridden
+}
+
+/// Whether `rule` shadows the same target as `other` differently, for the
+/// same programs.
+fn overrides(rule: &Rule, other: &Rule) -> bool {
+    let expand = |path: &str| expand_tilde(path.trim_end_matches('/'));
+    rule.is_enabled()
+        && other.is_enabled()
+        && expand(&rule.target) == expand(&other.target)
+        && (rule.mode != other.mode || expand(&rule.rewrite) != expand(&other.rewrite))
+        && share_programs(rule, other)
+}
+
+fn describe_rewrite(rule: &Rule) -> String {
+    if rule.rewrite.is_empty() {
+        let mode = serde_yaml::to_string(&rule.mode).unwrap_or_default();
+        format!("`mode: {}`", mode.trim())
+    } else {
+        rule.rewrite.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclosure::rule::RuleMode;
+
+    #[test]
+    fn test_drop_overridden() {
+        let config = |rules: Vec<Rule>| BoxxyRules {
+            rules,
+            ..Default::default()
+        };
+        let mut configs = vec![
+            (
+                "global".to_string(),
+                config(vec![
+                    Rule::builder("aws", "~/.aws", "~/.config/aws").build(),
+                    Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc").build(),
+                    Rule::builder("cache", "~/.cache", "")
+                        .with_mode(RuleMode::Tmpfs)
+                        .build(),
+                    Rule::builder("gpg", "~/.gnupg", "~/.local/share/gnupg")
+                        .with_only("gpg")
+                        .build(),
+                ]),
+            ),
+            (
+                "project".to_string(),
+                config(vec![
+                    Rule::builder("project aws", "~/.aws/", "./aws").build(),
+                    Rule::builder("same npm", "~/.npmrc", "~/.config/npm/npmrc").build(),
+                    Rule::builder("project cache", "~/.cache", "")
+                        .with_mode(RuleMode::Mask)
+                        .build(),
+                    Rule::builder("project gpg", "~/.gnupg", "./gnupg")
+                        .with_only("git")
+                        .build(),
+                ]),
+            ),
+        ];
+
+        let overridden = drop_overridden(&mut configs);
+        assert_eq!(
+            overridden,
+            vec![
+                "rule 'aws' from global is overridden by rule 'project aws' from project, which shadows ~/.aws with ./aws instead of ~/.config/aws",
+                "rule 'cache' from global is overridden by rule 'project cache' from project, which shadows ~/.cache with `mode: mask` instead of `mode: tmpfs`",
+            ]
+        );
+        let names: Vec<&str> = configs[0]
+            .1
+            .rules
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, ["npm", "gpg"]);
+        assert_eq!(configs[1].1.rules.len(), 4);
+    }
+}
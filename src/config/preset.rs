@@ -0,0 +1,159 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use color_eyre::Result;
+use log::*;
+
+use crate::enclosure::rule::{BoxxyRules, Rule, RuleMode};
+use crate::store::{projects_store_root, Project, ProjectKind};
+
+/// Credentials that a build tool never needs, denied by every preset.
+const CREDENTIALS: &[&str] = &[
+    "~/.ssh",
+    "~/.gnupg",
+    "~/.aws",
+    "~/.config/gh",
+    "~/.docker",
+    "~/.kube",
+];
+
+/// A ready-made sandbox for building a project with one build tool, picked
+/// with `--preset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    CargoStrict,
+    NpmStrict,
+    PipStrict,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cargo-strict" => Ok(Preset::CargoStrict),
+            "npm-strict" => Ok(Preset::NpmStrict),
+            "pip-strict" => Ok(Preset::PipStrict),
+            _ => Err(format!("invalid preset: {s}")),
+        }
+    }
+}
+
+impl Preset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::CargoStrict => "cargo-strict",
+            Preset::NpmStrict => "npm-strict",
+            Preset::PipStrict => "pip-strict",
+        }
+    }
+
+    fn kind(&self) -> ProjectKind {
+        match self {
+            Preset::CargoStrict => ProjectKind::Cargo,
+            Preset::NpmStrict => ProjectKind::Npm,
+            Preset::PipStrict => ProjectKind::Pip,
+        }
+    }
+
+    /// Env vars that turn off the tool's riskier defaults.
+    fn env(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Preset::CargoStrict => &[],
+            // Install scripts are the usual way in for malicious packages.
+            Preset::NpmStrict => &[("NPM_CONFIG_IGNORE_SCRIPTS", "true")],
+            // Keep installs in a virtualenv in the project.
+            Preset::PipStrict => &[("PIP_REQUIRE_VIRTUALENV", "true")],
+        }
+    }
+
+    /// The preset's rules for the project in `dir`: the home directory is
+    /// read-only, except for the project and the tool's caches, which are
+    /// isolated per project, and credentials are denied.
+    pub fn rules(&self, dir: &Path) -> Result<BoxxyRules> {
+        self.rules_in(dir, &projects_store_root()?)
+    }
+
+    /// [`Preset::rules`], isolating the caches in the store at `store_root`.
+    fn rules_in(&self, dir: &Path, store_root: &Path) -> Result<BoxxyRules> {
+        let kind = self.kind();
+        let project = match Project::detect(dir) {
+            Some(project) if project.kinds.contains(&kind) => Project {
+                root: project.root,
+                kinds: vec![kind],
+            },
+            _ => {
+                warn!(
+                    "--preset {}: no {kind} project found in {}, using it anyway",
+                    self.name(),
+                    dir.display()
+                );
+                Project {
+                    root: dir.to_path_buf(),
+                    kinds: vec![kind],
+                }
+            }
+        };
+        let store_dir = project.store_dir_in(store_root)?;
+        project.mark_used(&store_dir)?;
+
+        let name = |what: &str| format!("preset {}: {what}", self.name());
+        let root = project.root.to_string_lossy();
+        // Bound over itself, so that it stays writable in the read-only home.
+        let mut project_rule =
+            Rule::builder(name("project"), root.clone(), root).with_mode(RuleMode::Directory);
+        for (key, value) in self.env() {
+            project_rule = project_rule.with_env(*key, *value);
+        }
+        let mut rules = vec![project_rule.build()];
+        rules.extend(project.cache_rules(&store_dir)?);
+        for credentials in CREDENTIALS {
+            let target = shellexpand::tilde(credentials).to_string();
+            if Path::new(&target).exists() {
+                rules.push(
+                    Rule::builder(name(&format!("deny {credentials}")), target, "")
+                        .with_mode(RuleMode::Directory)
+                        .with_deny(true)
+                        .build(),
+                );
+            }
+        }
+
+        Ok(BoxxyRules {
+            rules,
+            ro_home: true,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_preset_rules() -> Result<()> {
+        assert_eq!("cargo-strict".parse(), Ok(Preset::CargoStrict));
+        assert!("cargo".parse::<Preset>().is_err());
+
+        let dir = Scratch::new("preset")?;
+        let project = dir.join("project");
+        let store = dir.join("store");
+        std::fs::create_dir(&project)?;
+        std::fs::write(project.join("package.json"), "{}")?;
+        let preset = Preset::NpmStrict.rules_in(&project, &store)?;
+        assert!(preset.ro_home);
+        let rules = preset.rules;
+        assert_eq!(rules[0].target, project.to_string_lossy());
+        assert_eq!(rules[0].rewrite, project.to_string_lossy());
+        assert_eq!(rules[0].env["NPM_CONFIG_IGNORE_SCRIPTS"], "true");
+        assert!(rules[1].name.starts_with("isolate npm cache"));
+        assert!(rules[1].rewrite.starts_with(&*store.to_string_lossy()));
+        assert!(rules[2..].iter().all(|rule| rule.deny));
+
+        let store_dir = Project::detect(&project).unwrap().store_dir_in(&store)?;
+        assert!(store_dir.join("last-used").exists());
+        Ok(())
+    }
+}
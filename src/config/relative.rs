@@ -0,0 +1,98 @@
+use std::path::{Component, Path, PathBuf};
+
+use serde_yaml::Value;
+
+use super::extends::rules_mut;
+
+/// Make the relative `target`s and `rewrite`s of the rules in a config
+/// document relative to `dir`, the directory of the config file, instead of
+/// wherever boxxy is run from, so that project configs can point into the
+/// project. Rewrite candidates are resolved too.
+pub fn resolve_relative_paths(document: &mut Value, dir: &Path) {
+    for rule in rules_mut(document) {
+        let Some(rule) = rule.as_mapping_mut() else {
+            continue;
+        };
+        for key in ["target", "rewrite"] {
+            match rule.get_mut(key) {
+                Some(Value::String(path)) => resolve(path, dir),
+                Some(Value::Sequence(candidates)) => {
+                    for candidate in candidates {
+                        if let Value::String(path) = candidate {
+                            resolve(path, dir);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// `path` joined onto `dir`, unless it's empty, absolute, starts with `~`, or
+/// starts with a template, which renders to a path of its own. `.` and `..`
+/// are resolved lexically, so that the same path is always spelled the same
+/// way, whether or not it exists yet.
+fn resolve(path: &mut String, dir: &Path) {
+    if path.is_empty() || path.starts_with(['/', '~']) || path.starts_with("{{") {
+        return;
+    }
+    let mut resolved = PathBuf::new();
+    for component in dir.join(&*path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    *path = resolved.to_string_lossy().to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_relative_paths() -> color_eyre::Result<()> {
+        let mut document: Value = serde_yaml::from_str(
+            r#"
+rules:
+- name: "project cache"
+  target: "~/.cache/tool"
+  rewrite: "./.boxxy/cache"
+- name: "candidates"
+  target: "/etc/tool"
+  rewrite: ["config/tool", "~/.tool"]
+- name: "untouched"
+  target: "{{ xdg_data }}/tool"
+  rewrite: ""
+workspaces:
+  app:
+    rules:
+    - name: "workspace"
+      target: "data"
+      rewrite: "../shared"
+    - name: "nested"
+      target: "./a/./b/../c"
+      rewrite: "../../../../.."
+"#,
+        )?;
+        resolve_relative_paths(&mut document, Path::new("/home/me/project"));
+        let rules = &document["rules"];
+        assert_eq!(rules[0]["target"], "~/.cache/tool");
+        assert_eq!(rules[0]["rewrite"], "/home/me/project/.boxxy/cache");
+        assert_eq!(rules[1]["rewrite"][0], "/home/me/project/config/tool");
+        assert_eq!(rules[1]["rewrite"][1], "~/.tool");
+        assert_eq!(rules[2]["target"], "{{ xdg_data }}/tool");
+        assert_eq!(rules[2]["rewrite"], "");
+        let workspace = &document["workspaces"]["app"]["rules"][0];
+        assert_eq!(workspace["target"], "/home/me/project/data");
+        assert_eq!(workspace["rewrite"], "/home/me/shared");
+        let nested = &document["workspaces"]["app"]["rules"][1];
+        assert_eq!(nested["target"], "/home/me/project/a/c");
+        assert_eq!(nested["rewrite"], "/");
+        Ok(())
+    }
+}
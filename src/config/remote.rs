@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use sha2::{Digest, Sha256};
+
+use crate::enclosure::fs::append_all;
+
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+
+/// How long a fetched rule file is used before it's fetched again.
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where files from boxxy's repo, like the app database, are fetched from.
+const REPO_DATA_URL: &str = "https://raw.githubusercontent.com/queer/boxxy/mistress/data";
+/// The checksums of the files in `data/`, in `sha256sum` format. They're an
+/// integrity check, not a signature.
+pub const CHECKSUMS_FILE: &str = "checksums.sha256";
+
+/// Decide whether `include_url`s may be fetched, or only read from the
+/// cache. Must be called before any config is loaded.
+pub fn init(offline: bool) {
+    OFFLINE.get_or_init(|| offline);
+}
+
+/// Whether `url` is an `include_url` that can be fetched.
+pub fn is_url(include: &str) -> bool {
+    include.starts_with("https://")
+}
+
+/// The local copy of the rule file at `url`, fetching it first if the cached
+/// copy is missing or more than an hour old. A stale copy is used if the
+/// fetch fails, and with `--offline` only the cache is read.
+pub fn fetch(url: &str) -> Result<PathBuf> {
+    let cached = cache_path(url)?;
+    let age = std::fs::metadata(&cached)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok());
+
+    if OFFLINE.get().copied().unwrap_or(false) {
+        if age.is_none() {
+            return Err(eyre!(
+                "{url} isn't cached, and can't be fetched with --offline"
+            ));
+        }
+        debug!("offline, using cached {url}");
+        return Ok(cached);
+    }
+    if age.is_some_and(|age| age < MAX_AGE) {
+        debug!("using cached {url}");
+        return Ok(cached);
+    }
+
+    info!("fetching rules from {url}");
+    match download(url, &cached) {
+        Ok(()) => Ok(cached),
+        Err(err) if age.is_some() => {
+            warn!("could not fetch {url}, using the cached copy: {err}");
+            Ok(cached)
+        }
+        Err(err) => Err(eyre!("could not fetch {url}: {err}")),
+    }
+}
+
+/// The body of `url`, which must be served over HTTPS.
+pub fn get(url: &str) -> Result<String> {
+    Ok(ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .https_only(true)
+        .build()
+        .get(url)
+        .call()?
+        .into_string()?)
+}
+
+/// Fetch `files` from `data/` in boxxy's repo, and check each against the
+/// checksums published with them. The checksums come from the same place as
+/// the files, so this only catches broken or mixed-up downloads; it says
+/// nothing about who wrote them.
+pub fn get_repo_files(files: &[&str]) -> Result<Vec<String>> {
+    let checksums = get(&format!("{REPO_DATA_URL}/{CHECKSUMS_FILE}"))?;
+    files
+        .iter()
+        .map(|file| {
+            let expected = checksum_for(&checksums, file)
+                .ok_or_else(|| eyre!("{CHECKSUMS_FILE} has no checksum for {file}"))?;
+            info!("fetching {file}");
+            let contents = get(&format!("{REPO_DATA_URL}/{file}"))?;
+            let actual = sha256(contents.as_bytes());
+            if actual != expected {
+                return Err(eyre!(
+                    "{file} doesn't match its checksum: expected {expected}, got {actual}"
+                ));
+            }
+            Ok(contents)
+        })
+        .collect()
+}
+
+/// Write `contents` to `path` through a temporary file, so that a failed
+/// write doesn't leave a broken copy behind.
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// The checksum of `file` in `checksums`, in `sha256sum` format.
+fn checksum_for<'a>(checksums: &'a str, file: &str) -> Option<&'a str> {
+    checksums.lines().find_map(|line| {
+        let (checksum, name) = line.split_once(char::is_whitespace)?;
+        (name.trim_start().trim_start_matches('*') == file).then_some(checksum)
+    })
+}
+
+pub(super) fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn download(url: &str, to: &Path) -> Result<()> {
+    write_atomically(to, &get(url)?)
+}
+
+/// Where the rule file at `url` is cached. JSON files keep their extension,
+/// so that they're parsed as JSON.
+fn cache_path(url: &str) -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir().ok_or_else(|| eyre!("could not find cache directory"))?;
+    Ok(append_all(
+        &cache_dir,
+        vec!["boxxy", "includes", &cache_file_name(url)],
+    ))
+}
+
+fn cache_file_name(url: &str) -> String {
+    let name: String = url
+        .trim_start_matches("https://")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if url.ends_with(".json") {
+        format!("{name}.json")
+    } else {
+        format!("{name}.yaml")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_for() {
+        let checksums = "abc  one.json\ndef *two.json\n";
+        assert_eq!(checksum_for(checksums, "one.json"), Some("abc"));
+        assert_eq!(checksum_for(checksums, "two.json"), Some("def"));
+        assert_eq!(checksum_for(checksums, "three.json"), None);
+    }
+
+    #[test]
+    fn test_published_checksums_match() {
+        // The checksums are published alongside the files, so they have to
+        // be updated whenever one changes: `sha256sum community-rules.yaml
+        // *.json > checksums.sha256` in data/.
+        let checksums = include_str!("../../data/checksums.sha256");
+        for (file, contents) in [
+            (
+                "community-rules.yaml",
+                include_str!("../../data/community-rules.yaml"),
+            ),
+            (
+                "hardcoded-applications.json",
+                include_str!("../../data/hardcoded-applications.json"),
+            ),
+            (
+                "partial-support-applications.json",
+                include_str!("../../data/partial-support-applications.json"),
+            ),
+        ] {
+            assert_eq!(
+                checksum_for(checksums, file),
+                Some(sha256(contents.as_bytes()).as_str()),
+                "{file}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_cache_file_name() {
+        assert_eq!(
+            cache_file_name("https://example.com/team/boxxy.yaml"),
+            "example_com_team_boxxy_yaml.yaml"
+        );
+        assert_eq!(
+            cache_file_name("https://example.com/rules.json?ref=main"),
+            "example_com_rules_json_ref_main.yaml"
+        );
+        assert_eq!(
+            cache_file_name("https://example.com/rules.json"),
+            "example_com_rules_json.json"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use super::VersionCheck;
+
+/// The version of the config file layout that this boxxy writes. Files
+/// without a `version` are version 1, the layout from before there were
+/// versions.
+pub const CURRENT_VERSION: u64 = 1;
+
+/// How to upgrade a config document to version `to` from the one before it.
+pub struct Migration {
+    pub to: u64,
+    /// Rule keys that were renamed, as `(old, new)`.
+    pub renamed: &'static [(&'static str, &'static str)],
+    /// Values of rule keys that were renamed, ex. a mode, as `(key, old,
+    /// new)`.
+    pub renamed_values: &'static [(&'static str, &'static str, &'static str)],
+    /// Rule keys whose default changed, with their old default as YAML.
+    /// Rules that don't set them get the old default, so that they keep
+    /// working the same way.
+    pub old_defaults: &'static [(&'static str, &'static str)],
+}
+
+/// Every migration, oldest first.
+const MIGRATIONS: &[Migration] = &[];
+
+/// The layout version of a config document.
+pub fn version_of(document: &Value) -> Result<u64> {
+    match document.get("version") {
+        None => Ok(1),
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version > 0)
+            .ok_or_else(|| eyre!("version must be a whole number, ex. `1`")),
+    }
+}
+
+/// Upgrade `document` to the current layout in place, returning what was
+/// changed.
+pub fn upgrade(document: &mut Value, version_check: VersionCheck) -> Result<Vec<String>> {
+    upgrade_with(document, MIGRATIONS, CURRENT_VERSION, version_check)
+}
+
+fn upgrade_with(
+    document: &mut Value,
+    migrations: &[Migration],
+    current: u64,
+    version_check: VersionCheck,
+) -> Result<Vec<String>> {
+    let version = version_of(document)?;
+    if version > current {
+        let message = format!(
+            "this config is version {version}, but this boxxy only knows versions up to {current}"
+        );
+        return match version_check {
+            VersionCheck::Error => Err(eyre!(
+                "{message}; upgrade boxxy, or pass `--version-check warn` to use it anyway"
+            )),
+            VersionCheck::Warn => {
+                warn!("{message}, some rules may not work as intended");
+                Ok(vec![])
+            }
+            VersionCheck::Ignore => Ok(vec![]),
+        };
+    }
+
+    let mut changes = vec![];
+    for migration in migrations
+        .iter()
+        .filter(|migration| migration.to > version && migration.to <= current)
+    {
+        for_each_rule(document, &mut |rule| migration.apply(rule, &mut changes))?;
+    }
+    if version < current {
+        if let Some(mapping) = document.as_mapping_mut() {
+            mapping.insert("version".into(), current.into());
+        }
+    }
+
+    Ok(changes)
+}
+
+impl Migration {
+    fn apply(&self, rule: &mut Mapping, changes: &mut Vec<String>) -> Result<()> {
+        let name = rule
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("?")
+            .to_string();
+        for (old, new) in self.renamed {
+            let Some(value) = rule.remove(*old) else {
+                continue;
+            };
+            if rule.contains_key(*new) {
+                changes.push(format!(
+                    "rule '{name}': `{old}` is now `{new}`, which is also set, so `{old}` is ignored"
+                ));
+            } else {
+                rule.insert((*new).into(), value);
+                changes.push(format!("rule '{name}': `{old}` is now `{new}`"));
+            }
+        }
+        for (key, old, new) in self.renamed_values {
+            let Some(value) = rule.get_mut(*key) else {
+                continue;
+            };
+            if value.as_str() == Some(*old) {
+                *value = (*new).into();
+                changes.push(format!(
+                    "rule '{name}': `{key}: {old}` is now `{key}: {new}`"
+                ));
+            }
+        }
+        for (key, old_default) in self.old_defaults {
+            if !rule.contains_key(*key) {
+                rule.insert((*key).into(), serde_yaml::from_str(old_default)?);
+                changes.push(format!(
+                    "rule '{name}': the default `{key}` changed in config version {}, keeping the old default `{old_default}`",
+                    self.to
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Call `f` with every rule in `document`, including the rules in its
+/// profiles and workspaces.
+fn for_each_rule(
+    document: &mut Value,
+    f: &mut impl FnMut(&mut Mapping) -> Result<()>,
+) -> Result<()> {
+    fn in_list(
+        rules: Option<&mut Value>,
+        f: &mut impl FnMut(&mut Mapping) -> Result<()>,
+    ) -> Result<()> {
+        for rule in rules
+            .and_then(Value::as_sequence_mut)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_mapping_mut)
+        {
+            f(rule)?;
+        }
+        Ok(())
+    }
+
+    in_list(document.get_mut("rules"), f)?;
+    if let Some(profiles) = document.get_mut("profiles").and_then(Value::as_mapping_mut) {
+        for rules in profiles.values_mut() {
+            in_list(Some(rules), f)?;
+        }
+    }
+    if let Some(workspaces) = document
+        .get_mut("workspaces")
+        .and_then(Value::as_mapping_mut)
+    {
+        for workspace in workspaces.values_mut() {
+            in_list(workspace.get_mut("rules"), f)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the config file `contents` in the current layout, or `None` if
+/// it's already current. The file is re-serialized, so comments, anchors,
+/// and formatting aren't kept.
+pub fn migrate(contents: &str, json: bool) -> Result<Option<String>> {
+    migrate_with(contents, json, MIGRATIONS, CURRENT_VERSION)
+}
+
+fn migrate_with(
+    contents: &str,
+    json: bool,
+    migrations: &[Migration],
+    current: u64,
+) -> Result<Option<String>> {
+    let mut documents: Vec<Value> = if json {
+        vec![serde_json::from_str(contents)?]
+    } else {
+        serde_yaml::Deserializer::from_str(contents)
+            .map(Value::deserialize)
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    documents.retain(|document| !document.is_null());
+    let mut outdated = false;
+    for document in &documents {
+        let version = version_of(document)?;
+        if version > current {
+            return Err(eyre!(
+                "this config is version {version}, but this boxxy only knows versions up to {current}"
+            ));
+        }
+        outdated |= version < current;
+    }
+    if !outdated {
+        return Ok(None);
+    }
+
+    for document in &mut documents {
+        for change in upgrade_with(document, migrations, current, VersionCheck::Error)? {
+            info!("{change}");
+        }
+        // Put `version` first, where people will look for it.
+        if let Some(mapping) = document.as_mapping_mut() {
+            let mut versioned = Mapping::new();
+            versioned.insert("version".into(), current.into());
+            for (key, value) in std::mem::take(mapping) {
+                if key.as_str() != Some("version") {
+                    versioned.insert(key, value);
+                }
+            }
+            *mapping = versioned;
+        }
+    }
+
+    let output = if json {
+        let mut output = serde_json::to_string_pretty(&documents[0])?;
+        output.push('\n');
+        output
+    } else {
+        documents
+            .iter()
+            .map(serde_yaml::to_string)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("---\n")
+    };
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            to: 2,
+            renamed: &[("ro", "readonly")],
+            renamed_values: &[("mode", "dir", "directory")],
+            old_defaults: &[],
+        },
+        Migration {
+            to: 3,
+            renamed: &[],
+            renamed_values: &[],
+            old_defaults: &[("mode", "file")],
+        },
+    ];
+
+    #[test]
+    fn test_upgrade() -> Result<()> {
+        let mut document: Value = serde_yaml::from_str(
+            r#"
+rules:
+- name: "a"
+  target: "~/.a"
+  ro: true
+profiles:
+  work:
+  - name: "b"
+    target: "~/.b"
+    mode: "dir"
+workspaces:
+  home:
+    rules:
+    - name: "c"
+      target: "~/.c"
+      ro: true
+      readonly: false
+"#,
+        )?;
+        let changes = upgrade_with(&mut document, MIGRATIONS, 3, VersionCheck::Error)?;
+        assert_eq!(
+            changes,
+            vec![
+                "rule 'a': `ro` is now `readonly`",
+                "rule 'b': `mode: dir` is now `mode: directory`",
+                "rule 'c': `ro` is now `readonly`, which is also set, so `ro` is ignored",
+                "rule 'a': the default `mode` changed in config version 3, keeping the old default `file`",
+                "rule 'c': the default `mode` changed in config version 3, keeping the old default `file`",
+            ]
+        );
+        assert_eq!(version_of(&document)?, 3);
+        assert_eq!(document["rules"][0]["readonly"], Value::Bool(true));
+        assert_eq!(document["profiles"]["work"][0]["mode"], "directory");
+        assert_eq!(
+            document["workspaces"]["home"]["rules"][0]["readonly"],
+            Value::Bool(false)
+        );
+
+        // Only the migrations after a document's version apply.
+        let mut document: Value =
+            serde_yaml::from_str("version: 2\nrules:\n- name: a\n  target: ~/.a\n  ro: true\n")?;
+        let changes = upgrade_with(&mut document, MIGRATIONS, 3, VersionCheck::Error)?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(document["rules"][0]["ro"], Value::Bool(true));
+
+        let mut document: Value = serde_yaml::from_str("version: 4\n")?;
+        assert!(upgrade_with(&mut document, MIGRATIONS, 3, VersionCheck::Error).is_err());
+        assert!(upgrade_with(&mut document, MIGRATIONS, 3, VersionCheck::Warn).is_ok());
+        let mut document: Value = serde_yaml::from_str("version: one\n")?;
+        assert!(upgrade(&mut document, VersionCheck::Ignore).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate() -> Result<()> {
+        let contents =
+            "rules:\n- name: a\n  target: ~/.a\n  ro: true\n---\nversion: 2\nrules: []\n";
+        let migrated = migrate_with(contents, false, MIGRATIONS, 3)?.unwrap();
+        assert_eq!(
+            migrated,
+            "version: 3\nrules:\n- name: a\n  target: ~/.a\n  readonly: true\n  mode: file\n---\nversion: 3\nrules: []\n"
+        );
+        assert_eq!(migrate_with(&migrated, false, MIGRATIONS, 3)?, None);
+
+        let migrated = migrate_with(r#"{"rules": []}"#, true, MIGRATIONS, 2)?.unwrap();
+        assert_eq!(migrated, "{\n  \"version\": 2,\n  \"rules\": []\n}\n");
+        assert_eq!(migrate("rules: []\n", false)?, None);
+        assert!(migrate_with("version: 4\n", false, MIGRATIONS, 3).is_err());
+        Ok(())
+    }
+}
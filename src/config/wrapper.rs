@@ -0,0 +1,162 @@
+use std::ffi::{OsStr, OsString};
+use std::io::Read;
+use std::path::Path;
+
+/// Programs that run the rest of their command line: their name, the options
+/// that take a separate value, and how many arguments they take before the
+/// program.
+const WRAPPERS: &[(&str, &[&str], usize)] = &[
+    ("env", &["-u", "--unset", "-C", "--chdir"], 0),
+    ("nice", &["-n", "--adjustment"], 0),
+    ("nohup", &[], 0),
+    ("setsid", &[], 0),
+    ("stdbuf", &["-i", "-o", "-e"], 0),
+    ("timeout", &["-s", "--signal", "-k", "--kill-after"], 1),
+];
+
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh"];
+
+/// How much of a script to look through for the program it runs.
+const SCRIPT_LIMIT: u64 = 64 * 1024;
+
+/// The program that `program` really runs, looking through one level of
+/// wrapping: wrappers like `env` and `nohup` run the program after their
+/// options, shell scripts whose last `exec` runs another program run that,
+/// and other scripts run their shebang's interpreter.
+pub fn wrapped_program(program: &OsStr, args: &[&OsStr]) -> Option<OsString> {
+    let name = Path::new(program).file_name()?.to_str()?;
+    if let Some((_, valued, positional)) = WRAPPERS.iter().find(|(wrapper, ..)| *wrapper == name) {
+        return after_options(args, valued, *positional, name == "env").map(OsStr::to_os_string);
+    }
+
+    let path = which::which(program).ok()?;
+    let mut script = vec![];
+    std::fs::File::open(path)
+        .ok()?
+        .take(SCRIPT_LIMIT)
+        .read_to_end(&mut script)
+        .ok()?;
+    let script = String::from_utf8_lossy(&script);
+    let shebang = script.lines().next()?.strip_prefix("#!")?;
+    let (interpreter, arg) = match shebang.trim().split_once([' ', '\t']) {
+        Some((interpreter, arg)) => (interpreter, Some(arg.trim())),
+        None => (shebang.trim(), None),
+    };
+
+    let interpreter = match Path::new(interpreter).file_name()?.to_str()? {
+        // `#!/usr/bin/env python3`, maybe with `-S` to split the rest.
+        "env" => {
+            let words: Vec<&OsStr> = arg?.split_whitespace().map(OsStr::new).collect();
+            let words = match words.first() {
+                Some(flag) if *flag == "-S" => &words[1..],
+                _ => &words[..],
+            };
+            after_options(words, &[], 0, true)?.to_str()?
+        }
+        interpreter => interpreter,
+    };
+    let interpreter = Path::new(interpreter).file_name()?.to_str()?;
+    if SHELLS.contains(&interpreter) {
+        return last_exec(&script).map(OsString::from);
+    }
+
+    Some(interpreter.into())
+}
+
+/// The first argument after a wrapper's options and `positional` other
+/// arguments. `env` also takes `KEY=value` assignments.
+fn after_options<'a>(
+    args: &[&'a OsStr],
+    valued: &[&str],
+    positional: usize,
+    assignments: bool,
+) -> Option<&'a OsStr> {
+    let mut args = args.iter();
+    let mut positional = positional;
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == "--" {
+            return args.nth(positional).copied();
+        }
+        if valued.contains(&text.as_ref()) {
+            args.next();
+        } else if text.starts_with('-') || (assignments && text.contains('=')) {
+            continue;
+        } else if positional > 0 {
+            positional -= 1;
+        } else {
+            return Some(arg);
+        }
+    }
+
+    None
+}
+
+/// The program run by the last `exec` in a shell script, by file name, ex.
+/// `real-tool` for `exec "$HERE/real-tool" "$@"`.
+fn last_exec(script: &str) -> Option<&str> {
+    script
+        .lines()
+        .rev()
+        .filter_map(|line| line.trim().strip_prefix("exec "))
+        .filter_map(|command| {
+            let program = command.split_whitespace().next()?.trim_matches(['"', '\'']);
+            let name = program.rsplit('/').next()?;
+            (!name.is_empty() && !name.contains('$')).then_some(name)
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::testing::Scratch;
+
+    fn wrapped(command: &str) -> Option<String> {
+        let words: Vec<&OsStr> = command.split_whitespace().map(OsStr::new).collect();
+        wrapped_program(words[0], &words[1..]).map(|program| program.to_string_lossy().into())
+    }
+
+    #[test]
+    fn test_wrapped_program() {
+        assert_eq!(
+            wrapped("env FOO=1 -u BAR aws s3 ls").as_deref(),
+            Some("aws")
+        );
+        assert_eq!(wrapped("/usr/bin/env -- aws").as_deref(), Some("aws"));
+        assert_eq!(wrapped("nice -n 10 cargo build").as_deref(), Some("cargo"));
+        assert_eq!(
+            wrapped("timeout -s KILL 10s npm test").as_deref(),
+            Some("npm")
+        );
+        assert_eq!(wrapped("nohup").as_deref(), None);
+        assert_eq!(wrapped("env FOO=1").as_deref(), None);
+    }
+
+    #[test]
+    fn test_wrapped_program_scripts() -> std::io::Result<()> {
+        let dir = Scratch::new("wrapper")?;
+        let script = |name: &str, contents: &str| -> std::io::Result<String> {
+            let path = dir.join(name);
+            std::fs::write(&path, contents)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+            Ok(path.to_string_lossy().into())
+        };
+
+        let python = script("python-tool", "#!/usr/bin/env -S python3 -u\nprint()\n")?;
+        let shell = script(
+            "shell-tool",
+            "#!/bin/sh\nHERE=$(dirname \"$0\")\nexec \"$HERE/real-tool\" \"$@\"\n",
+        )?;
+        let plain = script("plain-tool", "#!/bin/bash\necho hi\n")?;
+        let node = script("node-tool", "#!/usr/local/bin/node\n")?;
+
+        assert_eq!(wrapped(&python).as_deref(), Some("python3"));
+        assert_eq!(wrapped(&shell).as_deref(), Some("real-tool"));
+        assert_eq!(wrapped(&plain).as_deref(), None);
+        assert_eq!(wrapped(&node).as_deref(), Some("node"));
+        Ok(())
+    }
+}
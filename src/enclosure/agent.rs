@@ -0,0 +1,328 @@
+//! `--ssh-agent`: whether the boxxed program can use the SSH agent, and the
+//! proxy that lets it list keys but asks before it signs anything with them.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+
+use super::fs::append_all;
+use super::rule::{Rule, RuleMode};
+use crate::config::BoxxyConfig;
+
+/// The env var that SSH clients find the agent with.
+pub const SOCKET_ENV: &str = "SSH_AUTH_SOCK";
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENTC_EXTENSION: u8 = 27;
+
+/// Agent messages bigger than this are refused, like OpenSSH's agent does.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SshAgent {
+    /// The boxxed program uses the agent like any other program.
+    #[default]
+    Allow,
+    /// The agent's socket is hidden.
+    Deny,
+    /// The agent's socket is hidden, and a proxy that asks before signing is
+    /// put in its place.
+    Proxy,
+}
+
+impl FromStr for SshAgent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(SshAgent::Allow),
+            "deny" => Ok(SshAgent::Deny),
+            "proxy" => Ok(SshAgent::Proxy),
+            _ => Err(format!("invalid ssh agent mode: {s}")),
+        }
+    }
+}
+
+/// The agent's socket, if there's one.
+pub fn upstream_socket() -> Option<PathBuf> {
+    std::env::var_os(SOCKET_ENV)
+        .map(PathBuf::from)
+        .filter(|path| path.exists())
+}
+
+/// The proxy socket of the box called `name`.
+pub fn socket_path(name: &str) -> Result<PathBuf> {
+    Ok(append_all(
+        &BoxxyConfig::runtime_dir()?,
+        vec![format!("{name}.ssh-agent.sock")],
+    ))
+}
+
+/// Remove the proxy socket of the box called `name`, if it has one.
+pub fn remove_socket(name: &str) {
+    if let Ok(socket) = socket_path(name) {
+        let _ = std::fs::remove_file(socket);
+    }
+}
+
+/// A rule that hides the agent's socket at `socket`.
+pub fn deny_rule(socket: &Path) -> Rule {
+    Rule::builder("ssh agent", socket.to_string_lossy(), "")
+        .with_mode(RuleMode::File)
+        .with_deny(true)
+        .build()
+}
+
+/// Whether to let the boxxed program sign with a key, given its comment.
+pub type Approve = dyn Fn(&str) -> bool + Send + Sync;
+
+/// Serve the proxy on `listener`, forwarding to the agent at `upstream`,
+/// until it fails. Must be run from boxxy's process, which can still reach
+/// the real agent.
+pub fn serve(listener: UnixListener, upstream: PathBuf, approve: Arc<Approve>) {
+    let comments = Arc::new(Mutex::new(HashMap::new()));
+    for client in listener.incoming() {
+        let client = match client {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("ssh agent proxy: could not accept a client: {err}");
+                continue;
+            }
+        };
+        let upstream = upstream.clone();
+        let approve = approve.clone();
+        let comments = comments.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle_client(client, &upstream, &*approve, &comments) {
+                debug!("ssh agent proxy: client went away: {err}");
+            }
+        });
+    }
+}
+
+/// Key blobs mapped to their comments, from the agent's answers to
+/// identity requests.
+type Comments = Mutex<HashMap<Vec<u8>, String>>;
+
+fn handle_client(
+    mut client: UnixStream,
+    upstream: &Path,
+    approve: &Approve,
+    comments: &Comments,
+) -> Result<()> {
+    let mut agent = UnixStream::connect(upstream)?;
+    while let Some(message) = read_message(&mut client)? {
+        let forward = match message.first().copied() {
+            Some(SSH_AGENTC_REQUEST_IDENTITIES) | Some(SSH_AGENTC_EXTENSION) => true,
+            Some(SSH_AGENTC_SIGN_REQUEST) => {
+                let key = read_string(&message[1..]).unwrap_or_default();
+                let comment = comments
+                    .lock()
+                    .map_err(|_| eyre!("key comments are poisoned"))?
+                    .get(key)
+                    .cloned()
+                    .unwrap_or_else(|| "an unknown key".to_string());
+                approve(&comment)
+            }
+            kind => {
+                debug!("ssh agent proxy: refusing message type {kind:?}");
+                false
+            }
+        };
+        if !forward {
+            write_message(&mut client, &[SSH_AGENT_FAILURE])?;
+            continue;
+        }
+
+        write_message(&mut agent, &message)?;
+        let response = read_message(&mut agent)?
+            .ok_or_else(|| eyre!("the ssh agent closed the connection"))?;
+        if response.first() == Some(&SSH_AGENT_IDENTITIES_ANSWER) {
+            let mut comments = comments
+                .lock()
+                .map_err(|_| eyre!("key comments are poisoned"))?;
+            comments.extend(parse_identities(&response[1..]));
+        }
+        write_message(&mut client, &response)?;
+    }
+
+    Ok(())
+}
+
+/// Ask on the terminal whether `program` in the box `name` can sign with a
+/// key, remembering keys that are always allowed. Without a terminal to ask
+/// on, signing is allowed and logged.
+pub fn prompt(program: String, name: String) -> Arc<Approve> {
+    let always: Mutex<HashSet<String>> = Mutex::default();
+    Arc::new(move |comment: &str| {
+        let Ok(mut always) = always.lock() else {
+            return false;
+        };
+        if always.contains(comment) {
+            info!("ssh agent proxy: {program} signed with {comment}");
+            return true;
+        }
+        let Ok(tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+            warn!("ssh agent proxy: {program} signed with {comment}, no terminal to ask on");
+            return true;
+        };
+        let mut writer = &tty;
+        let _ = write!(
+            writer,
+            "boxxy: {program} in box {name} wants to sign with {comment}. Allow? [y/N/a(lways)] "
+        );
+        let mut answer = String::new();
+        let _ = BufReader::new(&tty).read_line(&mut answer);
+        match answer.trim() {
+            "y" | "Y" | "yes" => true,
+            "a" | "A" | "always" => {
+                always.insert(comment.to_string());
+                true
+            }
+            _ => {
+                warn!("ssh agent proxy: refused to let {program} sign with {comment}");
+                false
+            }
+        }
+    })
+}
+
+fn read_message(stream: &mut UnixStream) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(eyre!("ssh agent message is too long ({len} bytes)"));
+    }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(stream: &mut UnixStream, message: &[u8]) -> Result<()> {
+    let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+    Ok(())
+}
+
+/// The length-prefixed string at the start of `data`.
+fn read_string(data: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    data.get(4..4 + len)
+}
+
+/// The key blobs and comments in an identities answer, without its type.
+fn parse_identities(mut data: &[u8]) -> Vec<(Vec<u8>, String)> {
+    let mut identities = vec![];
+    let Some(count) = data.get(..4) else {
+        return identities;
+    };
+    let count = u32::from_be_bytes(count.try_into().unwrap_or_default());
+    data = &data[4..];
+    for _ in 0..count {
+        let Some(key) = read_string(data) else {
+            break;
+        };
+        data = &data[4 + key.len()..];
+        let Some(comment) = read_string(data) else {
+            break;
+        };
+        data = &data[4 + comment.len()..];
+        identities.push((key.to_vec(), String::from_utf8_lossy(comment).to_string()));
+    }
+    identities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    fn string(data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// An agent with one key, that signs anything.
+    fn fake_agent(listener: UnixListener) {
+        for mut stream in listener.incoming().flatten() {
+            while let Ok(Some(message)) = read_message(&mut stream) {
+                let response = match message[0] {
+                    SSH_AGENTC_REQUEST_IDENTITIES => {
+                        let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+                        response.extend(1u32.to_be_bytes());
+                        response.extend(string(b"key"));
+                        response.extend(string(b"me@laptop"));
+                        response
+                    }
+                    SSH_AGENTC_SIGN_REQUEST => vec![14],
+                    _ => vec![6],
+                };
+                write_message(&mut stream, &response).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_agent_proxy() -> Result<()> {
+        assert_eq!("proxy".parse(), Ok(SshAgent::Proxy));
+        assert!("ask".parse::<SshAgent>().is_err());
+
+        let dir = Scratch::new("agent")?;
+        let upstream = dir.join("agent.sock");
+        let agent = UnixListener::bind(&upstream)?;
+        thread::spawn(move || fake_agent(agent));
+        let proxy = dir.join("proxy.sock");
+        let listener = UnixListener::bind(&proxy)?;
+        let asked = Arc::new(Mutex::new(vec![]));
+        let approve: Arc<Approve> = {
+            let asked = asked.clone();
+            Arc::new(move |comment: &str| {
+                let mut asked = asked.lock().unwrap();
+                asked.push(comment.to_string());
+                asked.len() > 1
+            })
+        };
+        thread::spawn(move || serve(listener, upstream, approve));
+
+        let mut client = UnixStream::connect(&proxy)?;
+        let mut request = |message: &[u8]| -> Result<Vec<u8>> {
+            write_message(&mut client, message)?;
+            Ok(read_message(&mut client)?.unwrap())
+        };
+        let identities = request(&[SSH_AGENTC_REQUEST_IDENTITIES])?;
+        assert_eq!(
+            parse_identities(&identities[1..]),
+            [(b"key".to_vec(), "me@laptop".to_string())]
+        );
+        let mut sign = vec![SSH_AGENTC_SIGN_REQUEST];
+        sign.extend(string(b"key"));
+        sign.extend(string(b"data"));
+        sign.extend(0u32.to_be_bytes());
+        assert_eq!(request(&sign)?, [SSH_AGENT_FAILURE]);
+        assert_eq!(request(&sign)?, [14]);
+        assert_eq!(*asked.lock().unwrap(), ["me@laptop", "me@laptop"]);
+        // Adding keys (17) never reaches the agent.
+        assert_eq!(request(&[17])?, [SSH_AGENT_FAILURE]);
+
+        Ok(())
+    }
+}
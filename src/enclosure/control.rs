@@ -0,0 +1,303 @@
+//! The control socket that `--control-socket` exposes inside a box, at
+//! `$BOXXY_CONTROL_SOCKET`. The boxxed program, or tools it runs, send one
+//! JSON request per line and get one JSON response per line back.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use super::fs::{append_all, FsDriver};
+use super::rule::{Rule, RuleMode};
+use crate::config::BoxxyConfig;
+
+/// The env var that tells the boxxed program where the control socket is.
+pub const SOCKET_ENV: &str = "BOXXY_CONTROL_SOCKET";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ControlRequest {
+    /// Apply another of the rules in boxxy's config, by name, to the running
+    /// box.
+    Mount { rule: String },
+    /// Paths that the program uses, written to `boxxy-usage.txt` when the box
+    /// exits.
+    Report { paths: Vec<PathBuf> },
+    /// The program is ready, ex. a daemon that has finished starting up.
+    Ready,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The control socket of the box called `name`.
+pub fn socket_path(name: &str) -> Result<PathBuf> {
+    Ok(append_all(
+        &BoxxyConfig::runtime_dir()?,
+        vec![format!("{name}.control.sock")],
+    ))
+}
+
+/// Paths reported over the control socket, shared with the thread serving it.
+pub type ReportedPaths = Arc<Mutex<Vec<PathBuf>>>;
+
+/// Serve requests on `listener` until it fails. Must be run from the
+/// container process, which can still mount things in the box. Only `rules`,
+/// the rules in boxxy's config, can be mounted, since anything in the box can
+/// connect to the socket.
+pub fn serve(listener: UnixListener, rules: Vec<Rule>, reported: ReportedPaths) {
+    let fs = FsDriver::new();
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(|err| err.into())
+            .and_then(|stream| handle_client(stream, &fs, &rules, &reported));
+        if let Err(err) = result {
+            warn!("failed to handle control socket client: {err}");
+        }
+    }
+}
+
+fn handle_client(
+    stream: UnixStream,
+    fs: &FsDriver,
+    rules: &[Rule],
+    reported: &ReportedPaths,
+) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line)
+            .map_err(|err| eyre!("invalid request: {err}"))
+            .and_then(|request| handle_request(request, fs, rules, reported))
+        {
+            Ok(()) => ControlResponse {
+                ok: true,
+                error: None,
+            },
+            Err(err) => ControlResponse {
+                ok: false,
+                error: Some(err.to_string()),
+            },
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    }
+
+    Ok(())
+}
+
+fn handle_request(
+    request: ControlRequest,
+    fs: &FsDriver,
+    rules: &[Rule],
+    reported: &ReportedPaths,
+) -> Result<()> {
+    match request {
+        ControlRequest::Mount { rule } => {
+            let rule = rules
+                .iter()
+                .find(|configured| configured.name == rule)
+                .ok_or_else(|| eyre!("no rule named '{rule}' in boxxy's config"))?;
+            mount(rule, fs)?;
+            info!("control socket: mounted rule '{}'", rule.name);
+        }
+        ControlRequest::Report { paths } => {
+            debug!("control socket: {} path(s) reported", paths.len());
+            reported
+                .lock()
+                .map_err(|_| eyre!("reported paths are poisoned"))?
+                .extend(paths);
+        }
+        ControlRequest::Ready => {
+            info!("control socket: the boxxed program is ready");
+            notify_systemd()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply `rule` to the running box. The box's staging area is gone by now,
+/// so rules that need it, ie. deny, omit, mask, copy, and faultmodes, can't be
+/// added.
+fn mount(rule: &Rule, fs: &FsDriver) -> Result<()> {
+    rule.validate()?;
+    if rule.deny
+        || !rule.omit.is_empty()
+        || rule.faultmode.is_some()
+        || matches!(rule.mode, RuleMode::Mask | RuleMode::Copy)
+    {
+        return Err(eyre!(
+            "{}: deny, omit, mask, copy, and faultmode rules can't be added to a running box",
+            rule.name
+        ));
+    }
+    if rule.has_rewrite() && rule.rewrite.is_empty() {
+        return Err(eyre!("{}: a rewrite is required", rule.name));
+    }
+
+    let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+    match rule.mode {
+        RuleMode::File => ensure(&target, false)?,
+        RuleMode::Directory | RuleMode::Tmpfs => ensure(&target, true)?,
+        RuleMode::ExposeRo if !target.exists() => {
+            return Err(eyre!("{}: target {} doesn't exist", rule.name, rule.target))
+        }
+        RuleMode::ExposeRo | RuleMode::Mask | RuleMode::Copy => {}
+    }
+
+    match rule.mode {
+        RuleMode::Tmpfs => {
+            let options = match &rule.size {
+                Some(size) => format!("mode=755,size={size}"),
+                None => "mode=755".to_string(),
+            };
+            fs.mount_tmpfs_with(&target, &options, rule.mount_flags())?;
+        }
+        RuleMode::ExposeRo => fs.bind_mount_ro(&target, &target)?,
+        _ => {
+            let rewrite = fs.fully_expand_path(&rule.rewrite)?;
+            ensure(&rewrite, rule.mode == RuleMode::Directory)?;
+            if rule.readonly {
+                fs.bind_mount_ro(&rewrite, &target)?;
+            } else {
+                fs.bind_mount_rw(&rewrite, &target)?;
+            }
+        }
+    }
+    if !rule.mount_options.is_empty() && rule.mode != RuleMode::Tmpfs {
+        fs.remount_with(&target, rule.mount_flags())?;
+    }
+
+    Ok(())
+}
+
+fn ensure(path: &Path, directory: bool) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    if directory {
+        std::fs::create_dir_all(path)?;
+    } else {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::File::create(path)?;
+    }
+    Ok(())
+}
+
+/// Pass readiness on to systemd, for `Type=notify` services. boxxy isn't the
+/// process that makes the call, so the service needs `NotifyAccess=all`.
+fn notify_systemd() -> Result<()> {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    if socket.to_string_lossy().starts_with('@') {
+        return Err(eyre!("abstract NOTIFY_SOCKETs aren't supported"));
+    }
+    UnixDatagram::unbound()?.send_to(b"READY=1", &socket)?;
+    debug!("notified systemd that the box is ready");
+    Ok(())
+}
+
+/// Send `request` to the box this is running in.
+pub fn send(request: &ControlRequest) -> Result<()> {
+    let path = std::env::var_os(SOCKET_ENV).ok_or_else(|| {
+        eyre!("${SOCKET_ENV} isn't set, run this in a box started with --control-socket")
+    })?;
+    send_to(Path::new(&path), request)
+}
+
+fn send_to(path: &Path, request: &ControlRequest) -> Result<()> {
+    let mut stream = UnixStream::connect(path).map_err(|err| {
+        eyre!(
+            "could not connect to the control socket at {}: {err}",
+            path.display()
+        )
+    })?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: ControlResponse = serde_json::from_str(&line)?;
+    match response.error {
+        Some(error) if !response.ok => Err(eyre!(error)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_control_requests() -> Result<()> {
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"action": "mount", "rule": "npm"}"#)?;
+        assert!(matches!(request, ControlRequest::Mount { rule } if rule == "npm"));
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"action": "report", "paths": ["/a", "/b"]}"#)?;
+        assert!(matches!(request, ControlRequest::Report { paths } if paths.len() == 2));
+        let request: ControlRequest = serde_json::from_str(r#"{"action": "ready"}"#)?;
+        assert!(matches!(request, ControlRequest::Ready));
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"action": "explode"}"#).is_err());
+
+        let fs = FsDriver::new();
+        let denied = Rule::builder("ssh", "~/.ssh", "").with_deny(true).build();
+        assert!(mount(&denied, &fs).is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_control_socket() -> Result<()> {
+        let dir = Scratch::new("control")?;
+        let path = dir.join("control.sock");
+        let listener = UnixListener::bind(&path)?;
+        let rules = vec![Rule::builder("ssh", "~/.ssh", "").with_deny(true).build()];
+        let reported = ReportedPaths::default();
+        let serving = reported.clone();
+        std::thread::spawn(move || serve(listener, rules, serving));
+
+        send_to(
+            &path,
+            &ControlRequest::Report {
+                paths: vec![PathBuf::from("/a")],
+            },
+        )?;
+        assert_eq!(*reported.lock().unwrap(), [PathBuf::from("/a")]);
+        // The boxxed program can only mount rules that are in the config.
+        let err = send_to(
+            &path,
+            &ControlRequest::Mount {
+                rule: "host root".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no rule named 'host root' in boxxy's config"
+        );
+        let err = send_to(
+            &path,
+            &ControlRequest::Mount {
+                rule: "ssh".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("can't be added"), "{err}");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+
+/// The copy made for a `mode: copy` rule, to sync back to the rule's rewrite
+/// when the box exits cleanly.
+pub struct CopyBack {
+    pub rule: String,
+    /// The copy, where the boxxed program sees it.
+    pub target: PathBuf,
+    /// The rewrite's directory, opened before the container's root changes
+    /// so that it can still be reached afterwards, even if it's covered.
+    dir: File,
+    /// The rewrite's name in `dir`, if it's a file.
+    file_name: Option<OsString>,
+}
+
+impl CopyBack {
+    pub fn new(rule: &str, target: PathBuf, rewrite: &Path, directory: bool) -> Result<Self> {
+        let (dir, file_name) = if directory {
+            (rewrite, None)
+        } else {
+            let (Some(parent), Some(name)) = (rewrite.parent(), rewrite.file_name()) else {
+                return Err(eyre!("{rule}: can't copy back to {}", rewrite.display()));
+            };
+            (parent, Some(name.to_os_string()))
+        };
+
+        Ok(Self {
+            rule: rule.to_string(),
+            target,
+            dir: File::open(dir)?,
+            file_name,
+        })
+    }
+
+    fn rewrite(&self) -> PathBuf {
+        let dir = PathBuf::from(format!("/proc/self/fd/{}", self.dir.as_raw_fd()));
+        match &self.file_name {
+            Some(name) => dir.join(name),
+            None => dir,
+        }
+    }
+
+    /// Copy the target's contents back to the rewrite.
+    pub fn sync(&self) -> Result<()> {
+        let rewrite = self.rewrite();
+        if self.file_name.is_none() {
+            return mirror(&self.target, &rewrite);
+        }
+        if !self.target.is_file() {
+            warn!(
+                "{}: {} is gone, leaving the rewrite as it was",
+                self.rule,
+                self.target.display()
+            );
+            return Ok(());
+        }
+        // Copy next to the rewrite first, so that it's never half-written.
+        let mut partial = rewrite.clone().into_os_string();
+        partial.push(".boxxy-partial");
+        fs::copy(&self.target, &partial)?;
+        fs::rename(&partial, &rewrite)?;
+        Ok(())
+    }
+}
+
+/// Make the directory `to` hold the same files as `from`, removing anything
+/// that isn't in `from`. Permissions are kept, ownership and times aren't.
+pub fn mirror(from: &Path, to: &Path) -> Result<()> {
+    let mut names = HashSet::new();
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let dest = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        names.insert(entry.file_name());
+
+        if let Ok(existing) = dest.symlink_metadata() {
+            if !(existing.is_dir() && file_type.is_dir()) {
+                remove(&dest)?;
+            }
+        }
+        if file_type.is_dir() {
+            if !dest.exists() {
+                fs::create_dir(&dest)?;
+            }
+            fs::set_permissions(&dest, entry.metadata()?.permissions())?;
+            mirror(&source, &dest)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&source)?, &dest)?;
+        } else {
+            fs::copy(&source, &dest)?;
+        }
+    }
+
+    for entry in fs::read_dir(to)? {
+        let entry = entry?;
+        if !names.contains(&entry.file_name()) {
+            remove(&entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_copy_back() -> Result<()> {
+        let root = Scratch::new("copy")?;
+        let rewrite = root.join("rewrite");
+        let target = root.join("target");
+        fs::create_dir_all(rewrite.join("sub"))?;
+        fs::create_dir_all(&target)?;
+        fs::write(rewrite.join("config"), "old")?;
+        fs::write(rewrite.join("sub/stale"), "")?;
+        std::os::unix::fs::symlink("config", rewrite.join("link"))?;
+
+        mirror(&rewrite, &target)?;
+        assert_eq!(fs::read_to_string(target.join("config"))?, "old");
+        assert_eq!(fs::read_link(target.join("link"))?, Path::new("config"));
+        assert!(target.join("sub/stale").exists());
+
+        fs::write(target.join("config"), "new")?;
+        fs::remove_dir_all(target.join("sub"))?;
+        fs::write(target.join("sub"), "now a file")?;
+        CopyBack::new("dir", target.clone(), &rewrite, true)?.sync()?;
+        assert_eq!(fs::read_to_string(rewrite.join("config"))?, "new");
+        assert_eq!(fs::read_to_string(rewrite.join("sub"))?, "now a file");
+
+        fs::write(root.join("copied"), "copied")?;
+        CopyBack::new("file", root.join("copied"), &rewrite.join("config"), false)?.sync()?;
+        assert_eq!(fs::read_to_string(rewrite.join("config"))?, "copied");
+        assert!(!rewrite.join("config.boxxy-partial").exists());
+
+        Ok(())
+    }
+}
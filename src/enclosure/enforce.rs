@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// What `--trace-enforce` does to a process that makes a forbidden syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnforceAction {
+    /// Skip the syscall and make it fail with `EPERM`.
+    Eperm,
+    /// Kill the process before the syscall runs.
+    Kill,
+}
+
+impl FromStr for EnforceAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eperm" => Ok(Self::Eperm),
+            "kill" => Ok(Self::Kill),
+            _ => Err(format!("unknown action {s}, expected eperm or kill")),
+        }
+    }
+}
+
+/// Syscalls that traced processes aren't allowed to make, by number.
+#[derive(Debug, Clone)]
+pub struct Enforcement {
+    syscalls: HashMap<u64, &'static str>,
+    pub action: EnforceAction,
+}
+
+impl Enforcement {
+    pub fn new(syscalls: &[String], action: EnforceAction) -> Result<Self, String> {
+        let syscalls = syscalls
+            .iter()
+            .map(|name| {
+                let number =
+                    syscall_number(name).ok_or_else(|| format!("unknown syscall {name}"))?;
+                Ok((number, syscall_name(number)))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { syscalls, action })
+    }
+
+    /// The name of syscall `number`, if it's forbidden.
+    pub fn forbids(&self, number: u64) -> Option<&'static str> {
+        self.syscalls.get(&number).copied()
+    }
+}
+
+/// Check that `name` is a syscall on this architecture, for clap.
+pub fn parse_syscall(name: &str) -> Result<String, String> {
+    syscall_number(name)
+        .map(|_| name.to_string())
+        .ok_or_else(|| format!("unknown syscall {name}"))
+}
+
+fn syscall_number(name: &str) -> Option<u64> {
+    (0..1024)
+        .find(|number| syscall_numbers::native::sys_call_name(*number) == Some(name))
+        .map(|number| number as u64)
+}
+
+fn syscall_name(number: u64) -> &'static str {
+    syscall_numbers::native::sys_call_name(number as i64).unwrap_or("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enforcement() {
+        let enforcement = Enforcement::new(
+            &["mount".to_string(), "ptrace".to_string()],
+            EnforceAction::Eperm,
+        )
+        .unwrap();
+        assert_eq!(enforcement.forbids(libc::SYS_mount as u64), Some("mount"));
+        assert_eq!(enforcement.forbids(libc::SYS_ptrace as u64), Some("ptrace"));
+        assert_eq!(enforcement.forbids(libc::SYS_openat as u64), None);
+
+        assert!(parse_syscall("connect").is_ok());
+        assert!(parse_syscall("not_a_syscall").is_err());
+        assert!(Enforcement::new(&["nope".to_string()], EnforceAction::Kill).is_err());
+        assert_eq!("kill".parse(), Ok(EnforceAction::Kill));
+        assert!("maybe".parse::<EnforceAction>().is_err());
+    }
+}
@@ -1,36 +1,176 @@
 use std::fs::{self, OpenOptions};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use color_eyre::Result;
 use log::*;
 use nix::mount::{mount, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{access, AccessFlags, User};
 
 pub struct FsDriver;
 
-#[allow(unused)]
+/// The directory configured with `containers_root`, tried before the
+/// defaults.
+static CONFIGURED_CONTAINERS_ROOT: OnceLock<PathBuf> = OnceLock::new();
+/// Where container roots go, chosen the first time one is needed.
+static CONTAINERS_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// The start of the source that boxxy's tmpfs mounts show in
+/// `/proc/self/mountinfo` when they're tagged, followed by the box's name.
+pub const MOUNT_SOURCE_PREFIX: &str = "boxxy-";
+/// The source boxxy's tmpfs mounts are made with, if they're tagged.
+static MOUNT_SOURCE: OnceLock<String> = OnceLock::new();
+
+/// How much space a directory needs free to hold container roots, since
+/// private `TMPDIR`s and extracted AppImages go there too.
+const MIN_CONTAINERS_ROOT_FREE: u64 = 64 * 1024 * 1024;
+
+/// Try `path` for container roots before the defaults. Only has an effect
+/// before the first container root is needed.
+pub fn configure_containers_root(path: PathBuf) {
+    let _ = CONFIGURED_CONTAINERS_ROOT.set(path);
+}
+
+/// Make the tmpfs mounts for the box `name` with a source that says boxxy
+/// made them, instead of `tmpfs`. Bind mounts show the filesystem they're
+/// from, which can't be changed.
+pub fn tag_mounts(name: &str) {
+    let _ = MOUNT_SOURCE.set(format!("{MOUNT_SOURCE_PREFIX}{name}"));
+}
+
+/// The first of `candidates` that can hold container roots, or the first
+/// one if none of them can.
+fn pick_containers_root(candidates: &[PathBuf], min_free: u64) -> PathBuf {
+    for candidate in candidates {
+        match unsuitable_containers_root(candidate, min_free) {
+            None => {
+                debug!("putting container roots in {}", candidate.display());
+                return candidate.clone();
+            }
+            Some(reason) => warn!(
+                "not putting container roots in {}: {reason}",
+                candidate.display()
+            ),
+        }
+    }
+    warn!(
+        "no good place for container roots, using {} anyway",
+        candidates[0].display()
+    );
+    candidates[0].clone()
+}
+
+/// Why `path` can't hold container roots: it, or the directory it would be
+/// created in, is read-only, noexec, or short on space.
+fn unsuitable_containers_root(path: &Path, min_free: u64) -> Option<String> {
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let stat = match statvfs(existing) {
+        Ok(stat) => stat,
+        Err(err) => return Some(format!("could not stat {}: {err}", existing.display())),
+    };
+    if stat.flags().contains(FsFlags::ST_RDONLY) {
+        return Some("it's on a read-only filesystem".to_string());
+    }
+    if stat.flags().contains(FsFlags::ST_NOEXEC) {
+        return Some("it's on a filesystem mounted noexec".to_string());
+    }
+    if access(existing, AccessFlags::W_OK).is_err() {
+        return Some(format!("{} isn't writable", existing.display()));
+    }
+    let free = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    if free < min_free {
+        return Some(format!("only {} MiB is free", free / 1024 / 1024));
+    }
+    None
+}
+
 impl FsDriver {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {}
     }
 
+    /// The directory that container roots go in: the configured
+    /// `containers_root`, `/tmp/boxxy-containers`, or
+    /// `$XDG_RUNTIME_DIR/boxxy/containers`, whichever is the first that's
+    /// writable, allows exec, and has space.
     pub fn all_containers_root(&self) -> PathBuf {
-        PathBuf::from("/tmp/boxxy-containers")
+        CONTAINERS_ROOT
+            .get_or_init(|| {
+                let mut candidates: Vec<PathBuf> = CONFIGURED_CONTAINERS_ROOT
+                    .get()
+                    .cloned()
+                    .into_iter()
+                    .collect();
+                candidates.push(PathBuf::from("/tmp/boxxy-containers"));
+                if let Some(runtime_dir) = dirs::runtime_dir() {
+                    candidates.push(append_all(&runtime_dir, vec!["boxxy", "containers"]));
+                }
+                pick_containers_root(&candidates, MIN_CONTAINERS_ROOT_FREE)
+            })
+            .clone()
     }
 
     pub fn container_root(&self, name: &str) -> PathBuf {
         append_all(&self.all_containers_root(), vec![name])
     }
 
+    /// Mountpoint for the scratch tmpfs that files used by rules (ex. fault
+    /// injection) are created in.
+    pub fn staging_root(&self, name: &str) -> PathBuf {
+        append_all(&self.all_containers_root(), vec![format!("{name}.staging")])
+    }
+
+    /// Where the container's temporary directories go, each in its own
+    /// [`FsDriver::scratch_dir`].
+    pub fn scratch_root(&self, name: &str) -> PathBuf {
+        append_all(&self.all_containers_root(), vec![format!("{name}.tmp")])
+    }
+
+    /// A directory under [`FsDriver::scratch_root`] that only `purpose` uses,
+    /// ex. the private `TMPDIR` for `--private-tmpdir`, or the one that
+    /// AppImages mount or extract themselves into.
+    pub fn scratch_dir(&self, name: &str, purpose: &str) -> Result<PathBuf> {
+        let dir = append_all(&self.scratch_root(name), vec![purpose]);
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        Ok(dir)
+    }
+
     pub fn setup_root(&self, name: &str) -> Result<()> {
         debug!("setting up root for {}", name);
         fs::create_dir_all(self.container_root(name))?;
+        fs::create_dir_all(self.staging_root(name))?;
+        fs::create_dir_all(self.scratch_root(name))?;
         Ok(())
     }
 
+    /// Mount a tmpfs over the directory that container roots go in, so that
+    /// this container's root, staging, and scratch directories never touch
+    /// the disk. Must be called from the container's own mount namespace,
+    /// before [`FsDriver::setup_root`]; other boxes' roots are hidden from it,
+    /// but not from anywhere else.
+    pub fn mount_root_tmpfs(&self) -> Result<()> {
+        let root = self.all_containers_root();
+        debug!("mounting a tmpfs over {}", root.display());
+        fs::create_dir_all(&root)?;
+        self.mount_tmpfs(&root, "mode=0700")
+    }
+
+    /// Remove the container's directories. With `--root-tmpfs`, they're only
+    /// in the container's mount namespace, so they aren't there to remove.
     pub fn cleanup_root(&self, name: &str) -> Result<()> {
         debug!("cleaning up root for {}", name);
-        fs::remove_dir_all(self.container_root(name))?;
+        for root in [
+            self.container_root(name),
+            self.staging_root(name),
+            self.scratch_root(name),
+        ] {
+            if root.exists() {
+                fs::remove_dir_all(root)?;
+            }
+        }
         Ok(())
     }
 
@@ -55,6 +195,32 @@ impl FsDriver {
         Ok(())
     }
 
+    /// Remount the bind mount at `target` with `flags` as well as the ones it
+    /// already has, which can't be dropped inside a user namespace.
+    pub fn remount_with(&self, target: &Path, flags: MsFlags) -> Result<()> {
+        debug!("remount {target:?} with {flags:?}");
+        let existing = statvfs(target)?.flags();
+        let mut kept = MsFlags::empty();
+        for (statvfs_flag, ms_flag) in [
+            (FsFlags::ST_RDONLY, MsFlags::MS_RDONLY),
+            (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+            (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+            (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        ] {
+            if existing.contains(statvfs_flag) {
+                kept |= ms_flag;
+            }
+        }
+        mount::<Path, Path, str, str>(
+            None,
+            target,
+            Some(""),
+            MsFlags::MS_REMOUNT | MsFlags::MS_BIND | kept | flags,
+            Some(""),
+        )?;
+        Ok(())
+    }
+
     pub fn bind_mount_rw(&self, src: &Path, target: &Path) -> Result<()> {
         debug!("bind mount {src:?} onto {target:?} as rw");
         self.bind_mount(src, target, MsFlags::MS_BIND)
@@ -72,9 +238,25 @@ impl FsDriver {
         Ok(())
     }
 
+    pub fn mount_tmpfs(&self, target: &Path, options: &str) -> Result<()> {
+        self.mount_tmpfs_with(target, options, MsFlags::empty())
+    }
+
+    pub fn mount_tmpfs_with(&self, target: &Path, options: &str, flags: MsFlags) -> Result<()> {
+        debug!("mount tmpfs onto {target:?} with options {options:?} and {flags:?}");
+        let source = MOUNT_SOURCE.get().map(String::as_str).unwrap_or("tmpfs");
+        mount(Some(source), target, Some("tmpfs"), flags, Some(options))?;
+        Ok(())
+    }
+
     pub fn touch(&self, path: &Path) -> Result<()> {
         debug!("touching {path:?}");
-        match OpenOptions::new().create(true).write(true).open(path) {
+        match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+        {
             Ok(_) => Ok(()),
             Err(e) => Err(e.into()),
         }
@@ -88,8 +270,28 @@ impl FsDriver {
         }
     }
 
-    pub fn fully_expand_path(&self, path: &String) -> Result<PathBuf> {
-        let expanded = shellexpand::tilde(&path).to_string();
+    /// Copy every extended attribute of `from` onto `to`, including POSIX
+    /// ACLs, which are stored as `system.posix_acl_*` attributes. Attributes
+    /// that can't be copied, ex. `security.*` ones without the privileges to
+    /// set them, are skipped with a warning. Returns how many were copied.
+    pub fn copy_xattrs(&self, from: &Path, to: &Path) -> Result<usize> {
+        let mut copied = 0;
+        for name in xattr::list(from)? {
+            let Some(value) = xattr::get(from, &name)? else {
+                continue;
+            };
+            match xattr::set(to, &name, &value) {
+                Ok(_) => copied += 1,
+                Err(err) => warn!("could not copy xattr {name:?} onto {to:?}: {err}"),
+            }
+        }
+        debug!("copied {copied} xattrs from {from:?} to {to:?}");
+
+        Ok(copied)
+    }
+
+    pub fn fully_expand_path(&self, path: &str) -> Result<PathBuf> {
+        let expanded = expand_tilde(path);
         match Path::new(&expanded).canonicalize() {
             Ok(path) => match self.maybe_resolve_symlink(&path) {
                 Ok(path) => match path.canonicalize() {
@@ -119,7 +321,13 @@ impl FsDriver {
         }
 
         let path = if path.is_symlink() {
-            path.read_link()?.canonicalize()?
+            // Relative links are relative to the link's directory.
+            let link = path.read_link()?;
+            match path.parent() {
+                Some(parent) => parent.join(link),
+                None => link,
+            }
+            .canonicalize()?
         } else {
             path.to_path_buf()
         };
@@ -132,6 +340,22 @@ impl FsDriver {
     }
 }
 
+/// `path` with a leading `~` replaced by your home directory, and a leading
+/// `~user` by that user's. Users that don't exist are left alone.
+pub fn expand_tilde(path: &str) -> String {
+    let Some(rest) = path.strip_prefix('~') else {
+        return path.to_string();
+    };
+    let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if user.is_empty() {
+        return shellexpand::tilde(path).to_string();
+    }
+    match User::from_name(user) {
+        Ok(Some(user)) => format!("{}{rest}", user.dir.display()),
+        _ => path.to_string(),
+    }
+}
+
 pub fn append_all<P: AsRef<Path>>(buf: &Path, parts: Vec<P>) -> PathBuf {
     let mut buf = buf.to_path_buf();
     for part in parts {
@@ -147,9 +371,76 @@ pub fn append_all<P: AsRef<Path>>(buf: &Path, parts: Vec<P>) -> PathBuf {
     buf
 }
 
+/// Disk space used by `path` and everything under it, in bytes. Symlinks are
+/// not followed.
+pub fn disk_usage(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut total = metadata.blocks() * 512;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            total += disk_usage(&entry?.path())?;
+        }
+    }
+
+    Ok(total)
+}
+
+/// What's in a file or directory that boxxy created empty, if anything. A
+/// placeholder with data in it, ex. because a mount failed and the boxxed
+/// program wrote into the placeholder instead of the rewrite, must be kept.
+pub fn placeholder_contents(path: &Path) -> Result<Option<String>> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        let entries = fs::read_dir(path)?.count();
+        match entries {
+            0 => {}
+            1 => return Ok(Some("1 entry".to_string())),
+            entries => return Ok(Some(format!("{entries} entries"))),
+        }
+    } else if metadata.len() > 0 {
+        return Ok(Some(human_size(metadata.len())));
+    }
+
+    Ok(None)
+}
+
+/// Parse a size like `512k`, `8M`, or `1g` into bytes. Units are powers of
+/// 1024, and a bare number is in bytes.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last().map(|unit| unit.to_ascii_lowercase()) {
+        Some('k') => (&size[..size.len() - 1], 1024),
+        Some('m') => (&size[..size.len() - 1], 1024 * 1024),
+        Some('g') => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size: {size}, expected ex. `8m`"))
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[unit])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::Scratch;
 
     use color_eyre::Result;
 
@@ -161,6 +452,114 @@ mod tests {
         assert_eq!(append_all(&buf, parts), expected);
     }
 
+    #[test]
+    fn test_expand_tilde() {
+        let home = shellexpand::tilde("~").to_string();
+        assert_eq!(expand_tilde("~/.aws"), format!("{home}/.aws"));
+        let root = User::from_name("root").unwrap().unwrap().dir;
+        assert_eq!(expand_tilde("~root"), root.display().to_string());
+        assert_eq!(
+            expand_tilde("~root/.ssh"),
+            format!("{}/.ssh", root.display())
+        );
+        assert_eq!(expand_tilde("~nobody-at-all/x"), "~nobody-at-all/x");
+        assert_eq!(expand_tilde("/etc/~root"), "/etc/~root");
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512k"), Ok(512 * 1024));
+        assert_eq!(parse_size("8M"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("m").is_err());
+        assert!(parse_size("8x").is_err());
+        assert!(parse_size("99999999999g").is_err());
+    }
+
+    #[test]
+    fn test_placeholder_contents() -> Result<()> {
+        let dir = Scratch::new("placeholder")?;
+        fs::create_dir_all(dir.join("empty"))?;
+        fs::write(dir.join("empty-file"), "")?;
+        fs::write(dir.join("file"), "data")?;
+
+        assert_eq!(placeholder_contents(&dir.join("empty"))?, None);
+        assert_eq!(placeholder_contents(&dir.join("empty-file"))?, None);
+        assert_eq!(
+            placeholder_contents(&dir.join("file"))?.as_deref(),
+            Some("4 B")
+        );
+        assert_eq!(placeholder_contents(&dir)?.as_deref(), Some("3 entries"));
+        assert!(placeholder_contents(&dir.join("missing")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_xattrs() -> Result<()> {
+        let dir = Scratch::new("xattrs")?;
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::create_dir_all(&from)?;
+        fs::create_dir_all(&to)?;
+        if xattr::set(&from, "user.boxxy", b"yes").is_err() {
+            // Not every filesystem supports user xattrs.
+            return Ok(());
+        }
+
+        assert_eq!(FsDriver::new().copy_xattrs(&from, &to)?, 1);
+        assert_eq!(xattr::get(&to, "user.boxxy")?, Some(b"yes".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_maybe_resolve_symlink() -> Result<()> {
+        let dir = Scratch::new("resolve-symlink")?;
+        let real = dir.canonicalize()?.join("real/config");
+        fs::create_dir_all(&real)?;
+        fs::create_dir_all(dir.join("links"))?;
+        // Relative links are relative to the link's directory, not to the
+        // current directory.
+        std::os::unix::fs::symlink("../real/config", dir.join("links/relative"))?;
+        std::os::unix::fs::symlink(&real, dir.join("links/absolute"))?;
+        std::os::unix::fs::symlink("relative", dir.join("links/chained"))?;
+
+        let driver = FsDriver::new();
+        for link in ["relative", "absolute", "chained"] {
+            assert_eq!(
+                driver.maybe_resolve_symlink(&dir.join("links").join(link))?,
+                real
+            );
+        }
+        assert_eq!(driver.maybe_resolve_symlink(&real)?, real);
+        assert!(driver
+            .maybe_resolve_symlink(&dir.join("links/missing"))
+            .is_ok_and(|path| path == dir.join("links/missing")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pick_containers_root() {
+        let tmp = std::env::temp_dir();
+        let missing = tmp.join(format!("boxxy-containers-{}/a/b", std::process::id()));
+        assert_eq!(unsuitable_containers_root(&missing, 0), None);
+        assert!(unsuitable_containers_root(&tmp, u64::MAX)
+            .unwrap()
+            .ends_with("MiB is free"));
+
+        let candidates = [tmp.join("first"), tmp.join("second")];
+        assert_eq!(pick_containers_root(&candidates, 0), candidates[0]);
+        assert_eq!(pick_containers_root(&candidates, u64::MAX), candidates[0]);
+    }
+
     #[test]
     fn test_fs_driver_creates_and_destroys_roots() -> Result<()> {
         let driver = FsDriver::new();
@@ -174,6 +573,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_fs_driver_cleans_up_missing_roots() -> Result<()> {
+        // With --root-tmpfs, the roots are only in the container's mount
+        // namespace, so boxxy's own process never sees them.
+        let driver = FsDriver::new();
+        let name = "test-cleanup-missing-root";
+        assert!(!driver.container_root(name).exists());
+        driver.cleanup_root(name)?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_fs_driver_touches_files() -> Result<()> {
         let driver = FsDriver::new();
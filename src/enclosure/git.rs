@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+/// The root of the git repo that `dir` is in, if it's in one.
+pub fn repo_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// The URLs of the remotes of the repo at `root`, read from its config.
+/// Worktrees and submodules, whose `.git` is a file pointing elsewhere, are
+/// followed to the config they share.
+pub fn remotes(root: &Path) -> Vec<String> {
+    let Some(git_dir) = git_dir(root) else {
+        return vec![];
+    };
+    let common_dir = std::fs::read_to_string(git_dir.join("commondir"))
+        .map(|common| git_dir.join(common.trim()))
+        .unwrap_or(git_dir);
+    std::fs::read_to_string(common_dir.join("config"))
+        .map(|config| remote_urls(&config))
+        .unwrap_or_default()
+}
+
+fn git_dir(root: &Path) -> Option<PathBuf> {
+    let dot_git = root.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let contents = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = contents.trim().strip_prefix("gitdir:")?.trim();
+    Some(root.join(git_dir))
+}
+
+/// The `url`s of the `[remote "..."]` sections of a git config.
+fn remote_urls(config: &str) -> Vec<String> {
+    let mut urls = vec![];
+    let mut in_remote = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_remote = line.starts_with("[remote ");
+            continue;
+        }
+        if !in_remote {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim() == "url" {
+                urls.push(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_remotes() -> std::io::Result<()> {
+        let root = Scratch::new("git")?;
+        std::fs::create_dir_all(root.join("repo/.git/worktrees/feature"))?;
+        std::fs::create_dir_all(root.join("repo/src"))?;
+        std::fs::create_dir_all(root.join("feature"))?;
+        std::fs::write(
+            root.join("repo/.git/config"),
+            r#"[core]
+	bare = false
+[remote "origin"]
+	url = git@github.com:queer/boxxy.git
+	fetch = +refs/heads/*:refs/remotes/origin/*
+[branch "mistress"]
+	remote = origin
+[remote "fork"]
+	url = "https://example.com/me/boxxy.git"
+"#,
+        )?;
+        std::fs::write(
+            root.join("repo/.git/worktrees/feature/commondir"),
+            "../..\n",
+        )?;
+        std::fs::write(
+            root.join("feature/.git"),
+            format!(
+                "gitdir: {}\n",
+                root.join("repo/.git/worktrees/feature").display()
+            ),
+        )?;
+
+        assert_eq!(repo_root(&root.join("repo/src")), Some(root.join("repo")));
+        let urls = vec![
+            "git@github.com:queer/boxxy.git".to_string(),
+            "https://example.com/me/boxxy.git".to_string(),
+        ];
+        assert_eq!(remotes(&root.join("repo")), urls);
+        assert_eq!(remotes(&root.join("feature")), urls);
+        assert!(remotes(&root).is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::process::Command;
+use std::sync::{Arc, Once};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+
+use super::fs::FsDriver;
+use super::rule::Rule;
+
+/// A rule's `before` or `after` command, run on the host with `sh -c`.
+/// The rule's expanded target and rewrite are in `BOXXY_TARGET` and
+/// `BOXXY_REWRITE`, and the box's name in `BOXXY_CONTAINER_NAME`.
+fn command(rule: &Rule, line: &str, name: &str, fs: &FsDriver) -> Result<Command> {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(line)
+        .env("BOXXY_CONTAINER_NAME", name)
+        .env("BOXXY_RULE", &rule.name)
+        .env("BOXXY_TARGET", fs.fully_expand_path(&rule.target)?);
+    if rule.has_rewrite() {
+        command.env("BOXXY_REWRITE", fs.fully_expand_path(&rule.rewrite)?);
+    }
+    Ok(command)
+}
+
+/// Run the `before` commands of `rules`, in order, stopping at the first
+/// that fails. If one fails, the `after` commands of the rules before it
+/// are run, since the box won't be.
+pub fn run_before(rules: &[Rule], name: &str, fs: &FsDriver) -> Result<()> {
+    let _span = tracing::info_span!("before hooks").entered();
+    for (i, rule) in rules.iter().enumerate() {
+        for line in &rule.before {
+            debug!("{}: before: {line}", rule.name);
+            let status = command(rule, line, name, fs).and_then(|mut command| {
+                command
+                    .status()
+                    .map_err(|err| eyre!("could not run it: {err}"))
+            });
+            let failure = match status {
+                Ok(status) if status.success() => continue,
+                Ok(status) => status.to_string(),
+                Err(err) => err.to_string(),
+            };
+            run_after(&rules[..i], name, fs);
+            return Err(eyre!(
+                "{}: `before` command `{line}` failed ({failure}), not running the command",
+                rule.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Run the `after` commands of `rules`, from the last rule to the first, so
+/// that they undo their `before` commands in the opposite order. Failures
+/// are only warned about, so that every rule gets to clean up.
+pub fn run_after(rules: &[Rule], name: &str, fs: &FsDriver) {
+    for rule in rules.iter().rev() {
+        for line in &rule.after {
+            debug!("{}: after: {line}", rule.name);
+            match command(rule, line, name, fs).and_then(|mut command| Ok(command.status()?)) {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("{}: `after` command `{line}` failed ({status})", rule.name),
+                Err(err) => warn!(
+                    "{}: could not run `after` command `{line}`: {err}",
+                    rule.name
+                ),
+            }
+        }
+    }
+}
+
+/// The `after` commands of a box's rules, run once however the box ends:
+/// when [`After::run`] is called, ex. before exiting or from the ^C
+/// handler, or when it's dropped, ex. on an early return. A second caller
+/// waits for the first to finish, so that boxxy doesn't exit while they're
+/// running.
+pub struct After {
+    inner: Arc<AfterInner>,
+    /// Whether dropping this handle runs the commands. Only the handle from
+    /// [`After::new`] does, since a handle moved into the ^C handler is
+    /// never dropped.
+    guard: bool,
+}
+
+struct AfterInner {
+    rules: Vec<Rule>,
+    name: String,
+    once: Once,
+}
+
+impl After {
+    pub fn new(rules: &[Rule], name: &str) -> Self {
+        Self {
+            inner: Arc::new(AfterInner {
+                rules: rules.to_vec(),
+                name: name.to_string(),
+                once: Once::new(),
+            }),
+            guard: true,
+        }
+    }
+
+    /// Another handle to the same commands, that doesn't run them when it's
+    /// dropped.
+    pub fn handle(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            guard: false,
+        }
+    }
+
+    pub fn run(&self) {
+        let inner = &self.inner;
+        inner
+            .once
+            .call_once(|| run_after(&inner.rules, &inner.name, &FsDriver::new()));
+    }
+}
+
+impl Drop for After {
+    fn drop(&mut self) {
+        if self.guard {
+            self.run();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_hooks() -> Result<()> {
+        let dir = Scratch::new("hooks")?;
+        std::fs::create_dir_all(dir.join("rewrite"))?;
+        let log = dir.join("log");
+        let append = |what: &str| format!("echo {what} $BOXXY_RULE >> {}", log.display());
+        let rule = |name: &str| {
+            Rule::builder(
+                name,
+                dir.join("target").to_string_lossy(),
+                dir.join("rewrite").to_string_lossy(),
+            )
+            .with_before(append("before"))
+            .with_before(format!(
+                "test \"$BOXXY_REWRITE\" = \"$(realpath {}/rewrite)\"",
+                dir.display()
+            ))
+            .with_after(append("after"))
+            .build()
+        };
+        let fs = FsDriver::new();
+        let rules = [rule("a"), rule("b")];
+
+        run_before(&rules, "box", &fs)?;
+        run_after(&rules, "box", &fs);
+        assert_eq!(
+            std::fs::read_to_string(&log)?,
+            "before a\nbefore b\nafter b\nafter a\n"
+        );
+
+        // Rules that were set up are cleaned up if a later one fails.
+        std::fs::remove_file(&log)?;
+        let failing = Rule::builder("c", "~/.c", "~/c")
+            .with_before("false")
+            .with_before(append("never"))
+            .with_after(append("never"))
+            .build();
+        assert!(run_before(&[rule("a"), failing], "box", &fs).is_err());
+        assert_eq!(std::fs::read_to_string(&log)?, "before a\nafter a\n");
+
+        // `after` commands run once, whether they're run by hand, from
+        // another handle, or when the last handle goes away.
+        std::fs::remove_file(&log)?;
+        let after = After::new(&[rule("a")], "box");
+        let handler = after.handle();
+        after.run();
+        handler.run();
+        drop(after);
+        assert_eq!(std::fs::read_to_string(&log)?, "after a\n");
+        std::fs::remove_file(&log)?;
+        let after = After::new(&[rule("a")], "box");
+        drop(after.handle());
+        assert!(!log.exists());
+        drop(after);
+        assert_eq!(std::fs::read_to_string(&log)?, "after a\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::str::FromStr;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use nix::sys::stat::{umask, Mode};
+use rlimit::Resource;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A resource limit: a number, or `unlimited`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Limit {
+    Value(u64),
+    Unlimited(Unlimited),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unlimited {
+    Unlimited,
+}
+
+impl Limit {
+    fn get(self) -> u64 {
+        match self {
+            Limit::Value(value) => value,
+            Limit::Unlimited(_) => rlimit::INFINITY,
+        }
+    }
+}
+
+/// Resource limits for the boxxed program, ex. to reproduce production's
+/// limits locally. Each one sets both the soft and the hard limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rlimits {
+    /// The most file descriptors that can be open at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nofile: Option<Limit>,
+    /// The most processes the user can have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nproc: Option<Limit>,
+    /// The largest core dump, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub core: Option<Limit>,
+    /// The largest file that can be written, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fsize: Option<Limit>,
+}
+
+impl Rlimits {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Take each limit from `other` if it sets it.
+    pub fn merge(&mut self, other: Rlimits) {
+        self.nofile = other.nofile.or(self.nofile);
+        self.nproc = other.nproc.or(self.nproc);
+        self.core = other.core.or(self.core);
+        self.fsize = other.fsize.or(self.fsize);
+    }
+
+    /// Set the limits for this process, and so for everything it runs.
+    pub fn apply(&self) -> Result<()> {
+        for (name, resource, limit) in [
+            ("nofile", Resource::NOFILE, self.nofile),
+            ("nproc", Resource::NPROC, self.nproc),
+            ("core", Resource::CORE, self.core),
+            ("fsize", Resource::FSIZE, self.fsize),
+        ] {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let limit = limit.get();
+            resource.set(limit, limit).map_err(|err| {
+                let hard = resource.get().map(|(_, hard)| hard).unwrap_or_default();
+                eyre!("could not set rlimit {name} to {limit} (the hard limit is {hard}): {err}")
+            })?;
+            debug!("set rlimit {name} to {limit}");
+        }
+
+        Ok(())
+    }
+}
+
+/// A file mode creation mask, in octal, ex. `"077"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Umask(pub u32);
+
+impl FromStr for Umask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0o").unwrap_or(s);
+        match u32::from_str_radix(digits, 8) {
+            Ok(mask) if mask <= 0o777 => Ok(Umask(mask)),
+            _ => Err(format!("invalid umask: {s}, expected octal, ex. `077`")),
+        }
+    }
+}
+
+impl Serialize for Umask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:03o}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_octal(deserializer, "umask", "077")
+    }
+}
+
+/// Deserialize the mode called `what`, written in octal as a string, ex.
+/// `example`. YAML reads unquoted `0o077` as 63 and `77` as 77, which can't
+/// be told apart from what was meant, so numbers are an error.
+pub(super) fn deserialize_octal<'de, D, T>(
+    deserializer: D,
+    what: &str,
+    example: &str,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Written {
+        Text(String),
+        Other(serde::de::IgnoredAny),
+    }
+
+    match Written::deserialize(deserializer)? {
+        Written::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        Written::Other(_) => Err(serde::de::Error::custom(format!(
+            "the {what} must be quoted, ex. '{example}', or YAML doesn't read it as octal"
+        ))),
+    }
+}
+
+impl Umask {
+    /// The mask that's at least as tight as all of `masks`.
+    pub fn tightest(masks: impl IntoIterator<Item = Umask>) -> Option<Umask> {
+        masks
+            .into_iter()
+            .reduce(|tightest, mask| Umask(tightest.0 | mask.0))
+    }
+
+    /// Set the umask for this process, and so for everything it runs.
+    pub fn apply(self) {
+        umask(Mode::from_bits_truncate(self.0));
+        debug!("set umask to {:03o}", self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rlimits() -> Result<()> {
+        let mut rlimits: Rlimits = serde_yaml::from_str("{nofile: 1024, core: unlimited}")?;
+        assert_eq!(rlimits.nofile, Some(Limit::Value(1024)));
+        assert_eq!(rlimits.core.map(Limit::get), Some(rlimit::INFINITY));
+        assert!(serde_yaml::from_str::<Rlimits>("{nofile: lots}").is_err());
+        assert!(serde_yaml::from_str::<Rlimits>("{stack: 1}").is_err());
+
+        rlimits.merge(serde_yaml::from_str("{nofile: 64, fsize: 1}")?);
+        assert_eq!(rlimits.nofile, Some(Limit::Value(64)));
+        assert_eq!(rlimits.fsize, Some(Limit::Value(1)));
+        assert!(rlimits.core.is_some());
+        assert!(Rlimits::default().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_umask() -> Result<()> {
+        assert_eq!(serde_yaml::from_str::<Umask>("\"077\"")?, Umask(0o77));
+        // YAML leaves numbers with a leading zero as strings.
+        assert_eq!(serde_yaml::from_str::<Umask>("077")?, Umask(0o77));
+        assert_eq!(serde_yaml::from_str::<Umask>("\"0o027\"")?, Umask(0o27));
+        assert!(serde_yaml::from_str::<Umask>("\"089\"").is_err());
+        assert!(serde_yaml::from_str::<Umask>("\"1777\"").is_err());
+        // Unquoted, these are numbers, and not the octal they look like, ex.
+        // 0o077 is 63.
+        for unquoted in ["0o077", "0x3f", "77"] {
+            let err = serde_yaml::from_str::<Umask>(unquoted).unwrap_err();
+            assert!(err.to_string().contains("must be quoted"), "{err}");
+        }
+        assert_eq!(serde_yaml::to_string(&Umask(0o22))?, "'022'\n");
+        assert_eq!(
+            Umask::tightest([Umask(0o22), Umask(0o77), Umask(0o7)]),
+            Some(Umask(0o77))
+        );
+        assert_eq!(Umask::tightest([]), None);
+        Ok(())
+    }
+}
@@ -8,6 +8,13 @@ use regex::Regex;
 
 pub fn map_uids<I: Into<i32>>(pid: I, uids: &mut HashMap<Uid, Uid>) -> Result<()> {
     let pid = pid.into();
+    if uids.len() <= 1 || which::which("newuidmap").is_err() {
+        // A single mapping of our own uid doesn't need the setuid helper.
+        let uid = nix::unistd::geteuid();
+        debug!("mapping uid {uid} without newuidmap");
+        return write_single_mapping(pid, "uid_map", uid.as_raw());
+    }
+
     let mut args = vec![pid.to_string()];
     for (old_uid, new_uid) in uids.iter() {
         args.push(old_uid.to_string());
@@ -36,6 +43,18 @@ pub fn map_uids<I: Into<i32>>(pid: I, uids: &mut HashMap<Uid, Uid>) -> Result<()
 
 pub fn map_gids<I: Into<i32>>(pid: I, gids: &mut HashMap<Gid, Gid>) -> Result<()> {
     let pid = pid.into();
+    if gids.len() <= 1 || which::which("newgidmap").is_err() {
+        let gid = nix::unistd::getegid();
+        if gids.len() > 1 {
+            warn!("newgidmap not found, only mapping primary group {gid} into the container");
+        }
+        debug!("mapping gid {gid} without newgidmap");
+        // Unprivileged gid maps are only accepted once setgroups(2) has been
+        // disabled for the namespace.
+        std::fs::write(format!("/proc/{pid}/setgroups"), "deny")?;
+        return write_single_mapping(pid, "gid_map", gid.as_raw());
+    }
+
     let mut args = vec![pid.to_string()];
     for (old_gid, new_gid) in gids.iter() {
         args.push(old_gid.to_string());
@@ -62,6 +81,16 @@ pub fn map_gids<I: Into<i32>>(pid: I, gids: &mut HashMap<Gid, Gid>) -> Result<()
     Ok(())
 }
 
+/// Write a one-to-one mapping of `id` directly to `/proc/<pid>/<map_file>`.
+/// The kernel allows this without privileges as long as the only id mapped
+/// is the caller's own, which means static builds don't need `shadow`'s
+/// setuid helpers to be installed.
+fn write_single_mapping(pid: i32, map_file: &str, id: u32) -> Result<()> {
+    std::fs::write(format!("/proc/{pid}/{map_file}"), format!("{id} {id} 1\n"))?;
+    debug!("wrote {map_file} for {pid}: {id} => {id}");
+    Ok(())
+}
+
 fn check_mapping_regex(regex: &str, stderr: &str) -> Result<Option<u32>> {
     let regex = Regex::new(regex)?;
     let bad_id = regex.captures(stderr);
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use log::*;
+
+/// Keeps hot paths from flooding the log. The first few lines of each kind
+/// are logged as usual, the rest are only counted, and the counts are logged
+/// by `summarise`.
+pub(crate) struct LogSampler {
+    level: Level,
+    /// Checked once up front, since samplers sit on paths that run for every
+    /// syscall.
+    enabled: bool,
+    limit: usize,
+    counts: BTreeMap<String, usize>,
+}
+
+impl LogSampler {
+    pub fn new(level: Level, limit: usize) -> Self {
+        Self {
+            level,
+            enabled: log_enabled!(level),
+            limit,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Count a line of `kind`, and return whether it should be logged. Does
+    /// nothing when the sampler's level is disabled.
+    pub fn sample(&mut self, kind: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let count = self.bump(kind);
+        if count == self.limit + 1 {
+            log!(
+                self.level,
+                "{kind}: not logging any more of these, see the summary at the end"
+            );
+        }
+
+        count <= self.limit
+    }
+
+    /// Count a line of `kind` without ever logging it.
+    pub fn count(&mut self, kind: &str) {
+        if !self.enabled {
+            return;
+        }
+        self.bump(kind);
+    }
+
+    fn bump(&mut self, kind: &str) -> usize {
+        if let Some(count) = self.counts.get_mut(kind) {
+            *count += 1;
+            return *count;
+        }
+        self.counts.insert(kind.to_string(), 1);
+        1
+    }
+
+    /// Log how many lines of each kind were seen.
+    pub fn summarise(&mut self, what: &str) {
+        if self.counts.is_empty() {
+            return;
+        }
+
+        log!(self.level, "{what}:");
+        for (kind, count) in std::mem::take(&mut self.counts) {
+            log!(self.level, "  {kind}: {count}");
+        }
+    }
+
+    #[cfg(test)]
+    fn counts(&self) -> &BTreeMap<String, usize> {
+        &self.counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_stops_after_limit() {
+        let mut sampler = LogSampler::new(Level::Trace, 2);
+        sampler.enabled = true;
+        let logged = (0..5)
+            .map(|_| sampler.sample("process stopped"))
+            .collect::<Vec<_>>();
+        sampler.count("syscall openat");
+        sampler.count("syscall openat");
+
+        assert_eq!(logged, vec![true, true, false, false, false]);
+        assert_eq!(sampler.counts()["process stopped"], 5);
+        assert_eq!(sampler.counts()["syscall openat"], 2);
+
+        sampler.summarise("test");
+        assert!(sampler.counts().is_empty());
+    }
+}
@@ -1,10 +1,12 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::CString;
 use std::fs::{read_to_string, File};
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
-use std::process::exit;
-use std::sync::mpsc::channel;
+use std::process::{exit, Command};
 use std::thread;
 use std::time::Duration;
 
@@ -14,27 +16,54 @@ use dotenv_parser::parse_dotenv;
 use haikunator::Haikunator;
 use log::*;
 use nix::errno::Errno;
-use nix::mount::{umount2, MntFlags};
+use nix::mount::{umount2, MntFlags, MsFlags};
 use nix::sched::{clone, CloneFlags};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::sys::{ptrace, signal};
-use nix::unistd::{chdir, chroot, getgrouplist, getpid, pivot_root, Gid, Pid, User};
+use nix::unistd::{chdir, chroot, getgrouplist, getpid, getuid, pivot_root, Gid, Pid, User};
 use owo_colors::colors::xterm::PinkSalmon;
 use owo_colors::OwoColorize;
 use rlimit::Resource;
 
 use crate::config::BoxxyConfig;
-use crate::enclosure::tracer::Tracer;
-
-use self::fs::{append_all, FsDriver};
-use self::rule::{Rule, RuleMode};
-
+use crate::scanner::Scanner;
+use crate::top::BoxState;
+
+use self::agent::SshAgent;
+use self::fs::{append_all, placeholder_contents, FsDriver};
+use self::rule::{BoxxyRules, FaultMode, Rule, RuleMode};
+use self::watch::Watcher;
+
+pub mod agent;
+pub mod control;
+mod copy;
+pub mod enforce;
 pub mod fs;
+mod git;
+mod hooks;
+pub mod limits;
 mod linux;
+mod logsample;
+pub mod mountinfo;
+pub mod ownership;
+pub mod plan;
+pub mod profiler;
 mod register;
+pub mod report;
 pub mod rule;
+pub mod sandbox;
+pub mod secrets;
+pub mod selftest;
+mod shellinit;
+mod stack;
 mod syscall;
+pub mod template;
 mod tracer;
+pub mod verify;
+mod watch;
+
+pub use self::syscall::Syscall;
+pub use self::tracer::TraceSession;
 
 pub struct Enclosure {
     config: BoxxyConfig,
@@ -43,66 +72,188 @@ pub struct Enclosure {
     child_exit_status: i32,
     created_files: Vec<PathBuf>,
     created_directories: Vec<PathBuf>,
+    /// The staging tmpfs inside the container, once mounted.
+    staging_dir: Option<PathBuf>,
+    staged_paths: usize,
+    /// The copies made for `mode: copy` rules.
+    copies: Vec<copy::CopyBack>,
+    /// The mounts that the rules were applied with, for `--verbose-mounts`.
+    mounts: Vec<plan::PlannedMount>,
+    /// How each rule looked from the host, for `boxxy verify`.
+    host_views: Vec<verify::HostView>,
+    /// boxxy's own files, made read-only in the container.
+    protected_paths: Vec<PathBuf>,
+    /// The AppImage that `--appimage` runs, if the command is one.
+    appimage: Option<AppImage>,
+}
+
+/// An AppImage run with `--appimage`.
+#[derive(Debug, Clone, Copy)]
+struct AppImage {
+    /// Whether it was asked to extract itself with
+    /// `--appimage-extract-and-run`.
+    self_extracting: bool,
+    /// Why it can't mount itself with FUSE in the box, if it can't.
+    fuse_unusable: Option<&'static str>,
 }
 
 impl Enclosure {
     pub fn new(config: BoxxyConfig) -> Self {
+        let fs = FsDriver::new();
+        // Pick where container roots go once, before the container process
+        // is cloned, so that it doesn't pick again.
+        fs.all_containers_root();
+        let name = if config.name_from_command {
+            // Another run of the same command may still be using the name.
+            let base = config.name_from_command();
+            let mut name = base.clone();
+            let mut suffix = 1;
+            while fs.container_root(&name).exists() {
+                suffix += 1;
+                name = format!("{base}-{suffix}");
+            }
+            name
+        } else {
+            Haikunator::default().haikunate()
+        };
+        debug!("container name: {name}");
+        if config.tag_mounts {
+            fs::tag_mounts(&name);
+        }
+
         Self {
             config,
-            fs: FsDriver::new(),
-            name: Haikunator::default().haikunate(),
+            fs,
+            name,
             child_exit_status: -1,
             created_files: vec![],
             created_directories: vec![],
+            staging_dir: None,
+            staged_paths: 0,
+            copies: vec![],
+            mounts: vec![],
+            host_views: vec![],
+            protected_paths: vec![],
+            appimage: None,
         }
     }
 
     pub fn run(&mut self) -> Result<()> {
+        if self.config.dry_run {
+            let applicable_rules = self.applicable_rules()?;
+            if self.config.show_mount_order {
+                self.show_mount_order(&applicable_rules)?;
+            }
+            return self.dry_run(&applicable_rules);
+        }
+
+        let sandbox = sandbox::detect();
+        if let Some(sandbox) = &sandbox {
+            debug!("running inside {sandbox}, checking that it allows namespaces");
+            if !sandbox::can_create_namespaces() {
+                return Err(color_eyre::eyre::eyre!(
+                    "could not create a user namespace. {}",
+                    sandbox.explanation()
+                ));
+            }
+        }
+        // Failures to create namespaces or map ids inside another sandbox
+        // are almost always the sandbox's doing, so say how to fix it.
+        let explain = |err: color_eyre::Report| match &sandbox {
+            Some(sandbox) => err.wrap_err(sandbox.explanation()),
+            None => err,
+        };
+
         // Prepare the filesystem
-        let applicable_rules = &self
-            .config
-            .rules
-            .get_all_applicable_rules(self.config.command.get_program(), &self.fs)?;
+        let mut applicable_rules = self.applicable_rules()?;
+        for rule in &mut applicable_rules {
+            rule.resolve_env_cmd()?;
+        }
+        self.set_up_strict_secrets(&mut applicable_rules);
+        let agent_proxy = self.set_up_ssh_agent(&mut applicable_rules)?;
+        if self.config.shell_init {
+            shellinit::set_up(
+                &mut self.config.command,
+                &self.name,
+                &self.config.rules.shell_init,
+            )?;
+        }
+        let applicable_rules = &applicable_rules;
+        if self.config.show_mount_order {
+            self.show_mount_order(applicable_rules)?;
+        }
+        self.appimage = self.detect_appimage()?;
+        if let Some(binary) = Path::new(self.config.command.get_program()).file_name() {
+            crate::stats::record(&binary.to_string_lossy(), applicable_rules);
+        }
+        if self.config.verify {
+            self.host_views = verify::host_views(applicable_rules, &self.fs)?;
+        }
+        self.check_shadowed_data(applicable_rules)?;
         self.set_up_temporary_files(applicable_rules)?;
+        if let Err(err) = hooks::run_before(applicable_rules, &self.name, &self.fs) {
+            self.clean_up_container()?;
+            return Err(err);
+        }
+        // From here on, every way out of this function runs the rules'
+        // `after` commands, even early returns.
+        let after = hooks::After::new(applicable_rules, &self.name);
+
+        // The child can't create files until its uid/gid are mapped, so it
+        // waits for the parent to say that the mapping is done.
+        let (mapped_read, mapped_write) = nix::unistd::pipe()?;
+        let mapped_read_fd = mapped_read.as_raw_fd();
+        let mapped_write_fd = mapped_write.as_raw_fd();
 
         // Set up the container: callback, stack, etc.
-        let callback = || match self.run_in_container(applicable_rules) {
-            Ok(exit_code) => exit_code,
-            Err(err) => {
-                error!("{}", err);
-                -1isize
+        let stack_size = stack::stack_size(
+            self.config.stack_size,
+            Resource::STACK.get().ok().map(|(soft, _hard)| soft),
+        );
+        debug!("container stack size: {stack_size} bytes");
+        let mut stack = stack::Stack::new(stack_size)?;
+        let stack: &mut [u8] = stack.as_mut_slice();
+
+        let callback = || {
+            // SAFETY: the child has its own copy of the fd table, so closing
+            // our copy of the write end doesn't affect the parent.
+            unsafe { libc::close(mapped_write_fd) };
+            let mut byte = [0u8; 1];
+            // SAFETY: `byte` is valid for a single-byte write.
+            if unsafe { libc::read(mapped_read_fd, byte.as_mut_ptr().cast(), 1) } != 1 {
+                error!("parent failed to map uids/gids into the container");
+                return -1isize;
             }
-        };
 
-        let stack_size = match Resource::STACK.get() {
-            Ok((soft, _hard)) => soft as usize,
-            Err(_) => {
-                // 8MB
-                8 * 1024 * 1024
-            }
+            let exit_code = match self.run_in_container(applicable_rules) {
+                Ok(exit_code) => exit_code,
+                Err(err) => {
+                    error!("{}", err);
+                    -1isize
+                }
+            };
+            // Returning only exits this thread, which would leave the
+            // process hanging around if the control socket's thread is
+            // still waiting for clients.
+            std::process::exit(exit_code as i32)
         };
 
-        let mut stack_vec = vec![0u8; stack_size];
-        let stack: &mut [u8] = stack_vec.as_mut_slice();
-
         // Clone off the container process
-        // SAFETY: we ask the OS for the right stack size, and failover to a
-        // safe, probably-oversized stack in case.
+        // SAFETY: the stack is sized from the stack rlimit or --stack-size,
+        // clamped to a sane range, and has a guard page below it.
         let pid = unsafe {
             clone(
                 Box::new(callback),
                 stack,
                 CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWUSER,
                 Some(nix::sys::signal::Signal::SIGCHLD as i32),
-            )?
+            )
+            .map_err(|err| explain(err.into()))?
         };
         if pid.as_raw() == -1 {
             return Err(std::io::Error::last_os_error().into());
         }
-
-        // Await PTRACE_TRACEME from child
-        waitpid(pid, Some(WaitPidFlag::WSTOPPED))?;
-        debug!("child stopped!");
+        drop(mapped_read);
 
         // Map current UID + GID into the container so that things continue to
         // work as expected.
@@ -120,11 +271,12 @@ impl Enclosure {
         // tries to map them over and over, removing broken uids/gids until it
         // happens to work.
         // This isn't optimal, but it works.
+        let map_span = tracing::info_span!("map uids and gids").entered();
         if let Some(user) = User::from_uid(uid)? {
             let mut uid_map = HashMap::new();
             uid_map.insert(user.uid, user.uid);
 
-            linux::map_uids(pid, &mut uid_map)?;
+            linux::map_uids(pid, &mut uid_map).map_err(explain)?;
 
             let mut gid_map = HashMap::new();
             gid_map.insert(user.gid, user.gid);
@@ -135,16 +287,56 @@ impl Enclosure {
                     gid_map.insert(*gid, *gid);
                 });
 
-            linux::map_gids(pid, &mut gid_map)?;
+            linux::map_gids(pid, &mut gid_map).map_err(explain)?;
 
             debug!("finished setting up uid/gid mapping");
         } else {
             unreachable!("it should be impossible to have a user that doesn't have your uid");
         }
+        drop(map_span);
+
+        // Let the child set up the container, then await PTRACE_TRACEME
+        let wait_span = tracing::info_span!("wait for container setup").entered();
+        nix::unistd::write(&mapped_write, &[1])?;
+        drop(mapped_write);
+        let stopped = waitpid(pid, Some(WaitPidFlag::WSTOPPED))?;
+        drop(wait_span);
+        if let WaitStatus::Exited(_pid, status) = stopped {
+            error!(
+                "failed to set up the container, not running {:?}",
+                self.config.command.get_program()
+            );
+            if let Some(sandbox) = &sandbox {
+                error!("{}", sandbox.explanation());
+            }
+            if let Err(err) = self.fs.cleanup_root(&self.name) {
+                debug!("could not clean up container root: {err}");
+            }
+            after.run();
+            self.clean_up_container()?;
+            exit(status);
+        }
+        debug!("child stopped!");
+        if let Err(err) = self.register_box(pid, applicable_rules) {
+            debug!("could not register box for `boxxy top`: {err}");
+        }
+        if let Some((listener, upstream)) = agent_proxy {
+            let approve = agent::prompt(
+                self.config
+                    .command
+                    .get_program()
+                    .to_string_lossy()
+                    .to_string(),
+                self.name.clone(),
+            );
+            thread::spawn(move || agent::serve(listener, upstream, approve));
+        }
 
         // Set up ^C handling
         let name_clone = self.name.clone();
+        let serve_clone = self.config.serve.clone();
         let pid_clone = pid.as_raw();
+        let after_clone = after.handle();
         #[allow(unused_must_use)]
         ctrlc::set_handler(move || {
             nix::sys::signal::kill(
@@ -152,16 +344,39 @@ impl Enclosure {
                 nix::sys::signal::SIGTERM,
             );
             FsDriver::new().cleanup_root(&name_clone);
+            after_clone.run();
+            BoxState::unregister(&name_clone);
+            if let Some(Ok(socket)) = serve_clone.as_deref().map(crate::server::socket_path) {
+                std::fs::remove_file(socket);
+            }
+            if let Ok(socket) = control::socket_path(&name_clone) {
+                std::fs::remove_file(socket);
+            }
+            agent::remove_socket(&name_clone);
+            shellinit::remove(&name_clone);
             exit(1);
         })?;
 
+        // The container is set up by now, so its mounts can be watched.
+        let watcher = if self.config.watch && self.watchable() {
+            match Watcher::new(pid, &self.fs.container_root(&self.name)) {
+                Ok(watcher) => Some(watcher),
+                Err(err) => {
+                    signal::kill(pid, signal::SIGKILL)?;
+                    return Err(err);
+                }
+            }
+        } else {
+            None
+        };
+
         // Restart stopped child if not tracing
         if self.config.trace {
-            self.run_with_tracing(pid)?;
+            self.run_with_tracing(pid, &after)?;
         } else {
             match ptrace::detach(pid, None) {
                 Ok(_) => {
-                    self.run_without_tracing(pid)?;
+                    self.run_without_tracing(pid, watcher, &after)?;
                 }
                 Err(Errno::ESRCH) => {
                     error!("child exited early (ESRCH)! try running boxxy with `-l debug` or `-l trace` if it isn't obvious why");
@@ -175,53 +390,234 @@ impl Enclosure {
     }
 
     #[allow(unreachable_code)]
-    fn run_with_tracing(&mut self, pid: Pid) -> Result<()> {
-        Tracer::flag(pid)?;
-        let (tx, rx) = channel();
-
+    fn run_with_tracing(&mut self, pid: Pid, after: &hooks::After) -> Result<()> {
         debug!("restarting child and starting tracer!");
-        ptrace::syscall(pid, None)?;
-        Tracer::new(pid).run(tx)?;
+        let container_root = self.fs.container_root(&self.name);
+        let mut paths = vec![];
+        let mut seen_paths = HashSet::new();
+        // Which programs accessed each path, for suggesting rules.
+        let mut accesses: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+        let mut session = TraceSession::attach(pid)?;
+        if let Some(enforcement) = &self.config.trace_enforce {
+            session = session.enforce(enforcement.clone());
+        }
+        for syscall in &mut session {
+            let syscall = syscall?;
+            let Some(path) = syscall.path else {
+                continue;
+            };
+            let Ok(relative) = path.strip_prefix(&container_root) else {
+                continue;
+            };
+            let path_in_box = Path::new("/").join(relative);
+            if seen_paths.insert(path.clone()) {
+                paths.push(path_in_box.clone());
+            }
+            if let Some(program) = syscall.program.as_deref().and_then(Path::file_name) {
+                accesses
+                    .entry(path_in_box)
+                    .or_default()
+                    .insert(program.to_string_lossy().to_string());
+            }
+        }
         debug!("tracing finished!");
 
-        match waitpid(pid, None)? {
+        match session.wait()? {
             WaitStatus::Exited(_pid, status) => {
                 self.child_exit_status = status;
             }
+            // Ex. killed by `--trace-enforce-action kill`.
+            WaitStatus::Signaled(_pid, signal, _) => {
+                self.child_exit_status = 128 + signal as i32;
+            }
             _ => unreachable!("child should have exited!"),
         }
+        after.run();
+        agent::remove_socket(&self.name);
+        shellinit::remove(&self.name);
+
+        report::write(
+            &paths,
+            self.report_rollup(),
+            File::create("./boxxy-report.txt")?,
+        )?;
+        info!("wrote trace report to boxxy-report.txt");
+        let rules = Scanner::new().rules_for_trace(&accesses)?;
+        if !rules.is_empty() {
+            std::fs::write(
+                "./boxxy-rules.yaml",
+                serde_yaml::to_string(&BoxxyRules {
+                    rules: rules.clone(),
+                    ..Default::default()
+                })?,
+            )?;
+            info!(
+                "wrote {} suggested rule(s) for the programs that used them to boxxy-rules.yaml",
+                rules.len()
+            );
+        }
+        BoxState::unregister(&self.name);
 
-        let mut buffer = String::new();
-        let mut seen_paths = HashSet::new();
-        let mut counter = 0;
+        exit(self.child_exit_status);
+    }
+
+    /// The rules that apply to the command, and to the `and_then` commands,
+    /// with their templates rendered.
+    fn applicable_rules(&self) -> Result<Vec<Rule>> {
+        let _span = tracing::info_span!("match rules").entered();
+        let mut applicable_rules: Vec<Rule> = vec![];
+        for (program, args) in self.config.programs_for_matching() {
+            for rule in self
+                .config
+                .rules
+                .get_all_applicable_rules(&program, &args, &self.fs)?
+            {
+                let rule = rule.render_templates(Some(&program))?;
+                if !applicable_rules
+                    .iter()
+                    .any(|applied| applied.name == rule.name && applied.target == rule.target)
+                {
+                    applicable_rules.push(rule);
+                }
+            }
+        }
+        Ok(applicable_rules)
+    }
+
+    /// Print what running the command would do: the rules that match, the
+    /// mounts they'd be applied with, what would be created for them, and
+    /// the env vars they'd set. Nothing is changed.
+    fn dry_run(&self, applicable_rules: &[Rule]) -> Result<()> {
+        let mut mounts = vec![];
+        let mut creations = vec![];
+        let mut not_creating = vec![];
+        let mut env = vec![];
+        for rule in rule::mount_order(applicable_rules, &self.fs)? {
+            let expanded_target = self.fs.fully_expand_path(&rule.target)?;
+            let target_path = self.fs.maybe_resolve_symlink(&expanded_target)?;
+            let rewrite_path = self.fs.fully_expand_path(&rule.rewrite)?;
+            let (created, not_created): (Vec<_>, Vec<_>) =
+                plan::creations(rule, &target_path, &rewrite_path)
+                    .into_iter()
+                    .filter(|creation| !creation.path.exists())
+                    .partition(|_| rule.creates());
+            creations.extend(created);
+            not_creating.extend(
+                not_created
+                    .into_iter()
+                    .map(|creation| (creation.path, &rule.name)),
+            );
+            let missing = !target_path.exists();
+            match rule.mode {
+                RuleMode::ExposeRo | RuleMode::Mask if missing => {}
+                _ => mounts.push(plan::mount_for(
+                    rule,
+                    &expanded_target,
+                    &rewrite_path,
+                    target_path.clone(),
+                )),
+            }
+            for key in rule.env_vars()?.into_keys() {
+                env.push(format!("{key} (rule '{}')", rule.name));
+            }
+            let mut commands: Vec<&String> = rule
+                .env_cmd
+                .keys()
+                .filter(|key| !rule.env.contains_key(*key))
+                .collect();
+            commands.sort();
+            for key in commands {
+                env.push(format!("{key} (rule '{}', from env_cmd)", rule.name));
+            }
+            if !rule.prepend_path.is_empty() || !rule.append_path.is_empty() {
+                env.push(format!("PATH (rule '{}', extended)", rule.name));
+            }
+        }
+
+        println!("rules:");
+        for rule in applicable_rules {
+            println!("  {}: {}", rule.name, rule.target);
+        }
+        println!("mounts:");
+        for line in plan::table(&mounts).lines() {
+            println!("  {line}");
+        }
+        println!("would create:");
+        for creation in &creations {
+            let kind = if creation.directory {
+                "directory"
+            } else {
+                "file"
+            };
+            println!("  {kind} {}", creation.path.display());
+        }
+        if !not_creating.is_empty() {
+            println!("would fail, missing with `create: false`:");
+            for (path, rule) in &not_creating {
+                println!("  {} (rule '{rule}')", path.display());
+            }
+        }
+        println!("env:");
+        println!("  BOXXY_CONTAINER_NAME");
+        for var in &env {
+            println!("  {var}");
+        }
+        if applicable_rules
+            .iter()
+            .any(|rule| !rule.before.is_empty() || !rule.after.is_empty())
         {
-            use std::fmt::Write;
-            while let Ok(syscall) = rx.recv() {
-                if let Some(path) = syscall.path {
-                    let container_root = self.fs.container_root(&self.name);
-
-                    if path.starts_with(&container_root) && !seen_paths.contains(&path) {
-                        writeln!(buffer, "/{}", path.strip_prefix(&container_root)?.display())?;
-                        seen_paths.insert(path);
-                        counter += 1;
-                    }
+            println!("would run on the host:");
+            for rule in applicable_rules {
+                for line in &rule.before {
+                    println!("  before (rule '{}'): {line}", rule.name);
+                }
+            }
+            for rule in applicable_rules.iter().rev() {
+                for line in &rule.after {
+                    println!("  after (rule '{}'): {line}", rule.name);
                 }
             }
-            writeln!(buffer, "# total: {counter}")?;
         }
+        Ok(())
+    }
 
-        let mut file = File::create("./boxxy-report.txt")?;
-        file.write_all(buffer.as_bytes())?;
-        info!("wrote trace report to boxxy-report.txt");
+    /// Print the order `applicable_rules` are mounted in to stderr, for
+    /// `--show-mount-order`.
+    fn show_mount_order(&self, applicable_rules: &[Rule]) -> Result<()> {
+        eprint!(
+            "{}",
+            plan::order_table(&rule::mount_order_keys(applicable_rules, &self.fs)?)
+        );
+        Ok(())
+    }
 
-        exit(self.child_exit_status);
+    /// Whether `--watch` can watch this run. Only commands that run to
+    /// completion in the foreground are watched.
+    fn watchable(&self) -> bool {
+        if self.config.daemon {
+            warn!("--watch doesn't work with --daemon, not watching");
+            return false;
+        }
+
+        !self.config.verify && self.config.serve.is_none()
+    }
+
+    /// The entry count above which the report rolls up a directory.
+    fn report_rollup(&self) -> Option<usize> {
+        (!self.config.expand_report).then_some(report::ROLLUP_THRESHOLD)
     }
 
-    fn run_without_tracing(&mut self, pid: Pid) -> Result<()> {
+    fn run_without_tracing(
+        &mut self,
+        pid: Pid,
+        mut watcher: Option<Watcher>,
+        after: &hooks::After,
+    ) -> Result<()> {
         // Wait for exit
         let mut exit_status: i32 = -1;
+        let options = watcher.as_ref().map(|_| WaitPidFlag::WNOHANG);
         loop {
-            match waitpid(pid, None) {
+            match waitpid(pid, options) {
                 Ok(WaitStatus::Exited(_pid, status)) => {
                     exit_status = status;
                     break;
@@ -231,54 +627,213 @@ impl Enclosure {
                     thread::sleep(Duration::from_millis(100));
                     break;
                 }
-                _ => thread::sleep(Duration::from_millis(100)),
+                _ => match &mut watcher {
+                    Some(watcher) => {
+                        if let Err(err) = watcher.poll(Duration::from_millis(100)) {
+                            warn!("--watch: could not read events: {err}");
+                        }
+                    }
+                    None => thread::sleep(Duration::from_millis(100)),
+                },
             }
         }
         self.child_exit_status = exit_status;
 
+        if let Some(watcher) = watcher {
+            report::write(
+                &watcher.finish()?,
+                self.report_rollup(),
+                File::create("./boxxy-report.txt")?,
+            )?;
+            info!("wrote watch report to boxxy-report.txt");
+        }
+
         // Clean up!
+        after.run();
+        if let Some(name) = &self.config.serve {
+            let socket = crate::server::socket_path(name)?;
+            if socket.exists() {
+                std::fs::remove_file(socket)?;
+            }
+        }
         self.fs.cleanup_root(&self.name)?;
         self.clean_up_container()?;
+        // A daemonised box keeps running after the child exits. `boxxy top`
+        // cleans up after it once it's gone.
+        if !self.config.daemon {
+            BoxState::unregister(&self.name);
+        }
 
         // All done! Return the child's exit status
         debug!("exiting with status {}", self.child_exit_status);
         exit(self.child_exit_status);
     }
 
-    fn set_up_temporary_files(&mut self, applicable_rules: &[Rule]) -> Result<Vec<PathBuf>> {
+    /// Apply `--strict-secrets`: hide the gpg-agent and the keyrings unless
+    /// a rule that applies allows them.
+    fn set_up_strict_secrets(&mut self, applicable_rules: &mut Vec<Rule>) {
+        if !self.config.strict_secrets {
+            return;
+        }
+        if let Some(rule) = applicable_rules.iter().find(|rule| rule.allow_secrets) {
+            debug!("--strict-secrets: rule '{}' allows secrets", rule.name);
+            return;
+        }
+        let Some(home) = dirs::home_dir() else {
+            warn!("--strict-secrets: could not find your home directory");
+            return;
+        };
+        let runtime_dir = dirs::runtime_dir();
+        let socket_dirs = secrets::gpg_socket_dirs(runtime_dir.as_deref(), &home);
+        let paths = secrets::hidden_paths(&socket_dirs, runtime_dir.as_deref(), &home);
+        for path in &paths {
+            debug!("--strict-secrets: hiding {}", path.display());
+        }
+        applicable_rules.extend(secrets::deny_rules(&paths));
+        for key in secrets::ENV_VARS {
+            self.config.command.env_remove(key);
+        }
+    }
+
+    /// Apply `--ssh-agent`: hide the agent's socket from the box unless it's
+    /// allowed, and for `proxy`, bind the proxy's socket and point the
+    /// command at it. The proxy is served once the container is set up, from
+    /// this process, since the box can't reach the real agent.
+    fn set_up_ssh_agent(
+        &mut self,
+        applicable_rules: &mut Vec<Rule>,
+    ) -> Result<Option<(UnixListener, PathBuf)>> {
+        if self.config.ssh_agent == SshAgent::Allow {
+            return Ok(None);
+        }
+        let Some(upstream) = agent::upstream_socket() else {
+            debug!("--ssh-agent: no agent is running, nothing to hide");
+            self.config.command.env_remove(agent::SOCKET_ENV);
+            return Ok(None);
+        };
+        applicable_rules.push(agent::deny_rule(&upstream));
+        if self.config.ssh_agent == SshAgent::Deny {
+            self.config.command.env_remove(agent::SOCKET_ENV);
+            return Ok(None);
+        }
+
+        let socket = agent::socket_path(&self.name)?;
+        if let Some(parent) = socket.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let listener = UnixListener::bind(&socket)
+            .map_err(|err| color_eyre::eyre::eyre!("could not bind {}: {err}", socket.display()))?;
+        debug!("serving an ssh agent proxy at {}", socket.display());
+        self.config.command.env(agent::SOCKET_ENV, &socket);
+        Ok(Some((listener, upstream)))
+    }
+
+    /// Record the box so that `boxxy top` can find it.
+    fn register_box(&self, pid: Pid, applicable_rules: &[Rule]) -> Result<()> {
+        let command = std::iter::once(self.config.command.get_program())
+            .chain(self.config.command.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        let mut targets = vec![];
+        for rule in applicable_rules {
+            targets.push(
+                self.fs
+                    .maybe_resolve_symlink(&self.fs.fully_expand_path(&rule.target)?)?,
+            );
+        }
+
+        BoxState::new(&self.name, pid.as_raw(), command, targets)?.register()
+    }
+
+    /// Warn about rules whose empty rewrite would hide the data in their
+    /// target, which makes the program look like it lost its config, and ask
+    /// whether to go ahead if there's someone to ask.
+    fn check_shadowed_data(&self, applicable_rules: &[Rule]) -> Result<()> {
+        let mut shadowed = 0;
         for rule in applicable_rules {
-            debug!("processing path creation for rule '{}'", rule.name);
+            if let Some(contents) = crate::store::shadowed_data(rule, &self.fs)? {
+                shadowed += 1;
+                warn!(
+                    "{}: {} has data in it ({contents}), but the rewrite {} is empty, so the program won't see it. `boxxy migrate '{}'` copies it over.",
+                    rule.name, rule.target, rule.rewrite, rule.name
+                );
+            }
+        }
+
+        if shadowed == 0 || self.config.assume_yes || !std::io::stdin().is_terminal() {
+            return Ok(());
+        }
+        if !crate::cli::confirm("run anyway?")? {
+            return Err(color_eyre::eyre::eyre!(
+                "not running, pass --assume-yes to run anyway"
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_up_temporary_files(&mut self, applicable_rules: &[Rule]) -> Result<Vec<PathBuf>> {
+        let _span = tracing::info_span!("create temporary files").entered();
+        // Check every rule before creating anything, so that a missing path
+        // doesn't leave the other rules' paths behind.
+        for rule in applicable_rules.iter().filter(|rule| !rule.creates()) {
+            let target_path = self
+                .fs
+                .maybe_resolve_symlink(&self.fs.fully_expand_path(&rule.target)?)?;
+            let rewrite_path = self.fs.fully_expand_path(&rule.rewrite)?;
+            if let Some(creation) = plan::creations(rule, &target_path, &rewrite_path)
+                .into_iter()
+                .find(|creation| creation.path.symlink_metadata().is_err())
+            {
+                return Err(color_eyre::eyre::eyre!(
+                    "{}: {} doesn't exist, and the rule has `create: false`, not running the command",
+                    rule.name,
+                    creation.path.display()
+                ));
+            }
+        }
+        for rule in applicable_rules.iter().filter(|rule| rule.creates()) {
+            let level = self.config.log_level_for(rule);
+            log!(level, "processing path creation for rule '{}'", rule.name);
 
             let expanded_target = self.fs.fully_expand_path(&rule.target)?;
             let target_path = self.fs.maybe_resolve_symlink(&expanded_target)?;
 
             let rewrite_path = self.fs.fully_expand_path(&rule.rewrite)?;
 
-            debug!("ensuring path: {target_path:?}");
-            debug!("rewriting to: {rewrite_path:?}");
+            log!(level, "ensuring path: {target_path:?}");
+            log!(level, "rewriting to: {rewrite_path:?}");
 
-            match rule.mode {
-                RuleMode::File => {
-                    self.ensure_file(&rewrite_path)?;
-                    if self.ensure_file(&target_path)? {
-                        self.created_files.push(target_path.clone());
-                    }
+            for creation in plan::creations(rule, &target_path, &rewrite_path) {
+                let created = if creation.directory {
+                    self.ensure_directory(&creation.path)?
+                } else {
+                    self.ensure_file(&creation.path)?
+                };
+                if !created {
+                    continue;
                 }
-                RuleMode::Directory => {
-                    self.ensure_directory(&rewrite_path)?;
-                    if self.ensure_directory(&target_path)? {
-                        self.created_directories.push(target_path.clone());
-                    }
+                ownership::apply(&creation.path, rule.permissions, rule.owner.as_ref())?;
+                if creation.rewrite {
+                    self.maybe_copy_xattrs(rule, &target_path, &creation.path)?;
+                } else if creation.directory {
+                    self.created_directories.push(creation.path);
+                } else {
+                    self.created_files.push(creation.path);
                 }
             }
 
-            debug!("rewrote base bath {rewrite_path:?} => {target_path:?}");
+            log!(
+                level,
+                "rewrote base path {rewrite_path:?} => {target_path:?}"
+            );
         }
 
         Ok(vec![])
     }
 
     fn set_up_container(&mut self, applicable_rules: &[Rule]) -> Result<()> {
+        let _span =
+            tracing::info_span!("set up container", rules = applicable_rules.len()).entered();
         // Load .env vars
         if self.config.dotenv {
             debug!("dotenv enabled!");
@@ -295,32 +850,53 @@ impl Enclosure {
             }
         }
 
+        self.config.command.env("BOXXY_CONTAINER_NAME", &self.name);
+
         // Load env vars from applicable rules
         for rule in applicable_rules {
-            for (key, value) in rule.env.iter() {
+            let vars = rule.env_vars()?;
+            for (key, value) in &vars {
                 self.config.command.env(key, value);
                 debug!("loaded env var: {}=********", key);
             }
-            if !rule.env.is_empty() {
-                debug!(
-                    "loaded {} env vars from rule '{}'",
-                    rule.env.len(),
-                    rule.name
-                );
+            if !vars.is_empty() {
+                debug!("loaded {} env vars from rule '{}'", vars.len(), rule.name);
             }
         }
+        let path = self
+            .config
+            .command
+            .get_envs()
+            .find(|(key, _)| *key == "PATH")
+            .map(|(_, value)| value.map(|value| value.to_os_string()))
+            .unwrap_or_else(|| std::env::var_os("PATH"));
+        if let Some(path) = rule::extend_path(applicable_rules, path.as_deref())? {
+            debug!("PATH is now {}", path.to_string_lossy());
+            self.config.command.env("PATH", path);
+        }
 
         // Mount root RW
         debug!("setup root");
+        if self.config.root_tmpfs {
+            self.fs.mount_root_tmpfs()?;
+        }
         self.fs.setup_root(&self.name)?;
         let container_root = self.fs.container_root(&self.name);
         debug!("bind mount root rw");
         self.fs.bind_mount_rw(Path::new("/"), &container_root)?;
+        if self.config.ro_home {
+            self.make_home_read_only(&container_root)?;
+        }
 
         // Apply all rules via bind mounts
         debug!("applying {} rules", applicable_rules.len());
-        for rule in applicable_rules {
-            debug!("applying rule '{}'", rule.name);
+        // Masked after every other rule, so that their mounts are bound back
+        // with the rest of the masked paths' directories.
+        let mut masked = vec![];
+        for rule in rule::mount_order(applicable_rules, &self.fs)? {
+            let _span = tracing::info_span!("apply rule", rule = %rule.name).entered();
+            let level = self.config.log_level_for(rule);
+            log!(level, "applying rule '{}'", rule.name);
 
             let expanded_target = self.fs.fully_expand_path(&rule.target)?;
             // Rewrite target path into the container
@@ -333,60 +909,561 @@ impl Enclosure {
                     }
                 };
             let target_path = self.fs.maybe_resolve_symlink(&target_path)?;
-
-            let rewrite_path = self.fs.fully_expand_path(&rule.rewrite)?;
-
-            debug!("source exists: {}", rewrite_path.exists());
-            debug!("target exists: {}", target_path.exists());
+            log!(level, "target exists: {}", target_path.exists());
 
             // If the target file doesn't exist, we have to create it in order to bind mount over it.
             match rule.mode {
                 RuleMode::File => {
                     if !target_path.exists() {
-                        debug!("creating file: {target_path:?}");
+                        log!(level, "creating file: {target_path:?}");
                         self.ensure_file(&target_path)?;
                         self.created_files.push(target_path.clone());
                     }
-                    self.fs.bind_mount_rw(&rewrite_path, &target_path)?;
                 }
-                RuleMode::Directory => {
+                RuleMode::Directory | RuleMode::Tmpfs => {
                     if !target_path.exists() {
-                        debug!("creating directory: {target_path:?}");
+                        log!(level, "creating directory: {target_path:?}");
                         self.ensure_directory(&target_path)?;
-                        self.created_files.push(target_path.clone());
+                        ownership::apply(&target_path, rule.permissions, rule.owner.as_ref())?;
+                        self.created_directories.push(target_path.clone());
+                    }
+                }
+                RuleMode::ExposeRo => {
+                    if !target_path.exists() {
+                        warn!(
+                            "{}: target {} doesn't exist, so there's nothing to expose",
+                            rule.name, rule.target
+                        );
+                        continue;
+                    }
+                }
+                RuleMode::Mask => {
+                    if !target_path.exists() {
+                        log!(level, "{target_path:?} doesn't exist, nothing to mask");
+                        continue;
                     }
+                }
+                // Created with the rule's temporary files.
+                RuleMode::Copy => {}
+            }
+            let planned = plan::mount_for(
+                rule,
+                &expanded_target,
+                &self.fs.fully_expand_path(&rule.rewrite)?,
+                self.destination(&target_path),
+            );
+
+            if rule.deny {
+                if rule.faultmode.is_some() || !rule.omit.is_empty() {
+                    warn!(
+                        "{}: faultmode and omit don't apply to deny rules, ignoring them",
+                        rule.name
+                    );
+                }
+                self.cover_with_empty(&target_path)?;
+                self.mounts.push(planned);
+                log!(level, "denied {target_path:?}");
+                continue;
+            }
+
+            if rule.mode == RuleMode::Tmpfs {
+                if rule.faultmode.is_some() || !rule.omit.is_empty() {
+                    warn!(
+                        "{}: faultmode and omit don't apply to tmpfs rules, ignoring them",
+                        rule.name
+                    );
+                }
+                let mode = rule.permissions.map_or(0o755, |permissions| permissions.0);
+                let mut options = format!("mode={mode:o}");
+                if let Some(size) = &rule.size {
+                    options.push_str(&format!(",size={size}"));
+                }
+                if let Some(owner) = &rule.owner {
+                    options.push_str(&format!(",{}", owner.tmpfs_options()?));
+                }
+                self.fs
+                    .mount_tmpfs_with(&target_path, &options, rule.mount_flags())?;
+                self.mounts.push(planned);
+                log!(level, "mounted a tmpfs at {target_path:?}");
+                continue;
+            }
+
+            if rule.mode == RuleMode::Mask {
+                if !rule.omit.is_empty() {
+                    warn!(
+                        "{}: omit doesn't apply to mask rules, ignoring it",
+                        rule.name
+                    );
+                }
+                log!(level, "masking {target_path:?}");
+                masked.push(target_path);
+                self.mounts.push(planned);
+                continue;
+            }
+
+            if rule.mode == RuleMode::Copy {
+                self.copy(rule, &target_path, &container_root)?;
+                self.mounts.push(planned);
+                log!(level, "copied {} to {target_path:?}", rule.rewrite);
+                continue;
+            }
+
+            if rule.mode == RuleMode::ExposeRo {
+                if !rule.omit.is_empty() {
+                    warn!(
+                        "{}: omit doesn't apply to expose-ro rules, ignoring it",
+                        rule.name
+                    );
+                }
+                self.fs.bind_mount_ro(&target_path, &target_path)?;
+                if !rule.mount_options.is_empty() {
+                    self.fs.remount_with(&target_path, rule.mount_flags())?;
+                }
+                self.mounts.push(planned);
+                log!(level, "exposed {target_path:?} as ro");
+                continue;
+            }
+
+            let rewrite_path = self.fs.fully_expand_path(&rule.rewrite)?;
+            log!(level, "source exists: {}", rewrite_path.exists());
+
+            match rule.faultmode {
+                Some(fault) => {
+                    self.inject_fault(rule, fault, &rewrite_path, &target_path)?;
+                    self.mounts.push(planned);
+                    log!(level, "injected fault {fault:?} at {target_path:?}");
+                }
+                None if rule.readonly => {
+                    self.fs.bind_mount_ro(&rewrite_path, &target_path)?;
+                    self.mounts.push(planned);
+                    log!(
+                        level,
+                        "rewrote base path {rewrite_path:?} => {target_path:?} as ro"
+                    );
+                }
+                None => {
                     self.fs.bind_mount_rw(&rewrite_path, &target_path)?;
+                    self.mounts.push(planned);
+                    log!(
+                        level,
+                        "rewrote base path {rewrite_path:?} => {target_path:?}"
+                    );
+                }
+            }
+
+            if !rule.mount_options.is_empty() && rule.faultmode.is_none() {
+                self.fs.remount_with(&target_path, rule.mount_flags())?;
+                log!(
+                    level,
+                    "remounted {target_path:?} with {:?}",
+                    rule.mount_options
+                );
+            }
+
+            if !rule.omit.is_empty() {
+                if rule.mode != RuleMode::Directory || rule.faultmode.is_some() {
+                    warn!(
+                        "{}: omit only works on directory rules without a faultmode, ignoring it",
+                        rule.name
+                    );
+                } else {
+                    self.omit(rule, &target_path)?;
+                }
+            }
+        }
+
+        self.mask(&masked)?;
+
+        if self.config.expose_boxxy_config {
+            warn!("--expose-boxxy-config: the boxxed program can change boxxy's config");
+        } else {
+            self.protect_boxxy_files(&container_root)?;
+        }
+
+        // Hide the staging tmpfs from the boxxed program. Anything mounted
+        // from it stays mounted.
+        if let Some(staging_dir) = self.staging_dir.take() {
+            umount2(&staging_dir, MntFlags::MNT_DETACH)?;
+        }
+
+        Ok(())
+    }
+
+    /// Make boxxy's own config and state read-only, so that the boxxed
+    /// program can't change the rules that later boxes are set up with.
+    fn protect_boxxy_files(&mut self, container_root: &Path) -> Result<()> {
+        self.protected_paths = BoxxyConfig::protected_paths()?;
+        for path in &self.protected_paths {
+            let path = append_all(container_root, vec![path]);
+            if !path.exists() {
+                // Shadowed by a rule.
+                continue;
+            }
+            self.fs.bind_mount_ro(&path, &path)?;
+            debug!("protected {path:?}");
+        }
+
+        Ok(())
+    }
+
+    /// The scratch tmpfs for files that rules need, mounted on first use.
+    fn staging_dir(&mut self) -> Result<PathBuf> {
+        if let Some(staging_dir) = &self.staging_dir {
+            return Ok(staging_dir.clone());
+        }
+
+        let staging_dir = append_all(
+            &self.fs.container_root(&self.name),
+            vec![self.fs.staging_root(&self.name)],
+        );
+        self.fs.mount_tmpfs(&staging_dir, "mode=700")?;
+        self.staging_dir = Some(staging_dir.clone());
+
+        Ok(staging_dir)
+    }
+
+    /// A fresh, uniquely-named path in the staging tmpfs.
+    fn staging_path(&mut self, name: &str) -> Result<PathBuf> {
+        let staging_dir = self.staging_dir()?;
+        self.staged_paths += 1;
+        Ok(append_all(
+            &staging_dir,
+            vec![format!("{}-{name}", self.staged_paths)],
+        ))
+    }
+
+    /// Hide `paths` so that looking them up fails with `ENOENT`. Mounts can
+    /// only cover paths, not remove them, so each of their directories is
+    /// covered with a read-only tmpfs that everything else in it is bound
+    /// back into. That's a mount for each entry in the directory, and the
+    /// boxxed program can't create, remove, or rename entries in the
+    /// directory itself, though the entries bound back work as usual.
+    fn mask(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let mut by_parent: BTreeMap<&Path, Vec<&std::ffi::OsStr>> = BTreeMap::new();
+        for path in paths {
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                return Err(color_eyre::eyre::eyre!("can't mask {}", path.display()));
+            };
+            by_parent.entry(parent).or_default().push(name);
+        }
+
+        // Parents come before their children, so a directory that's in a
+        // masked one is already gone.
+        for (parent, names) in by_parent {
+            if !parent.exists() {
+                continue;
+            }
+            let original = self.staging_path("masked")?;
+            self.fs.touch_dir(&original)?;
+            self.fs.bind_mount_rw(parent, &original)?;
+            let mode = parent.metadata()?.permissions().mode() & 0o7777;
+            self.fs.mount_tmpfs(parent, &format!("mode={mode:o}"))?;
+
+            let mut bound = 0;
+            for entry in std::fs::read_dir(&original)? {
+                let entry = entry?;
+                if names.contains(&entry.file_name().as_os_str()) {
+                    continue;
+                }
+                let source = entry.path();
+                let placeholder = parent.join(entry.file_name());
+                let file_type = entry.file_type()?;
+                if file_type.is_symlink() {
+                    std::os::unix::fs::symlink(source.read_link()?, &placeholder)?;
+                    continue;
+                }
+                if file_type.is_dir() {
+                    self.fs.touch_dir(&placeholder)?;
+                } else {
+                    self.fs.touch(&placeholder)?;
                 }
+                self.fs.bind_mount_rw(&source, &placeholder)?;
+                bound += 1;
             }
+            // Read-only, so that new entries fail loudly instead of being
+            // thrown away with the tmpfs.
+            self.fs.remount_with(parent, MsFlags::MS_RDONLY)?;
+            debug!(
+                "masked {} in {parent:?}, binding {bound} other entries back",
+                names.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Make `$HOME` read-only in the box for `--ro-home`, before the rules
+    /// are applied, so that their rewrites are the only places in it the
+    /// command can write to. Mounts under `$HOME` keep their own flags.
+    fn make_home_read_only(&self, container_root: &Path) -> Result<()> {
+        let home = dirs::home_dir()
+            .ok_or_else(|| color_eyre::eyre::eyre!("could not find home directory"))?
+            .canonicalize()?;
+        let home_in_box = append_all(container_root, vec![&home]);
+        debug!("making {} read-only", home.display());
+        self.fs.bind_mount_rw(&home_in_box, &home_in_box)?;
+        self.fs.remount_with(&home_in_box, MsFlags::MS_RDONLY)?;
+        Ok(())
+    }
 
-            debug!("rewrote base bath {rewrite_path:?} => {target_path:?}");
+    /// Copy the rule's rewrite to `target_path` rather than binding it there,
+    /// and remember to sync it back. A directory is copied into a fresh
+    /// tmpfs, so that the files in it can be renamed over. A file is copied
+    /// into the staging tmpfs and bound over the target.
+    fn copy(&mut self, rule: &Rule, target_path: &Path, container_root: &Path) -> Result<()> {
+        let rewrite_path = self.fs.fully_expand_path(&rule.rewrite)?;
+        let directory = rewrite_path.is_dir();
+        if directory {
+            let mode = rewrite_path.metadata()?.permissions().mode() & 0o7777;
+            self.fs
+                .mount_tmpfs(target_path, &format!("mode={mode:o}"))?;
+            copy::mirror(&rewrite_path, target_path)?;
+        } else {
+            let staged = self.staging_path("copy")?;
+            std::fs::copy(&rewrite_path, &staged)?;
+            self.fs.bind_mount_rw(&staged, target_path)?;
         }
 
+        let in_box = Path::new("/").join(target_path.strip_prefix(container_root)?);
+        self.copies.push(copy::CopyBack::new(
+            &rule.name,
+            in_box,
+            &rewrite_path,
+            directory,
+        )?);
         Ok(())
     }
 
+    /// Sync the copies made for `mode: copy` rules back to their rewrites.
+    fn sync_copies(&self, exit_status: i32) {
+        if self.copies.is_empty() {
+            return;
+        }
+        if exit_status != 0 {
+            warn!(
+                "the command exited with {exit_status}, not syncing {} copied target(s) back",
+                self.copies.len()
+            );
+            return;
+        }
+        for copy in &self.copies {
+            match copy.sync() {
+                Ok(()) => info!(
+                    "{}: synced {} back to its rewrite",
+                    copy.rule,
+                    copy.target.display()
+                ),
+                Err(err) => error!(
+                    "{}: could not sync {} back to its rewrite: {err}",
+                    copy.rule,
+                    copy.target.display()
+                ),
+            }
+        }
+    }
+
+    /// Where `target_path`, a path in the container's root, is as the boxxed
+    /// program sees it.
+    fn destination(&self, target_path: &Path) -> PathBuf {
+        let container_root = self.fs.container_root(&self.name);
+        match target_path.strip_prefix(&container_root) {
+            Ok(path) => Path::new("/").join(path),
+            Err(_) => target_path.to_path_buf(),
+        }
+    }
+
+    /// Cover each of the rule's omitted children with an empty, read-only
+    /// file or directory, so the boxxed program can neither read nor replace
+    /// them.
+    fn omit(&mut self, rule: &Rule, target_path: &Path) -> Result<()> {
+        let level = self.config.log_level_for(rule);
+        for path in rule.omitted_paths(target_path)? {
+            let metadata = match path.symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    log!(level, "{}: nothing to omit at {path:?}", rule.name);
+                    continue;
+                }
+            };
+            if metadata.is_symlink() {
+                warn!("{}: not omitting symlink {}", rule.name, path.display());
+                continue;
+            }
+
+            self.cover_with_empty(&path)?;
+            self.mounts.push(plan::PlannedMount {
+                rule: rule.name.clone(),
+                source: "(empty)".to_string(),
+                destination: self.destination(&path),
+                flags: plan::flags(rule, true, true),
+                strategy: "omit".to_string(),
+            });
+            log!(level, "{}: omitted {path:?}", rule.name);
+        }
+
+        Ok(())
+    }
+
+    /// Bind-mount an empty, read-only file or directory, whichever `path`
+    /// is, over `path`.
+    fn cover_with_empty(&mut self, path: &Path) -> Result<()> {
+        let empty = self.staging_path("empty")?;
+        if path.is_dir() {
+            self.fs.touch_dir(&empty)?;
+        } else {
+            self.fs.touch(&empty)?;
+        }
+        self.fs.bind_mount_ro(&empty, path)
+    }
+
+    fn inject_fault(
+        &mut self,
+        rule: &Rule,
+        fault: FaultMode,
+        rewrite_path: &Path,
+        target_path: &Path,
+    ) -> Result<()> {
+        match (fault, rule.mode) {
+            (FaultMode::Erofs, _) => self.fs.bind_mount_ro(rewrite_path, target_path)?,
+            (_, RuleMode::ExposeRo | RuleMode::Mask | RuleMode::Copy) => {
+                unreachable!("{:?} rules can't have a faultmode", rule.mode)
+            }
+            (FaultMode::Eacces, RuleMode::Directory | RuleMode::Tmpfs) => {
+                self.fs.mount_tmpfs(target_path, "mode=000")?;
+            }
+            (FaultMode::Eacces, RuleMode::File) => {
+                let file = self.staging_path("eacces")?;
+                self.fs.touch(&file)?;
+                std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o000))?;
+                self.fs.bind_mount_rw(&file, target_path)?;
+            }
+            (FaultMode::Enospc, RuleMode::Directory | RuleMode::Tmpfs) => {
+                // The root inode uses up the only inode, so nothing can be
+                // created.
+                self.fs
+                    .mount_tmpfs(target_path, "size=4k,nr_inodes=1,mode=755")?;
+            }
+            (FaultMode::Enospc, RuleMode::File) => {
+                // Copy the rewrite onto a tiny tmpfs, then fill the rest of it
+                // up so that the file can be read but not grown.
+                let contents = std::fs::read(rewrite_path).map_err(|err| {
+                    color_eyre::eyre::eyre!(
+                        "rule '{}': could not read {} to inject enospc: {err}",
+                        rule.name,
+                        rewrite_path.display()
+                    )
+                })?;
+                let dir = self.staging_path("enospc")?;
+                self.fs.touch_dir(&dir)?;
+                let size = contents.len().div_ceil(4096) * 4096 + 4096;
+                self.fs
+                    .mount_tmpfs(&dir, &format!("size={size},nr_inodes=3"))?;
+                let file = append_all(&dir, vec!["file"]);
+                std::fs::write(&file, contents)?;
+                fill_filesystem(&append_all(&dir, vec!["fill"]))?;
+                self.fs.bind_mount_rw(&file, target_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Point `TMPDIR` at a directory only this box uses. It lives in the
+    /// container root, so it's deleted with it. That's normally on disk, but
+    /// with `--root-tmpfs` the whole container root is a tmpfs, and big
+    /// temporary files then eat memory like any other tmpfs would.
+    fn set_up_private_tmpdir(&mut self) -> Result<()> {
+        let tmpdir = self.fs.scratch_dir(&self.name, "tmp")?;
+        let box_tmpdir = append_all(&self.fs.container_root(&self.name), vec![&tmpdir]);
+        self.fs.bind_mount_rw(&tmpdir, &box_tmpdir)?;
+        self.config.command.env("TMPDIR", &tmpdir);
+        debug!("private TMPDIR is {tmpdir:?}");
+        Ok(())
+    }
+
+    /// Give the AppImage a private `TMPDIR` to mount or extract itself into,
+    /// so that nothing is left behind in `/tmp` and it still works with an
+    /// immutable root. Rules still shadow whatever it writes to `$HOME`.
+    fn set_up_appimage(&mut self, appimage: AppImage) -> Result<()> {
+        let tmpdir = self.fs.scratch_dir(&self.name, "appimage")?;
+        let box_tmpdir = append_all(&self.fs.container_root(&self.name), vec![&tmpdir]);
+        self.fs.mount_tmpfs(&box_tmpdir, "mode=700")?;
+        self.config.command.env("TMPDIR", &tmpdir);
+        debug!("AppImage TMPDIR is {tmpdir:?}");
+
+        if appimage.self_extracting {
+            return Ok(());
+        }
+        match appimage.fuse_unusable {
+            None => debug!("letting AppImage mount itself via /dev/fuse"),
+            Some(reason) => {
+                info!("FUSE isn't usable in the container ({reason}), extracting the AppImage instead. please be patient (:");
+                self.config.command.env("APPIMAGE_EXTRACT_AND_RUN", "1");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Why an AppImage can't mount itself with FUSE in the box, if it can't.
+    /// The AppImage runtime mounts itself with `fusermount`, which is setuid
+    /// root, and the kernel ignores that in a user namespace that root isn't
+    /// mapped into, so it only works when boxxy runs as root.
+    fn fuse_unusable_reason() -> Option<&'static str> {
+        if !getuid().is_root() {
+            Some("boxxy isn't running as root, and `fusermount` can't mount from a rootless box")
+        } else if !Path::new("/dev/fuse").exists() {
+            Some("/dev/fuse doesn't exist")
+        } else {
+            None
+        }
+    }
+
     fn clean_up_container(&mut self) -> Result<()> {
+        agent::remove_socket(&self.name);
+        shellinit::remove(&self.name);
         debug!(
             "{}",
             format!(
                 "cleaning up {} path(s) ♥",
                 self.created_directories.len() + self.created_files.len()
             )
-            .if_supports_color(owo_colors::Stream::Stdout, |text| text.fg::<PinkSalmon>())
+            .if_supports_color(owo_colors::Stream::Stderr, |text| text.fg::<PinkSalmon>())
         );
-        for file in &self.created_files {
-            debug!("removing temporary file {}", file.display());
-            std::fs::remove_file(file)?;
+        // Remove nested directories before the directories they're in.
+        let mut directories: Vec<&PathBuf> = self.created_directories.iter().collect();
+        directories.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        let mut kept = vec![];
+        for path in self.created_files.iter().chain(directories) {
+            if std::fs::symlink_metadata(path).is_err() {
+                continue;
+            }
+            if let Some(contents) = placeholder_contents(path)? {
+                kept.push((path, contents));
+                continue;
+            }
+            debug!("removing temporary path {}", path.display());
+            if path.is_dir() {
+                std::fs::remove_dir(path)?;
+            } else {
+                std::fs::remove_file(path)?;
+            }
         }
-        for dir in &self.created_directories {
-            debug!("removing temporary directory {}", dir.display());
-            std::fs::remove_dir(dir)?;
+
+        for (path, contents) in kept {
+            warn!(
+                "kept {} ({contents}): boxxy created it empty for a rule's target, but something wrote to it instead of the rewrite. move what you need out of it, then delete it",
+                path.display()
+            );
         }
 
         Ok(())
     }
 
-    fn run_in_container(&mut self, applicable_rules: &[Rule]) -> Result<isize> {
+    /// Check whether the command is an AppImage, and whether it can run in
+    /// the box. This is done before the box is set up, so that the user
+    /// hears why it can't rather than that the box couldn't be set up.
+    fn detect_appimage(&self) -> Result<Option<AppImage>> {
         // TODO: There HAS to be a better way than this...
         let mut grep = grep::searcher::SearcherBuilder::new().build();
 
@@ -416,6 +1493,7 @@ impl Enclosure {
         let mut found_appimage_help = false;
         let mut found_appimage_mount = false;
         let mut found_appimage_extract = false;
+        let mut found_appimage_extract_and_run = false;
         let matcher = grep::regex::RegexMatcher::new(
             r"(--appimage-help|--appimage-mount|--appimage-extract)",
         )?;
@@ -424,13 +1502,11 @@ impl Enclosure {
             path_to_input_binary,
             // TODO: Write a sink that doesn't care about line numbers and won't raise
             grep::searcher::sinks::UTF8(|_, line| {
-                if line.contains("--appimage-help") {
-                    found_appimage_help = true;
-                } else if line.contains("--appimage-mount") {
-                    found_appimage_mount = true;
-                } else if line.contains("--appimage-extract") {
-                    found_appimage_extract = true;
-                }
+                // The flags are often all on one "line" of the binary.
+                found_appimage_help |= line.contains("--appimage-help");
+                found_appimage_mount |= line.contains("--appimage-mount");
+                found_appimage_extract |= line.contains("--appimage-extract");
+                found_appimage_extract_and_run |= line.contains("--appimage-extract-and-run");
                 Ok(true)
             }),
         )?;
@@ -449,18 +1525,51 @@ impl Enclosure {
             }
         }
 
-        if found_appimage_extract && found_appimage_help && found_appimage_mount && !self_extracting
-        {
+        let is_appimage = found_appimage_extract && found_appimage_help && found_appimage_mount;
+        if is_appimage && !self_extracting && !self.config.appimage {
             return Err(color_eyre::eyre::eyre!(
-                "{program:?} is an AppImage! Please extract it first with --appimage-extract. You can also use --appimage-extract-and-run. For more information, see https://github.com/AppImage/AppImageKit/wiki/FUSE#fallback",
+                "{program:?} is an AppImage! Run it with `boxxy --appimage`, or extract it first with --appimage-extract. You can also use --appimage-extract-and-run. For more information, see https://github.com/AppImage/AppImageKit/wiki/FUSE#fallback",
                 program = self.config.command.get_program()
             ));
         }
+        if self.config.appimage && !is_appimage {
+            warn!(
+                "{:?} doesn't look like an AppImage, ignoring --appimage",
+                self.config.command.get_program()
+            );
+        }
+        let fuse_unusable = Self::fuse_unusable_reason();
+        if let Some(reason) = fuse_unusable {
+            // Older AppImages can't extract themselves on the fly, so they
+            // can only run in the box if FUSE works there.
+            if self.config.appimage && is_appimage && !found_appimage_extract_and_run {
+                return Err(color_eyre::eyre::eyre!(
+                    "{program:?} can only run by mounting itself with FUSE, but FUSE isn't usable in the box: {reason}. Extract it with --appimage-extract and box the extracted AppRun instead",
+                    program = self.config.command.get_program()
+                ));
+            }
+        }
+
+        Ok((self.config.appimage && is_appimage).then_some(AppImage {
+            self_extracting,
+            fuse_unusable,
+        }))
+    }
 
+    fn run_in_container(&mut self, applicable_rules: &[Rule]) -> Result<isize> {
+        profiler::name_process("container");
         self.set_up_container(applicable_rules)?;
 
+        if self.config.private_tmpdir {
+            self.set_up_private_tmpdir()?;
+        }
+        if let Some(appimage) = self.appimage {
+            self.set_up_appimage(appimage)?;
+        }
+
         let pwd = std::env::current_dir()?;
 
+        let root_span = tracing::info_span!("change root").entered();
         if self.config.trace {
             chroot(&self.fs.container_root(&self.name))?;
             chdir(&pwd)?;
@@ -470,6 +1579,7 @@ impl Enclosure {
             umount2(".", MntFlags::MNT_DETACH)?;
             chdir(&pwd)?;
         }
+        drop(root_span);
 
         // Remount rootfs as ro
         if self.config.immutable_root {
@@ -482,6 +1592,36 @@ impl Enclosure {
             self.fs.container_root(&self.name).display()
         );
 
+        if self.config.self_test {
+            selftest::run(
+                applicable_rules,
+                &self.protected_paths,
+                &self.fs,
+                self.config.immutable_root,
+            )?;
+        }
+
+        if self.config.verbose_mounts {
+            eprint!("{}", plan::table(&self.mounts));
+        }
+
+        self.config.rules.rlimits.apply()?;
+        let umasks = applicable_rules.iter().filter_map(|rule| rule.umask);
+        if let Some(umask) =
+            limits::Umask::tightest(self.config.rules.umask.into_iter().chain(umasks))
+        {
+            umask.apply();
+        }
+        if !self.config.verify {
+            self.run_container_init()?;
+        }
+        // Started before tracing, so that the thread serving it isn't traced.
+        let reported = if self.config.control_socket && !self.config.verify {
+            Some(self.start_control_socket()?)
+        } else {
+            None
+        };
+
         // Initiate ptrace with the parent process
         ptrace::traceme()?;
         signal::kill(getpid(), signal::SIGSTOP)?;
@@ -491,14 +1631,37 @@ impl Enclosure {
         debug!("setting CHILD_SUBREAPER to {}", getpid());
         unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, getpid()) };
 
+        if self.config.verify {
+            return Ok(if verify::report(&self.host_views)? {
+                0
+            } else {
+                1
+            });
+        }
+
         // Do the thing!
         debug!("running command: {:?}", self.config.command.get_program());
         info!(
             "{}",
             format!("boxed {:?} ♥", self.config.command.get_program())
-                .if_supports_color(owo_colors::Stream::Stdout, |text| text.fg::<PinkSalmon>())
+                .if_supports_color(owo_colors::Stream::Stderr, |text| text.fg::<PinkSalmon>())
         );
 
+        if let Some(name) = &self.config.serve {
+            let listener = crate::server::bind(name)?;
+            info!("serving box {name}, run commands in it with `boxxy run-in {name} <command>`");
+            let env: Vec<_> = self
+                .config
+                .command
+                .get_envs()
+                .map(|(key, value)| (key.to_os_string(), value.map(|value| value.to_os_string())))
+                .collect();
+            crate::server::serve(listener, &env)?;
+            self.sync_copies(0);
+            self.finish_control_socket(reported)?;
+            return Ok(0);
+        }
+
         debug!("and spawn!");
         let child = self.config.command.spawn()?; // .wait()?;
 
@@ -519,31 +1682,136 @@ impl Enclosure {
             }
         }
 
-        debug!("waiting for child exit...");
-        let child_exit_status = unsafe {
-            let mut exit_status = -1;
-            loop {
-                let mut wstatus = -1;
-                let wpid = libc::wait(&mut wstatus);
-                if wpid == -1 && nix::errno::errno() != libc::ECHILD {
-                    warn!("!!! NOT ECHLD");
-                    break;
-                }
-                if wpid == child.id() as i32 {
-                    debug!("primary child exited with status {wstatus}!");
-                    exit_status = wstatus;
-                }
-                if exit_status >= 0 && wpid == -1 {
-                    debug!("execution finished!");
-                    break;
-                }
+        let mut child_exit_status = Self::wait_for_children(child.id() as i32);
+        debug!("command exited with status: {:?}", child);
+
+        // Run any follow-up commands in the same container, stopping at the
+        // first failure like `&&` would.
+        for line in &self.config.and_then {
+            if child_exit_status != 0 {
+                debug!("previous command failed, not running: {line}");
+                break;
             }
-            exit_status
+
+            info!("and then: {line}");
+            let child = self.shell(line).spawn()?;
+            child_exit_status = Self::wait_for_children(child.id() as i32);
+            debug!("command exited with status: {child_exit_status}");
+        }
+        self.sync_copies(child_exit_status);
+        self.finish_control_socket(reported)?;
+
+        Ok(child_exit_status as isize)
+    }
+
+    /// Serve `--control-socket` from a thread of the container process, and
+    /// tell the boxxed program where it is.
+    fn start_control_socket(&mut self) -> Result<control::ReportedPaths> {
+        let path = control::socket_path(&self.name)?;
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        self.config.command.env(control::SOCKET_ENV, &path);
+        debug!("serving the control socket at {}", path.display());
+
+        let reported = control::ReportedPaths::default();
+        let serving = reported.clone();
+        let rules = self.config.rules.rules.clone();
+        thread::spawn(move || control::serve(listener, rules, serving));
+        Ok(reported)
+    }
+
+    /// Remove the control socket, and write the paths reported over it to
+    /// `boxxy-usage.txt`.
+    fn finish_control_socket(&self, reported: Option<control::ReportedPaths>) -> Result<()> {
+        let Some(reported) = reported else {
+            return Ok(());
         };
+        std::fs::remove_file(control::socket_path(&self.name)?)?;
+        let paths = reported
+            .lock()
+            .map_err(|_| color_eyre::eyre::eyre!("reported paths are poisoned"))?;
+        if !paths.is_empty() {
+            report::write(&paths, None, File::create("./boxxy-usage.txt")?)?;
+            info!("wrote {} reported path(s) to boxxy-usage.txt", paths.len());
+        }
+        Ok(())
+    }
 
-        debug!("command exited with status: {:?}", child);
+    /// `sh -c line`, with the boxxed program's environment.
+    fn shell(&self, line: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(line);
+        for (key, value) in self.config.command.get_envs() {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+        command
+    }
+
+    /// Run the config's `container_init` commands in the container, with the
+    /// boxxed program's environment, stopping at the first that fails.
+    fn run_container_init(&self) -> Result<()> {
+        let _span = tracing::info_span!("container init").entered();
+        for line in &self.config.rules.container_init {
+            debug!("container init: {line}");
+            let status = self.shell(line).status()?;
+            if !status.success() {
+                return Err(color_eyre::eyre::eyre!(
+                    "container_init command `{line}` failed ({status}), not running the command"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for `primary` and every other process reparented to us to exit,
+    /// returning the exit code of `primary` (or 128 + the signal that killed
+    /// it).
+    fn wait_for_children(primary: i32) -> i32 {
+        debug!("waiting for child exit...");
+        let mut exit_status = -1;
+        loop {
+            let mut wstatus = -1;
+            let wpid = unsafe { libc::wait(&mut wstatus) };
+            if wpid == -1 && Errno::last_raw() != libc::ECHILD {
+                warn!("!!! NOT ECHLD");
+                break;
+            }
+            if wpid == primary {
+                debug!("primary child exited with status {wstatus}!");
+                exit_status = if libc::WIFSIGNALED(wstatus) {
+                    128 + libc::WTERMSIG(wstatus)
+                } else {
+                    libc::WEXITSTATUS(wstatus)
+                };
+            }
+            if exit_status >= 0 && wpid == -1 {
+                debug!("execution finished!");
+                break;
+            }
+        }
 
-        Ok(child_exit_status.try_into()?)
+        exit_status
+    }
+
+    /// Copy the target's xattrs onto a rewrite that was just created, if the
+    /// rule asks for it and the target exists.
+    fn maybe_copy_xattrs(
+        &self,
+        rule: &Rule,
+        target_path: &Path,
+        rewrite_path: &Path,
+    ) -> Result<()> {
+        if rule.copy_xattrs && target_path.exists() {
+            self.fs.copy_xattrs(target_path, rewrite_path)?;
+        }
+
+        Ok(())
     }
 
     fn ensure_file(&self, path: &Path) -> Result<bool> {
@@ -569,3 +1837,27 @@ impl Enclosure {
         }
     }
 }
+
+/// Write zeroes to `path` until the filesystem it's on runs out of space.
+fn fill_filesystem(path: &Path) -> Result<()> {
+    let mut file = File::create(path)?;
+    let zeroes = [0u8; 4096];
+    loop {
+        match file.write(&zeroes) {
+            Ok(0) => return Ok(()),
+            Ok(_) => continue,
+            Err(err) if err.raw_os_error() == Some(libc::ENOSPC) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Whether a `mode: copy` rule copies a directory rather than a file. The
+/// rewrite decides, or the target, if the rewrite doesn't exist yet.
+fn copies_directory(rewrite: &Path, target: &Path) -> bool {
+    if rewrite.exists() {
+        rewrite.is_dir()
+    } else {
+        !target.is_file()
+    }
+}
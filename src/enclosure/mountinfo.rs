@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use super::fs::{FsDriver, MOUNT_SOURCE_PREFIX};
+use super::rule::Rule;
+
+/// A line of `/proc/<pid>/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    /// The directory in the mounted filesystem that's mounted, ex. a bind
+    /// mount's source.
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String,
+}
+
+/// Parse the contents of a mountinfo file. See proc(5) for its layout.
+pub fn parse(text: &str) -> Result<Vec<MountInfo>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            // Optional fields end at a lone `-`.
+            let separator = fields
+                .iter()
+                .skip(6)
+                .position(|field| *field == "-")
+                .map(|i| i + 6)
+                .filter(|i| fields.len() > i + 2)
+                .ok_or_else(|| eyre!("invalid mountinfo line: {line}"))?;
+            Ok(MountInfo {
+                root: PathBuf::from(unescape(fields[3])),
+                mount_point: PathBuf::from(unescape(fields[4])),
+                fs_type: fields[separator + 1].to_string(),
+                source: unescape(fields[separator + 2]),
+            })
+        })
+        .collect()
+}
+
+/// Undo mountinfo's octal escapes, ex. `\040` for a space.
+fn unescape(field: &str) -> String {
+    let mut out = String::new();
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let code = rest.get(i + 1..i + 4);
+        match code.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Which of `mounts` boxxy made and why, as `(mount, explanation)` pairs.
+/// Mounts are matched to the rule whose target they're on, or else to
+/// boxxy by their source, if boxxy tagged it, or by being in a container
+/// root. Mounts read from outside the box, with `--pid`, are under its
+/// container root, which is taken off before they're matched to rules.
+pub fn explain<'a>(
+    mounts: &'a [MountInfo],
+    rules: &[Rule],
+    fs: &FsDriver,
+) -> Result<Vec<(&'a MountInfo, String)>> {
+    let mut targets = vec![];
+    for rule in rules.iter().filter(|rule| rule.is_enabled()) {
+        let rule = rule.render_templates(None)?;
+        let rewrite = if rule.has_rewrite() {
+            Some(fs.fully_expand_path(&rule.rewrite)?)
+        } else {
+            None
+        };
+        targets.push((fs.fully_expand_path(&rule.target)?, rewrite, rule));
+    }
+    let containers_root = fs.all_containers_root();
+
+    let mut explained = vec![];
+    for mount in mounts {
+        let tag = mount
+            .source
+            .strip_prefix(MOUNT_SOURCE_PREFIX)
+            .map(|name| format!(", in box {name}"))
+            .unwrap_or_default();
+        let mount_point = in_box(&mount.mount_point, &containers_root)
+            .unwrap_or_else(|| mount.mount_point.clone());
+        // Later rules are mounted over earlier ones.
+        let rule = targets
+            .iter()
+            .rev()
+            .find(|(target, _, _)| *target == mount_point);
+        let explanation = match rule {
+            Some((_, rewrite, rule)) => {
+                let how = if mount.fs_type == "tmpfs" {
+                    "tmpfs".to_string()
+                } else if rewrite.as_deref() == Some(mount.root.as_path()) {
+                    format!("bind mount of its rewrite {}", mount.root.display())
+                } else {
+                    format!("bind mount of {}", mount.root.display())
+                };
+                format!("rule '{}': {how}{tag}", rule.name)
+            }
+            None if !tag.is_empty() => format!("tmpfs set up by boxxy{tag}"),
+            None if mount.mount_point.starts_with(&containers_root) => {
+                "boxxy's container root".to_string()
+            }
+            None => continue,
+        };
+        explained.push((mount, explanation));
+    }
+
+    Ok(explained)
+}
+
+/// Where `mount_point`, if it's in a container root under `containers_root`,
+/// is in the box.
+fn in_box(mount_point: &Path, containers_root: &Path) -> Option<PathBuf> {
+    let mut components = mount_point.strip_prefix(containers_root).ok()?.components();
+    // The box's name.
+    components.next()?;
+    Some(Path::new("/").join(components.as_path()))
+}
+
+/// The mountinfo file of `pid`, or of this process.
+pub fn path(pid: Option<u32>) -> PathBuf {
+    match pid {
+        Some(pid) => Path::new("/proc").join(pid.to_string()).join("mountinfo"),
+        None => PathBuf::from("/proc/self/mountinfo"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclosure::rule::RuleMode;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_explain_mountinfo() -> Result<()> {
+        let home = Scratch::new("mountinfo")?;
+        std::fs::create_dir_all(home.join(".aws"))?;
+        std::fs::create_dir_all(home.join("aws config"))?;
+        let escaped = home.to_string_lossy();
+        let fs = FsDriver::new();
+        let container_root = fs.container_root("brave-bird");
+        let container_root = container_root.to_string_lossy();
+        let text = format!(
+            "22 1 0:21 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 {escaped}/aws\\040config {escaped}/.aws rw,relatime shared:1 - ext4 /dev/sda1 rw
+24 22 0:30 / {escaped}/.cache rw,relatime - tmpfs boxxy-brave-bird rw
+25 22 0:31 / /run rw - tmpfs tmpfs rw
+26 22 0:21 {escaped}/aws\\040config {container_root}{escaped}/.aws rw - ext4 /dev/sda1 rw
+27 22 0:32 / {container_root}/tmp rw - tmpfs tmpfs rw
+"
+        );
+        let mounts = parse(&text)?;
+        assert_eq!(mounts.len(), 6);
+        assert_eq!(mounts[1].root, home.join("aws config"));
+        assert_eq!(mounts[2].source, "boxxy-brave-bird");
+        assert!(parse("1 2 3").is_err());
+
+        let rules = [
+            Rule::builder("aws", home.join(".aws").to_string_lossy(), "")
+                .with_mode(RuleMode::Tmpfs)
+                .build(),
+            Rule::builder(
+                "aws",
+                home.join(".aws").to_string_lossy(),
+                home.join("aws config").to_string_lossy(),
+            )
+            .build(),
+        ];
+        let explained: Vec<(PathBuf, String)> = explain(&mounts, &rules, &fs)?
+            .into_iter()
+            .map(|(mount, explanation)| (mount.mount_point.clone(), explanation))
+            .collect();
+        assert_eq!(
+            explained,
+            [
+                (
+                    home.join(".aws"),
+                    format!("rule 'aws': bind mount of its rewrite {escaped}/aws config")
+                ),
+                (
+                    home.join(".cache"),
+                    "tmpfs set up by boxxy, in box brave-bird".to_string()
+                ),
+                // Read from outside the box.
+                (
+                    PathBuf::from(format!("{container_root}{escaped}/.aws")),
+                    format!("rule 'aws': bind mount of its rewrite {escaped}/aws config")
+                ),
+                (
+                    PathBuf::from(format!("{container_root}/tmp")),
+                    "boxxy's container root".to_string()
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+}
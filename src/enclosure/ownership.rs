@@ -0,0 +1,216 @@
+//! The `permissions` and `owner` of the paths a rule makes boxxy create.
+
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use nix::unistd::{chown, Gid, Group, Uid, User};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::limits::deserialize_octal;
+
+/// Mode bits, written in octal, ex. `0700`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions(pub u32);
+
+impl FromStr for Permissions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0o").unwrap_or(s);
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(Permissions(mode)),
+            _ => Err(format!(
+                "invalid permissions: {s}, expected octal, ex. `0700`"
+            )),
+        }
+    }
+}
+
+impl Serialize for Permissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:04o}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Permissions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_octal(deserializer, "permissions", "0700")
+    }
+}
+
+/// A user and group, by name or id, written `user`, `user:group`, or
+/// `:group`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Owner {
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let owner = Owner {
+            user: Some(user)
+                .filter(|user| !user.is_empty())
+                .map(str::to_string),
+            group: group.filter(|group| !group.is_empty()).map(str::to_string),
+        };
+        if owner.user.is_none() && owner.group.is_none() {
+            return Err(format!(
+                "invalid owner: {s:?}, expected `user`, `user:group`, or `:group`"
+            ));
+        }
+        Ok(owner)
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.user.as_deref().unwrap_or_default())?;
+        if let Some(group) = &self.group {
+            write!(f, ":{group}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Owner {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Owner {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Owner {
+    /// The ids of the user and group, looking names up on the host. The box
+    /// maps ids to themselves, so they're the same in it.
+    pub fn resolve(&self) -> Result<(Option<Uid>, Option<Gid>)> {
+        let uid = match &self.user {
+            Some(user) => Some(match user.parse() {
+                Ok(id) => Uid::from_raw(id),
+                Err(_) => {
+                    User::from_name(user)?
+                        .ok_or_else(|| eyre!("no such user: {user}"))?
+                        .uid
+                }
+            }),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => Some(match group.parse() {
+                Ok(id) => Gid::from_raw(id),
+                Err(_) => {
+                    Group::from_name(group)?
+                        .ok_or_else(|| eyre!("no such group: {group}"))?
+                        .gid
+                }
+            }),
+            None => None,
+        };
+        Ok((uid, gid))
+    }
+
+    /// `uid=` and `gid=` options for a tmpfs owned by this owner.
+    pub fn tmpfs_options(&self) -> Result<String> {
+        let (uid, gid) = self.resolve()?;
+        let mut options = vec![];
+        if let Some(uid) = uid {
+            options.push(format!("uid={uid}"));
+        }
+        if let Some(gid) = gid {
+            options.push(format!("gid={gid}"));
+        }
+        Ok(options.join(","))
+    }
+}
+
+/// Give `path`, which boxxy just created, the `permissions` and `owner` its
+/// rule asks for. Only ids mapped into the box can be used, which for most
+/// users means their own user and groups.
+pub fn apply(path: &Path, permissions: Option<Permissions>, owner: Option<&Owner>) -> Result<()> {
+    if let Some(owner) = owner {
+        let (uid, gid) = owner.resolve()?;
+        debug!("chowning {path:?} to {owner}");
+        chown(path, uid, gid).map_err(|err| {
+            eyre!(
+                "could not make {owner} the owner of {}: {err}; only your user and groups are mapped into the box",
+                path.display()
+            )
+        })?;
+    }
+    if let Some(Permissions(mode)) = permissions {
+        debug!("setting permissions of {path:?} to {mode:04o}");
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_permissions_and_owner() -> Result<()> {
+        assert_eq!(
+            serde_yaml::from_str::<Permissions>("0700")?,
+            Permissions(0o700)
+        );
+        assert_eq!(
+            serde_yaml::from_str::<Permissions>("\"0o750\"")?,
+            Permissions(0o750)
+        );
+        assert_eq!(
+            serde_yaml::from_str::<Permissions>("\"0o644\"")?,
+            Permissions(0o644)
+        );
+        assert!(serde_yaml::from_str::<Permissions>("\"0800\"").is_err());
+        // Unquoted, these are numbers, ex. 0o644 is 420.
+        for unquoted in ["0o644", "0o600", "644"] {
+            let err = serde_yaml::from_str::<Permissions>(unquoted).unwrap_err();
+            assert!(err.to_string().contains("must be quoted"), "{err}");
+        }
+        assert_eq!(serde_yaml::to_string(&Permissions(0o700))?, "'0700'\n");
+
+        let owner: Owner = serde_yaml::from_str("\"me:wheel\"")?;
+        assert_eq!(owner.user.as_deref(), Some("me"));
+        assert_eq!(owner.group.as_deref(), Some("wheel"));
+        let group_only: Owner = ":1234".parse().map_err(|err: String| eyre!(err))?;
+        assert_eq!(group_only.user, None);
+        assert_eq!(group_only.to_string(), ":1234");
+        assert_eq!(group_only.tmpfs_options()?, "gid=1234");
+        assert!(":".parse::<Owner>().is_err());
+
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let dir = Scratch::new("ownership")?;
+        let owner: Owner = format!("{uid}:{gid}")
+            .parse()
+            .map_err(|err: String| eyre!(err))?;
+        apply(&dir, Some(Permissions(0o700)), Some(&owner))?;
+        let metadata = std::fs::metadata(&dir)?;
+        assert_eq!(metadata.mode() & 0o7777, 0o700);
+        assert_eq!(metadata.uid(), uid.as_raw());
+        assert_eq!(metadata.gid(), gid.as_raw());
+
+        Ok(())
+    }
+}
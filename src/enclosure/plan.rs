@@ -0,0 +1,319 @@
+use std::path::{Path, PathBuf};
+
+use super::copies_directory;
+use super::rule::{MountKey, Rule, RuleMode};
+
+/// A mount that a rule was applied with, for `--verbose-mounts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMount {
+    pub rule: String,
+    pub source: String,
+    /// Where the mount is, as the boxxed program sees it.
+    pub destination: PathBuf,
+    pub flags: String,
+    /// How the rule was applied, ex. `bind` or `tmpfs`.
+    pub strategy: String,
+}
+
+/// A file or directory that setting up a rule creates, if it doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Creation {
+    pub path: PathBuf,
+    pub directory: bool,
+    /// Whether it's the rule's rewrite, rather than somewhere to mount it.
+    pub rewrite: bool,
+}
+
+/// How `rule` is mounted at `destination`, without mounting anything.
+/// `target` and `rewrite` are the rule's expanded paths on the host.
+pub fn mount_for(rule: &Rule, target: &Path, rewrite: &Path, destination: PathBuf) -> PlannedMount {
+    let rewrite = rewrite.display().to_string();
+    let (source, flags, strategy) = if rule.deny {
+        (
+            "(empty)".to_string(),
+            flags(rule, true, true),
+            "deny".to_string(),
+        )
+    } else {
+        match rule.mode {
+            RuleMode::Tmpfs => (
+                "tmpfs".to_string(),
+                flags(rule, false, false),
+                "tmpfs".to_string(),
+            ),
+            RuleMode::Mask => ("-".to_string(), "-".to_string(), "mask".to_string()),
+            RuleMode::Copy => (rewrite, flags(rule, false, false), "copy".to_string()),
+            RuleMode::ExposeRo => (
+                target.display().to_string(),
+                flags(rule, true, true),
+                "expose-ro".to_string(),
+            ),
+            RuleMode::File | RuleMode::Directory => match rule.faultmode {
+                Some(fault) => (
+                    rewrite,
+                    "-".to_string(),
+                    format!("fault: {}", format!("{fault:?}").to_lowercase()),
+                ),
+                None => (
+                    rewrite,
+                    flags(rule, rule.readonly, true),
+                    "bind".to_string(),
+                ),
+            },
+        }
+    };
+
+    PlannedMount {
+        rule: rule.name.clone(),
+        source,
+        destination,
+        flags,
+        strategy,
+    }
+}
+
+/// What setting up `rule` on the host needs to exist, in the order it's
+/// created. `target` and `rewrite` are the rule's expanded paths.
+pub fn creations(rule: &Rule, target: &Path, rewrite: &Path) -> Vec<Creation> {
+    let creation = |path: &Path, directory, rewrite| Creation {
+        path: path.to_path_buf(),
+        directory,
+        rewrite,
+    };
+    let mut creations = vec![];
+    match rule.mode {
+        RuleMode::File | RuleMode::Directory | RuleMode::Tmpfs => {
+            let directory = rule.mode != RuleMode::File;
+            if rule.has_rewrite() {
+                creations.push(creation(rewrite, directory, true));
+            }
+            creations.push(creation(target, directory, false));
+        }
+        // The real target is exposed or hidden as it is, so there's nothing
+        // to create.
+        RuleMode::ExposeRo | RuleMode::Mask => {}
+        // The copy is mounted over the target, so it has to exist.
+        RuleMode::Copy => {
+            let directory = copies_directory(rewrite, target);
+            creations.push(creation(rewrite, directory, true));
+            creations.push(creation(target, directory, false));
+        }
+    }
+    creations
+}
+
+/// `rw` or `ro`, `rec` for recursive bind mounts, then the rule's
+/// `mount_options`.
+pub fn flags(rule: &Rule, read_only: bool, recursive: bool) -> String {
+    let mut flags = vec![if read_only { "ro" } else { "rw" }.to_string()];
+    if recursive {
+        flags.push("rec".to_string());
+    }
+    flags.extend(
+        rule.mount_options
+            .iter()
+            .map(|option| format!("{option:?}").to_lowercase()),
+    );
+    flags.join(",")
+}
+
+/// `mounts` as an aligned table, one mount per line.
+pub fn table(mounts: &[PlannedMount]) -> String {
+    let header = ["RULE", "SOURCE", "DESTINATION", "FLAGS", "STRATEGY"].map(str::to_string);
+    let rows: Vec<[String; 5]> = std::iter::once(header)
+        .chain(mounts.iter().map(|mount| {
+            [
+                mount.rule.clone(),
+                mount.source.clone(),
+                mount.destination.display().to_string(),
+                mount.flags.clone(),
+                mount.strategy.clone(),
+            ]
+        }))
+        .collect();
+    align(&rows)
+}
+
+/// The order rules are mounted in, for `--show-mount-order`, as an aligned
+/// table with what they were sorted by.
+pub fn order_table(keys: &[MountKey]) -> String {
+    let header = ["#", "RULE", "PRIORITY", "DEPTH", "TARGET"].map(str::to_string);
+    let rows: Vec<[String; 5]> = std::iter::once(header)
+        .chain(keys.iter().enumerate().map(|(i, key)| {
+            [
+                (i + 1).to_string(),
+                key.rule.name.clone(),
+                key.priority.to_string(),
+                key.depth.to_string(),
+                key.target.display().to_string(),
+            ]
+        }))
+        .collect();
+    align(&rows)
+}
+
+fn align<const N: usize>(rows: &[[String; N]]) -> String {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (i, (cell, width)) in row.iter().zip(widths).enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            line.push_str(cell);
+            line.extend(std::iter::repeat_n(' ', width - cell.chars().count()));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclosure::rule::{FaultMode, MountOption};
+
+    #[test]
+    fn test_table() {
+        let rule = Rule::builder("cache", "~/.cache", "")
+            .with_mount_option(MountOption::Noexec)
+            .build();
+        assert_eq!(flags(&rule, false, false), "rw,noexec");
+        assert_eq!(
+            flags(&Rule::builder("a", "~/.a", "~/b").build(), true, true),
+            "ro,rec"
+        );
+
+        let (target, rewrite) = (
+            Path::new("/home/me/.aws"),
+            Path::new("/home/me/.config/aws"),
+        );
+        let mount = |rule: Rule| mount_for(&rule, target, rewrite, target.to_path_buf());
+        let bind = mount(Rule::builder("aws", "~/.aws", "~/.config/aws").build());
+        assert_eq!(
+            (
+                bind.source.as_str(),
+                bind.flags.as_str(),
+                bind.strategy.as_str()
+            ),
+            ("/home/me/.config/aws", "rw,rec", "bind")
+        );
+        let deny = mount(
+            Rule::builder("aws", "~/.aws", "")
+                .with_deny(true)
+                .with_mode(RuleMode::Tmpfs)
+                .build(),
+        );
+        assert_eq!(
+            (deny.source.as_str(), deny.strategy.as_str()),
+            ("(empty)", "deny")
+        );
+        let fault = mount(
+            Rule::builder("aws", "~/.aws", "~/.config/aws")
+                .with_faultmode(FaultMode::Eacces)
+                .build(),
+        );
+        assert_eq!(fault.strategy, "fault: eacces");
+        let exposed = mount(
+            Rule::builder("aws", "~/.aws", "")
+                .with_mode(RuleMode::ExposeRo)
+                .build(),
+        );
+        assert_eq!(
+            (exposed.source.as_str(), exposed.flags.as_str()),
+            ("/home/me/.aws", "ro,rec")
+        );
+
+        let mounts = vec![
+            PlannedMount {
+                rule: "aws".to_string(),
+                source: "/home/me/.config/aws".to_string(),
+                destination: PathBuf::from("/home/me/.aws"),
+                flags: "rw,rec".to_string(),
+                strategy: "bind".to_string(),
+            },
+            PlannedMount {
+                rule: "cache".to_string(),
+                source: "tmpfs".to_string(),
+                destination: PathBuf::from("/home/me/.cache"),
+                flags: "rw,noexec".to_string(),
+                strategy: "tmpfs".to_string(),
+            },
+        ];
+        assert_eq!(
+            table(&mounts),
+            "RULE   SOURCE                DESTINATION      FLAGS      STRATEGY
+aws    /home/me/.config/aws  /home/me/.aws    rw,rec     bind
+cache  tmpfs                 /home/me/.cache  rw,noexec  tmpfs
+"
+        );
+    }
+
+    #[test]
+    fn test_order_table() -> color_eyre::Result<()> {
+        let rules = [
+            Rule::builder("foo", "/boxxy/config/foo", "/a").build(),
+            Rule::builder("config", "/boxxy/config", "/b")
+                .with_priority(-1)
+                .build(),
+        ];
+        let keys = crate::enclosure::rule::mount_order_keys(
+            &rules,
+            &crate::enclosure::fs::FsDriver::new(),
+        )?;
+        assert_eq!(
+            order_table(&keys),
+            "#  RULE    PRIORITY  DEPTH  TARGET
+1  config  -1        3      /boxxy/config
+2  foo     0         4      /boxxy/config/foo
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_creations() {
+        let (target, rewrite) = (Path::new("/boxxy/missing/.aws"), Path::new("/boxxy/aws"));
+        let paths = |rule: Rule| -> Vec<(PathBuf, bool, bool)> {
+            creations(&rule, target, rewrite)
+                .into_iter()
+                .map(|creation| (creation.path, creation.directory, creation.rewrite))
+                .collect()
+        };
+        assert_eq!(
+            paths(Rule::builder("aws", "~/.aws", "~/aws").build()),
+            [(rewrite.into(), true, true), (target.into(), true, false)]
+        );
+        assert_eq!(
+            paths(
+                Rule::builder("aws", "~/.aws", "")
+                    .with_mode(RuleMode::Tmpfs)
+                    .build()
+            ),
+            [(target.into(), true, false)]
+        );
+        assert!(paths(
+            Rule::builder("aws", "~/.aws", "")
+                .with_mode(RuleMode::Mask)
+                .build()
+        )
+        .is_empty());
+        // A copy of a rewrite that doesn't exist yet is a directory, unless
+        // the target is a file.
+        assert_eq!(
+            paths(
+                Rule::builder("aws", "~/.aws", "~/aws")
+                    .with_mode(RuleMode::Copy)
+                    .build()
+            ),
+            [(rewrite.into(), true, true), (target.into(), true, false)]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// The file that `--profile-setup` writes spans to. It's opened before the
+/// container process is cloned, so that both processes can append to it.
+static TRACE_FILE: OnceLock<File> = OnceLock::new();
+
+/// Write every span to `path` as a Chrome trace, which Perfetto and
+/// `chrome://tracing` can show as a flame graph. The closing `]` is left
+/// out, which the format allows, since the box can exit from anywhere.
+pub fn install(path: &Path) -> Result<()> {
+    let mut file =
+        File::create(path).map_err(|err| eyre!("could not create {}: {err}", path.display()))?;
+    file.write_all(b"[\n")?;
+    TRACE_FILE
+        .set(file)
+        .map_err(|_| eyre!("setup is already being profiled"))?;
+    name_process("boxxy");
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(ChromeTrace))?;
+    Ok(())
+}
+
+/// Name this process in the trace, ex. to tell the container process apart
+/// from boxxy's.
+pub fn name_process(name: &str) {
+    write_event(json!({
+        "name": "process_name",
+        "ph": "M",
+        "pid": std::process::id(),
+        "args": { "name": name },
+    }));
+}
+
+fn write_event(event: Value) {
+    if let Some(mut file) = TRACE_FILE.get() {
+        // One write per event, so that the two processes' events don't
+        // interleave.
+        let _ = file.write_all(format!("{event},\n").as_bytes());
+    }
+}
+
+fn now_micros() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros()
+}
+
+/// When a span was entered, and its fields.
+struct Timing {
+    start: u128,
+    entered: Instant,
+    args: Map<String, Value>,
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+/// Writes a complete event for each span when it's exited.
+struct ChromeTrace;
+
+impl<S> Layer<S> for ChromeTrace
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut args = Map::new();
+        attrs.record(&mut Fields(&mut args));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timing {
+                start: now_micros(),
+                entered: Instant::now(),
+                args,
+            });
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                timing.start = now_micros();
+                timing.entered = Instant::now();
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(timing) = extensions.get::<Timing>() else {
+            return;
+        };
+        write_event(json!({
+            "name": span.name(),
+            "cat": span.metadata().target(),
+            "ph": "X",
+            "ts": timing.start as u64,
+            "dur": timing.entered.elapsed().as_micros() as u64,
+            "pid": std::process::id(),
+            "tid": nix::unistd::gettid().as_raw(),
+            "args": timing.args,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chrome_trace() {
+        let mut args = Map::new();
+        let subscriber = tracing_subscriber::registry().with(ChromeTrace);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("apply rule", rule = "aws", count = 2u64);
+            span.in_scope(|| {});
+            span.with_subscriber(|(id, dispatch)| {
+                let registry = dispatch
+                    .downcast_ref::<tracing_subscriber::Registry>()
+                    .unwrap();
+                let span = registry.span(id).unwrap();
+                args = span.extensions().get::<Timing>().unwrap().args.clone();
+            });
+        });
+        assert_eq!(Value::Object(args), json!({ "rule": "aws", "count": 2 }));
+    }
+}
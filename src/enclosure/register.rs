@@ -22,12 +22,43 @@ macro_rules! syscall_number_from_user_regs {
     };
 }
 
+/// Change the syscall a process is about to make, at its syscall-enter stop.
+/// `u64::MAX`, ie. -1, skips the syscall.
+#[cfg(target_arch = "x86_64")]
+macro_rules! set_syscall_number {
+    ($regs: ident, $number: expr) => {
+        $regs.orig_rax = $number
+    };
+}
+
+#[cfg(target_arch = "riscv64")]
+macro_rules! set_syscall_number {
+    ($regs: ident, $number: expr) => {
+        $regs.a7 = $number
+    };
+}
+
+/// Change what a syscall returns, at its syscall-exit stop.
+#[cfg(target_arch = "x86_64")]
+macro_rules! set_return_value {
+    ($regs: ident, $value: expr) => {
+        $regs.rax = $value
+    };
+}
+
+#[cfg(target_arch = "riscv64")]
+macro_rules! set_return_value {
+    ($regs: ident, $value: expr) => {
+        $regs.a0 = $value
+    };
+}
+
 #[cfg(target_arch = "x86_64")]
 string_registers! {
     Rdi,
     Rsi,
     Rdx,
-    Rcx,
+    R10,
     R8,
     R9,
 }
@@ -42,6 +73,27 @@ string_registers! {
     A5
 }
 
+/// The registers that syscall arguments are passed in, in order.
+#[cfg(target_arch = "x86_64")]
+pub const ARG_REGISTERS: [StringRegister; 6] = [
+    StringRegister::Rdi,
+    StringRegister::Rsi,
+    StringRegister::Rdx,
+    StringRegister::R10,
+    StringRegister::R8,
+    StringRegister::R9,
+];
+
+#[cfg(target_arch = "riscv64")]
+pub const ARG_REGISTERS: [StringRegister; 6] = [
+    StringRegister::A0,
+    StringRegister::A1,
+    StringRegister::A2,
+    StringRegister::A3,
+    StringRegister::A4,
+    StringRegister::A5,
+];
+
 #[cfg(target_arch = "x86_64")]
 macro_rules! get_register_from_regs {
     ($string_register: expr, $registers: ident) => {
@@ -49,7 +101,7 @@ macro_rules! get_register_from_regs {
             StringRegister::Rdi => $registers.rdi,
             StringRegister::Rsi => $registers.rsi,
             StringRegister::Rdx => $registers.rdx,
-            StringRegister::Rcx => $registers.rcx,
+            StringRegister::R10 => $registers.r10,
             StringRegister::R8 => $registers.r8,
             StringRegister::R9 => $registers.r9,
         }
@@ -71,4 +123,6 @@ macro_rules! get_register_from_regs {
 }
 
 pub(crate) use get_register_from_regs;
+pub(crate) use set_return_value;
+pub(crate) use set_syscall_number;
 pub(crate) use syscall_number_from_user_regs;
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+
+/// How many entries a directory can have before the report rolls it up into
+/// one line, unless `--expand-report` is passed.
+pub const ROLLUP_THRESHOLD: usize = 100;
+
+/// Write the `--trace` report: every path the boxxed program touched, one per
+/// line, followed by a `# total: N` line. With `rollup`, a directory with
+/// more entries than that is written as one `dir/ (N files)` line in place of
+/// everything under it, so that trees like `node_modules` don't drown out
+/// the rest. The total always counts every path.
+pub fn write<W: Write>(paths: &[PathBuf], rollup: Option<usize>, mut out: W) -> Result<()> {
+    let rolled_up = rollup.map(|threshold| rolled_up_dirs(paths, threshold));
+    let mut written: HashSet<&Path> = HashSet::new();
+    for path in paths {
+        let dir = rolled_up
+            .as_ref()
+            .and_then(|rolled_up| rolled_up_dir(path, rolled_up));
+        match dir {
+            Some((dir, count)) => {
+                if written.insert(dir) {
+                    writeln!(out, "{} ({count} files)", quote_path(&dir.join("")))?;
+                }
+            }
+            None => writeln!(out, "{}", quote_path(path))?,
+        }
+    }
+    writeln!(out, "# total: {}", paths.len())?;
+
+    Ok(())
+}
+
+/// The directories with more than `threshold` entries in the report, with
+/// how many of the paths are under each.
+fn rolled_up_dirs(paths: &[PathBuf], threshold: usize) -> HashMap<&Path, usize> {
+    let mut entries: HashMap<&Path, HashSet<&OsStr>> = HashMap::new();
+    for path in paths {
+        for ancestor in path.ancestors() {
+            if let (Some(parent), Some(name)) = (ancestor.parent(), ancestor.file_name()) {
+                entries.entry(parent).or_default().insert(name);
+            }
+        }
+    }
+
+    let mut rolled_up: HashMap<&Path, usize> = entries
+        .into_iter()
+        .filter(|(_, entries)| entries.len() > threshold)
+        .map(|(dir, _)| (dir, 0))
+        .collect();
+    for path in paths {
+        for ancestor in path.ancestors() {
+            if let Some(count) = rolled_up.get_mut(ancestor) {
+                *count += 1;
+            }
+        }
+    }
+    rolled_up
+}
+
+/// The outermost rolled-up directory that `path` is in, or is, and how many
+/// paths are under it.
+fn rolled_up_dir<'a>(
+    path: &Path,
+    rolled_up: &HashMap<&'a Path, usize>,
+) -> Option<(&'a Path, usize)> {
+    path.ancestors()
+        .filter_map(|ancestor| rolled_up.get_key_value(ancestor))
+        .last()
+        .map(|(dir, count)| (*dir, *count))
+}
+
+/// Render `path` so that it takes up exactly one line and can be read back
+/// unambiguously. Paths made of printable characters other than spaces,
+/// quotes, and backslashes are written as-is. Anything else is put in double
+/// quotes, with `\"`, `\\`, `\n`, `\t`, and `\xNN` escapes for quotes,
+/// backslashes, control characters, and bytes that aren't valid UTF-8.
+pub fn quote_path(path: &Path) -> String {
+    let bytes = path.as_os_str().as_bytes();
+    let needs_quoting = match std::str::from_utf8(bytes) {
+        Ok(path) => path
+            .chars()
+            .any(|c| c.is_control() || matches!(c, ' ' | '"' | '\\')),
+        Err(_) => true,
+    };
+    if !needs_quoting {
+        return path.to_string_lossy().to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => {
+                    let mut buffer = [0u8; 4];
+                    for byte in c.encode_utf8(&mut buffer).bytes() {
+                        quoted.push_str(&format!("\\x{byte:02x}"));
+                    }
+                }
+                c => quoted.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            quoted.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_path() {
+        let quote = |path: &str| quote_path(Path::new(path));
+        assert_eq!(quote("/home/boxxy/.config"), "/home/boxxy/.config");
+        assert_eq!(
+            quote("/home/boxxy/日本語/ünïcödé"),
+            "/home/boxxy/日本語/ünïcödé"
+        );
+        assert_eq!(quote("/tmp/with space"), "\"/tmp/with space\"");
+        assert_eq!(quote("/tmp/\"quoted\""), "\"/tmp/\\\"quoted\\\"\"");
+        assert_eq!(quote("/tmp/back\\slash"), "\"/tmp/back\\\\slash\"");
+        assert_eq!(quote("/tmp/new\nline"), "\"/tmp/new\\nline\"");
+        assert_eq!(quote("/tmp/bell\u{7}"), "\"/tmp/bell\\x07\"");
+        assert_eq!(
+            quote_path(Path::new(OsStr::from_bytes(b"/tmp/bad\xffutf8"))),
+            "\"/tmp/bad\\xffutf8\""
+        );
+    }
+
+    #[test]
+    fn test_write_report() -> Result<()> {
+        let mut out = vec![];
+        write(
+            &[PathBuf::from("/a b"), PathBuf::from("/c\nd")],
+            Some(ROLLUP_THRESHOLD),
+            &mut out,
+        )?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "\"/a b\"\n\"/c\\nd\"\n# total: 2\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_rolled_up_report() -> Result<()> {
+        let mut paths = vec![PathBuf::from("/app/package.json")];
+        for package in 0..4 {
+            paths.push(PathBuf::from(format!(
+                "/app/node_modules/{package}/index.js"
+            )));
+        }
+        paths.push(PathBuf::from("/app/node_modules/0/lib/util.js"));
+        paths.push(PathBuf::from("/app/src/main.js"));
+        paths.push(PathBuf::from("/app/src/lib.js"));
+
+        let report = |rollup| -> Result<String> {
+            let mut out = vec![];
+            write(&paths, rollup, &mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+        assert_eq!(
+            report(Some(3))?,
+            "/app/package.json\n/app/node_modules/ (5 files)\n/app/src/main.js\n/app/src/lib.js\n# total: 8\n"
+        );
+        assert_eq!(report(None)?.lines().count(), 9);
+        assert_eq!(report(Some(1))?, "/app/ (8 files)\n# total: 8\n");
+        Ok(())
+    }
+}
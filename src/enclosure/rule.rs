@@ -1,35 +1,131 @@
-use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use dotenv_parser::parse_dotenv;
 use log::*;
+use nix::mount::MsFlags;
 use serde::{Deserialize, Serialize};
 
 use super::fs::FsDriver;
+use super::git;
+use super::limits::{Rlimits, Umask};
+use super::ownership::{Owner, Permissions};
+use super::template::{self, TemplateContext};
 
 /// Container for deserialisation
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct BoxxyRules {
+    /// The version of the config file's layout. See `config::schema`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(default)]
     pub rules: Vec<Rule>,
+    /// Named sets of rules that only apply when selected with `--profile`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Vec<Rule>>,
+    /// Named sets of rules that apply when selected with `--workspace`, or
+    /// when the working directory is in one of their `context` directories.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub workspaces: BTreeMap<String, Workspace>,
+    /// Shell commands run in the container after the rules are applied and
+    /// before the boxxed program starts. If any fails, the program isn't run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub container_init: Vec<String>,
+    /// Lines added to the rc snippet that `--shell-init` starts the shell
+    /// with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shell_init: Vec<String>,
+    /// Resource limits for the boxxed program.
+    #[serde(default, skip_serializing_if = "Rlimits::is_empty")]
+    pub rlimits: Rlimits,
+    /// Where rules without a rewrite keep their files, instead of
+    /// `~/.local/share/boxxy/store`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_root: Option<String>,
+    /// A directory to try putting container roots in before
+    /// `/tmp/boxxy-containers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub containers_root: Option<String>,
+    /// The umask the boxxed program runs with, whichever rules apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umask: Option<Umask>,
+    /// Whether to make boxxy's tmpfs mounts with a source naming the box,
+    /// so that they can be told apart in `/proc/self/mountinfo`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tag_mounts: bool,
+    /// Whether to hide the gpg-agent's sockets and the keyrings from boxxed
+    /// programs unless a rule that applies sets `allow_secrets`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict_secrets: bool,
+    /// Whether to make `$HOME` read-only for boxxed programs, except where
+    /// rules rewrite it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ro_home: bool,
+}
+
+/// A named set of rules in a config file, ex. for work or personal projects.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Workspace {
+    /// Directories that select the workspace when the working directory is
+    /// in one of them. Without any, it's only used when picked by name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+/// Why a rule does or doesn't apply to a command, for `boxxy rules list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub applies: bool,
+    /// Each check the rule passed, then the one it failed, if any.
+    pub reasons: Vec<String>,
 }
 
 impl BoxxyRules {
-    pub fn get_all_applicable_rules(&self, binary: &OsStr, fs: &FsDriver) -> Result<Vec<Rule>> {
+    pub fn get_all_applicable_rules(
+        &self,
+        binary: &OsStr,
+        args: &[OsString],
+        fs: &FsDriver,
+    ) -> Result<Vec<Rule>> {
+        self.get_applicable_rules_in(
+            binary,
+            args,
+            &std::env::current_dir()?,
+            |key| std::env::var(key).ok(),
+            fs,
+        )
+    }
+
+    /// The rules that apply to `binary` run from `pwd`, with the
+    /// environment seen through `var`.
+    pub fn get_applicable_rules_in<F: Fn(&str) -> Option<String>>(
+        &self,
+        binary: &OsStr,
+        args: &[OsString],
+        pwd: &Path,
+        var: F,
+        fs: &FsDriver,
+    ) -> Result<Vec<Rule>> {
         let mut applicable_rules = vec![];
 
         for rule in &self.rules {
-            debug!("{}: checking if rule applies to binary", rule.name);
-            if rule.currently_in_context(fs)? && rule.applies_to_binary(binary, fs)? {
-                debug!("{}: rule applies to binary via only + context!", rule.name);
-                applicable_rules.push(rule.clone());
-            } else if rule.applies_to_binary(binary, fs)? {
+            trace!("{}: checking if rule applies to binary", rule.name);
+            let explanation = rule.explain(binary, args, pwd, &var, fs)?;
+            if explanation.applies {
                 debug!(
-                    "{}: rule applies to binary via only but NOT context!",
-                    rule.name
+                    "{}: rule applies: {}",
+                    rule.name,
+                    explanation.reasons.join(", ")
                 );
                 applicable_rules.push(rule.clone());
+            } else if let Some(reason) = explanation.reasons.last() {
+                debug!("{}: rule doesn't apply, {reason}", rule.name);
             }
         }
 
@@ -37,54 +133,491 @@ impl BoxxyRules {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Rule {
     /// The name of this rule
     pub name: String,
     /// The target directory/file of this rule, ie the path that will be
     /// shadowed.
     pub target: String,
-    /// The path to shadow the target with.
+    /// The path to shadow the target with. Not used by deny or tmpfs rules.
+    #[serde(default)]
     pub rewrite: String,
-    /// The mode of the rule, ie whether the target is a file or a directory.
+    /// The mode of the rule, ie whether the target is a file or a directory,
+    /// or should be shadowed with a fresh tmpfs.
     #[serde(default = "default_rule_mode")]
     pub mode: RuleMode,
+    /// The size limit of a tmpfs rule's tmpfs, ex. `512m` or `10%`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
     /// The context of the rule, ie the full path to the directories where this rule applies.
     #[serde(default = "empty_vec")]
     pub context: Vec<String>,
+    /// Globs matched against the root and remote URLs of the git repo that
+    /// the working directory is in, ex. `*github.com*/work-org/*`. The rule
+    /// applies in a matching repo as well as under its `context`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context_git: Vec<String>,
+    /// Directories where this rule doesn't apply, even if they're in its
+    /// context.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_context: Vec<String>,
     /// The binaries that this rule applies to. If this is not specified, or if
-    /// this is an empty list, then the rule applies to all binaries.
+    /// this is an empty list, then the rule applies to all binaries. Entries
+    /// can be globs: ones without a `/` match the binary's file name, ex.
+    /// `python3.*`, and ones with a `/` match its path.
     #[serde(default = "empty_vec")]
     pub only: Vec<String>,
+    /// Arguments that the binary must be run with for this rule to apply,
+    /// ex. `configure` for `aws configure`. Each entry's words must appear
+    /// next to each other, in order, somewhere in the arguments. If this is
+    /// empty, the rule applies whatever the arguments are.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_args: Vec<String>,
     /// Environment variables that this rule applies if it matches. Any env
     /// vars listed here will be injected into the environment of the command
     /// that is being boxxed.
     #[serde(default = "empty_hashmap")]
     pub env: HashMap<String, String>,
+    /// A dotenv file to load environment variables from when this rule
+    /// applies, so that secrets don't have to be written into the config.
+    /// Variables in `env` take precedence over the ones in this file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_file: Option<String>,
+    /// Environment variables whose values are the output of shell commands
+    /// run on the host when this rule applies, ex. `pass show aws/token`,
+    /// so that secrets don't have to be stored in plain text. Variables in
+    /// `env` take precedence over these.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env_cmd: HashMap<String, String>,
+    /// Directories to put at the start of the boxxed program's `PATH`, in
+    /// order, ex. to shim wrapper binaries in front of the real ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prepend_path: Vec<String>,
+    /// Directories to put at the end of the boxxed program's `PATH`, in
+    /// order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub append_path: Vec<String>,
+    /// Environment variables that must match for this rule to apply. A
+    /// variable with no value (`~`) must be unset, `"*"` matches any value,
+    /// and any other value must match exactly.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub when_env: HashMap<String, Option<String>>,
+    /// Instead of the rewrite, make the target fail in a specific way, so
+    /// that programs can be tested against unwritable config dirs or full
+    /// disks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub faultmode: Option<FaultMode>,
+    /// Children of a directory rule's rewrite, relative to it, to hide from
+    /// the boxxed program. Each one is replaced with an empty read-only
+    /// file or directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub omit: Vec<String>,
+    /// Hide the target entirely instead of rewriting it, by covering it with
+    /// an empty, read-only file or directory.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny: bool,
+    /// Mount the rewrite over the target read-only, so that the boxxed
+    /// program can read it but not change it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub readonly: bool,
+    /// Extra flags for the rule's mount, ex. `noexec` to keep a cache from
+    /// being used to run binaries. Not used by deny rules or faultmodes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mount_options: Vec<MountOption>,
+    /// Rules are mounted from the lowest priority to the highest, so when
+    /// targets overlap, the highest priority wins. Rules with the same
+    /// priority are mounted from the shallowest target to the deepest, so
+    /// nested targets stay visible.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Copy the target's extended attributes and POSIX ACLs onto the rewrite
+    /// when boxxy creates it, for tools that rely on them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub copy_xattrs: bool,
+    /// Let the boxxed program use the gpg-agent and the keyrings when this
+    /// rule applies, even with `--strict-secrets`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_secrets: bool,
+    /// The umask the boxxed program runs with when this rule applies. When
+    /// several rules and the config set one, the tightest of them wins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umask: Option<Umask>,
+    /// Shell commands run on the host, outside the box, when the rule is
+    /// applied, before the boxxed program starts, ex. to decrypt a config
+    /// into the rewrite. If any fails, the program isn't run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<String>,
+    /// Shell commands run on the host when the box exits, ex. to encrypt the
+    /// rewrite again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+    /// Labels for picking rules out of a large config, with `--only-tags`,
+    /// `--skip-tags`, and `--log-tags`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// `false` turns the rule off without removing it from the config. It's
+    /// still validated. Rules are enabled unless this says otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// `false` stops boxxy from creating the target or the rewrite when
+    /// they don't exist, so that the command isn't run instead of boxxy
+    /// leaving empty files behind.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create: Option<bool>,
+    /// The mode bits of the target and rewrite when boxxy creates them, and
+    /// of `tmpfs` mounts, instead of what the umask leaves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Permissions>,
+    /// Who owns the target and rewrite when boxxy creates them, and `tmpfs`
+    /// mounts, as `user`, `user:group`, or `:group`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+}
+
+fn is_zero(priority: &i32) -> bool {
+    *priority == 0
+}
+
+/// `path` with the `prepend_path` directories of `rules` before it and their
+/// `append_path` directories after it, in the order of the rules, or `None`
+/// if none of them change it.
+pub fn extend_path(rules: &[Rule], path: Option<&OsStr>) -> Result<Option<OsString>> {
+    if rules
+        .iter()
+        .all(|rule| rule.prepend_path.is_empty() && rule.append_path.is_empty())
+    {
+        return Ok(None);
+    }
+    let expand = |dir: &String| PathBuf::from(shellexpand::tilde(dir).as_ref());
+    let dirs = rules
+        .iter()
+        .flat_map(|rule| rule.prepend_path.iter().map(expand))
+        .chain(path.into_iter().flat_map(std::env::split_paths))
+        .chain(
+            rules
+                .iter()
+                .flat_map(|rule| rule.append_path.iter().map(expand)),
+        );
+    let path = std::env::join_paths(dirs).map_err(|err| eyre!("could not add to PATH: {err}"))?;
+    Ok(Some(path))
+}
+
+/// The order to mount `rules` in: by priority, then by the depth of their
+/// targets, so that parents are mounted before the files and directories in
+/// them, then by target, so that the order doesn't depend on the order the
+/// rules were written in. Rules with the same target keep their order.
+pub fn mount_order<'a>(rules: &'a [Rule], fs: &FsDriver) -> Result<Vec<&'a Rule>> {
+    Ok(mount_order_keys(rules, fs)?
+        .into_iter()
+        .map(|key| key.rule)
+        .collect())
+}
+
+/// Why a rule is mounted where it is in [`mount_order`].
+pub struct MountKey<'a> {
+    pub priority: i32,
+    /// The number of components in the target, after symlinks are resolved,
+    /// since that's where it's mounted.
+    pub depth: usize,
+    pub target: PathBuf,
+    pub rule: &'a Rule,
+}
+
+/// `rules` in [`mount_order`], with what they were sorted by.
+pub fn mount_order_keys<'a>(rules: &'a [Rule], fs: &FsDriver) -> Result<Vec<MountKey<'a>>> {
+    let mut keys = vec![];
+    for rule in rules {
+        let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+        keys.push(MountKey {
+            priority: rule.priority,
+            depth: target.components().count(),
+            target,
+            rule,
+        });
+    }
+    keys.sort_by(|a, b| (a.priority, a.depth, &a.target).cmp(&(b.priority, b.depth, &b.target)));
+
+    Ok(keys)
 }
 
 impl Rule {
-    pub fn currently_in_context(&self, fs: &FsDriver) -> Result<bool> {
-        if self.context.is_empty() {
-            return Ok(true);
+    /// Start building a rule that shadows `target` with `rewrite`.
+    pub fn builder<N, T, R>(name: N, target: T, rewrite: R) -> RuleBuilder
+    where
+        N: Into<String>,
+        T: Into<String>,
+        R: Into<String>,
+    {
+        RuleBuilder {
+            rule: Rule {
+                name: name.into(),
+                target: target.into(),
+                rewrite: rewrite.into(),
+                ..Default::default()
+            },
         }
+    }
 
-        for context in &self.context {
-            debug!("{}: resolving context: {}", self.name, context);
-            let expanded_context = shellexpand::tilde(&context).to_string();
-            let expanded_context = Path::new(&expanded_context).canonicalize()?;
-            let resolved_context = fs.maybe_resolve_symlink(&expanded_context)?;
+    /// Check the parts of the rule that serde can't.
+    pub fn validate(&self) -> Result<()> {
+        if self.mode == RuleMode::ExposeRo && (self.deny || !self.rewrite.is_empty()) {
+            return Err(eyre!(
+                "{}: `mode: expose-ro` exposes the real target, so it can't have a rewrite or be denied",
+                self.name
+            ));
+        }
+        if self.mode == RuleMode::Mask
+            && (self.deny
+                || !self.rewrite.is_empty()
+                || self.faultmode.is_some()
+                || !self.mount_options.is_empty())
+        {
+            return Err(eyre!(
+                "{}: `mode: mask` hides the target entirely, so it can't have a rewrite, deny, faultmode, or mount_options",
+                self.name
+            ));
+        }
+        if self.mode == RuleMode::Copy
+            && (self.deny
+                || self.readonly
+                || self.faultmode.is_some()
+                || !self.omit.is_empty()
+                || !self.mount_options.is_empty())
+        {
+            return Err(eyre!(
+                "{}: `mode: copy` rules can't be denied, or have readonly, faultmode, omit, or mount_options",
+                self.name
+            ));
+        }
+        if self.readonly && !self.has_rewrite() {
+            return Err(eyre!(
+                "{}: readonly only works on rules with a rewrite",
+                self.name
+            ));
+        }
+        if self.copy_xattrs && !self.has_rewrite() {
+            return Err(eyre!(
+                "{}: copy_xattrs only works on rules with a rewrite",
+                self.name
+            ));
+        }
+        if self.mode == RuleMode::ExposeRo && self.faultmode.is_some() {
+            return Err(eyre!(
+                "{}: expose-ro rules are already read-only, and can't have a faultmode",
+                self.name
+            ));
+        }
+        if self.readonly && self.faultmode.is_some() {
+            return Err(eyre!(
+                "{}: readonly can't be combined with a faultmode",
+                self.name
+            ));
+        }
+        if !self.mount_options.is_empty() && (self.deny || self.faultmode.is_some()) {
+            return Err(eyre!(
+                "{}: mount_options can't be combined with deny or a faultmode",
+                self.name
+            ));
+        }
+        for pattern in &self.context_git {
+            glob::Pattern::new(pattern)
+                .map_err(|err| eyre!("{}: invalid context_git {pattern}: {err}", self.name))?;
+        }
+        for program in self.only.iter().filter(|program| is_glob(program)) {
+            glob::Pattern::new(program)
+                .map_err(|err| eyre!("{}: invalid only pattern {program}: {err}", self.name))?;
+        }
+        if let Some(size) = &self.size {
+            if self.mode != RuleMode::Tmpfs {
+                return Err(eyre!("{}: size only works with `mode: tmpfs`", self.name));
+            }
+            let number = size.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G', '%']);
+            if number.is_empty()
+                || size.len() - number.len() > 1
+                || !number.chars().all(|c| c.is_ascii_digit())
+            {
+                return Err(eyre!(
+                    "{}: invalid tmpfs size `{size}`, expected ex. `512m`, `2g`, or `10%`",
+                    self.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
 
-            let pwd = std::env::current_dir()?;
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Whether boxxy creates the rule's target and rewrite if they're
+    /// missing.
+    pub fn creates(&self) -> bool {
+        self.create.unwrap_or(true)
+    }
 
+    /// The rewrite of a rule that doesn't set one: a directory named after
+    /// the rule under `store_root`.
+    pub fn default_rewrite(&self, store_root: &str) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        format!("{}/{name}", store_root.trim_end_matches('/'))
+    }
+
+    /// Whether the rule has any of `tags`.
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        self.tags.iter().any(|tag| tags.contains(tag))
+    }
+
+    /// Whether the target is shadowed with the rewrite, rather than being
+    /// denied, replaced with a tmpfs, exposed read-only, or masked.
+    pub fn has_rewrite(&self) -> bool {
+        !self.deny
+            && !matches!(
+                self.mode,
+                RuleMode::Tmpfs | RuleMode::ExposeRo | RuleMode::Mask
+            )
+    }
+
+    /// Render any `{{ ... }}` templates in the target and rewrite of this
+    /// rule for the given binary. The target is rendered first, so that the
+    /// rewrite can refer to it. If no binary is given, templates that use
+    /// `binary` fail to render.
+    pub fn render_templates(&self, binary: Option<&OsStr>) -> Result<Rule> {
+        let mut ctx = TemplateContext::new();
+        if let Some(binary) = binary {
+            let binary = Path::new(binary).file_name().unwrap_or(binary);
+            ctx = ctx.with_binary(binary.to_string_lossy());
+        }
+
+        let mut rule = self.clone();
+        rule.target = template::render(&self.target, &ctx)?;
+        let expanded_target = super::fs::expand_tilde(&rule.target);
+        rule.rewrite = template::render(&self.rewrite, &ctx.with_target(expanded_target))?;
+
+        if template::is_template(&self.target) || template::is_template(&self.rewrite) {
             debug!(
-                "{}: {} <> {}",
+                "{}: rendered templates: {} -> {}",
+                self.name, rule.target, rule.rewrite
+            );
+        }
+
+        Ok(rule)
+    }
+
+    /// The paths under `target` that `omit` hides. Entries must be relative
+    /// and stay inside the target.
+    pub fn omitted_paths(&self, target: &Path) -> Result<Vec<PathBuf>> {
+        self.omit
+            .iter()
+            .map(|child| {
+                let child = Path::new(child);
+                let escapes = child
+                    .components()
+                    .any(|component| !matches!(component, Component::Normal(_)));
+                if escapes || child.as_os_str().is_empty() {
+                    return Err(eyre!(
+                        "{}: omit entry `{}` must be a relative path inside the target",
+                        self.name,
+                        child.display()
+                    ));
+                }
+                Ok(target.join(child))
+            })
+            .collect()
+    }
+
+    pub fn currently_in_context(&self, fs: &FsDriver) -> Result<bool> {
+        self.in_context_of(&std::env::current_dir()?, fs)
+    }
+
+    /// The flags that the rule's `mount_options` add to its mount.
+    pub fn mount_flags(&self) -> MsFlags {
+        self.mount_options
+            .iter()
+            .fold(MsFlags::empty(), |flags, option| flags | option.flag())
+    }
+
+    /// Whether `pwd` is under one of the rule's `context` directories, if it
+    /// has any, and not under any of its `not_context` ones.
+    fn in_context_of(&self, pwd: &Path, fs: &FsDriver) -> Result<bool> {
+        Ok(self.explain_context(pwd, fs)?.applies)
+    }
+
+    /// Why `pwd` is or isn't in the rule's context, for
+    /// [`Rule::in_context_of`] and [`Rule::explain`].
+    fn explain_context(&self, pwd: &Path, fs: &FsDriver) -> Result<Explanation> {
+        let mut reasons = vec![];
+        if self.context.is_empty() && self.context_git.is_empty() {
+            reasons.push("no `context`, so it applies in every directory".to_string());
+        } else if self.any_contains(&self.context, pwd, fs)? {
+            reasons.push(format!("{} is in its `context`", pwd.display()));
+        } else if self.in_git_context(pwd)? {
+            reasons.push("the git repo here matches its `context_git`".to_string());
+        } else {
+            reasons.push(format!("{} isn't in its `context`", pwd.display()));
+            return Ok(Explanation {
+                applies: false,
+                reasons,
+            });
+        }
+        if self.any_contains(&self.not_context, pwd, fs)? {
+            reasons.push(format!("{} is in its `not_context`", pwd.display()));
+            return Ok(Explanation {
+                applies: false,
+                reasons,
+            });
+        }
+
+        Ok(Explanation {
+            applies: true,
+            reasons,
+        })
+    }
+
+    /// Whether `pwd` is in a git repo whose root or one of whose remote URLs
+    /// matches one of the rule's `context_git` globs.
+    fn in_git_context(&self, pwd: &Path) -> Result<bool> {
+        if self.context_git.is_empty() {
+            return Ok(false);
+        }
+        let Some(root) = git::repo_root(pwd) else {
+            return Ok(false);
+        };
+        let mut candidates = git::remotes(&root);
+        candidates.push(root.to_string_lossy().to_string());
+
+        for pattern in &self.context_git {
+            let pattern = glob::Pattern::new(&shellexpand::tilde(pattern))
+                .map_err(|err| eyre!("{}: invalid context_git {pattern}: {err}", self.name))?;
+            if let Some(matched) = candidates.iter().find(|c| pattern.matches(c)) {
+                debug!("{}: git repo {matched} matches {pattern}", self.name);
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn any_contains(&self, dirs: &[String], pwd: &Path, fs: &FsDriver) -> Result<bool> {
+        for dir in dirs {
+            trace!(
+                "{}: resolving context: {} <> {}",
                 self.name,
                 pwd.display(),
-                resolved_context.display()
+                dir
             );
-
-            if pwd.starts_with(&resolved_context) {
+            if context_contains(dir, pwd, fs)? {
                 return Ok(true);
             }
         }
@@ -92,30 +625,221 @@ impl Rule {
         Ok(false)
     }
 
-    pub fn applies_to_binary(&self, program: &OsStr, fs: &FsDriver) -> Result<bool> {
-        if self.only.is_empty() {
-            return Ok(true);
+    pub fn applies_to_args(&self, args: &[OsString]) -> bool {
+        if self.only_args.is_empty() {
+            return true;
+        }
+
+        self.only_args.iter().any(|pattern| {
+            let words: Vec<&str> = pattern.split_whitespace().collect();
+            !words.is_empty()
+                && args
+                    .windows(words.len())
+                    .any(|window| window.iter().zip(&words).all(|(arg, word)| arg == word))
+        })
+    }
+
+    /// The env vars the rule injects: the ones in `env_file`, then the ones
+    /// in `env`, which win.
+    pub fn env_vars(&self) -> Result<BTreeMap<String, String>> {
+        let mut vars = BTreeMap::new();
+        if let Some(env_file) = &self.env_file {
+            let path = shellexpand::tilde(env_file).to_string();
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| eyre!("{}: could not read env_file {path}: {err}", self.name))?;
+            vars = parse_dotenv(&contents)
+                .map_err(|err| eyre!("{}: could not parse env_file {path}: {err}", self.name))?;
+        }
+        vars.extend(self.env.clone());
+        Ok(vars)
+    }
+
+    /// Run the `env_cmd` commands and set their output in `env`, unless
+    /// `env` already sets the variable. The commands can prompt, ex. for a
+    /// password, and trailing newlines are dropped from their output.
+    pub fn resolve_env_cmd(&mut self) -> Result<()> {
+        let mut commands: Vec<(String, String)> = self.env_cmd.drain().collect();
+        commands.sort();
+        for (key, command) in commands {
+            if self.env.contains_key(&key) {
+                continue;
+            }
+            debug!("{}: running env_cmd for {key}", self.name);
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .stderr(std::process::Stdio::inherit())
+                .output()
+                .map_err(|err| eyre!("{}: could not run env_cmd for {key}: {err}", self.name))?;
+            if !output.status.success() {
+                return Err(eyre!(
+                    "{}: env_cmd for {key} failed ({}), not running the command",
+                    self.name,
+                    output.status
+                ));
+            }
+            let value = String::from_utf8(output.stdout)
+                .map_err(|_| eyre!("{}: env_cmd for {key} didn't print UTF-8", self.name))?;
+            self.env
+                .insert(key, value.trim_end_matches(['\n', '\r']).to_string());
         }
+        Ok(())
+    }
 
+    /// Whether the environment, as seen through `var`, matches `when_env`.
+    pub fn matches_env<F: Fn(&str) -> Option<String>>(&self, var: F) -> bool {
+        self.when_env
+            .iter()
+            .all(|(key, expected)| match (expected.as_deref(), var(key)) {
+                (None, value) => value.is_none(),
+                (Some("*"), value) => value.is_some(),
+                (Some(expected), value) => value.as_deref() == Some(expected),
+            })
+    }
+
+    pub fn applies_to_binary(&self, program: &OsStr, fs: &FsDriver) -> Result<bool> {
+        Ok(self.only.is_empty() || self.matching_only(program, fs)?.is_some())
+    }
+
+    /// The first of the rule's `only` entries that `program` matches.
+    fn matching_only(&self, program: &OsStr, fs: &FsDriver) -> Result<Option<&String>> {
         for rule_binary in &self.only {
+            if is_glob(rule_binary) {
+                if self.test_program_glob(program, rule_binary, fs)? {
+                    debug!("{}: rule applies to binary via {rule_binary}!", self.name);
+                    return Ok(Some(rule_binary));
+                }
+                continue;
+            }
             if self.test_program(program, &PathBuf::from(rule_binary), fs)? {
                 debug!("{}: rule applies to binary!", self.name);
-                return Ok(true);
+                return Ok(Some(rule_binary));
             }
         }
 
-        Ok(false)
+        Ok(None)
+    }
+
+    /// Why the rule does or doesn't apply to `program` run with `args` from
+    /// `pwd`, with the environment seen through `var`. This is what
+    /// [`BoxxyRules::get_applicable_rules_in`] checks, so that `boxxy rules
+    /// list` can't disagree with what a run applies.
+    pub fn explain<F: Fn(&str) -> Option<String>>(
+        &self,
+        program: &OsStr,
+        args: &[OsString],
+        pwd: &Path,
+        var: F,
+        fs: &FsDriver,
+    ) -> Result<Explanation> {
+        let mut reasons = vec![];
+        let program_name = program.to_string_lossy();
+        let fail = |mut reasons: Vec<String>, reason: String| {
+            reasons.push(reason);
+            Ok(Explanation {
+                applies: false,
+                reasons,
+            })
+        };
+
+        if self.only.is_empty() {
+            reasons.push("no `only`, so it applies to every program".to_string());
+        } else {
+            match self.matching_only(program, fs)? {
+                Some(entry) if entry.as_str() == program_name => {
+                    reasons.push(format!("`only` lists {entry}"))
+                }
+                Some(entry) => reasons.push(format!("`only` entry {entry} matches {program_name}")),
+                None => {
+                    return fail(
+                        reasons,
+                        format!(
+                            "none of the `only` entries ({}) match {program_name}",
+                            self.only.join(", ")
+                        ),
+                    )
+                }
+            }
+        }
+
+        if !self.only_args.is_empty() {
+            if !self.applies_to_args(args) {
+                return fail(
+                    reasons,
+                    format!(
+                        "the arguments don't contain any of the `only_args` ({})",
+                        self.only_args.join(", ")
+                    ),
+                );
+            }
+            reasons.push("the arguments match `only_args`".to_string());
+        }
+
+        if !self.when_env.is_empty() {
+            if !self.matches_env(&var) {
+                let mut keys: Vec<&String> = self.when_env.keys().collect();
+                keys.sort();
+                let keys: Vec<&str> = keys.into_iter().map(String::as_str).collect();
+                return fail(
+                    reasons,
+                    format!(
+                        "the environment doesn't match `when_env` ({})",
+                        keys.join(", ")
+                    ),
+                );
+            }
+            reasons.push("the environment matches `when_env`".to_string());
+        }
+
+        let context = self.explain_context(pwd, fs)?;
+        reasons.extend(context.reasons);
+        Ok(Explanation {
+            applies: context.applies,
+            reasons,
+        })
+    }
+
+    /// Whether `program` matches the glob `pattern`: by file name if the
+    /// pattern has no `/`, else by path. The program is matched as given,
+    /// with symlinks resolved, and as found in `PATH`.
+    fn test_program_glob(&self, program: &OsStr, pattern: &str, fs: &FsDriver) -> Result<bool> {
+        let by_path = pattern.contains('/');
+        let pattern = glob::Pattern::new(&shellexpand::tilde(pattern))
+            .map_err(|err| eyre!("{}: invalid only pattern {pattern}: {err}", self.name))?;
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        let expanded = fs.fully_expand_path(program.to_string_lossy().as_ref())?;
+        let mut candidates = vec![PathBuf::from(program), expanded.clone()];
+        candidates.push(fs.maybe_resolve_symlink(&expanded)?);
+        if let Ok(found) = which::which(program) {
+            candidates.push(fs.maybe_resolve_symlink(&found)?);
+            candidates.push(found);
+        }
+
+        Ok(candidates.iter().any(|candidate| {
+            trace!("{}: matching {candidate:?} against {pattern}", self.name);
+            if by_path {
+                pattern.matches_path_with(candidate, options)
+            } else {
+                candidate
+                    .file_name()
+                    .is_some_and(|name| pattern.matches_with(&name.to_string_lossy(), options))
+            }
+        }))
     }
 
     fn test_program(&self, program: &OsStr, rule_binary: &Path, fs: &FsDriver) -> Result<bool> {
-        debug!(
+        trace!(
             "{}: testing program: program={program:?}, rule_binary={rule_binary:?}",
             self.name
         );
 
         // Compare program by file name, ex. ls == ls
         if let Some(file_name) = rule_binary.file_name() {
-            debug!("{}: comparing file names: program={program:?}, rule binary file_name={file_name:?}", self.name);
+            trace!("{}: comparing file names: program={program:?}, rule binary file_name={file_name:?}", self.name);
             if program == file_name {
                 return Ok(true);
             }
@@ -123,16 +847,16 @@ impl Rule {
 
         // Compare by given paths, ex. ls == /usr/bin/ls
         if let Some(path) = rule_binary.to_str() {
-            debug!("{}: comparing binaries by given paths: program={program:?}, rule_binary={rule_binary:?}", self.name);
+            trace!("{}: comparing binaries by given paths: program={program:?}, rule_binary={rule_binary:?}", self.name);
             if program == path {
                 return Ok(true);
             }
         }
 
         // Fully expand rule path and program path, and compare. ex. /usr/bin/ls == /bin/ls
-        let expanded_user_program = fs.fully_expand_path(&program.to_string_lossy().to_string())?;
+        let expanded_user_program = fs.fully_expand_path(program.to_string_lossy().as_ref())?;
         if let Ok(expanded_rule_binary) = rule_binary.canonicalize() {
-            debug!("{}: comparing binaries by full expansion: expanded_user_program={expanded_user_program:?}, expanded_rule_binary={expanded_rule_binary:?}", self.name);
+            trace!("{}: comparing binaries by full expansion: expanded_user_program={expanded_user_program:?}, expanded_rule_binary={expanded_rule_binary:?}", self.name);
             if expanded_rule_binary == expanded_user_program {
                 return Ok(true);
             }
@@ -140,7 +864,7 @@ impl Rule {
             // Resolve rule path and program path as symlinks, and compare. ex. /bin/ls == /bin/ls
             let resolved_rule_binary = fs.maybe_resolve_symlink(&expanded_rule_binary)?;
             let resolved_user_program = fs.maybe_resolve_symlink(&expanded_user_program)?;
-            debug!("{}: comparing binaries as resolved symlinks: resolved_user_program={resolved_user_program:?}, resolved_rule_binary={resolved_rule_binary:?}", self.name);
+            trace!("{}: comparing binaries as resolved symlinks: resolved_user_program={resolved_user_program:?}, resolved_rule_binary={resolved_rule_binary:?}", self.name);
             if resolved_rule_binary == resolved_user_program {
                 return Ok(true);
             }
@@ -148,28 +872,22 @@ impl Rule {
             // If we can't canonicalize the rule binary, try to resolve the
             // user program symlinks.
             let resolved_user_program = fs.maybe_resolve_symlink(&expanded_user_program)?;
-            debug!("{}: comparing rule binary to user program as resolved symlinks: resolved_user_program={resolved_user_program:?}, rule_binary={rule_binary:?}", self.name);
+            trace!("{}: comparing rule binary to user program as resolved symlinks: resolved_user_program={resolved_user_program:?}, rule_binary={rule_binary:?}", self.name);
             if let Some(file_name) = resolved_user_program.file_name() {
                 if file_name == rule_binary {
-                    debug!("{}: rule binary {rule_binary:?} matches user program file name for {resolved_user_program:?}", self.name);
+                    trace!("{}: rule binary {rule_binary:?} matches user program file name for {resolved_user_program:?}", self.name);
                     return Ok(true);
                 }
             } else if rule_binary == resolved_user_program {
-                debug!("{}: rule binary {rule_binary:?} matches user program {resolved_user_program:?}", self.name);
+                trace!("{}: rule binary {rule_binary:?} matches user program {resolved_user_program:?}", self.name);
                 return Ok(true);
             }
         }
 
         // Resolve both program and rule_binary with `which` and compare. ex. /usr/bin/ls == /usr/bin/ls
-        let which_rule_binary = match which::which(rule_binary) {
-            Ok(which_rule_binary) => Some(which_rule_binary),
-            Err(_) => None,
-        };
-        let which_user_program = match which::which(program) {
-            Ok(which_user_program) => Some(which_user_program),
-            Err(_) => None,
-        };
-        debug!("{}: comparing binaries with which(1): which_user_program={which_user_program:?}, which_rule_binary={which_rule_binary:?}", self.name);
+        let which_rule_binary = which::which(rule_binary).ok();
+        let which_user_program = which::which(program).ok();
+        trace!("{}: comparing binaries with which(1): which_user_program={which_user_program:?}, which_rule_binary={which_rule_binary:?}", self.name);
         if which_rule_binary == which_user_program
             && (which_rule_binary.is_some() || which_user_program.is_some())
         {
@@ -193,11 +911,216 @@ fn empty_hashmap<K, V>() -> HashMap<K, V> {
     HashMap::new()
 }
 
+/// Builds a [`Rule`] in code, ex.
+/// `Rule::builder("aws", "~/.aws", "~/.config/aws").with_only("aws").build()`.
+#[derive(Debug, Clone)]
+pub struct RuleBuilder {
+    rule: Rule,
+}
+
+impl RuleBuilder {
+    pub fn with_mode(mut self, mode: RuleMode) -> Self {
+        self.rule.mode = mode;
+        self
+    }
+
+    /// Only apply the rule under `context`. Can be called multiple times.
+    pub fn with_context<S: Into<String>>(mut self, context: S) -> Self {
+        self.rule.context.push(context.into());
+        self
+    }
+
+    /// Apply the rule in git repos whose root or remote matches `pattern`.
+    /// Can be called multiple times.
+    pub fn with_context_git<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.rule.context_git.push(pattern.into());
+        self
+    }
+
+    /// Don't apply the rule under `dir`. Can be called multiple times.
+    pub fn with_not_context<S: Into<String>>(mut self, dir: S) -> Self {
+        self.rule.not_context.push(dir.into());
+        self
+    }
+
+    /// Only apply the rule to `binary`. Can be called multiple times.
+    pub fn with_only<S: Into<String>>(mut self, binary: S) -> Self {
+        self.rule.only.push(binary.into());
+        self
+    }
+
+    /// Only apply the rule when the binary's arguments contain `args`. Can
+    /// be called multiple times.
+    pub fn with_only_args<S: Into<String>>(mut self, args: S) -> Self {
+        self.rule.only_args.push(args.into());
+        self
+    }
+
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.rule.env.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn with_env_file<S: Into<String>>(mut self, path: S) -> Self {
+        self.rule.env_file = Some(path.into());
+        self
+    }
+
+    pub fn with_env_cmd<K: Into<String>, V: Into<String>>(mut self, key: K, command: V) -> Self {
+        self.rule.env_cmd.insert(key.into(), command.into());
+        self
+    }
+
+    pub fn with_prepend_path<S: Into<String>>(mut self, dir: S) -> Self {
+        self.rule.prepend_path.push(dir.into());
+        self
+    }
+
+    pub fn with_append_path<S: Into<String>>(mut self, dir: S) -> Self {
+        self.rule.append_path.push(dir.into());
+        self
+    }
+
+    /// Only apply the rule when `key` is set to `value`, or unset for `None`.
+    pub fn with_when_env<K: Into<String>>(mut self, key: K, value: Option<&str>) -> Self {
+        self.rule
+            .when_env
+            .insert(key.into(), value.map(str::to_string));
+        self
+    }
+
+    pub fn with_faultmode(mut self, faultmode: FaultMode) -> Self {
+        self.rule.faultmode = Some(faultmode);
+        self
+    }
+
+    /// Hide `child` of the rewrite from the boxxed program. Can be called
+    /// multiple times.
+    pub fn with_omit<S: Into<String>>(mut self, child: S) -> Self {
+        self.rule.omit.push(child.into());
+        self
+    }
+
+    /// Limit the size of a tmpfs rule's tmpfs, ex. `512m`.
+    pub fn with_size<S: Into<String>>(mut self, size: S) -> Self {
+        self.rule.size = Some(size.into());
+        self
+    }
+
+    /// Hide the target instead of rewriting it.
+    pub fn with_deny(mut self, deny: bool) -> Self {
+        self.rule.deny = deny;
+        self
+    }
+
+    /// Mount the rewrite over the target read-only.
+    pub fn with_readonly(mut self, readonly: bool) -> Self {
+        self.rule.readonly = readonly;
+        self
+    }
+
+    /// Set the priority that the rule is mounted with.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.rule.priority = priority;
+        self
+    }
+
+    /// Copy the target's xattrs onto the rewrite when it's created.
+    pub fn with_copy_xattrs(mut self, copy_xattrs: bool) -> Self {
+        self.rule.copy_xattrs = copy_xattrs;
+        self
+    }
+
+    pub fn with_allow_secrets(mut self, allow_secrets: bool) -> Self {
+        self.rule.allow_secrets = allow_secrets;
+        self
+    }
+
+    /// Run the boxxed program with at least this umask when the rule
+    /// applies.
+    pub fn with_umask(mut self, umask: Umask) -> Self {
+        self.rule.umask = Some(umask);
+        self
+    }
+
+    /// Run `command` on the host when the rule is applied. Can be called
+    /// multiple times.
+    pub fn with_before<S: Into<String>>(mut self, command: S) -> Self {
+        self.rule.before.push(command.into());
+        self
+    }
+
+    /// Run `command` on the host when the box exits. Can be called multiple
+    /// times.
+    pub fn with_after<S: Into<String>>(mut self, command: S) -> Self {
+        self.rule.after.push(command.into());
+        self
+    }
+
+    /// Mount the rule with `option`. Can be called multiple times.
+    pub fn with_mount_option(mut self, option: MountOption) -> Self {
+        self.rule.mount_options.push(option);
+        self
+    }
+
+    /// Tag the rule with `tag`. Can be called multiple times.
+    pub fn with_tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.rule.tags.push(tag.into());
+        self
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.rule.enabled = Some(enabled);
+        self
+    }
+
+    pub fn with_create(mut self, create: bool) -> Self {
+        self.rule.create = Some(create);
+        self
+    }
+
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.rule.permissions = Some(permissions);
+        self
+    }
+
+    pub fn with_owner(mut self, owner: Owner) -> Self {
+        self.rule.owner = Some(owner);
+        self
+    }
+
+    pub fn build(self) -> Rule {
+        self.rule
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum RuleMode {
     File,
     Directory,
+    /// The target is a directory that's shadowed with a fresh tmpfs, which
+    /// is thrown away when the box exits.
+    Tmpfs,
+    /// The real target, file or directory, is bound over itself read-only,
+    /// so the program can read it but not change it.
+    #[serde(rename = "expose-ro")]
+    ExposeRo,
+    /// The target is hidden, so that looking it up fails with `ENOENT`. Its
+    /// directory is covered with a read-only tmpfs that the rest of the
+    /// directory's entries are bound back into.
+    Mask,
+    /// The rewrite, file or directory, is copied to the target instead of
+    /// being bound over it, and the copy is synced back to the rewrite when
+    /// the box exits cleanly. For programs that replace their config by
+    /// renaming over it, which fails on a mount point.
+    Copy,
+}
+
+impl Default for RuleMode {
+    fn default() -> Self {
+        default_rule_mode()
+    }
 }
 
 impl FromStr for RuleMode {
@@ -207,7 +1130,545 @@ impl FromStr for RuleMode {
         match s {
             "file" => Ok(RuleMode::File),
             "directory" => Ok(RuleMode::Directory),
+            "tmpfs" => Ok(RuleMode::Tmpfs),
+            "expose-ro" => Ok(RuleMode::ExposeRo),
+            "mask" => Ok(RuleMode::Mask),
+            "copy" => Ok(RuleMode::Copy),
             _ => Err(format!("invalid rule mode: {}", s)),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FaultMode {
+    /// Every access to the target fails with `EACCES`.
+    Eacces,
+    /// The target is on a full filesystem, so writes fail with `ENOSPC`.
+    Enospc,
+    /// The rewrite is mounted read-only, so writes fail with `EROFS`.
+    Erofs,
+}
+
+/// A flag that a rule's mount can be made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountOption {
+    /// Ignore setuid and setgid bits.
+    Nosuid,
+    /// Don't allow access to device files.
+    Nodev,
+    /// Don't allow running binaries.
+    Noexec,
+}
+
+impl MountOption {
+    pub fn flag(self) -> MsFlags {
+        match self {
+            Self::Nosuid => MsFlags::MS_NOSUID,
+            Self::Nodev => MsFlags::MS_NODEV,
+            Self::Noexec => MsFlags::MS_NOEXEC,
+        }
+    }
+}
+
+impl FromStr for MountOption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nosuid" => Ok(Self::Nosuid),
+            "nodev" => Ok(Self::Nodev),
+            "noexec" => Ok(Self::Noexec),
+            _ => Err(format!("unknown mount option {s}")),
+        }
+    }
+}
+
+/// Whether an `only` entry is a glob, rather than a program name or path.
+pub fn is_glob(program: &str) -> bool {
+    program.contains(['*', '?', '['])
+}
+
+/// Whether `pwd` is under the context directory `dir`, after expanding `~`
+/// and resolving symlinks. A directory that doesn't exist can't contain the
+/// working directory.
+pub fn context_contains(dir: &str, pwd: &Path, fs: &FsDriver) -> Result<bool> {
+    let expanded_dir = shellexpand::tilde(dir).to_string();
+    let Ok(expanded_dir) = Path::new(&expanded_dir).canonicalize() else {
+        return Ok(false);
+    };
+
+    Ok(pwd.starts_with(fs.maybe_resolve_symlink(&expanded_dir)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_validate() {
+        assert!(Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .build()
+            .validate()
+            .is_ok());
+        assert!(Rule::builder("aws", "~/.aws", "")
+            .build()
+            .validate()
+            .is_ok());
+        assert_eq!(
+            Rule::builder("aws at work/../x", "~/.aws", "")
+                .build()
+                .default_rewrite("~/store/"),
+            "~/store/aws-at-work----x"
+        );
+        assert!(Rule::builder("aws", "~/.aws", "")
+            .with_deny(true)
+            .build()
+            .validate()
+            .is_ok());
+
+        let rules: BoxxyRules =
+            serde_yaml::from_str("rules: [{name: ssh, target: ~/.ssh, deny: true}]").unwrap();
+        assert!(rules.rules[0].deny);
+        assert!(rules.rules[0].validate().is_ok());
+
+        let tmpfs = |size: &str| {
+            Rule::builder("cache", "~/.cache/foo", "")
+                .with_mode(RuleMode::Tmpfs)
+                .with_size(size)
+                .build()
+                .validate()
+        };
+        for size in ["512m", "2G", "10%", "4096"] {
+            assert!(tmpfs(size).is_ok(), "{size}");
+        }
+        for size in ["", "m", "1.5g", "10mb", "-1"] {
+            assert!(tmpfs(size).is_err(), "{size}");
+        }
+        assert!(Rule::builder("cache", "~/.cache/foo", "~/.cache/bar")
+            .with_size("1g")
+            .build()
+            .validate()
+            .is_err());
+
+        let readonly = |rule: RuleBuilder| rule.with_readonly(true).build().validate();
+        assert!(readonly(Rule::builder("git", "~/.gitconfig", "~/.config/git/config")).is_ok());
+        assert!(readonly(Rule::builder("ssh", "~/.ssh", "").with_deny(true)).is_err());
+        assert!(
+            readonly(Rule::builder("cache", "~/.cache", "").with_mode(RuleMode::Tmpfs)).is_err()
+        );
+        assert!(readonly(
+            Rule::builder("git", "~/.gitconfig", "~/.config/git/config")
+                .with_faultmode(FaultMode::Erofs)
+        )
+        .is_err());
+
+        let rules: BoxxyRules =
+            serde_yaml::from_str("rules: [{name: docs, target: /usr/share/doc, mode: expose-ro}]")
+                .unwrap();
+        assert_eq!(rules.rules[0].mode, RuleMode::ExposeRo);
+        assert!(!rules.rules[0].has_rewrite());
+        assert!(rules.rules[0].validate().is_ok());
+        let expose = |rule: RuleBuilder| rule.with_mode(RuleMode::ExposeRo).build().validate();
+        assert!(expose(Rule::builder("docs", "/usr/share/doc", "~/docs")).is_err());
+        assert!(expose(Rule::builder("docs", "/usr/share/doc", "").with_deny(true)).is_err());
+        assert!(expose(
+            Rule::builder("docs", "/usr/share/doc", "").with_faultmode(FaultMode::Eacces)
+        )
+        .is_err());
+        assert_eq!("expose-ro".parse(), Ok(RuleMode::ExposeRo));
+
+        let rules: BoxxyRules =
+            serde_yaml::from_str("rules: [{name: vim, target: ~/.vim, mode: mask}]").unwrap();
+        assert_eq!(rules.rules[0].mode, RuleMode::Mask);
+        assert!(!rules.rules[0].has_rewrite());
+        assert!(rules.rules[0].validate().is_ok());
+        let mask = |rule: RuleBuilder| rule.with_mode(RuleMode::Mask).build().validate();
+        assert!(mask(Rule::builder("vim", "~/.vim", "~/vim")).is_err());
+        assert!(mask(Rule::builder("vim", "~/.vim", "").with_deny(true)).is_err());
+        assert!(mask(Rule::builder("vim", "~/.vim", "").with_readonly(true)).is_err());
+        assert!(
+            mask(Rule::builder("vim", "~/.vim", "").with_mount_option(MountOption::Noexec))
+                .is_err()
+        );
+        assert_eq!("mask".parse(), Ok(RuleMode::Mask));
+
+        let rules: BoxxyRules = serde_yaml::from_str(
+            "rules: [{name: gh, target: ~/.config/gh/hosts.yml, rewrite: ~/gh/hosts.yml, mode: copy}]",
+        )
+        .unwrap();
+        assert_eq!(rules.rules[0].mode, RuleMode::Copy);
+        assert!(rules.rules[0].has_rewrite());
+        assert!(rules.rules[0].validate().is_ok());
+        let copy = |rule: RuleBuilder| rule.with_mode(RuleMode::Copy).build().validate();
+        assert!(copy(Rule::builder("gh", "~/.gh", "~/gh").with_readonly(true)).is_err());
+        assert!(copy(Rule::builder("gh", "~/.gh", "~/gh").with_omit("hosts.yml")).is_err());
+        assert!(
+            copy(Rule::builder("gh", "~/.gh", "~/gh").with_faultmode(FaultMode::Erofs)).is_err()
+        );
+        assert_eq!("copy".parse(), Ok(RuleMode::Copy));
+
+        let rules: BoxxyRules = serde_yaml::from_str(
+            "rules: [{name: cache, target: ~/.cache, mode: tmpfs, mount_options: [nosuid, nodev, noexec]}]",
+        )
+        .unwrap();
+        assert!(rules.rules[0].validate().is_ok());
+        assert_eq!(
+            rules.rules[0].mount_flags(),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC
+        );
+        let noexec = |rule: RuleBuilder| {
+            rule.with_mount_option(MountOption::Noexec)
+                .build()
+                .validate()
+        };
+        assert!(noexec(Rule::builder("ssh", "~/.ssh", "").with_deny(true)).is_err());
+        assert!(noexec(
+            Rule::builder("git", "~/.gitconfig", "~/.config/git/config")
+                .with_faultmode(FaultMode::Eacces)
+        )
+        .is_err());
+        assert!(serde_yaml::from_str::<BoxxyRules>(
+            "rules: [{name: a, target: /a, mount_options: [sync]}]"
+        )
+        .is_err());
+        assert_eq!("nodev".parse(), Ok(MountOption::Nodev));
+
+        let only = |program: &str| {
+            Rule::builder("python", "~/.python_history", "")
+                .with_only(program)
+                .build()
+                .validate()
+        };
+        assert!(only("python3.*").is_ok());
+        assert!(only("/opt/jetbrains/*/bin/idea").is_ok());
+        assert!(only("python3.[").is_err());
+        assert!(Rule::builder("npm", "~/.npmrc", "")
+            .with_context_git("*github.com*[")
+            .build()
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_mount_order() -> Result<()> {
+        let rules = vec![
+            Rule::builder("foo", "/boxxy/config/foo", "/a").build(),
+            Rule::builder("config", "/boxxy/config", "/b").build(),
+            Rule::builder("bar", "/boxxy/config/bar", "/c").build(),
+            Rule::builder("winner", "/boxxy", "/d")
+                .with_priority(1)
+                .build(),
+            Rule::builder("loser", "/boxxy/config/baz", "/e")
+                .with_priority(-1)
+                .build(),
+        ];
+        let names: Vec<&str> = mount_order(&rules, &FsDriver::new())?
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["loser", "config", "bar", "foo", "winner"]);
+
+        // The order the rules are written in doesn't matter, except between
+        // rules with the same target.
+        let mut reversed = rules.clone();
+        reversed.reverse();
+        let reversed_names: Vec<&str> = mount_order(&reversed, &FsDriver::new())?
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(reversed_names, names);
+
+        // Targets behind symlinks are ordered by where they're mounted.
+        let dir = Scratch::new("mount-order")?;
+        std::fs::create_dir_all(dir.join("real/nested/deeper"))?;
+        std::os::unix::fs::symlink(dir.join("real/nested/deeper"), dir.join("link"))?;
+        let rules = vec![
+            Rule::builder("link", dir.join("link").to_string_lossy(), "/a").build(),
+            Rule::builder("nested", dir.join("real/nested").to_string_lossy(), "/b").build(),
+        ];
+        let names: Vec<&str> = mount_order(&rules, &FsDriver::new())?
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["nested", "link"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_context_of() -> Result<()> {
+        let root = Scratch::new("context")?;
+        std::fs::create_dir_all(root.join("projects/legacy/src"))?;
+        std::fs::create_dir_all(root.join("elsewhere"))?;
+        let root = root.canonicalize()?;
+        let dir = |path: &str| root.join(path).to_string_lossy().to_string();
+        let fs = FsDriver::new();
+
+        let everywhere = Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc")
+            .with_not_context(dir("projects/legacy"))
+            .with_not_context(dir("missing"))
+            .build();
+        assert!(everywhere.in_context_of(&root.join("projects"), &fs)?);
+        assert!(!everywhere.in_context_of(&root.join("projects/legacy/src"), &fs)?);
+
+        let projects = Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc")
+            .with_context(dir("projects"))
+            .with_not_context(dir("projects/legacy"))
+            .build();
+        assert!(projects.in_context_of(&root.join("projects"), &fs)?);
+        assert!(!projects.in_context_of(&root.join("projects/legacy"), &fs)?);
+        assert!(!projects.in_context_of(&root.join("elsewhere"), &fs)?);
+
+        std::fs::create_dir_all(root.join("work/.git"))?;
+        std::fs::create_dir_all(root.join("work/src"))?;
+        std::fs::write(
+            root.join("work/.git/config"),
+            "[remote \"origin\"]\n\turl = git@github.com:work-org/api.git\n",
+        )?;
+        let git = |pattern: &str| {
+            Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc")
+                .with_context_git(pattern)
+                .build()
+        };
+        assert!(git("*github.com*work-org/*").in_context_of(&root.join("work/src"), &fs)?);
+        assert!(git(&format!("{}/work", root.display())).in_context_of(&root.join("work"), &fs)?);
+        assert!(!git("*github.com*queer/*").in_context_of(&root.join("work/src"), &fs)?);
+        assert!(!git("*work-org*").in_context_of(&root.join("elsewhere"), &fs)?);
+        let either = Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc")
+            .with_context(dir("projects"))
+            .with_context_git("*work-org*")
+            .build();
+        assert!(either.in_context_of(&root.join("projects"), &fs)?);
+        assert!(either.in_context_of(&root.join("work/src"), &fs)?);
+        assert!(!either.in_context_of(&root.join("elsewhere"), &fs)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_applies_to_binary_glob() -> Result<()> {
+        let root = Scratch::new("only-glob")?;
+        std::fs::create_dir_all(root.join("idea-2024.1/bin"))?;
+        std::fs::write(root.join("python3.12"), "")?;
+        std::fs::write(root.join("idea-2024.1/bin/idea"), "")?;
+        std::os::unix::fs::symlink(root.join("python3.12"), root.join("python3"))?;
+        let root = root.canonicalize()?;
+        let fs = FsDriver::new();
+        let applies = |only: &str, program: &str| {
+            Rule::builder("glob", "~/.a", "~/.b")
+                .with_only(only)
+                .build()
+                .applies_to_binary(root.join(program).as_os_str(), &fs)
+        };
+
+        assert!(applies("python3.*", "python3.12")?);
+        assert!(applies("python3.*", "python3")?);
+        assert!(!applies("python2.*", "python3.12")?);
+        assert!(applies("idea*", "idea-2024.1/bin/idea")?);
+        assert!(!applies("idea-*", "idea-2024.1/bin/idea")?);
+        let idea = format!("{}/idea-*/bin/idea", root.display());
+        assert!(applies(&idea, "idea-2024.1/bin/idea")?);
+        assert!(!applies(
+            &format!("{}/*/idea", root.display()),
+            "idea-2024.1/bin/idea"
+        )?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_applies_to_args() {
+        let args =
+            |line: &str| -> Vec<OsString> { line.split_whitespace().map(OsString::from).collect() };
+        let configure = Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .with_only("aws")
+            .with_only_args("configure")
+            .with_only_args("sso login")
+            .build();
+
+        assert!(configure.applies_to_args(&args("configure")));
+        assert!(configure.applies_to_args(&args("--profile work configure list")));
+        assert!(configure.applies_to_args(&args("sso login")));
+        assert!(!configure.applies_to_args(&args("s3 ls")));
+        assert!(!configure.applies_to_args(&args("login sso")));
+        assert!(!configure.applies_to_args(&[]));
+        assert!(Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .build()
+            .applies_to_args(&[]));
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let fs = FsDriver::new();
+        let pwd = std::env::current_dir()?;
+        let no_env = |_: &str| None;
+        let args =
+            |line: &str| -> Vec<OsString> { line.split_whitespace().map(OsString::from).collect() };
+        let aws = OsStr::new("aws");
+        let rule = Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .with_only("aws")
+            .with_only_args("configure")
+            .build();
+
+        let explanation = rule.explain(aws, &args("configure"), &pwd, no_env, &fs)?;
+        assert!(explanation.applies);
+        assert_eq!(
+            explanation.reasons,
+            [
+                "`only` lists aws",
+                "the arguments match `only_args`",
+                "no `context`, so it applies in every directory",
+            ]
+        );
+
+        let explanation = rule.explain(aws, &args("s3 ls"), &pwd, no_env, &fs)?;
+        assert!(!explanation.applies);
+        assert_eq!(
+            explanation.reasons.last().unwrap(),
+            "the arguments don't contain any of the `only_args` (configure)"
+        );
+        let explanation = rule.explain(OsStr::new("gcloud"), &[], &pwd, no_env, &fs)?;
+        assert_eq!(
+            explanation.reasons,
+            ["none of the `only` entries (aws) match gcloud"]
+        );
+
+        let elsewhere = Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .with_context("/boxxy/nowhere")
+            .build()
+            .explain(aws, &[], &pwd, no_env, &fs)?;
+        assert!(!elsewhere.applies);
+        assert!(elsewhere.reasons[1].ends_with("isn't in its `context`"));
+
+        // Runs check the same things, with the same environment.
+        let ci = Rule::builder("ci", "~/.ci", "~/.config/ci")
+            .with_when_env("CI", Some("true"))
+            .build();
+        let in_ci = |key: &str| (key == "CI").then(|| "true".to_string());
+        let explanation = ci.explain(aws, &[], &pwd, in_ci, &fs)?;
+        assert!(explanation.applies);
+        assert_eq!(explanation.reasons[1], "the environment matches `when_env`");
+        assert!(!ci.explain(aws, &[], &pwd, no_env, &fs)?.applies);
+        let rules = BoxxyRules {
+            rules: vec![rule, ci],
+            ..Default::default()
+        };
+        let applied = |args: &[OsString], var: fn(&str) -> Option<String>| -> Result<Vec<String>> {
+            Ok(rules
+                .get_applicable_rules_in(aws, args, &pwd, var, &fs)?
+                .into_iter()
+                .map(|rule| rule.name)
+                .collect())
+        };
+        assert_eq!(applied(&args("configure"), |_| None)?, ["aws"]);
+        assert_eq!(
+            applied(&args("s3 ls"), |key| (key == "CI")
+                .then(|| "true".to_string()))?,
+            ["ci"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_env() {
+        let rule: Rule = serde_yaml::from_str(
+            "{name: npm, target: ~/.npmrc, rewrite: ~/.config/npm/npmrc, when_env: {CI: ~, HOME: '*', SHELL: /bin/zsh}}",
+        )
+        .unwrap();
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |key: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert!(rule.matches_env(env(&[("HOME", "/home/boxxy"), ("SHELL", "/bin/zsh")])));
+        assert!(!rule.matches_env(env(&[
+            ("CI", "true"),
+            ("HOME", "/home/boxxy"),
+            ("SHELL", "/bin/zsh")
+        ])));
+        assert!(!rule.matches_env(env(&[("HOME", "/home/boxxy"), ("SHELL", "/bin/bash")])));
+        assert!(!rule.matches_env(env(&[("SHELL", "/bin/zsh")])));
+        assert!(Rule::builder("any", "~/a", "~/b")
+            .build()
+            .matches_env(env(&[])));
+    }
+
+    #[test]
+    fn test_resolve_env_cmd() -> Result<()> {
+        let mut rule = Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .with_env("AWS_PROFILE", "work")
+            .with_env_cmd("AWS_TOKEN", "printf 'secret\\n\\n'")
+            .with_env_cmd("AWS_PROFILE", "false")
+            .build();
+        rule.resolve_env_cmd()?;
+        assert!(rule.env_cmd.is_empty());
+        assert_eq!(rule.env["AWS_TOKEN"], "secret");
+        assert_eq!(rule.env["AWS_PROFILE"], "work");
+
+        let mut failing = Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .with_env_cmd("AWS_TOKEN", "exit 3")
+            .build();
+        assert!(failing.resolve_env_cmd().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extend_path() -> Result<()> {
+        let rules = [
+            Rule::builder("shims", "~/.a", "~/a")
+                .with_prepend_path("/opt/shims")
+                .with_append_path("/opt/fallback")
+                .build(),
+            Rule::builder("more shims", "~/.b", "~/b")
+                .with_prepend_path("~/bin")
+                .build(),
+        ];
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            extend_path(&rules, Some(OsStr::new("/usr/bin:/bin")))?,
+            Some(OsString::from(format!(
+                "/opt/shims:{}/bin:/usr/bin:/bin:/opt/fallback",
+                home.display()
+            )))
+        );
+        assert_eq!(
+            extend_path(&rules[1..], None)?,
+            Some(home.join("bin").into())
+        );
+        assert_eq!(extend_path(&[], Some(OsStr::new("/bin")))?, None);
+        let colon = Rule::builder("bad", "~/.c", "~/c")
+            .with_prepend_path("/a:b")
+            .build();
+        assert!(extend_path(&[colon], None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_omitted_paths() -> Result<()> {
+        let rule = Rule::builder("aws", "~/.aws", "~/.config/aws")
+            .with_mode(RuleMode::Directory)
+            .with_omit("credentials-prod")
+            .with_omit("sso/cache")
+            .build();
+        assert_eq!(
+            rule.omitted_paths(Path::new("/home/boxxy/.aws"))?,
+            vec![
+                PathBuf::from("/home/boxxy/.aws/credentials-prod"),
+                PathBuf::from("/home/boxxy/.aws/sso/cache"),
+            ]
+        );
+
+        for child in ["../escape", "/etc/passwd", "a/../../b", ""] {
+            let rule = Rule::builder("bad", "~/.aws", "~/.config/aws")
+                .with_omit(child)
+                .build();
+            assert!(rule.omitted_paths(Path::new("/home/boxxy/.aws")).is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::path::Path;
+
+use log::*;
+use nix::sched::CloneFlags;
+use nix::sys::wait::{waitpid, WaitStatus};
+
+/// Another sandbox that boxxy is running inside of. They usually stop boxxy
+/// from creating user namespaces or mounting things, which fails in ways that
+/// don't say why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    Flatpak,
+    Snap,
+    Docker,
+    Podman,
+    /// Some other container that follows systemd's `container` convention,
+    /// ex. LXC or systemd-nspawn.
+    Container(String),
+}
+
+impl fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sandbox::Flatpak => write!(f, "a Flatpak sandbox"),
+            Sandbox::Snap => write!(f, "a Snap"),
+            Sandbox::Docker => write!(f, "a Docker container"),
+            Sandbox::Podman => write!(f, "a Podman container"),
+            Sandbox::Container(kind) => write!(f, "a {kind} container"),
+        }
+    }
+}
+
+impl Sandbox {
+    /// Why boxxy can't set up a box in this sandbox, and what to do about it.
+    pub fn explanation(&self) -> String {
+        let why = match self {
+            Sandbox::Flatpak => {
+                "Flatpak's seccomp filter doesn't let apps create user namespaces. Run boxxy on the host instead, ex. with `flatpak-spawn --host boxxy ...`."
+            }
+            Sandbox::Snap => {
+                "strictly confined snaps can't create user namespaces. Install boxxy outside of the snap, or use a snap with classic confinement."
+            }
+            Sandbox::Docker | Sandbox::Podman => {
+                "the default seccomp and AppArmor profiles block creating user namespaces and mounting. Run the container with `--security-opt seccomp=unconfined --security-opt apparmor=unconfined`, or run boxxy on the host."
+            }
+            Sandbox::Container(_) => {
+                "the container may not allow nested user namespaces. Allow nesting for the container, or run boxxy on the host."
+            }
+        };
+        format!("boxxy is running inside {self}: {why}")
+    }
+}
+
+/// The sandbox boxxy is running inside of, if any.
+pub fn detect() -> Option<Sandbox> {
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    detect_with(|key| std::env::var(key).ok(), |path| path.exists(), &cgroup)
+}
+
+/// Whether a user and mount namespace can be created here, checked by
+/// cloning a child into new ones that exits right away.
+pub fn can_create_namespaces() -> bool {
+    let mut stack = vec![0u8; 64 * 1024];
+    // SAFETY: the child only returns, which doesn't touch anything shared
+    // with the parent.
+    let pid = unsafe {
+        nix::sched::clone(
+            Box::new(|| 0),
+            &mut stack,
+            CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWUSER,
+            Some(nix::sys::signal::Signal::SIGCHLD as i32),
+        )
+    };
+    match pid {
+        Ok(pid) => matches!(waitpid(pid, None), Ok(WaitStatus::Exited(_, 0))),
+        Err(err) => {
+            debug!("could not create namespaces: {err}");
+            false
+        }
+    }
+}
+
+/// Detect a sandbox from environment variables, marker files, and the
+/// contents of `/proc/self/cgroup`.
+fn detect_with<V, E>(var: V, exists: E, cgroup: &str) -> Option<Sandbox>
+where
+    V: Fn(&str) -> Option<String>,
+    E: Fn(&Path) -> bool,
+{
+    if exists(Path::new("/.flatpak-info")) || var("FLATPAK_ID").is_some() {
+        return Some(Sandbox::Flatpak);
+    }
+    if var("SNAP_NAME").is_some() || cgroup.contains("/snap.") {
+        return Some(Sandbox::Snap);
+    }
+    if exists(Path::new("/.dockerenv")) || cgroup.contains("/docker") {
+        return Some(Sandbox::Docker);
+    }
+    if exists(Path::new("/run/.containerenv")) || cgroup.contains("libpod") {
+        return Some(Sandbox::Podman);
+    }
+    match var("container") {
+        Some(kind) if kind == "docker" => Some(Sandbox::Docker),
+        Some(kind) if kind == "podman" => Some(Sandbox::Podman),
+        Some(kind) if !kind.is_empty() => Some(Sandbox::Container(kind)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_with() {
+        let no_var = |_: &str| None;
+        let no_file = |_: &Path| false;
+        assert_eq!(detect_with(no_var, no_file, "0::/user.slice"), None);
+
+        assert_eq!(
+            detect_with(no_var, |path| path == Path::new("/.flatpak-info"), ""),
+            Some(Sandbox::Flatpak)
+        );
+        assert_eq!(
+            detect_with(no_var, no_file, "0::/system.slice/docker-1234.scope"),
+            Some(Sandbox::Docker)
+        );
+        assert_eq!(
+            detect_with(
+                no_var,
+                no_file,
+                "0::/user.slice/snap.firefox.firefox-1.scope"
+            ),
+            Some(Sandbox::Snap)
+        );
+        assert_eq!(
+            detect_with(
+                |key| (key == "container").then(|| "lxc".to_string()),
+                no_file,
+                ""
+            ),
+            Some(Sandbox::Container("lxc".to_string()))
+        );
+    }
+}
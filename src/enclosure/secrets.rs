@@ -0,0 +1,122 @@
+//! `--strict-secrets`: hide the gpg-agent's sockets and the keyrings from the
+//! boxxed program, unless a rule that applies allows them.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::rule::{Rule, RuleMode};
+
+/// Env vars that point programs at the hidden sockets.
+pub const ENV_VARS: &[&str] = &[
+    "DBUS_SESSION_BUS_ADDRESS",
+    "GNOME_KEYRING_CONTROL",
+    "GPG_AGENT_INFO",
+];
+
+/// The directories gpg-agent can keep its sockets in: the one `gpgconf`
+/// reports, if it's installed, then the usual ones.
+pub fn gpg_socket_dirs(runtime_dir: Option<&Path>, home: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Ok(output) = Command::new("gpgconf")
+        .args(["--list-dirs", "socketdir"])
+        .output()
+    {
+        if output.status.success() {
+            let dir = String::from_utf8_lossy(&output.stdout);
+            dirs.push(PathBuf::from(dir.trim_end()));
+        }
+    }
+    dirs.extend(runtime_dir.map(|dir| dir.join("gnupg")));
+    dirs.push(home.join(".gnupg"));
+    dirs.dedup();
+    dirs
+}
+
+/// The paths to hide that exist: gpg-agent's sockets in `socket_dirs`, the
+/// D-Bus session bus that the Secret Service is reached over, and the GNOME
+/// keyring's and KWallet's sockets and files.
+pub fn hidden_paths(
+    socket_dirs: &[PathBuf],
+    runtime_dir: Option<&Path>,
+    home: &Path,
+) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    for dir in socket_dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut sockets: Vec<PathBuf> = entries
+            .flatten()
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("S.gpg-agent")
+            })
+            .map(|entry| entry.path())
+            .collect();
+        sockets.sort();
+        paths.extend(sockets);
+    }
+    if let Some(runtime_dir) = runtime_dir {
+        paths.push(runtime_dir.join("bus"));
+        paths.push(runtime_dir.join("keyring"));
+    }
+    paths.push(home.join(".local/share/keyrings"));
+    paths.push(home.join(".local/share/kwalletd"));
+    paths.retain(|path| path.symlink_metadata().is_ok());
+    paths
+}
+
+/// Rules that hide `paths`.
+pub fn deny_rules(paths: &[PathBuf]) -> Vec<Rule> {
+    paths
+        .iter()
+        .map(|path| {
+            let mode = if path.is_dir() {
+                RuleMode::Directory
+            } else {
+                RuleMode::File
+            };
+            Rule::builder("strict secrets", path.to_string_lossy(), "")
+                .with_mode(mode)
+                .with_deny(true)
+                .build()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_hidden_paths() -> color_eyre::Result<()> {
+        let home = Scratch::new("secrets")?;
+        let runtime = home.join("run");
+        std::fs::create_dir_all(home.join(".gnupg"))?;
+        std::fs::create_dir_all(runtime.join("keyring"))?;
+        std::fs::create_dir_all(home.join(".local/share/keyrings"))?;
+        for file in ["S.gpg-agent", "S.gpg-agent.ssh", "pubring.kbx"] {
+            std::fs::write(home.join(".gnupg").join(file), "")?;
+        }
+
+        let paths = hidden_paths(&[home.join(".gnupg")], Some(&runtime), &home);
+        assert_eq!(
+            paths,
+            [
+                home.join(".gnupg/S.gpg-agent"),
+                home.join(".gnupg/S.gpg-agent.ssh"),
+                runtime.join("keyring"),
+                home.join(".local/share/keyrings"),
+            ]
+        );
+        let rules = deny_rules(&paths);
+        assert!(rules.iter().all(|rule| rule.deny));
+        assert_eq!(rules[0].mode, RuleMode::File);
+        assert_eq!(rules[2].mode, RuleMode::Directory);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use nix::errno::Errno;
+use nix::sys::statfs::{statfs, TMPFS_MAGIC};
+use nix::unistd::{access, AccessFlags};
+
+use super::fs::FsDriver;
+use super::rule::{FaultMode, Rule, RuleMode};
+
+/// The result of a single self-test check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Outcome::Pass => write!(f, "pass: {}", self.name),
+            Outcome::Fail(reason) => write!(f, "FAIL: {}: {reason}", self.name),
+            Outcome::Skip(reason) => write!(f, "skip: {}: {reason}", self.name),
+        }
+    }
+}
+
+/// Check, from inside the container, that isolation is what the rules
+/// promise. Must be run after the container is set up and before the command
+/// is run, so that nothing else has touched the filesystem yet.
+pub fn run(
+    rules: &[Rule],
+    protected_paths: &[PathBuf],
+    fs: &FsDriver,
+    immutable_root: bool,
+) -> Result<()> {
+    let _span = tracing::info_span!("self-test").entered();
+    let mut checks = vec![check_root(immutable_root)];
+    for rule in rules {
+        checks.push(check_rule(rule, fs)?);
+        checks.extend(check_omitted(rule, fs)?);
+    }
+    checks.extend(protected_paths.iter().map(|path| check_protected(path)));
+
+    let count = |predicate: fn(&Outcome) -> bool| {
+        checks
+            .iter()
+            .filter(|check| predicate(&check.outcome))
+            .count()
+    };
+    let failed = count(|outcome| matches!(outcome, Outcome::Fail(_)));
+    let skipped = count(|outcome| matches!(outcome, Outcome::Skip(_)));
+    for check in &checks {
+        match check.outcome {
+            Outcome::Fail(_) => error!("self-test {check}"),
+            _ => info!("self-test {check}"),
+        }
+    }
+
+    if failed > 0 {
+        return Err(eyre!(
+            "self-test failed: {failed} of {} checks failed, refusing to run the command",
+            checks.len()
+        ));
+    }
+    info!(
+        "self-test passed: {} passed, {skipped} skipped",
+        checks.len() - skipped
+    );
+
+    Ok(())
+}
+
+fn check_root(immutable_root: bool) -> Check {
+    let name = "/ is read-only".to_string();
+    if !immutable_root {
+        return Check {
+            name,
+            outcome: Outcome::Skip("--immutable not set".into()),
+        };
+    }
+
+    let probe = Path::new("/.boxxy-self-test");
+    let outcome = match File::create(probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(probe);
+            Outcome::Fail("created a file in /".into())
+        }
+        Err(_) => Outcome::Pass,
+    };
+
+    Check { name, outcome }
+}
+
+fn check_protected(path: &Path) -> Check {
+    let outcome = match access(path, AccessFlags::W_OK) {
+        Err(Errno::EROFS) => Outcome::Pass,
+        Err(Errno::ENOENT) => Outcome::Skip("shadowed by a rule".into()),
+        _ => Outcome::Fail("is writable".into()),
+    };
+
+    Check {
+        name: format!("boxxy's {} is read-only", path.display()),
+        outcome,
+    }
+}
+
+fn check_rule(rule: &Rule, fs: &FsDriver) -> Result<Check> {
+    let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+    if rule.deny {
+        return Ok(Check {
+            name: format!("rule '{}' denies {}", rule.name, target.display()),
+            outcome: empty_and_read_only(&target),
+        });
+    }
+    if rule.mode == RuleMode::Tmpfs {
+        return Ok(Check {
+            name: format!(
+                "rule '{}' mounts a tmpfs at {}",
+                rule.name,
+                target.display()
+            ),
+            outcome: on_tmpfs(&target),
+        });
+    }
+    if rule.mode == RuleMode::ExposeRo {
+        return Ok(Check {
+            name: format!(
+                "rule '{}' exposes {} read-only",
+                rule.name,
+                target.display()
+            ),
+            outcome: read_only(&target),
+        });
+    }
+    if rule.mode == RuleMode::Mask {
+        return Ok(Check {
+            name: format!("rule '{}' masks {}", rule.name, target.display()),
+            outcome: masked(&target),
+        });
+    }
+    let rewrite = fs.fully_expand_path(&rule.rewrite)?;
+    if rule.mode == RuleMode::Copy {
+        return Ok(Check {
+            name: format!(
+                "rule '{}' copies {} to {}",
+                rule.name,
+                rewrite.display(),
+                target.display()
+            ),
+            outcome: copied(&target, &rewrite),
+        });
+    }
+    let name = format!("rule '{}' shadows {}", rule.name, target.display());
+
+    let outcome = match rule.faultmode {
+        None if rule.readonly => shadowed_read_only(&target, &rewrite),
+        None => same_file(&target, &rewrite),
+        Some(FaultMode::Erofs) => shadowed_read_only(&target, &rewrite),
+        Some(FaultMode::Eacces) if nix::unistd::getuid().is_root() => {
+            Outcome::Skip("root bypasses permission checks".into())
+        }
+        Some(FaultMode::Eacces) => match access(&target, AccessFlags::R_OK) {
+            Err(Errno::EACCES) => Outcome::Pass,
+            _ => Outcome::Fail("target is readable".into()),
+        },
+        Some(FaultMode::Enospc) => Outcome::Skip("enospc faults aren't checked".into()),
+    };
+
+    Ok(Check { name, outcome })
+}
+
+fn check_omitted(rule: &Rule, fs: &FsDriver) -> Result<Vec<Check>> {
+    if rule.mode != RuleMode::Directory || rule.faultmode.is_some() || rule.deny {
+        return Ok(vec![]);
+    }
+
+    let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+    let mut checks = vec![];
+    for path in rule.omitted_paths(&target)? {
+        let name = format!("rule '{}' omits {}", rule.name, path.display());
+        let outcome = match fs::symlink_metadata(&path) {
+            Err(_) => Outcome::Skip("doesn't exist".into()),
+            Ok(metadata) if metadata.is_symlink() => {
+                Outcome::Skip("symlinks aren't omitted".into())
+            }
+            Ok(_) => empty_and_read_only(&path),
+        };
+        checks.push(Check { name, outcome });
+    }
+
+    Ok(checks)
+}
+
+/// Whether `target` is `rewrite`, mounted read-only.
+pub(super) fn shadowed_read_only(target: &Path, rewrite: &Path) -> Outcome {
+    match same_file(target, rewrite) {
+        Outcome::Pass => match access(target, AccessFlags::W_OK) {
+            Err(Errno::EROFS) => Outcome::Pass,
+            _ => Outcome::Fail("target is writable".into()),
+        },
+        outcome => outcome,
+    }
+}
+
+/// Whether `path` can't be written to because it's on a read-only mount.
+pub(super) fn read_only(path: &Path) -> Outcome {
+    match access(path, AccessFlags::W_OK) {
+        Err(Errno::EROFS) => Outcome::Pass,
+        Err(Errno::ENOENT) => Outcome::Skip("doesn't exist".into()),
+        _ => Outcome::Fail("is writable".into()),
+    }
+}
+
+/// Whether `path` has been hidden by a mask rule, ie. it doesn't exist.
+pub(super) fn masked(path: &Path) -> Outcome {
+    match fs::symlink_metadata(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Outcome::Pass,
+        Err(err) => Outcome::Fail(format!("could not stat: {err}")),
+        Ok(_) => Outcome::Fail("still exists".into()),
+    }
+}
+
+/// Whether `target` is a copy of `rewrite` on a tmpfs, rather than the
+/// rewrite itself.
+pub(super) fn copied(target: &Path, rewrite: &Path) -> Outcome {
+    if same_file(target, rewrite) == Outcome::Pass {
+        return Outcome::Fail("is the rewrite itself, not a copy".into());
+    }
+    match (fs::metadata(target), fs::metadata(rewrite)) {
+        (Ok(target_meta), Ok(rewrite_meta)) if target_meta.is_dir() != rewrite_meta.is_dir() => {
+            Outcome::Fail("is not the same kind of file as the rewrite".into())
+        }
+        (Ok(_), Ok(_)) => on_tmpfs(target),
+        (Err(err), _) => Outcome::Fail(format!("could not stat target: {err}")),
+        (_, Err(err)) => Outcome::Fail(format!("could not stat rewrite: {err}")),
+    }
+}
+
+/// Whether `path` is on a tmpfs.
+pub(super) fn on_tmpfs(path: &Path) -> Outcome {
+    match statfs(path) {
+        Ok(stats) if stats.filesystem_type() == TMPFS_MAGIC => Outcome::Pass,
+        Ok(_) => Outcome::Fail("is not on a tmpfs".into()),
+        Err(err) => Outcome::Fail(format!("could not statfs: {err}")),
+    }
+}
+
+/// Whether `path` is an empty file or directory that can't be written to,
+/// ie. it's been covered up by an omit or deny rule.
+pub(super) fn empty_and_read_only(path: &Path) -> Outcome {
+    let empty = match fs::metadata(path) {
+        Err(err) => return Outcome::Fail(format!("could not stat: {err}")),
+        Ok(metadata) if metadata.is_dir() => {
+            fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+        }
+        Ok(metadata) => metadata.len() == 0,
+    };
+    match (empty, access(path, AccessFlags::W_OK)) {
+        (false, _) => Outcome::Fail("still has contents".into()),
+        (true, Err(Errno::EROFS)) => Outcome::Pass,
+        (true, _) => Outcome::Fail("is writable".into()),
+    }
+}
+
+/// Whether `target` is the bind mount of `rewrite`, ie. the real target is
+/// no longer reachable through it.
+pub(super) fn same_file(target: &Path, rewrite: &Path) -> Outcome {
+    match (fs::metadata(target), fs::metadata(rewrite)) {
+        (Ok(target_meta), Ok(rewrite_meta)) => {
+            if (target_meta.dev(), target_meta.ino()) == (rewrite_meta.dev(), rewrite_meta.ino()) {
+                Outcome::Pass
+            } else {
+                Outcome::Fail(format!(
+                    "{} is still the real target, not {}",
+                    target.display(),
+                    rewrite.display()
+                ))
+            }
+        }
+        (Err(err), _) => Outcome::Fail(format!("could not stat target: {err}")),
+        (_, Err(err)) => Outcome::Fail(format!("could not stat rewrite: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    use color_eyre::Result;
+
+    #[test]
+    fn test_same_file() -> Result<()> {
+        let dir = Scratch::new("self-test")?;
+        let a = dir.join("a");
+        let b = dir.join("b");
+        File::create(&a)?;
+        File::create(&b)?;
+
+        assert_eq!(same_file(&a, &a), Outcome::Pass);
+        assert!(matches!(same_file(&a, &b), Outcome::Fail(_)));
+        assert!(matches!(same_file(&dir.join("c"), &a), Outcome::Fail(_)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+//! `--shell-init`: start the default shell with an rc snippet written for the
+//! box, that loads the user's own rc file, marks the prompt, and runs the
+//! config's `shell_init` lines.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use color_eyre::Result;
+use log::*;
+
+use super::fs::append_all;
+use crate::config::BoxxyConfig;
+use crate::hook::Shell;
+
+/// Where the snippet for the box called `name` is written. zsh gets a
+/// directory to use as its `ZDOTDIR`, since it only reads rc files by name.
+pub fn rc_path(name: &str) -> Result<PathBuf> {
+    Ok(append_all(
+        &BoxxyConfig::runtime_dir()?,
+        vec![format!("{name}.shellrc")],
+    ))
+}
+
+/// Remove the snippet of the box called `name`, if it has one.
+pub fn remove(name: &str) {
+    if let Ok(path) = rc_path(name) {
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+/// The snippet for `shell`, or for a POSIX shell that reads `$ENV` if it's
+/// `None`, in which case `user_env` is the `$ENV` file it would have read.
+pub fn snippet(shell: Option<Shell>, user_env: Option<&Path>, lines: &[String]) -> String {
+    let mut snippet = match shell {
+        Some(Shell::Bash) => "[ -f ~/.bashrc ] && . ~/.bashrc\n".to_string(),
+        // zsh reads the rest of its startup files from `ZDOTDIR`, which is
+        // put back so that they're found as usual afterwards.
+        Some(Shell::Zsh) => "ZDOTDIR=\"$HOME\"\n[ -f ~/.zshrc ] && . ~/.zshrc\n".to_string(),
+        None => user_env
+            .map(|env| format!(". '{}'\n", env.display().to_string().replace('\'', "'\\''")))
+            .unwrap_or_default(),
+    };
+    snippet.push_str("PS1=\"(boxxy:$BOXXY_CONTAINER_NAME) $PS1\"\n");
+    for line in lines {
+        snippet.push_str(line);
+        snippet.push('\n');
+    }
+    snippet
+}
+
+/// Write the snippet for the box called `name` and point `command`, the
+/// user's shell, at it.
+pub fn set_up(command: &mut Command, name: &str, lines: &[String]) -> Result<()> {
+    let program = Path::new(command.get_program())
+        .file_name()
+        .map(|program| program.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if program == "fish" {
+        // fish has no rc file option, but can run commands once it starts.
+        let mut init = vec![
+            "functions -c fish_prompt __boxxy_fish_prompt".to_string(),
+            "function fish_prompt; echo -n \"(boxxy:$BOXXY_CONTAINER_NAME) \"; __boxxy_fish_prompt; end"
+                .to_string(),
+        ];
+        init.extend(lines.iter().cloned());
+        command.arg("-C").arg(init.join("; "));
+        return Ok(());
+    }
+
+    let shell = Shell::from_str(&program).ok();
+    let path = rc_path(name)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let user_env = std::env::var_os("ENV").map(PathBuf::from);
+    let snippet = snippet(shell, user_env.as_deref(), lines);
+    match shell {
+        Some(Shell::Bash) => {
+            std::fs::write(&path, snippet)?;
+            command.arg("--rcfile").arg(&path).arg("-i");
+        }
+        Some(Shell::Zsh) => {
+            std::fs::create_dir_all(&path)?;
+            std::fs::write(
+                path.join(".zshenv"),
+                "[ -f \"$HOME/.zshenv\" ] && . \"$HOME/.zshenv\"\n",
+            )?;
+            std::fs::write(path.join(".zshrc"), snippet)?;
+            command.env("ZDOTDIR", &path);
+        }
+        None => {
+            std::fs::write(&path, snippet)?;
+            command.env("ENV", &path).arg("-i");
+        }
+    }
+    debug!("--shell-init: wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_init_snippet() {
+        let lines = ["alias k=kubectl".to_string()];
+        assert_eq!(
+            snippet(Some(Shell::Bash), None, &lines),
+            "[ -f ~/.bashrc ] && . ~/.bashrc
+PS1=\"(boxxy:$BOXXY_CONTAINER_NAME) $PS1\"
+alias k=kubectl
+"
+        );
+        assert!(snippet(Some(Shell::Zsh), None, &[]).starts_with("ZDOTDIR=\"$HOME\"\n"));
+        assert!(snippet(None, Some(Path::new("/home/me/.it's.shrc")), &[])
+            .starts_with(". '/home/me/.it'\\''s.shrc'\n"));
+        assert!(snippet(None, None, &[]).starts_with("PS1="));
+    }
+}
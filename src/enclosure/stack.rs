@@ -0,0 +1,112 @@
+use std::ptr::NonNull;
+
+use color_eyre::Result;
+
+/// Used when `RLIMIT_STACK` is unlimited or can't be read.
+const DEFAULT_STACK_SIZE: usize = 8 * 1024 * 1024;
+const MIN_STACK_SIZE: usize = 256 * 1024;
+const MAX_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// How big the container process' stack should be: `requested` if given,
+/// else the soft `RLIMIT_STACK`, clamped to something sane either way.
+pub fn stack_size(requested: Option<u64>, soft_limit: Option<u64>) -> usize {
+    let size = match (requested, soft_limit) {
+        (Some(size), _) => size,
+        (None, Some(limit)) if limit != rlimit::INFINITY => limit,
+        _ => DEFAULT_STACK_SIZE as u64,
+    };
+    (size.min(MAX_STACK_SIZE as u64) as usize).clamp(MIN_STACK_SIZE, MAX_STACK_SIZE)
+}
+
+/// A stack for a cloned process, mapped with `mmap` so that pages are only
+/// committed as they're used, with a guard page below it so that overflowing
+/// it faults instead of running into other memory.
+pub struct Stack {
+    base: NonNull<libc::c_void>,
+    mapped: usize,
+    page_size: usize,
+}
+
+impl Stack {
+    pub fn new(size: usize) -> Result<Self> {
+        // SAFETY: sysconf has no preconditions.
+        let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => 4096,
+        };
+        let mapped = size.div_ceil(page_size) * page_size + page_size;
+
+        // SAFETY: an anonymous private mapping doesn't alias anything.
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mapped,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_STACK | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let stack = Self {
+            base: NonNull::new(base).ok_or_else(std::io::Error::last_os_error)?,
+            mapped,
+            page_size,
+        };
+
+        // Stacks grow down, so the guard page goes at the bottom.
+        // SAFETY: the first page is part of the mapping we just made.
+        if unsafe { libc::mprotect(base, page_size, libc::PROT_NONE) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(stack)
+    }
+
+    /// The usable part of the stack, above the guard page.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: everything above the guard page is mapped read-write and
+        // owned by this `Stack`.
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.base.as_ptr().cast::<u8>().add(self.page_size),
+                self.mapped - self.page_size,
+            )
+        }
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        // SAFETY: `base` and `mapped` describe the mapping made in `new`.
+        unsafe { libc::munmap(self.base.as_ptr(), self.mapped) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_size() {
+        assert_eq!(stack_size(None, Some(rlimit::INFINITY)), DEFAULT_STACK_SIZE);
+        assert_eq!(stack_size(None, None), DEFAULT_STACK_SIZE);
+        assert_eq!(stack_size(None, Some(16 * 1024 * 1024)), 16 * 1024 * 1024);
+        assert_eq!(stack_size(None, Some(4096)), MIN_STACK_SIZE);
+        assert_eq!(stack_size(Some(u64::MAX), None), MAX_STACK_SIZE);
+        assert_eq!(stack_size(Some(1024 * 1024), Some(4096)), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_stack() -> Result<()> {
+        let mut stack = Stack::new(MIN_STACK_SIZE + 1)?;
+        let slice = stack.as_mut_slice();
+        assert!(slice.len() > MIN_STACK_SIZE);
+        slice[0] = 1;
+        let last = slice.len() - 1;
+        slice[last] = 1;
+        Ok(())
+    }
+}
@@ -1,13 +1,21 @@
-use cfg_if::cfg_if;
 use color_eyre::Result;
 use nix::unistd::Pid;
 use std::{fs, path::PathBuf};
 
 use super::{
-    register::{get_register_from_regs, syscall_number_from_user_regs, StringRegister},
+    register::{
+        get_register_from_regs, syscall_number_from_user_regs, StringRegister, ARG_REGISTERS,
+    },
     tracer::{ChildProcess, PtraceRegisters, Tracer},
 };
 
+use self::table::{ArgKind, FILE_ARGS};
+
+mod table;
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "riscv64")))]
+compile_error!("The current architecture is unsupported!");
+
 #[allow(unused)]
 fn get_fd_path(pid: Pid, fd: i32) -> Result<Option<PathBuf>> {
     let fd_path = format!("/proc/{pid}/fd/{fd}");
@@ -23,11 +31,15 @@ fn get_fd_path(pid: Pid, fd: i32) -> Result<Option<PathBuf>> {
     }
 }
 
+/// A syscall made by a traced process.
 #[derive(Debug, Clone)]
 pub struct Syscall {
     pub name: String,
     pub number: u64,
+    /// The path the syscall operates on, if it takes one.
     pub path: Option<PathBuf>,
+    /// The program that made the syscall, as seen from outside the box.
+    pub program: Option<PathBuf>,
 }
 
 pub fn handle_syscall(tracer: &Tracer, pid: Pid) -> Result<Option<Syscall>> {
@@ -45,6 +57,7 @@ pub fn handle_syscall(tracer: &Tracer, pid: Pid) -> Result<Option<Syscall>> {
             name: syscall_name.to_string(),
             number: syscall_no,
             path,
+            program: child.program().cloned(),
         };
 
         Ok(Some(syscall))
@@ -58,31 +71,18 @@ fn get_path_from_syscall(
     syscall_no: u64,
     registers: &mut PtraceRegisters,
 ) -> Result<Option<PathBuf>> {
-    if let Some(register) = SYSCALL_REGISTERS.get(&(syscall_no as i64)) {
-        let path_ptr = get_register_from_regs!(register, registers);
-        let path = match child.read_string(register, path_ptr as *mut _) {
-            Ok(path) => PathBuf::from(path),
-            Err(_) => match get_fd_path(child.pid(), path_ptr as i32) {
-                Ok(Some(path)) => path,
-                Ok(None) => return Ok(None),
-                Err(_) => return Ok(None),
-            },
-        };
-
-        Ok(Some(path))
-    } else {
-        Ok(None)
-    }
-}
+    let Some(arg) = FILE_ARGS.get(&(syscall_no as libc::c_long)) else {
+        return Ok(None);
+    };
+    let register = &ARG_REGISTERS[arg.index];
+    let value = get_register_from_regs!(register, registers);
+    let path = match arg.kind {
+        ArgKind::Path => child
+            .read_string(register, value as *mut _)
+            .ok()
+            .map(PathBuf::from),
+        ArgKind::Fd => get_fd_path(child.pid(), value as i32)?,
+    };
 
-cfg_if! {
-    if #[cfg(target_arch = "x86_64")] {
-        mod x86_64;
-        pub use x86_64::*;
-    } else if #[cfg(target_arch = "riscv64")] {
-        mod riscv64;
-        pub use riscv64::*;
-    } else {
-        compile_error!("The current architecture is unsupported!");
-    }
+    Ok(path)
 }
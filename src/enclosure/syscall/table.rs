@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+/// What the argument of a syscall that names its file holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A pointer to a path.
+    Path,
+    /// A file descriptor, whose path is looked up in `/proc`.
+    Fd,
+}
+
+/// Which argument of a syscall names the file it operates on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileArg {
+    pub index: usize,
+    pub kind: ArgKind,
+}
+
+macro_rules! file_args {
+    ($($syscall:ident: $index:literal $kind:ident,)*) => {
+        &[$((libc::$syscall, FileArg { index: $index, kind: ArgKind::$kind }),)*]
+    };
+}
+
+/// Syscalls that every supported architecture has.
+const COMMON: &[(libc::c_long, FileArg)] = file_args! {
+    SYS_read: 0 Fd,
+    SYS_write: 0 Fd,
+    SYS_openat: 1 Path,
+    SYS_openat2: 1 Path,
+    SYS_close: 0 Fd,
+    SYS_unlinkat: 1 Path,
+    SYS_fstat: 0 Fd,
+    SYS_statx: 1 Path,
+    SYS_newfstatat: 1 Path,
+    SYS_lseek: 0 Fd,
+    SYS_pread64: 0 Fd,
+    SYS_pwrite64: 0 Fd,
+    SYS_preadv: 0 Fd,
+    SYS_pwritev: 0 Fd,
+    SYS_faccessat: 1 Path,
+    SYS_faccessat2: 1 Path,
+    SYS_dup: 0 Fd,
+    SYS_dup3: 0 Fd,
+    SYS_sendfile: 0 Fd,
+    SYS_fcntl: 0 Fd,
+    SYS_fsync: 0 Fd,
+    SYS_fdatasync: 0 Fd,
+    SYS_truncate: 0 Path,
+    SYS_ftruncate: 0 Fd,
+    SYS_getdents64: 0 Fd,
+    SYS_chdir: 0 Path,
+    SYS_fchdir: 0 Fd,
+    SYS_renameat2: 1 Path,
+    SYS_mkdirat: 1 Path,
+    SYS_linkat: 1 Path,
+    SYS_symlinkat: 2 Path,
+    SYS_readlinkat: 1 Path,
+    SYS_fchmod: 0 Fd,
+    SYS_fchown: 0 Fd,
+    SYS_fchownat: 1 Path,
+    SYS_fchmodat: 1 Path,
+    SYS_mknodat: 1 Path,
+    SYS_pivot_root: 0 Path,
+    SYS_chroot: 0 Path,
+    SYS_mount: 0 Path,
+    SYS_umount2: 0 Path,
+    SYS_swapon: 0 Path,
+    SYS_swapoff: 0 Path,
+    SYS_readahead: 0 Fd,
+    SYS_setxattr: 0 Path,
+    SYS_lsetxattr: 0 Path,
+    SYS_fsetxattr: 0 Fd,
+    SYS_getxattr: 0 Path,
+    SYS_lgetxattr: 0 Path,
+    SYS_fgetxattr: 0 Fd,
+    SYS_listxattr: 0 Path,
+    SYS_llistxattr: 0 Path,
+    SYS_flistxattr: 0 Fd,
+    SYS_removexattr: 0 Path,
+    SYS_lremovexattr: 0 Path,
+    SYS_fremovexattr: 0 Fd,
+    SYS_fadvise64: 0 Fd,
+    SYS_utimensat: 1 Path,
+    SYS_splice: 0 Fd,
+    SYS_tee: 0 Fd,
+    SYS_sync_file_range: 0 Fd,
+    SYS_vmsplice: 0 Fd,
+    SYS_fallocate: 0 Fd,
+    SYS_fanotify_mark: 0 Fd,
+    SYS_name_to_handle_at: 1 Path,
+    SYS_open_by_handle_at: 0 Fd,
+    SYS_syncfs: 0 Fd,
+};
+
+/// Syscalls that only older architectures have. Newer ones, like aarch64 and
+/// riscv64, use the generic syscall table, which only has the `*at` versions.
+#[cfg(target_arch = "x86_64")]
+const LEGACY: &[(libc::c_long, FileArg)] = file_args! {
+    SYS_open: 0 Path,
+    SYS_creat: 0 Path,
+    SYS_unlink: 0 Path,
+    SYS_stat: 0 Path,
+    SYS_lstat: 0 Path,
+    SYS_access: 0 Path,
+    SYS_dup2: 0 Fd,
+    SYS_getdents: 0 Fd,
+    SYS_rename: 0 Path,
+    SYS_renameat: 1 Path,
+    SYS_mkdir: 0 Path,
+    SYS_rmdir: 0 Path,
+    SYS_link: 1 Path,
+    SYS_symlink: 1 Path,
+    SYS_readlink: 0 Path,
+    SYS_chmod: 0 Path,
+    SYS_chown: 0 Path,
+    SYS_lchown: 0 Path,
+    SYS_mknod: 0 Path,
+    SYS_futimesat: 1 Path,
+};
+
+#[cfg(not(target_arch = "x86_64"))]
+const LEGACY: &[(libc::c_long, FileArg)] = &[];
+
+lazy_static::lazy_static! {
+    /// The argument that names the file, for every syscall that has one.
+    pub static ref FILE_ARGS: HashMap<libc::c_long, FileArg> =
+        COMMON.iter().chain(LEGACY).copied().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_args() {
+        let mut seen = std::collections::HashSet::new();
+        for (syscall, arg) in COMMON.iter().chain(LEGACY) {
+            assert!(seen.insert(syscall), "syscall {syscall} is listed twice");
+            assert!(arg.index < 6, "syscall {syscall} only has 6 arguments");
+        }
+
+        assert_eq!(
+            FILE_ARGS[&libc::SYS_openat],
+            FileArg {
+                index: 1,
+                kind: ArgKind::Path
+            }
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use crate::store::Project;
+
+/// Values available to `{{ ... }}` expressions in rule paths.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext {
+    /// The file name of the binary being boxxed, ex. `aws`.
+    pub binary: Option<String>,
+    /// The (tilde-expanded) target of the rule being rendered.
+    pub target: Option<String>,
+}
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_binary<S: Into<String>>(mut self, binary: S) -> Self {
+        self.binary = Some(binary.into());
+        self
+    }
+
+    pub fn with_target<S: Into<String>>(mut self, target: S) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    fn variable(&self, name: &str) -> Result<String> {
+        let value = match name {
+            "home" => dir_to_string(dirs::home_dir(), name)?,
+            "xdg_config" | "config" => dir_to_string(dirs::config_dir(), name)?,
+            "xdg_data" | "data" => dir_to_string(dirs::data_dir(), name)?,
+            "xdg_cache" => dir_to_string(dirs::cache_dir(), name)?,
+            "xdg_state" => dir_to_string(dirs::state_dir(), name)?,
+            "xdg_runtime" => dir_to_string(dirs::runtime_dir(), name)?,
+            "cwd" => dir_to_string(std::env::current_dir().ok(), name)?,
+            "project" => std::env::current_dir()
+                .ok()
+                .and_then(|cwd| project_root(&cwd))
+                .map(|root| root.to_string_lossy().to_string())
+                .ok_or_else(|| eyre!("`project` is only available inside a project"))?,
+            "date" => today(),
+            "binary" => self
+                .binary
+                .clone()
+                .ok_or_else(|| eyre!("`binary` is not known when rendering this template"))?,
+            "target" => self
+                .target
+                .clone()
+                .ok_or_else(|| eyre!("`target` is not available in this template"))?,
+            _ => return Err(eyre!("unknown template variable: {name}")),
+        };
+
+        Ok(value)
+    }
+
+    fn call(&self, function: &str, argument: &str) -> Result<String> {
+        let argument = self.evaluate_argument(argument)?;
+        match function {
+            "hash" => Ok(format!("{:016x}", fnv1a(argument.as_bytes()))),
+            _ => Err(eyre!("unknown template function: {function}")),
+        }
+    }
+
+    fn evaluate_argument(&self, argument: &str) -> Result<String> {
+        let argument = argument.trim();
+        if let Some(literal) = argument
+            .strip_prefix('"')
+            .and_then(|argument| argument.strip_suffix('"'))
+        {
+            Ok(literal.to_string())
+        } else {
+            self.variable(argument)
+        }
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<String> {
+        let expression = expression.trim();
+        if let Some((function, rest)) = expression.split_once('(') {
+            let argument = rest
+                .strip_suffix(')')
+                .ok_or_else(|| eyre!("unterminated function call in template: {expression}"))?;
+            self.call(function.trim(), argument)
+        } else {
+            self.variable(expression)
+        }
+    }
+}
+
+/// Render every `{{ expression }}` in `input`. Expressions are either a
+/// variable (`xdg_data`, `binary`, ...) or a single-argument function call
+/// (`hash(target)`, `hash("literal")`).
+pub fn render(input: &str, ctx: &TemplateContext) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        let end = after_open
+            .find("}}")
+            .ok_or_else(|| eyre!("unterminated `{{{{` in template: {input}"))?;
+        output.push_str(&ctx.evaluate(&after_open[..end])?);
+        rest = &after_open[end + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+pub fn is_template(input: &str) -> bool {
+    input.contains("{{")
+}
+
+/// The closest directory to `dir`, or `dir` itself, that's a git repo or has
+/// a project file boxxy knows about, like `Cargo.toml`.
+fn project_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join(".git").exists() || Project::detect(dir).is_some())
+        .map(Path::to_path_buf)
+}
+
+fn dir_to_string(dir: Option<PathBuf>, name: &str) -> Result<String> {
+    dir.map(|dir| dir.to_string_lossy().to_string())
+        .ok_or_else(|| eyre!("could not determine a value for `{name}`"))
+}
+
+/// FNV-1a is tiny and, unlike `DefaultHasher`, stable across Rust releases,
+/// which matters since hashes end up in paths on disk.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Today's date (UTC) as `YYYY-MM-DD`.
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    use color_eyre::Result;
+
+    #[test]
+    fn test_render_passes_plain_strings_through() -> Result<()> {
+        let ctx = TemplateContext::new();
+        assert_eq!(render("~/.config/aws", &ctx)?, "~/.config/aws");
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_variables_and_functions() -> Result<()> {
+        let ctx = TemplateContext::new()
+            .with_binary("aws")
+            .with_target("/home/boxxy/.aws");
+        assert_eq!(render("/store/{{ binary }}", &ctx)?, "/store/aws");
+        assert_eq!(
+            render("{{binary}}-{{ hash(target) }}", &ctx)?,
+            format!("aws-{:016x}", fnv1a(b"/home/boxxy/.aws"))
+        );
+        assert_eq!(
+            render("{{ hash(\"x\") }}", &ctx)?,
+            format!("{:016x}", fnv1a(b"x"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_aliases() -> Result<()> {
+        let ctx = TemplateContext::new();
+        assert_eq!(
+            render("{{config}}", &ctx)?,
+            render("{{ xdg_config }}", &ctx)?
+        );
+        assert_eq!(render("{{ data }}", &ctx)?, render("{{ xdg_data }}", &ctx)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_root() -> Result<()> {
+        let root = Scratch::new("project")?;
+        let nested = root.join("src/deeply/nested");
+        std::fs::create_dir_all(&nested)?;
+        assert_eq!(project_root(&nested), None);
+
+        std::fs::write(root.join("Cargo.toml"), "")?;
+        assert_eq!(project_root(&nested), Some(root.to_path_buf()));
+        std::fs::create_dir(root.join("src/.git"))?;
+        assert_eq!(project_root(&nested), Some(root.join("src")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_rejects_unknown_and_unterminated_expressions() {
+        let ctx = TemplateContext::new();
+        assert!(render("{{ nope }}", &ctx).is_err());
+        assert!(render("{{ binary }}", &ctx).is_err());
+        assert!(render("{{ home", &ctx).is_err());
+        assert!(render("{{ frobnicate(home) }}", &ctx).is_err());
+    }
+}
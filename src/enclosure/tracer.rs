@@ -1,21 +1,109 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::sync::mpsc::Sender;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 use cfg_if::cfg_if;
 use color_eyre::Result;
 use log::*;
 use nix::sys::ptrace;
-use nix::sys::signal::Signal;
-use nix::sys::wait::{waitpid, WaitStatus};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 
-use super::register::{syscall_number_from_user_regs, StringRegister};
+use super::enforce::{EnforceAction, Enforcement};
+use super::logsample::LogSampler;
+use super::register::{
+    set_return_value, set_syscall_number, syscall_number_from_user_regs, StringRegister,
+};
 use super::syscall::Syscall;
 
+/// Syscalls made by a traced process and all of its children, in the order
+/// that they're made. Iteration ends once the root process exits.
+///
+/// ptrace only works from the thread that started tracing, so the session
+/// must be iterated on the thread that created it.
+pub struct TraceSession {
+    tracer: Tracer,
+    pid: Pid,
+}
+
+impl TraceSession {
+    /// Start tracing `pid`, which must have called `PTRACE_TRACEME` and then
+    /// stopped itself.
+    pub fn attach(pid: Pid) -> Result<Self> {
+        Tracer::flag(pid)?;
+        ptrace::syscall(pid, None)?;
+
+        Ok(Self {
+            tracer: Tracer::new(pid),
+            pid,
+        })
+    }
+
+    /// Spawn `command` and trace it from its first syscall after `exec`.
+    pub fn spawn(command: &mut Command) -> Result<Self> {
+        // SAFETY: only an async-signal-safe syscall is made between fork and
+        // exec.
+        unsafe {
+            command.pre_exec(|| Ok(ptrace::traceme()?));
+        }
+        let child = command.spawn()?;
+        // A traced process stops with SIGTRAP once it execs.
+        let pid = Pid::from_raw(child.id() as i32);
+        waitpid(pid, Some(WaitPidFlag::WSTOPPED))?;
+
+        Self::attach(pid)
+    }
+
+    /// Stop the traced processes from making the syscalls in `enforcement`.
+    pub fn enforce(mut self, enforcement: Enforcement) -> Self {
+        self.tracer.enforcement = Some(enforcement);
+        self
+    }
+
+    /// The pid of the root process. Once iteration ends, it has been
+    /// detached and should be reaped with [`TraceSession::wait`].
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Reap the root process once iteration has ended, and return how it
+    /// exited. A root process that was killed, ex. by `--trace-enforce`, may
+    /// have been reaped while tracing already.
+    pub fn wait(self) -> Result<WaitStatus> {
+        match self.tracer.root_status {
+            Some(status) => Ok(status),
+            None => Ok(waitpid(self.pid, None)?),
+        }
+    }
+}
+
+impl Iterator for TraceSession {
+    type Item = Result<Syscall>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tracer.next_syscall().transpose()
+    }
+}
+
 pub struct Tracer {
     children: HashMap<Pid, ChildProcess>,
+    /// Syscalls seen while waiting on children, but not yet handed out.
+    pending: VecDeque<Syscall>,
+    /// Process lifecycle events happen for every process a build spawns, so
+    /// only the first few of each are logged.
+    log: LogSampler,
+    /// Counts of the syscalls that were traced, logged when tracing ends.
+    syscalls: LogSampler,
+    /// Syscalls that the traced processes aren't allowed to make.
+    enforcement: Option<Enforcement>,
+    /// How the root process exited, if it was reaped while tracing.
+    root_status: Option<WaitStatus>,
 }
 
 impl Tracer {
@@ -24,8 +112,16 @@ impl Tracer {
         let mut children = HashMap::new();
         let mut root_child = ChildProcess::new(pid, None);
         root_child.state = ChildProcessState::Running;
+        root_child.read_program();
         children.insert(pid, root_child);
-        Self { children }
+        Self {
+            children,
+            pending: VecDeque::new(),
+            log: LogSampler::new(Level::Debug, 10),
+            syscalls: LogSampler::new(Level::Debug, 0),
+            enforcement: None,
+            root_status: None,
+        }
     }
 
     pub fn flag(pid: Pid) -> Result<()> {
@@ -44,24 +140,45 @@ impl Tracer {
         Ok(())
     }
 
-    pub fn run(&mut self, tx: Sender<Syscall>) -> Result<()> {
-        debug!("starting to run!");
-        while !self.children.is_empty() {
+    /// Run the traced processes until one of them makes a syscall, returning
+    /// `None` once they've all exited.
+    pub fn next_syscall(&mut self) -> Result<Option<Syscall>> {
+        loop {
+            if let Some(syscall) = self.pending.pop_front() {
+                return Ok(Some(syscall));
+            }
+            if self.children.is_empty() {
+                return Ok(None);
+            }
+
             let mut pids = self.children.keys().cloned().collect::<Vec<_>>();
             pids.sort();
             for pid in pids {
-                self.wait_on_child(pid, &tx)?;
+                self.wait_on_child(pid)?;
             }
         }
-
-        Ok(())
     }
 
-    fn wait_on_child(&mut self, pid: Pid, tx: &Sender<Syscall>) -> Result<()> {
-        let status = waitpid(pid, Some(nix::sys::wait::WaitPidFlag::WNOHANG))?;
+    fn wait_on_child(&mut self, pid: Pid) -> Result<()> {
+        let status = match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+            // Reaped by someone else, ex. killed along with the rest of its
+            // pid namespace when the root process was.
+            Err(nix::errno::Errno::ECHILD) => return self.remove_child(pid),
+            status => status?,
+        };
+        if matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..))
+            && self
+                .children
+                .get(&pid)
+                .is_some_and(|child| child.parent.is_none())
+        {
+            self.root_status = Some(status);
+        }
         match status {
             WaitStatus::Exited(pid, status) => {
-                debug!("process {pid} exited with status {status}");
+                if self.log.sample("process exited") {
+                    debug!("process {pid} exited with status {status}");
+                }
                 self.remove_child(pid)?;
             }
             WaitStatus::PtraceEvent(pid, signal, event) => {
@@ -73,19 +190,28 @@ impl Tracer {
                     | libc::PTRACE_EVENT_VFORK => {
                         let child_pid = ptrace::getevent(pid)?;
                         let child_pid = Pid::from_raw(child_pid as i32);
-                        self.children
-                            .insert(child_pid, ChildProcess::new(child_pid, Some(pid)));
-                        debug!("process {pid} spawned {child_pid}");
+                        let mut new_child = ChildProcess::new(child_pid, Some(pid));
+                        new_child.program = child.program.clone();
+                        self.children.insert(child_pid, new_child);
+                        if self.log.sample("process spawned") {
+                            debug!("process {pid} spawned {child_pid}");
+                        }
                         ptrace::syscall(pid, signal)?;
                     }
                     libc::PTRACE_EVENT_EXEC => {
-                        debug!("process {pid} exec'd");
+                        child.read_program();
+                        if self.log.sample("process exec'd") {
+                            debug!("process {pid} exec'd");
+                        }
                         ptrace::syscall(pid, signal)?;
                     }
                     libc::PTRACE_EVENT_EXIT => {
-                        debug!("process {pid} exited");
+                        if self.log.sample("process exiting") {
+                            debug!("process {pid} exiting");
+                        }
                         if let Some(child) = self.children.get(&pid) {
                             if child.parent.is_none() {
+                                self.children.remove(&pid);
                                 ptrace::detach(pid, None)?;
                                 self.handle_root_exit()?;
                                 return Ok(());
@@ -101,81 +227,82 @@ impl Tracer {
                 child.last_signal = None;
                 match &child.state {
                     ChildProcessState::Running => {
-                        trace!("process {pid} entered syscall");
                         child.state = ChildProcessState::EnteringSyscall;
-                        self.handle_syscall_enter(pid, tx)?;
+                        self.handle_syscall_enter(pid)?;
+                        if self.enforce(pid)? {
+                            // The process was killed, so there's nothing
+                            // left to restart.
+                            return Ok(());
+                        }
                         ptrace::syscall(pid, None)?;
                     }
+                    // Syscall stops come in pairs, so the next one after
+                    // the exit is the entry of the next syscall.
                     ChildProcessState::EnteringSyscall => {
-                        trace!("process {pid} exited syscall");
-                        child.state = ChildProcessState::ExitingSyscall;
-                        self.handle_syscall_exit(pid)?;
-                        ptrace::syscall(pid, None)?;
-                    }
-                    ChildProcessState::ExitingSyscall => {
-                        trace!("process {pid} returned to running");
                         child.state = ChildProcessState::Running;
+                        self.handle_syscall_exit(pid)?;
                         ptrace::syscall(pid, None)?;
                     }
                     _ => {}
                 }
             }
             WaitStatus::Signaled(pid, signal, _core_dumped) => {
-                debug!("process {pid} signalled with {signal}");
+                if self.log.sample("process signalled") {
+                    debug!("process {pid} signalled with {signal}");
+                }
                 let child = self.children.get_mut(&pid).unwrap();
                 child.last_signal = Some(signal);
                 match signal {
                     Signal::SIGTRAP => match child.state {
                         ChildProcessState::Created => {
-                            debug!("transition created => running");
+                            trace!("process {pid} transitioned from created to running");
                             child.state = ChildProcessState::Running;
                             Self::flag(child.pid)?;
                             ptrace::syscall(pid, None)?;
                         }
                         ChildProcessState::Running => {
-                            debug!("ptrace event");
+                            trace!("process {pid} stopped for a ptrace event");
                             child.state = ChildProcessState::PtraceEvent;
                             ptrace::syscall(pid, None)?;
                         }
                         _ => {}
                     },
                     Signal::SIGTERM | Signal::SIGKILL => {
-                        debug!("process {pid} signalled with {signal}");
                         self.remove_child(pid)?;
                     }
                     _ => {
-                        debug!("process {pid} signalled with {signal}");
                         ptrace::syscall(pid, child.last_signal)?;
                     }
                 }
             }
             WaitStatus::Stopped(pid, signal) => {
                 let child = self.children.get_mut(&pid).unwrap();
-                debug!(
-                    "{} {pid} stopped with {signal}",
-                    if child.parent.is_none() {
-                        "root"
-                    } else {
-                        "child"
-                    }
-                );
+                if self.log.sample("process stopped") {
+                    debug!(
+                        "{} {pid} stopped with {signal}",
+                        if child.parent.is_none() {
+                            "root"
+                        } else {
+                            "child"
+                        }
+                    );
+                }
                 child.last_signal = None;
                 match signal {
                     Signal::SIGTRAP | Signal::SIGSTOP => match child.state {
                         ChildProcessState::Created => {
-                            debug!("transition created => running");
+                            trace!("process {pid} transitioned from created to running");
                             child.state = ChildProcessState::Running;
                             ptrace::syscall(pid, None)?;
                         }
                         ChildProcessState::Running => {
-                            debug!("ptrace event");
+                            trace!("process {pid} stopped for a ptrace event");
                             ptrace::syscall(pid, child.last_signal)?;
                         }
                         _ => {}
                     },
                     _ => {
                         self.remove_child(pid)?;
-                        debug!("process {pid} stopped with {signal}");
                     }
                 }
             }
@@ -190,9 +317,13 @@ impl Tracer {
     }
 
     fn remove_child(&mut self, pid: Pid) -> Result<()> {
-        debug!("! removing child {pid}");
+        trace!("removing child {pid}");
         let child = self.children.remove(&pid);
-        ptrace::detach(pid, None)?;
+        // A process that was killed is already gone, and can't be detached.
+        match ptrace::detach(pid, None) {
+            Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => return Err(err.into()),
+        }
 
         if let Some(child) = child {
             if child.parent.is_none() {
@@ -204,28 +335,86 @@ impl Tracer {
     }
 
     fn handle_root_exit(&mut self) -> Result<()> {
-        debug!("!!! parent died, stopping all children!");
-
         let children = self.children.clone();
         let children = children.values();
-        debug!("cleaning up {} children!", children.len());
+        debug!("root process exited, stopping {} children", children.len());
         for child in children {
-            ptrace::detach(child.pid, Signal::SIGTERM)?;
+            match ptrace::detach(child.pid, Signal::SIGTERM) {
+                Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+                Err(err) => return Err(err.into()),
+            }
             self.children.remove(&child.pid);
-            debug!("removed child {}", child.pid);
+            trace!("removed child {}", child.pid);
         }
 
         Ok(())
     }
 
-    fn handle_syscall_enter(&mut self, pid: Pid, tx: &Sender<Syscall>) -> Result<()> {
+    fn handle_syscall_enter(&mut self, pid: Pid) -> Result<()> {
         if let Some(syscall) = super::syscall::handle_syscall(self, pid)? {
-            tx.send(syscall)?;
+            trace!(
+                "process {pid} entered {} ({:?})",
+                syscall.name,
+                syscall.path
+            );
+            self.syscalls.count(&syscall.name);
+            self.pending.push_back(syscall);
         }
         Ok(())
     }
 
-    fn handle_syscall_exit(&self, pid: Pid) -> Result<()> {
+    /// Stop `pid`, at its syscall-enter stop, from making a syscall that
+    /// `--trace-enforce` forbids: either skip it and fail it with `EPERM` once
+    /// it exits, or kill the process. Returns whether it was killed.
+    fn enforce(&mut self, pid: Pid) -> Result<bool> {
+        let Some(enforcement) = &self.enforcement else {
+            return Ok(false);
+        };
+        let child = self.children.get_mut(&pid).unwrap();
+        let mut regs = child.get_registers()?;
+        let Some(name) = enforcement.forbids(syscall_number_from_user_regs!(regs)) else {
+            return Ok(false);
+        };
+        let program = child
+            .program
+            .as_ref()
+            .map(|program| program.display().to_string())
+            .unwrap_or_else(|| "?".to_string());
+
+        set_syscall_number!(regs, u64::MAX);
+        child.set_registers(regs)?;
+        match enforcement.action {
+            EnforceAction::Eperm => {
+                warn!("--trace-enforce: {program} ({pid}) called {name}, failing it with EPERM");
+                child.denied = true;
+                Ok(false)
+            }
+            EnforceAction::Kill => {
+                warn!("--trace-enforce: {program} ({pid}) called {name}, killing it");
+                kill(pid, Signal::SIGKILL)?;
+                ptrace::syscall(pid, None).or_else(|err| match err {
+                    nix::errno::Errno::ESRCH => Ok(()),
+                    err => Err(err),
+                })?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn handle_syscall_exit(&mut self, pid: Pid) -> Result<()> {
+        let child = self.children.get_mut(&pid).unwrap();
+        if child.denied {
+            child.denied = false;
+            let mut regs = child.get_registers()?;
+            set_return_value!(regs, -libc::EPERM as u64);
+            child.set_registers(regs)?;
+        }
+
+        // Reading the registers is a syscall of our own, so skip it when
+        // nobody will see the result.
+        if !log_enabled!(Level::Trace) {
+            return Ok(());
+        }
         let child = self.children.get(&pid).unwrap();
         let regs = child.get_registers()?;
         trace!(
@@ -249,7 +438,12 @@ pub struct ChildProcess {
     state: ChildProcessState,
     last_signal: Option<Signal>,
     parent: Option<Pid>,
-    register_cache: RefCell<HashMap<StringRegister, String>>,
+    /// The program the process is running, updated when it execs.
+    program: Option<PathBuf>,
+    register_cache: RefCell<HashMap<StringRegister, OsString>>,
+    /// Whether the syscall the process is in was skipped by `--trace-enforce`,
+    /// and should return `EPERM`.
+    denied: bool,
 }
 
 impl ChildProcess {
@@ -259,7 +453,9 @@ impl ChildProcess {
             state: ChildProcessState::Created,
             last_signal: None,
             parent,
+            program: None,
             register_cache: RefCell::new(HashMap::new()),
+            denied: false,
         }
     }
 
@@ -267,6 +463,14 @@ impl ChildProcess {
         self.pid
     }
 
+    pub fn program(&self) -> Option<&PathBuf> {
+        self.program.as_ref()
+    }
+
+    fn read_program(&mut self) {
+        self.program = std::fs::read_link(format!("/proc/{}/exe", self.pid)).ok();
+    }
+
     pub fn get_registers(&self) -> Result<PtraceRegisters> {
         cfg_if! {
             if #[cfg(target_arch = "x86_64")]  {
@@ -289,11 +493,31 @@ impl ChildProcess {
         }
     }
 
+    fn set_registers(&self, regs: PtraceRegisters) -> Result<()> {
+        cfg_if! {
+            if #[cfg(target_arch = "x86_64")]  {
+                ptrace::setregs(self.pid, regs).map_err(|e| e.into())
+            } else {
+                let iovec = libc::iovec {
+                    iov_base: &regs as *const _ as *mut libc::c_void,
+                    iov_len: std::mem::size_of::<PtraceRegisters>(),
+                };
+                if -1 == unsafe {
+                    libc::ptrace(libc::PTRACE_SETREGSET, libc::pid_t::from(self.pid), libc::NT_PRSTATUS, &iovec as *const _ as *const libc::c_void)
+                } {
+                    Err(nix::errno::Errno::last().into())
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
     pub fn clear_register_cache(&self) {
         self.register_cache.borrow_mut().clear();
     }
 
-    pub fn read_string(&self, register: &StringRegister, addr: *mut u64) -> Result<String> {
+    pub fn read_string(&self, register: &StringRegister, addr: *mut u64) -> Result<OsString> {
         if let Some(cached_str) = self.register_cache.borrow().get(register) {
             return Ok(cached_str.clone());
         }
@@ -326,14 +550,18 @@ impl ChildProcess {
             addr = unsafe { addr.add(1) };
         }
 
-        match String::from_utf8(buf.clone()) {
-            Ok(s) => {
-                let mut register_cache = self.register_cache.borrow_mut();
-                register_cache.insert(*register, s.clone());
-                Ok(s)
-            }
-            err @ Err(_) => err.map_err(|e| e.into()),
-        }
+        // Paths are just bytes, so don't assume that they're UTF-8.
+        let s = OsString::from_vec(buf);
+        let mut register_cache = self.register_cache.borrow_mut();
+        register_cache.insert(*register, s.clone());
+        Ok(s)
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.log.summarise("process events while tracing");
+        self.syscalls.summarise("syscalls traced");
     }
 }
 
@@ -342,6 +570,77 @@ pub enum ChildProcessState {
     Created,
     Running,
     EnteringSyscall,
-    ExitingSyscall,
     PtraceEvent,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_trace_non_utf8_path() -> Result<()> {
+        let mut name = format!("boxxy-tracer-{}-", std::process::id()).into_bytes();
+        name.push(0xff);
+        let path = std::env::temp_dir().join(OsStr::from_bytes(&name));
+
+        // `access` only takes a path, so there's no fd to fall back on.
+        let mut session = TraceSession::spawn(
+            Command::new("sh")
+                .arg("-c")
+                .arg("test -r \"$0\"")
+                .arg(&path),
+        )?;
+        let mut seen = false;
+        for syscall in &mut session {
+            seen |= syscall?.path.as_deref() == Some(path.as_path());
+        }
+        session.wait()?;
+
+        assert!(seen, "{path:?} not traced");
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_enforce() -> Result<()> {
+        let dir = Scratch::new("enforce")?;
+        let path = dir.join("made");
+        let enforce = |command: &mut Command, action| -> Result<WaitStatus> {
+            let mut session = TraceSession::spawn(command)?.enforce(
+                Enforcement::new(&["mkdir".to_string(), "mkdirat".to_string()], action)
+                    .map_err(|err| color_eyre::eyre::eyre!(err))?,
+            );
+            for syscall in &mut session {
+                syscall?;
+            }
+            session.wait()
+        };
+        let in_shell = || {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg("mkdir \"$0\"; exit $?").arg(&path);
+            command
+        };
+
+        assert!(matches!(
+            enforce(&mut in_shell(), EnforceAction::Eperm)?,
+            WaitStatus::Exited(_, 1)
+        ));
+        assert!(!path.exists());
+        assert!(matches!(
+            enforce(&mut in_shell(), EnforceAction::Kill)?,
+            WaitStatus::Exited(_, 137)
+        ));
+        assert!(!path.exists());
+        // The root process itself is killed.
+        assert!(matches!(
+            enforce(Command::new("mkdir").arg(&path), EnforceAction::Kill)?,
+            WaitStatus::Signaled(_, Signal::SIGKILL, _)
+        ));
+        assert!(!path.exists());
+        Ok(())
+    }
+}
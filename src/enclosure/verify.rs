@@ -0,0 +1,262 @@
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+
+use super::fs::FsDriver;
+use super::rule::{Rule, RuleMode};
+use super::selftest::{
+    copied, empty_and_read_only, masked, on_tmpfs, read_only, same_file, shadowed_read_only,
+    Outcome,
+};
+
+/// What a path looks like from one side of the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStats {
+    Missing,
+    Directory { entries: usize },
+    File { size: u64 },
+}
+
+impl PathStats {
+    pub fn of(path: &Path) -> Self {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => PathStats::Directory {
+                entries: fs::read_dir(path)
+                    .map(|entries| entries.count())
+                    .unwrap_or(0),
+            },
+            Ok(metadata) => PathStats::File {
+                size: metadata.len(),
+            },
+            Err(_) => PathStats::Missing,
+        }
+    }
+
+    fn entries(&self) -> usize {
+        match self {
+            PathStats::Directory { entries } => *entries,
+            _ => 0,
+        }
+    }
+}
+
+impl Display for PathStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathStats::Missing => write!(f, "missing"),
+            PathStats::Directory { entries } => write!(f, "directory, {entries} entries"),
+            PathStats::File { size } => write!(f, "file, {size} bytes"),
+        }
+    }
+}
+
+/// A rule's target and rewrite as seen from the host, before the container
+/// is set up.
+#[derive(Debug, Clone)]
+pub struct HostView {
+    pub rule: Rule,
+    pub target: PathBuf,
+    pub rewrite: PathBuf,
+    pub host_target: PathStats,
+    pub host_rewrite: PathStats,
+}
+
+/// Look at every rule's target and rewrite from the host. Must be called
+/// before any placeholder files are created.
+pub fn host_views(rules: &[Rule], fs: &FsDriver) -> Result<Vec<HostView>> {
+    let mut views = vec![];
+    for rule in rules {
+        let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+        let rewrite = fs.fully_expand_path(&rule.rewrite)?;
+        views.push(HostView {
+            rule: rule.clone(),
+            host_target: PathStats::of(&target),
+            host_rewrite: PathStats::of(&rewrite),
+            target,
+            rewrite,
+        });
+    }
+
+    Ok(views)
+}
+
+/// Compare each host view with what the target looks like from inside the
+/// container, and print a report. Returns whether every rule is in effect.
+pub fn report(views: &[HostView]) -> Result<bool> {
+    if views.is_empty() {
+        println!("no rules apply");
+        return Ok(true);
+    }
+
+    let mut all_in_effect = true;
+    for view in views {
+        let container_target = PathStats::of(&view.target);
+        let status = match view.rule.faultmode {
+            _ if view.rule.deny => match empty_and_read_only(&view.target) {
+                Outcome::Pass => "denied".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT DENIED: {reason}")
+                }
+            },
+            _ if view.rule.mode == RuleMode::Tmpfs => match on_tmpfs(&view.target) {
+                Outcome::Pass => "tmpfs".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT A TMPFS: {reason}")
+                }
+            },
+            _ if view.rule.mode == RuleMode::ExposeRo => match read_only(&view.target) {
+                Outcome::Pass => "exposed read-only".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT EXPOSED READ-ONLY: {reason}")
+                }
+            },
+            _ if view.rule.mode == RuleMode::Mask => match masked(&view.target) {
+                Outcome::Pass => "masked".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT MASKED: {reason}")
+                }
+            },
+            _ if view.rule.mode == RuleMode::Copy => match copied(&view.target, &view.rewrite) {
+                Outcome::Pass => "copied".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT COPIED: {reason}")
+                }
+            },
+            Some(fault) => format!("fault injected: {}", format!("{fault:?}").to_lowercase()),
+            None if view.rule.readonly => match shadowed_read_only(&view.target, &view.rewrite) {
+                Outcome::Pass => "shadowed read-only".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT SHADOWED READ-ONLY: {reason}")
+                }
+            },
+            None => match same_file(&view.target, &view.rewrite) {
+                Outcome::Pass => "shadowed".to_string(),
+                Outcome::Fail(reason) | Outcome::Skip(reason) => {
+                    all_in_effect = false;
+                    format!("NOT SHADOWED: {reason}")
+                }
+            },
+        };
+
+        if view.rule.deny {
+            println!("rule '{}' (deny {})", view.rule.name, view.rule.target);
+        } else if view.rule.mode == RuleMode::Tmpfs {
+            println!("rule '{}' (tmpfs at {})", view.rule.name, view.rule.target);
+        } else if view.rule.mode == RuleMode::ExposeRo {
+            println!(
+                "rule '{}' (expose {} read-only)",
+                view.rule.name, view.rule.target
+            );
+        } else if view.rule.mode == RuleMode::Mask {
+            println!("rule '{}' (mask {})", view.rule.name, view.rule.target);
+        } else if view.rule.mode == RuleMode::Copy {
+            println!(
+                "rule '{}' ({} copied to {})",
+                view.rule.name, view.rule.rewrite, view.rule.target
+            );
+        } else {
+            println!(
+                "rule '{}' ({} -> {})",
+                view.rule.name, view.rule.target, view.rule.rewrite
+            );
+        }
+        println!(
+            "  host:      {}: {}",
+            view.target.display(),
+            view.host_target
+        );
+        if view.rule.has_rewrite() {
+            println!(
+                "  rewrite:   {}: {}",
+                view.rewrite.display(),
+                view.host_rewrite
+            );
+        }
+        println!(
+            "  container: {}: {container_target} ({status})",
+            view.target.display()
+        );
+        for warning in warnings(view) {
+            println!("  warning: {warning}");
+        }
+    }
+
+    Ok(all_in_effect)
+}
+
+fn warnings(view: &HostView) -> Vec<String> {
+    let mut warnings = vec![];
+    match (&view.host_target, view.rule.mode) {
+        (PathStats::Missing, RuleMode::ExposeRo) => warnings
+            .push("the target doesn't exist on the host, so there's nothing to expose".to_string()),
+        (PathStats::Missing, RuleMode::Mask) => warnings
+            .push("the target doesn't exist on the host, so there's nothing to mask".to_string()),
+        (PathStats::Missing, RuleMode::Copy) => {}
+        (PathStats::Missing, _) => warnings.push(
+            "the target doesn't exist on the host, an empty placeholder is created for it"
+                .to_string(),
+        ),
+        (PathStats::Directory { .. }, RuleMode::File) => {
+            warnings.push("the target is a directory, but the rule's mode is `file`".to_string())
+        }
+        (PathStats::File { .. }, RuleMode::Directory | RuleMode::Tmpfs) => warnings.push(format!(
+            "the target is a file, but the rule's mode is `{}`",
+            format!("{:?}", view.rule.mode).to_lowercase()
+        )),
+        _ => {}
+    }
+
+    let hidden = view.host_target.entries();
+    if hidden > 0 && view.host_rewrite.entries() == 0 && view.rule.has_rewrite() {
+        warnings.push(format!(
+            "the rewrite is empty, so the {hidden} entries in the real target are hidden"
+        ));
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(mode: RuleMode, host_target: PathStats, host_rewrite: PathStats) -> HostView {
+        HostView {
+            rule: Rule::builder("test", "~/.test", "~/.config/test")
+                .with_mode(mode)
+                .build(),
+            target: PathBuf::from("/home/boxxy/.test"),
+            rewrite: PathBuf::from("/home/boxxy/.config/test"),
+            host_target,
+            host_rewrite,
+        }
+    }
+
+    #[test]
+    fn test_warnings() {
+        let dir = |entries| PathStats::Directory { entries };
+        let file = PathStats::File { size: 1 };
+
+        assert!(warnings(&view(RuleMode::Directory, dir(2), dir(1))).is_empty());
+        assert_eq!(
+            warnings(&view(RuleMode::Directory, PathStats::Missing, dir(0))).len(),
+            1
+        );
+        assert_eq!(
+            warnings(&view(RuleMode::Directory, file.clone(), file)).len(),
+            1
+        );
+        assert_eq!(
+            warnings(&view(RuleMode::File, dir(3), PathStats::Missing)).len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::unistd::Pid;
+
+/// Mount points whose accesses aren't worth reporting.
+const SKIPPED_MOUNTS: &[&str] = &["/proc", "/sys"];
+
+/// Records the files that the boxxed program opens with fanotify, as a
+/// cheaper alternative to tracing every syscall. Only opens are seen: not
+/// reads of metadata like `stat`, and not deletes or renames, which mount
+/// marks can't report.
+///
+/// Each of the container's mounts gets its own fanotify group, so that the
+/// directories in its events can be resolved through the mount they
+/// happened on, and paths under a rule's target are reported as the program
+/// saw them.
+///
+/// The kernel only lets a user namespace watch mounts of filesystems that
+/// it mounted itself, so the container can't watch itself. Instead, the
+/// host side watches the container's mounts through `/proc/<pid>/root`,
+/// which needs `CAP_SYS_ADMIN`.
+pub struct Watcher {
+    groups: Vec<Group>,
+    container_root: PathBuf,
+    /// Directory handles that have already been resolved, per group.
+    dirs: HashMap<(usize, Vec<u8>), PathBuf>,
+    paths: Vec<PathBuf>,
+    seen: HashSet<PathBuf>,
+    pid: i32,
+}
+
+struct Group {
+    fanotify: OwnedFd,
+    /// The mount point, for resolving directory handles.
+    mount: File,
+}
+
+impl Watcher {
+    /// Watch every mount of the container that `pid` runs in. Must be called
+    /// once the container is set up.
+    pub fn new(pid: Pid, container_root: &Path) -> Result<Self> {
+        let root = PathBuf::from(format!("/proc/{pid}/root"));
+        let mut groups = vec![];
+        for mount_point in mount_points(pid)? {
+            if SKIPPED_MOUNTS
+                .iter()
+                .any(|skipped| mount_point.starts_with(skipped))
+            {
+                continue;
+            }
+            let path = root.join(mount_point.strip_prefix("/")?);
+            // Rules bind-mount single files too, but there's no directory on
+            // those mounts to resolve events against.
+            if !path.is_dir() {
+                trace!("not watching {}: not a directory", mount_point.display());
+                continue;
+            }
+            match Group::new(&path) {
+                Ok(group) => groups.push(group),
+                // The root can't be missed, everything else is best-effort.
+                Err(err) if mount_point == Path::new("/") => {
+                    return Err(eyre!(
+                        "--watch needs CAP_SYS_ADMIN, ex. running boxxy as root, try --trace instead: could not watch the container's root: {err}"
+                    ))
+                }
+                Err(err) => debug!("not watching {}: {err}", mount_point.display()),
+            }
+        }
+        debug!("watching {} mounts", groups.len());
+
+        Ok(Self {
+            groups,
+            container_root: container_root.to_path_buf(),
+            dirs: HashMap::new(),
+            paths: vec![],
+            seen: HashSet::new(),
+            pid: std::process::id() as i32,
+        })
+    }
+
+    /// Wait up to `timeout` for events, and record the paths in them.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        let mut fds: Vec<PollFd> = self
+            .groups
+            .iter()
+            .map(|group| PollFd::new(group.fanotify.as_fd(), PollFlags::POLLIN))
+            .collect();
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        match poll(&mut fds, timeout) {
+            Ok(0) | Err(Errno::EINTR) => return Ok(()),
+            Ok(_) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let ready: Vec<usize> = fds
+            .iter()
+            .enumerate()
+            .filter(|(_, fd)| {
+                fd.revents()
+                    .is_some_and(|revents| revents.contains(PollFlags::POLLIN))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        for i in ready {
+            self.read_group(i)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record everything that's still queued, and return every path that
+    /// was seen, in the order they were first seen.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        for i in 0..self.groups.len() {
+            self.read_group(i)?;
+        }
+
+        Ok(self.paths)
+    }
+
+    fn read_group(&mut self, i: usize) -> Result<()> {
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            // SAFETY: the buffer is valid for `buffer.len()` bytes.
+            let len = unsafe {
+                libc::read(
+                    self.groups[i].fanotify.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if len < 0 {
+                return match Errno::last() {
+                    Errno::EAGAIN | Errno::EINTR => Ok(()),
+                    err => Err(err.into()),
+                };
+            }
+            if len == 0 {
+                return Ok(());
+            }
+
+            for event in events(&buffer[..len as usize]) {
+                self.record(i, event);
+            }
+        }
+    }
+
+    fn record(&mut self, i: usize, event: Event) {
+        if event.mask & libc::FAN_Q_OVERFLOW != 0 {
+            warn!("--watch: too many events at once, some accesses are missing from the report");
+            return;
+        }
+        if event.pid == self.pid {
+            return;
+        }
+        let Some(info) = event.info else {
+            return;
+        };
+
+        let key = (i, info.handle.clone());
+        let dir = match self.dirs.get(&key) {
+            Some(dir) => dir.clone(),
+            None => match self.groups[i].resolve(&info.handle) {
+                Ok(dir) => {
+                    self.dirs.insert(key, dir.clone());
+                    dir
+                }
+                Err(err) => {
+                    trace!("could not resolve a directory handle: {err}");
+                    return;
+                }
+            },
+        };
+
+        let mut path = match &info.name {
+            Some(name) if name.as_bytes() != b"." => dir.join(name),
+            _ => dir,
+        };
+        // A chrooted container's paths still show up under its root.
+        if let Ok(stripped) = path.strip_prefix(&self.container_root) {
+            path = Path::new("/").join(stripped);
+        }
+        trace!("process {} accessed {path:?}", event.pid);
+        if self.seen.insert(path.clone()) {
+            self.paths.push(path);
+        }
+    }
+}
+
+impl Group {
+    fn new(mount_point: &Path) -> Result<Self> {
+        // SAFETY: plain syscall, the result is checked below.
+        let fd = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF
+                    | libc::FAN_CLOEXEC
+                    | libc::FAN_NONBLOCK
+                    | libc::FAN_REPORT_DFID_NAME,
+                (libc::O_RDONLY | libc::O_LARGEFILE) as u32,
+            )
+        };
+        if fd < 0 {
+            return Err(Errno::last().into());
+        }
+        // SAFETY: `fd` was just opened, and nothing else owns it.
+        let fanotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let path = CString::new(mount_point.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a valid C string for the duration of the call.
+        let ret = unsafe {
+            libc::fanotify_mark(
+                fanotify.as_raw_fd(),
+                libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+                libc::FAN_OPEN | libc::FAN_ONDIR,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(Errno::last().into());
+        }
+
+        // open_by_handle_at(2) doesn't take O_PATH fds.
+        let mount = File::open(mount_point)?;
+
+        Ok(Self { fanotify, mount })
+    }
+
+    /// The path of the directory with the file handle `handle`, as seen
+    /// through this group's mount.
+    fn resolve(&self, handle: &[u8]) -> Result<PathBuf> {
+        // `struct file_handle` needs 4-byte alignment.
+        let mut aligned = vec![0u32; handle.len().div_ceil(4)];
+        // SAFETY: `aligned` is at least `handle.len()` bytes long.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                handle.as_ptr(),
+                aligned.as_mut_ptr().cast::<u8>(),
+                handle.len(),
+            );
+        }
+        // SAFETY: `aligned` holds a `struct file_handle` copied from the
+        // kernel.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_open_by_handle_at,
+                self.mount.as_raw_fd(),
+                aligned.as_ptr(),
+                libc::O_PATH | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(Errno::last().into());
+        }
+        // SAFETY: `fd` was just opened, and nothing else owns it.
+        let dir = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        Ok(std::fs::read_link(format!(
+            "/proc/self/fd/{}",
+            dir.as_raw_fd()
+        ))?)
+    }
+}
+
+/// A fanotify event, with the directory it happened in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Event {
+    mask: u64,
+    pid: i32,
+    info: Option<FidInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FidInfo {
+    /// The directory's `struct file_handle`, header included.
+    handle: Vec<u8>,
+    /// The name of the entry in the directory, if the event was about one.
+    name: Option<std::ffi::OsString>,
+}
+
+/// Parse the events in a buffer read from a fanotify group.
+fn events(mut buffer: &[u8]) -> Vec<Event> {
+    let u16_at = |bytes: &[u8], at: usize| u16::from_ne_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at =
+        |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+
+    let mut events = vec![];
+    // struct fanotify_event_metadata
+    while buffer.len() >= 24 {
+        let event_len = u32_at(buffer, 0) as usize;
+        let metadata_len = u16_at(buffer, 6) as usize;
+        if event_len < metadata_len || event_len > buffer.len() {
+            break;
+        }
+        let event = &buffer[..event_len];
+        let mask = u64::from_ne_bytes(event[8..16].try_into().unwrap());
+        let pid = u32_at(event, 20) as i32;
+
+        // struct fanotify_event_info_fid: a header, the fsid, a struct
+        // file_handle, and for DFID_NAME records, a NUL-terminated name.
+        let mut info = None;
+        let mut records = &event[metadata_len..];
+        while records.len() >= 4 {
+            let info_type = records[0];
+            let len = u16_at(records, 2) as usize;
+            if len < 4 || len > records.len() {
+                break;
+            }
+            let record = &records[..len];
+            if matches!(
+                info_type,
+                libc::FAN_EVENT_INFO_TYPE_FID
+                    | libc::FAN_EVENT_INFO_TYPE_DFID_NAME
+                    | libc::FAN_EVENT_INFO_TYPE_DFID
+            ) && record.len() >= 20
+            {
+                let handle_len = 8 + u32_at(record, 12) as usize;
+                if 12 + handle_len <= record.len() {
+                    let handle = record[12..12 + handle_len].to_vec();
+                    let name = (info_type == libc::FAN_EVENT_INFO_TYPE_DFID_NAME).then(|| {
+                        let name = &record[12 + handle_len..];
+                        let end = name
+                            .iter()
+                            .position(|byte| *byte == 0)
+                            .unwrap_or(name.len());
+                        OsStr::from_bytes(&name[..end]).to_os_string()
+                    });
+                    info = Some(FidInfo { handle, name });
+                    break;
+                }
+            }
+            records = &records[len..];
+        }
+
+        events.push(Event { mask, pid, info });
+        buffer = &buffer[event_len..];
+    }
+
+    events
+}
+
+/// Every mount point in `pid`'s mount namespace.
+fn mount_points(pid: Pid) -> Result<Vec<PathBuf>> {
+    let mountinfo = std::fs::read(format!("/proc/{pid}/mountinfo"))?;
+    Ok(mountinfo
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| line.split(|byte| *byte == b' ').nth(4))
+        .map(|mount_point| PathBuf::from(std::ffi::OsString::from_vec(unescape(mount_point))))
+        .collect())
+}
+
+/// Undo the octal escapes, ex. `\040` for a space, in a mountinfo field.
+fn unescape(field: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < field.len() {
+        let escaped = field.get(i + 1..i + 4).and_then(|digits| {
+            std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        });
+        match (field[i], escaped) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(mask: u64, pid: i32, info_type: u8, handle: &[u8], name: &[u8]) -> Vec<u8> {
+        let mut record = vec![info_type, 0, 0, 0];
+        record.extend_from_slice(&[0; 8]); // fsid
+        record.extend_from_slice(&(handle.len() as u32).to_ne_bytes());
+        record.extend_from_slice(&1i32.to_ne_bytes());
+        record.extend_from_slice(handle);
+        record.extend_from_slice(name);
+        record.push(0);
+        while record.len() % 4 != 0 {
+            record.push(0);
+        }
+        let len = record.len() as u16;
+        record[2..4].copy_from_slice(&len.to_ne_bytes());
+
+        let mut event = vec![];
+        event.extend_from_slice(&((24 + record.len()) as u32).to_ne_bytes());
+        event.extend_from_slice(&[3, 0]);
+        event.extend_from_slice(&24u16.to_ne_bytes());
+        event.extend_from_slice(&mask.to_ne_bytes());
+        event.extend_from_slice(&(-1i32).to_ne_bytes());
+        event.extend_from_slice(&pid.to_ne_bytes());
+        event.extend_from_slice(&record);
+        event
+    }
+
+    #[test]
+    fn test_events() {
+        let mut buffer = event(
+            libc::FAN_OPEN,
+            42,
+            libc::FAN_EVENT_INFO_TYPE_DFID_NAME,
+            &[1, 2, 3, 4, 5, 6, 7, 8],
+            b"config",
+        );
+        buffer.extend(event(
+            libc::FAN_OPEN | libc::FAN_ONDIR,
+            43,
+            libc::FAN_EVENT_INFO_TYPE_DFID,
+            &[9; 12],
+            b"",
+        ));
+
+        let events = events(&buffer);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].pid, 42);
+        let info = events[0].info.as_ref().unwrap();
+        assert_eq!(info.handle.len(), 16);
+        assert_eq!(&info.handle[8..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(info.name.as_deref(), Some(OsStr::new("config")));
+        assert_eq!(events[1].mask, libc::FAN_OPEN | libc::FAN_ONDIR);
+        assert_eq!(events[1].info.as_ref().unwrap().name, None);
+
+        // A truncated event is dropped rather than misread.
+        assert_eq!(super::events(&buffer[..30]).len(), 0);
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape(b"/mnt/with\\040space"), b"/mnt/with space");
+        assert_eq!(unescape(b"/a\\134b"), b"/a\\b");
+        assert_eq!(unescape(b"/trailing\\"), b"/trailing\\");
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::enclosure::rule::{is_glob, BoxxyRules};
+
+/// Shells that `boxxy hook` can generate a hook for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            _ => Err(format!("unsupported shell: {s}, expected bash or zsh")),
+        }
+    }
+}
+
+/// The programs that rules are limited to with `only`, by file name.
+pub fn programs_with_rules(rules: &BoxxyRules) -> BTreeSet<String> {
+    rules
+        .rules
+        .iter()
+        .flat_map(|rule| &rule.only)
+        .filter(|program| !is_glob(program))
+        .filter_map(|program| Path::new(program).file_name())
+        .map(|program| program.to_string_lossy().to_string())
+        .collect()
+}
+
+/// A script for `shell` that, before running a command, suggests running it
+/// with boxxy if it's one of `programs`. The programs are baked in, so the
+/// hook picks up new rules when the shell starts and evals it again. In
+/// bash, it's added to bash-preexec if that's loaded, and otherwise a
+/// `DEBUG` trap that's already set still runs after boxxy's.
+pub fn hook(shell: Shell, programs: &BTreeSet<String>) -> String {
+    let programs: Vec<String> = programs.iter().map(|program| quote(program)).collect();
+    let programs = programs.join(" ");
+    match shell {
+        Shell::Zsh => format!(
+            r#"__boxxy_programs=({programs})
+__boxxy_preexec() {{
+  local cmd=${{${{(z)1}}[1]}}
+  if (( ${{__boxxy_programs[(Ie)$cmd]}} )); then
+    print -u2 "boxxy: $cmd has boxxy rules, run \`boxxy $1\` to use them"
+  fi
+}}
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec __boxxy_preexec
+"#
+        ),
+        Shell::Bash => format!(
+            r#"__boxxy_programs=({programs})
+__boxxy_preexec() {{
+  [ -n "$COMP_LINE" ] && return
+  [ "$1" = "$PROMPT_COMMAND" ] && return
+  local cmd=${{1%% *}} program
+  for program in "${{__boxxy_programs[@]}}"; do
+    if [ "$cmd" = "$program" ]; then
+      echo "boxxy: $cmd has boxxy rules, run \`boxxy $1\` to use them" >&2
+      return
+    fi
+  done
+}}
+if [ -n "${{bash_preexec_imported:-${{__bp_imported:-}}}}" ]; then
+  [[ " ${{preexec_functions[*]}} " == *" __boxxy_preexec "* ]] || preexec_functions+=(__boxxy_preexec)
+else
+  __boxxy_trap_command() {{ __boxxy_debug_trap=$3; }}
+  __boxxy_debug_trap=
+  eval "__boxxy_trap_command $(trap -p DEBUG)"
+  case $__boxxy_debug_trap in
+    *__boxxy_preexec*) ;;
+    *) __boxxy_prev_debug_trap=$__boxxy_debug_trap ;;
+  esac
+  trap '__boxxy_preexec "$BASH_COMMAND"; eval "$__boxxy_prev_debug_trap"' DEBUG
+fi
+"#
+        ),
+    }
+}
+
+/// Quote `word` for bash and zsh.
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclosure::rule::Rule;
+
+    #[test]
+    fn test_hook() {
+        let rules = BoxxyRules {
+            rules: vec![
+                Rule::builder("aws", "~/.aws", "~/.config/aws")
+                    .with_only("/usr/bin/aws")
+                    .build(),
+                Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc")
+                    .with_only("npm")
+                    .with_only("it's")
+                    .build(),
+                Rule::builder("all", "~/.a", "~/.b").build(),
+            ],
+            ..Default::default()
+        };
+        let programs = programs_with_rules(&rules);
+        assert_eq!(
+            programs.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["aws", "it's", "npm"]
+        );
+
+        let zsh = hook(Shell::Zsh, &programs);
+        assert!(zsh.starts_with(r"__boxxy_programs=('aws' 'it'\''s' 'npm')"));
+        assert!(zsh.contains("add-zsh-hook preexec __boxxy_preexec"));
+        let bash = hook(Shell::Bash, &programs);
+        assert!(bash.contains("preexec_functions+=(__boxxy_preexec)"));
+        assert!(bash.contains(
+            r#"trap '__boxxy_preexec "$BASH_COMMAND"; eval "$__boxxy_prev_debug_trap"' DEBUG"#
+        ));
+    }
+}
@@ -0,0 +1,40 @@
+//! boxxy as a library, for tools that want to build rules or consume
+//! syscall traces themselves.
+//!
+//! ```no_run
+//! use std::process::Command;
+//!
+//! use boxxy::enclosure::rule::Rule;
+//! use boxxy::enclosure::TraceSession;
+//!
+//! # fn main() -> color_eyre::Result<()> {
+//! let rule = Rule::builder("aws", "~/.aws", "~/.config/aws")
+//!     .with_only("aws")
+//!     .build();
+//! println!("{}", serde_yaml::to_string(&rule)?);
+//!
+//! for syscall in TraceSession::spawn(&mut Command::new("ls"))? {
+//!     let syscall = syscall?;
+//!     if let Some(path) = syscall.path {
+//!         println!("{} {}", syscall.name, path.display());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod cli;
+pub mod colour;
+pub mod config;
+pub mod enclosure;
+pub mod hook;
+pub mod man;
+pub mod scanner;
+pub mod server;
+pub mod stats;
+pub mod store;
+#[cfg(test)]
+mod testing;
+pub mod top;
+
+pub use cli::{Args, BoxxySubcommand};
@@ -1,140 +1,407 @@
-use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::io::IsTerminal;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{ArgAction, Parser, Subcommand};
+use boxxy::cli::{Args, BoxxySubcommand, ConfigSubcommand, ControlSubcommand, RulesSubcommand};
+use boxxy::colour;
+use boxxy::config::{self, BoxxyConfig};
+use boxxy::enclosure::control::ControlRequest;
+use boxxy::enclosure::rule::{BoxxyRules, Rule};
+use boxxy::scanner::{self, update, App, Scanner};
+use boxxy::{enclosure, hook, man, server, stats, store, top};
+use clap::Parser;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use log::*;
-use scanner::App;
-
-use crate::config::BoxxyConfig;
-use crate::enclosure::rule::{BoxxyRules, Rule, RuleMode};
-use crate::scanner::Scanner;
-
-pub mod config;
-pub mod enclosure;
-pub mod scanner;
-
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-
-#[derive(Parser)]
-#[command(
-    name = "boxxy",
-    display_name = "boxxy",
-    about = "Put bad programs in a box with only their files.",
-    long_about = "boxxy forces bad programs to put their files somewhere else via Linux user namespaces.",
-    version = VERSION,
-    subcommand_negates_reqs = true,
-)]
-pub struct Args {
-    #[arg(
-        short = 'i',
-        long = "immutable",
-        default_value = "false",
-        help = "Make the root filesystem immutable."
-    )]
-    pub immutable_root: bool,
-
-    #[arg(
-        trailing_var_arg = true,
-        name = "COMMAND TO RUN",
-        required = true,
-        help = "The command to run, ex. `ls -lah` or `aws configure`."
-    )]
-    pub command_with_args: Vec<String>,
-
-    #[arg(short = 'l', long = "log-level", default_value = "info")]
-    pub log_level: String,
-
-    #[arg(
-        long = "force-colour",
-        default_value = "false",
-        help = "Force colour output even when stdout is not a tty."
-    )]
-    pub force_colour: bool,
-
-    #[arg(
-        short = 't',
-        long = "trace",
-        default_value = "false",
-        help = "Enable tracing of I/O-related syscalls and generate a report of files/directories the program touched."
-    )]
-    pub trace: bool,
-
-    #[arg(
-        short = 'd',
-        long = "dotenv",
-        default_value = "false",
-        help = "Load environment variables from the .env file in the current directory and apply them to the boxxed program."
-    )]
-    pub dotenv: bool,
-
-    #[arg(
-        long = "daemon",
-        default_value = "false",
-        help = "Fork to the background and run as a daemon."
-    )]
-    pub daemon: bool,
-
-    #[arg(
-        long = "no-config",
-        default_value = "false",
-        help = "Disable loading config files entirely.",
-        action = ArgAction::SetTrue
-    )]
-    pub no_config: bool,
-
-    #[arg(
-        short = 'r',
-        long = "rule",
-        help = "Pass rules via CLI. -r/--rule `/remount/this:/to/this:<file/dir>`",
-        action = ArgAction::Append
-    )]
-    pub arg_rules: Vec<String>,
-
-    #[command(subcommand)]
-    pub command: Option<BoxxySubcommand>,
-}
-
-#[derive(Subcommand)]
-pub enum BoxxySubcommand {
-    #[command(
-        name = "config",
-        about = "View the config file.",
-        subcommand_negates_reqs = true,
-        aliases = &["cfg", "conf", "c"]
-    )]
-    Config,
-    #[command(
-        name = "scan",
-        about = "Scan your homedir for applications that may benefit from boxxy.",
-        subcommand_negates_reqs = true,
-        aliases = &["s"]
-    )]
-    Scan,
-}
+use pretty_env_logger::env_logger::WriteStyle;
 
 fn main() -> Result<()> {
     // Fetch command to run
-    let cfg = Args::parse();
+    let mut cfg = Args::parse();
     setup_logging(&cfg)?;
+    if let Some(path) = &cfg.profile_setup {
+        enclosure::profiler::install(path)?;
+    }
+    config::remote::init(cfg.offline || matches!(cfg.command, Some(BoxxySubcommand::Check { .. })));
 
-    if let Some(cmd) = cfg.command {
-        match cmd {
-            BoxxySubcommand::Config => {
+    if let Some(cmd) = &cfg.command {
+        match *cmd {
+            BoxxySubcommand::Config { command: None } => {
                 for config_path in BoxxyConfig::rule_paths()? {
-                    let mut printer = bat::PrettyPrinter::new();
-                    printer.input_file(config_path).print()?;
+                    print_yaml(&std::fs::read(config_path)?)?;
+                }
+                return Ok(());
+            }
+            BoxxySubcommand::Config {
+                command: Some(ConfigSubcommand::Lint),
+            } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                let mut unreachable = 0;
+                for rule in &rules.rules {
+                    let reasons = config::unreachable_reasons(rule, |key| std::env::var(key).ok());
+                    if !reasons.is_empty() {
+                        unreachable += 1;
+                        println!("rule '{}' can never apply:", rule.name);
+                    }
+                    for reason in reasons {
+                        println!("  {reason}");
+                    }
+                }
+                if unreachable > 0 {
+                    std::process::exit(1);
+                }
+                println!("all {} rule(s) can apply", rules.rules.len());
+                return Ok(());
+            }
+            BoxxySubcommand::Config {
+                command: Some(ConfigSubcommand::Check),
+            } => {
+                let paths = BoxxyConfig::rule_paths()?;
+                let problems = config::check(&paths, &enclosure::fs::FsDriver::new());
+                for problem in &problems {
+                    println!("{problem}");
+                }
+                if !problems.is_empty() {
+                    std::process::exit(1);
+                }
+                println!("checked {} config file(s), no problems", paths.len());
+                return Ok(());
+            }
+            BoxxySubcommand::Config {
+                command: Some(ConfigSubcommand::Migrate { ref path }),
+            } => {
+                let paths = match path {
+                    Some(path) => vec![path.clone()],
+                    None => BoxxyConfig::rule_paths()?,
+                };
+                for path in &paths {
+                    let contents = std::fs::read_to_string(path)?;
+                    let json = path
+                        .extension()
+                        .is_some_and(|extension| extension == "json");
+                    let Some(migrated) = config::schema::migrate(&contents, json)
+                        .map_err(|err| eyre!("could not migrate {}: {err}", path.display()))?
+                    else {
+                        info!("{} is up to date", path.display());
+                        continue;
+                    };
+                    let mut backup = path.clone().into_os_string();
+                    backup.push(".bak");
+                    std::fs::copy(path, &backup)?;
+                    std::fs::write(path, migrated)?;
+                    info!(
+                        "migrated {} to config version {}, the old file is at {}",
+                        path.display(),
+                        config::schema::CURRENT_VERSION,
+                        PathBuf::from(backup).display()
+                    );
+                }
+                return Ok(());
+            }
+            BoxxySubcommand::Config {
+                command: Some(ConfigSubcommand::Trust { ref path }),
+            } => {
+                let paths = match path {
+                    Some(path) => vec![path.clone()],
+                    None => BoxxyConfig::config_file_names()?
+                        .iter()
+                        .map(PathBuf::from)
+                        .filter(|path| path.exists())
+                        .collect(),
+                };
+                if paths.is_empty() {
+                    return Err(eyre!("no config file in the current directory to trust"));
+                }
+                for path in &paths {
+                    config::trust::trust(path)?;
+                    info!("trusted {}", path.display());
                 }
                 return Ok(());
             }
-            BoxxySubcommand::Scan => {
+            BoxxySubcommand::Config {
+                command: Some(ConfigSubcommand::GenerateXdg),
+            } => {
+                let config = scanner::xdg::generate(&Scanner::new().apps)?;
+                let mut output = String::from(
+                    "# generated by `boxxy config generate-xdg`. move the existing files\n# into place before using it:\n",
+                );
+                for rule in &config.rules {
+                    let rendered = rule.render_templates(None)?;
+                    output.push_str(&format!(
+                        "#   mv {} {}\n",
+                        rendered.target.trim_end_matches('/'),
+                        rendered.rewrite
+                    ));
+                }
+                output.push_str(&serde_yaml::to_string(&config)?);
+                print_yaml(output.as_bytes())?;
+                info!("generated {} rule(s)", config.rules.len());
+                return Ok(());
+            }
+            BoxxySubcommand::Config {
+                command:
+                    Some(ConfigSubcommand::AddRule {
+                        ref name,
+                        ref target,
+                        ref rewrite,
+                        mode,
+                        ref only,
+                        ref context,
+                    }),
+            } => {
+                let mut rule = Rule::builder(name, target, rewrite.clone().unwrap_or_default())
+                    .with_mode(mode);
+                for program in only {
+                    rule = rule.with_only(program);
+                }
+                for dir in context {
+                    rule = rule.with_context(dir);
+                }
+                let path = BoxxyConfig::default_config_path()?;
+                let contents = match std::fs::read_to_string(&path) {
+                    Ok(contents) => contents,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+                    // Writing over it would lose the rules already in it.
+                    Err(err) => return Err(eyre!("could not read {}: {err}", path.display())),
+                };
+                std::fs::write(&path, config::add_rule(&contents, &rule.build())?)?;
+                info!("added rule '{name}' to {}", path.display());
+                return Ok(());
+            }
+            BoxxySubcommand::Rules {
+                command: RulesSubcommand::Sync,
+            } => {
+                let rules = config::community::sync()?;
+                info!(
+                    "synced {rules} community rule(s) to {}",
+                    config::community::rules_path()?.display()
+                );
+                return Ok(());
+            }
+            BoxxySubcommand::Rules {
+                command: RulesSubcommand::List { all, ref command },
+            } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                let fs = enclosure::fs::FsDriver::new();
+                let program = OsStr::new(&command[0]);
+                let args: Vec<OsString> = command[1..].iter().map(OsString::from).collect();
+                if let Ok(path) = which::which(program) {
+                    println!("{} is {}", command[0], path.display());
+                }
+                let pwd = std::env::current_dir()?;
+                let mut applying = 0;
+                for rule in &rules.rules {
+                    let explanation =
+                        rule.explain(program, &args, &pwd, |key| std::env::var(key).ok(), &fs)?;
+                    if explanation.applies {
+                        applying += 1;
+                    } else if !all {
+                        continue;
+                    }
+                    println!(
+                        "rule '{}' ({}) {}:",
+                        rule.name,
+                        rule.target,
+                        if explanation.applies {
+                            "applies"
+                        } else {
+                            "doesn't apply"
+                        }
+                    );
+                    for reason in explanation.reasons {
+                        println!("  {reason}");
+                    }
+                }
+                println!(
+                    "{applying} of {} rule(s) apply to {}",
+                    rules.rules.len(),
+                    command[0]
+                );
+                return Ok(());
+            }
+            BoxxySubcommand::Scan { update_db: true } => {
+                let apps = update::update()?;
+                info!(
+                    "updated the app database in {} ({apps} apps)",
+                    update::db_dir()?.display()
+                );
+                return Ok(());
+            }
+            BoxxySubcommand::Scan { update_db: false } => {
                 let apps = Scanner::new().scan()?;
                 return scan_homedir(apps);
             }
+            BoxxySubcommand::Gc { days, yes } => {
+                return store::gc(days, yes);
+            }
+            BoxxySubcommand::Stats { enable, disable } => {
+                return match (enable, disable) {
+                    (true, _) => stats::enable(),
+                    (_, true) => stats::disable(),
+                    _ => stats::print(),
+                };
+            }
+            BoxxySubcommand::Du { children } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                return store::du(&rules, &enclosure::fs::FsDriver::new(), children);
+            }
+            BoxxySubcommand::Migrate {
+                ref rule,
+                ref program,
+            } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                return store::migrate(
+                    &rules,
+                    &enclosure::fs::FsDriver::new(),
+                    rule,
+                    program.as_deref(),
+                );
+            }
+            BoxxySubcommand::Check {
+                ref binary,
+                ref args,
+            } => {
+                let rules = match BoxxyConfig::load_rules(&cfg) {
+                    Ok(rules) => rules,
+                    Err(err) => {
+                        error!("{err}");
+                        std::process::exit(2);
+                    }
+                };
+                let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+                let applies = match rules.get_all_applicable_rules(
+                    OsStr::new(binary),
+                    &args,
+                    &enclosure::fs::FsDriver::new(),
+                ) {
+                    Ok(applicable) => !applicable.is_empty(),
+                    Err(err) => {
+                        error!("{err}");
+                        std::process::exit(2);
+                    }
+                };
+                std::process::exit(if applies { 0 } else { 1 });
+            }
+            BoxxySubcommand::Hook { shell } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                print!("{}", hook::hook(shell, &hook::programs_with_rules(&rules)));
+                return Ok(());
+            }
+            BoxxySubcommand::Batch { ref file } => {
+                let mut lines = read_batch_file(file)?;
+                if lines.is_empty() {
+                    warn!("no commands in {}", file.display());
+                    return Ok(());
+                }
+                let first = lines.remove(0);
+                cfg.command_with_args = vec!["sh".into(), "-c".into(), first];
+                lines.append(&mut cfg.and_then);
+                cfg.and_then = lines;
+            }
+            BoxxySubcommand::Serve { ref program, .. }
+            | BoxxySubcommand::Verify { ref program } => {
+                cfg.command_with_args = vec![program.clone().unwrap_or_else(|| "sh".into())];
+            }
+            BoxxySubcommand::Man { rules, ref out_dir } => {
+                match out_dir {
+                    Some(dir) => man::write_pages(dir)?,
+                    None if rules => print!("{}", man::RULES_PAGE),
+                    None => print!("{}", man::boxxy_page()),
+                }
+                return Ok(());
+            }
+            BoxxySubcommand::Top {
+                ref name,
+                once,
+                interval,
+            } => {
+                return top::run(name.as_deref(), once, Duration::from_secs(interval));
+            }
+            BoxxySubcommand::TestRules { ref cases } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                let fs = enclosure::fs::FsDriver::new();
+                let dir = cases
+                    .canonicalize()?
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                let cases = config::cases::load(cases)?.cases;
+                let mut failed = 0;
+                for case in &cases {
+                    match case.run(&rules, &dir, &fs)? {
+                        None => println!("ok: {}", case.name()),
+                        Some(failure) => {
+                            failed += 1;
+                            println!("FAILED: {}", case.name());
+                            if !failure.missing.is_empty() {
+                                println!("  didn't apply: {}", failure.missing.join(", "));
+                            }
+                            if !failure.unexpected.is_empty() {
+                                println!(
+                                    "  applied but shouldn't: {}",
+                                    failure.unexpected.join(", ")
+                                );
+                            }
+                        }
+                    }
+                }
+                println!("{} of {} case(s) passed", cases.len() - failed, cases.len());
+                if failed > 0 {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            BoxxySubcommand::ExplainMountinfo { pid } => {
+                let rules = BoxxyConfig::load_rules(&cfg)?;
+                BoxxyConfig::configure_containers_root(&rules);
+                let path = enclosure::mountinfo::path(pid);
+                let mounts = enclosure::mountinfo::parse(&std::fs::read_to_string(&path)?)?;
+                let fs = enclosure::fs::FsDriver::new();
+                let explained = enclosure::mountinfo::explain(&mounts, &rules.rules, &fs)?;
+                for (mount, explanation) in &explained {
+                    println!("{}: {explanation}", mount.mount_point.display());
+                }
+                println!(
+                    "{} of {} mount(s) in {} are boxxy's",
+                    explained.len(),
+                    mounts.len(),
+                    path.display()
+                );
+                return Ok(());
+            }
+            BoxxySubcommand::Control { ref command } => {
+                let request = match command {
+                    ControlSubcommand::Ready => ControlRequest::Ready,
+                    ControlSubcommand::Report { paths } => ControlRequest::Report {
+                        paths: paths.clone(),
+                    },
+                    ControlSubcommand::Mount { rule } => {
+                        ControlRequest::Mount { rule: rule.clone() }
+                    }
+                };
+                enclosure::control::send(&request)?;
+                return Ok(());
+            }
+            BoxxySubcommand::RunIn {
+                ref name,
+                ref command,
+            } => {
+                std::process::exit(server::run_in(name, command.clone())?);
+            }
         }
     }
 
+    if cfg.command.is_none() && (cfg.shell || cfg.command_with_args == ["-"]) {
+        cfg.command_with_args = config::shell_command(&cfg.command_with_args, std::io::stdin())?;
+    }
+    if cfg.command.is_none() && cfg.command_with_args.is_empty() && !cfg.shell {
+        cfg.command_with_args = vec![config::default_shell()];
+    } else if cfg.shell_init {
+        warn!("--shell-init only applies when no command is given");
+        cfg.shell_init = false;
+    }
+
     // Do the thing!
     enclosure::Enclosure::new(BoxxyConfig::load_config(cfg)?).run()?;
 
@@ -154,63 +421,63 @@ fn setup_logging(cfg: &Args) -> Result<()> {
         std::env::set_var("RUST_LOG", &cfg.log_level);
     }
 
-    if !std::io::stdout().is_terminal() && !cfg.force_colour {
-        // Disable user-friendliness if we're not outputting to a terminal.
-        std::env::set_var("NO_COLOR", "1");
+    if !std::io::stderr().is_terminal() && !cfg.force_colour {
+        // Disable user-friendliness if we're not logging to a terminal.
         std::env::set_var("RUST_LOG", "warn");
         std::env::remove_var("RUST_DEBUG");
     }
 
+    colour::init(cfg.force_colour);
+
     // Set up basics
     color_eyre::config::HookBuilder::new()
         .issue_url(concat!(env!("CARGO_PKG_REPOSITORY"), "/issues/new"))
         .add_issue_metadata("version", env!("CARGO_PKG_VERSION"))
+        .theme(if colour::enabled(colour::Stream::Stderr) {
+            color_eyre::config::Theme::dark()
+        } else {
+            color_eyre::config::Theme::new()
+        })
         .install()?;
 
-    pretty_env_logger::init();
+    let mut logger = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        logger.parse_filters(&filters);
+    }
+    logger
+        .write_style(if colour::enabled(colour::Stream::Stderr) {
+            WriteStyle::Always
+        } else {
+            WriteStyle::Never
+        })
+        .init();
 
     Ok(())
 }
 
+fn read_batch_file(path: &Path) -> Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
 fn scan_homedir(apps: Vec<App>) -> Result<()> {
     if !apps.is_empty() {
         info!(
             "found {} applications that might be boxxable! generating config...",
             apps.len()
         );
-        let mut rules = vec![];
-        for app in apps {
-            for fix in app.fixes {
-                let (old, new) = fix.split_once(':').unwrap();
-                let path = PathBuf::from(old);
-                let mode = if path.is_dir() {
-                    RuleMode::Directory
-                } else {
-                    RuleMode::File
-                };
-                rules.push(Rule {
-                    name: app.name.clone(),
-                    target: old.into(),
-                    rewrite: new.into(),
-                    mode,
-                    context: vec![],
-                    only: vec![],
-                    // TODO: populate for apps where possible
-                    env: HashMap::new(),
-                });
-            }
-        }
+        let rules: Vec<Rule> = apps.iter().flat_map(App::rules).collect();
         let config = BoxxyRules {
             rules: rules.clone(),
+            ..Default::default()
         };
         let config = &serde_yaml::to_string(&config)?;
-        let mut printer = bat::PrettyPrinter::new();
         println!();
-        printer
-            .input_from_bytes(config.as_bytes())
-            .language("yaml")
-            .print()
-            .expect("failed to print config");
+        print_yaml(config.as_bytes())?;
         println!();
         warn!("!!! BE CAREFUL WITH THIS CONFIG !!!");
         warn!("SAFETY IS NOT GUARANTEED!!!");
@@ -226,3 +493,21 @@ fn scan_homedir(apps: Vec<App>) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(feature = "pretty")]
+fn print_yaml(yaml: &[u8]) -> Result<()> {
+    bat::PrettyPrinter::new()
+        .input_from_bytes(yaml)
+        .language("yaml")
+        .colored_output(colour::enabled(colour::Stream::Stdout))
+        .print()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "pretty"))]
+fn print_yaml(yaml: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    std::io::stdout().write_all(yaml)?;
+    Ok(())
+}
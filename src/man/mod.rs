@@ -0,0 +1,263 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use clap::{Arg, Command, CommandFactory};
+use color_eyre::Result;
+
+use crate::cli::Args;
+
+/// boxxy-rules(5), describing the config file. Hand-maintained, since it
+/// documents the YAML format rather than anything clap knows about.
+pub const RULES_PAGE: &str = include_str!("../../data/boxxy-rules.5");
+
+/// Render boxxy(1) from the clap definitions, so that it can't drift from
+/// `--help`.
+pub fn boxxy_page() -> String {
+    let mut cmd = Args::command();
+    cmd.build();
+
+    let mut page = String::new();
+    let version = cmd.get_version().unwrap_or_default();
+    let _ = writeln!(
+        page,
+        ".TH BOXXY 1 \"\" \"boxxy {}\" \"User Commands\"",
+        escape(version)
+    );
+    page.push_str(".SH NAME\n");
+    let about = cmd
+        .get_about()
+        .map(|about| about.to_string())
+        .unwrap_or_default();
+    let _ = writeln!(page, "boxxy \\- {}", escape(&about));
+
+    page.push_str(".SH SYNOPSIS\n");
+    let _ = writeln!(page, ".B boxxy\n[\\fIOPTIONS\\fR] \\fICOMMAND\\fR...");
+    let _ = writeln!(page, ".br\n.B boxxy\n\\fISUBCOMMAND\\fR [\\fIARGS\\fR]");
+
+    if let Some(long_about) = cmd.get_long_about() {
+        page.push_str(".SH DESCRIPTION\n");
+        let _ = writeln!(page, "{}", escape(&long_about.to_string()));
+    }
+
+    page.push_str(".SH OPTIONS\n");
+    write_args(&mut page, &cmd);
+
+    page.push_str(".SH SUBCOMMANDS\n");
+    for subcommand in cmd.get_subcommands() {
+        if subcommand.is_hide_set() || subcommand.get_name() == "help" {
+            continue;
+        }
+        write_subcommand(&mut page, subcommand);
+    }
+
+    page.push_str(".SH ENVIRONMENT\n");
+    for (var, description) in [
+        ("RUST_LOG", "Overrides \\fB\\-\\-log\\-level\\fR."),
+        (
+            "NO_COLOR",
+            "Disables colour output, unless \\fB\\-\\-force\\-colour\\fR is given.",
+        ),
+        (
+            "CLICOLOR_FORCE",
+            "Forces colour output when set to anything but 0.",
+        ),
+        ("CLICOLOR", "Disables colour output when set to 0."),
+    ] {
+        let _ = writeln!(page, ".TP\n.B {var}\n{description}");
+    }
+
+    page.push_str(".SH FILES\n");
+    page.push_str(".TP\n.I ~/.config/boxxy/boxxy.yaml\nThe global config file.\n");
+    page.push_str(
+        ".TP\n.I boxxy.yaml\nA config file in the current directory or any of its parents.\n",
+    );
+    page.push_str(".TP\n.I boxxy.json\nThe same, as JSON, next to or instead of boxxy.yaml.\n");
+    page.push_str(".SH SEE ALSO\n.BR boxxy\\-rules (5)\n");
+
+    page
+}
+
+/// Write boxxy.1 and boxxy-rules.5 into `dir`.
+pub fn write_pages(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("boxxy.1"), boxxy_page())?;
+    std::fs::write(dir.join("boxxy-rules.5"), RULES_PAGE)?;
+
+    Ok(())
+}
+
+fn write_subcommand(page: &mut String, subcommand: &Command) {
+    let _ = writeln!(page, ".SS {}", escape(subcommand.get_name()));
+    let usage: Vec<String> = subcommand
+        .get_arguments()
+        .filter(|arg| arg.is_positional() && !arg.is_hide_set())
+        .map(|arg| {
+            let name = format!("\\fI{}\\fR", escape(&value_name(arg)));
+            if arg.is_required_set() {
+                name
+            } else {
+                format!("[{name}]")
+            }
+        })
+        .collect();
+    let _ = writeln!(
+        page,
+        "\\fBboxxy {}\\fR [\\fIOPTIONS\\fR]",
+        escape(subcommand.get_name())
+    );
+    for name in usage {
+        let _ = writeln!(page, "{name}");
+    }
+    let _ = writeln!(page, ".br");
+    if let Some(about) = subcommand.get_about() {
+        let _ = writeln!(page, "{}", escape(&about.to_string()));
+    }
+    let aliases: Vec<_> = subcommand.get_all_aliases().collect();
+    if !aliases.is_empty() {
+        let _ = writeln!(page, "Aliases: {}.", escape(&aliases.join(", ")));
+    }
+    let _ = writeln!(page, ".RS");
+    write_args(page, subcommand);
+    let _ = writeln!(page, ".RE");
+}
+
+fn write_args(page: &mut String, cmd: &Command) {
+    for arg in cmd.get_arguments() {
+        if arg.is_hide_set() {
+            continue;
+        }
+
+        let mut names = vec![];
+        if let Some(short) = arg.get_short() {
+            names.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+        }
+        if let Some(long) = arg.get_long() {
+            names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+        }
+        let mut header = names.join(", ");
+        if arg.is_positional() {
+            header = format!("\\fI{}\\fR", escape(&value_name(arg)));
+        } else if arg.get_action().takes_values() {
+            let _ = write!(header, " \\fI{}\\fR", escape(&value_name(arg)));
+        }
+
+        let _ = writeln!(page, ".TP\n{header}");
+        let help = arg
+            .get_long_help()
+            .or(arg.get_help())
+            .map(|help| help.to_string())
+            .unwrap_or_default();
+        if !help.is_empty() {
+            let _ = writeln!(page, "{}", escape(&help));
+        }
+
+        let defaults: Vec<_> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy())
+            .collect();
+        if arg.get_action().takes_values() && !defaults.is_empty() {
+            let _ = writeln!(
+                page,
+                "Defaults to \\fB{}\\fR.",
+                escape(&defaults.join(", "))
+            );
+        }
+    }
+}
+
+fn value_name(arg: &Arg) -> String {
+    arg.get_value_names()
+        .and_then(|names| names.first())
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| arg.get_id().to_string().to_uppercase())
+}
+
+/// Escape `text` so that roff prints it as-is.
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    escaped
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{line}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::enclosure::limits::Umask;
+    use crate::enclosure::ownership::Permissions;
+    use crate::enclosure::rule::{FaultMode, MountOption, Rule};
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("--rule"), "\\-\\-rule");
+        assert_eq!(escape("a\\:b"), "a\\e:b");
+        assert_eq!(escape(".hidden\n'quoted"), "\\&.hidden\n\\&'quoted");
+    }
+
+    #[test]
+    fn test_boxxy_page_documents_every_flag() {
+        let page = boxxy_page();
+        for arg in Args::command().get_arguments() {
+            if let Some(long) = arg.get_long() {
+                assert!(
+                    page.contains(&format!("\\fB\\-\\-{}\\fR", escape(long))),
+                    "--{long} is missing from boxxy.1"
+                );
+            }
+        }
+        for subcommand in Args::command().get_subcommands() {
+            assert!(page.contains(&format!(".SS {}\n", escape(subcommand.get_name()))));
+        }
+    }
+
+    #[test]
+    fn test_rules_page_documents_every_field() -> Result<()> {
+        let rule = Rule::builder("test", "~/.test", "~/.config/test")
+            .with_faultmode(FaultMode::Erofs)
+            .with_omit("secret")
+            .with_deny(true)
+            .with_readonly(true)
+            .with_priority(1)
+            .with_copy_xattrs(true)
+            .with_allow_secrets(true)
+            .with_umask(Umask(0o77))
+            .with_before("true")
+            .with_after("true")
+            .with_mount_option(MountOption::Noexec)
+            .with_when_env("CI", None)
+            .with_not_context("~/Projects/legacy")
+            .with_context_git("*github.com*work-org/*")
+            .with_only_args("configure")
+            .with_tag("secrets")
+            .with_enabled(false)
+            .with_create(false)
+            .with_permissions(Permissions(0o700))
+            .with_owner(":wheel".parse().unwrap())
+            .with_env_file("~/.config/boxxy/secrets/test.env")
+            .with_env_cmd("TOKEN", "pass show test/token")
+            .with_prepend_path("~/.config/boxxy/shims")
+            .with_append_path("/opt/tools/bin")
+            .with_size("1g")
+            .build();
+        let fields = serde_yaml::to_value(&rule)?;
+        for field in fields.as_mapping().unwrap().keys() {
+            let field = field.as_str().unwrap();
+            assert!(
+                RULES_PAGE.contains(&format!(".TP\n.B {field}\n")),
+                "`{field}` is missing from boxxy-rules.5"
+            );
+        }
+
+        Ok(())
+    }
+}
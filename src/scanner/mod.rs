@@ -1,13 +1,74 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 
 use color_eyre::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::enclosure::rule::{Rule, RuleMode};
+
+pub mod update;
+pub mod xdg;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct App {
     pub name: String,
     pub paths: Vec<String>,
     pub fixes: Vec<String>,
+    /// Env vars the app honors to find its files somewhere else, with the
+    /// paths to set them to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl App {
+    /// A rule for each of the app's fixes, applying to every program.
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rules_with(str::to_string, |value| {
+            shellexpand::tilde(value).to_string()
+        })
+    }
+
+    /// Like `rules`, with each fix's rewrite passed through `rewrite` and
+    /// each env var's value through `env`. Env vars go on the rule whose
+    /// rewrite they point into, or the first rule if there isn't one.
+    pub fn rules_with(
+        &self,
+        rewrite: impl Fn(&str) -> String,
+        env: impl Fn(&str) -> String,
+    ) -> Vec<Rule> {
+        let fixes: Vec<(&str, &str)> = self
+            .fixes
+            .iter()
+            .filter_map(|fix| fix.split_once(':'))
+            .collect();
+        fixes
+            .iter()
+            .enumerate()
+            .map(|(i, (old, new))| {
+                let target = shellexpand::tilde(old).to_string();
+                let mut rule = Rule::builder(&self.name, *old, rewrite(new))
+                    .with_mode(mode_of(Path::new(&target)));
+                for (key, value) in &self.env {
+                    let owner = fixes
+                        .iter()
+                        .position(|(_, new)| Path::new(value).starts_with(new))
+                        .unwrap_or(0);
+                    if owner == i {
+                        rule = rule.with_env(key, env(value));
+                    }
+                }
+                rule.build()
+            })
+            .collect()
+    }
+}
+
+fn mode_of(path: &Path) -> RuleMode {
+    if path.is_dir() {
+        RuleMode::Directory
+    } else {
+        RuleMode::File
+    }
 }
 
 pub struct Scanner {
@@ -18,10 +79,14 @@ const HARDCODED_APPS_JSON: &str = include_str!("../../data/hardcoded-application
 const PARTIAL_APPS_JSON: &str = include_str!("../../data/partial-support-applications.json");
 
 impl Scanner {
+    /// A scanner for the apps in the database fetched by `boxxy scan
+    /// --update-db`, or in the one embedded in boxxy if there isn't one.
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        let mut hardcoded = serde_json::from_str::<Vec<App>>(HARDCODED_APPS_JSON).unwrap();
-        let mut partial = serde_json::from_str::<Vec<App>>(PARTIAL_APPS_JSON).unwrap();
+        let mut hardcoded = update::load(update::DB_FILES[0])
+            .unwrap_or_else(|| serde_json::from_str::<Vec<App>>(HARDCODED_APPS_JSON).unwrap());
+        let mut partial = update::load(update::DB_FILES[1])
+            .unwrap_or_else(|| serde_json::from_str::<Vec<App>>(PARTIAL_APPS_JSON).unwrap());
         let mut apps = vec![];
         apps.append(&mut hardcoded);
         apps.append(&mut partial);
@@ -43,4 +108,106 @@ impl Scanner {
 
         Ok(out)
     }
+
+    /// Rules for the fixes whose paths a trace saw being accessed, limited
+    /// with `only` to the programs that accessed them. `accesses` maps each
+    /// path to the file names of the programs that accessed it.
+    pub fn rules_for_trace(
+        &self,
+        accesses: &BTreeMap<PathBuf, BTreeSet<String>>,
+    ) -> Result<Vec<Rule>> {
+        let mut rules = vec![];
+        for app in &self.apps {
+            for (old, new) in app.fixes.iter().filter_map(|fix| fix.split_once(':')) {
+                let target = PathBuf::from(shellexpand::full(old)?.to_string());
+                let programs: BTreeSet<&String> = accesses
+                    .range(target.clone()..)
+                    .take_while(|(path, _)| path.starts_with(&target))
+                    .flat_map(|(_, programs)| programs)
+                    .collect();
+                if programs.is_empty() {
+                    continue;
+                }
+
+                let mut rule = Rule::builder(&app.name, old, new).with_mode(mode_of(&target));
+                for program in programs {
+                    rule = rule.with_only(program);
+                }
+                rules.push(rule.build());
+            }
+        }
+
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_for_trace() -> Result<()> {
+        let scanner = Scanner {
+            apps: vec![App {
+                name: "aws".into(),
+                paths: vec!["/boxxy/.aws".into()],
+                fixes: vec!["/boxxy/.aws:~/.config/aws".into()],
+                env: BTreeMap::new(),
+            }],
+        };
+        let accesses = |entries: &[(&str, &[&str])]| -> BTreeMap<PathBuf, BTreeSet<String>> {
+            entries
+                .iter()
+                .map(|(path, programs)| {
+                    let programs = programs.iter().map(|program| program.to_string()).collect();
+                    (PathBuf::from(path), programs)
+                })
+                .collect()
+        };
+
+        let rules = scanner.rules_for_trace(&accesses(&[
+            ("/boxxy/.aws/config", &["aws"]),
+            ("/boxxy/.aws/credentials", &["aws", "terraform"]),
+            ("/boxxy/.awsome", &["cat"]),
+            ("/etc/hosts", &["aws"]),
+        ]))?;
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].target, "/boxxy/.aws");
+        assert_eq!(rules[0].only, ["aws", "terraform"]);
+
+        assert!(scanner
+            .rules_for_trace(&accesses(&[("/etc/hosts", &["aws"])]))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_app_rules() {
+        let app = App {
+            name: "gnupg".into(),
+            paths: vec!["~/.gnupg".into()],
+            fixes: vec![
+                "~/.gnupg:~/.local/share/gnupg".into(),
+                "~/.gnupgrc:~/.config/gnupg/rc".into(),
+            ],
+            env: BTreeMap::from([
+                ("GNUPGHOME".into(), "~/.local/share/gnupg".into()),
+                ("GNUPGRC".into(), "~/.config/gnupg/rc".into()),
+                ("GNUPG_OTHER".into(), "/etc/gnupg".into()),
+            ]),
+        };
+        let rules = app.rules_with(str::to_uppercase, str::to_string);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].rewrite, "~/.LOCAL/SHARE/GNUPG");
+        let mut keys: Vec<&String> = rules[0].env.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["GNUPGHOME", "GNUPG_OTHER"]);
+        assert_eq!(rules[1].env["GNUPGRC"], "~/.config/gnupg/rc");
+
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            app.rules()[0].env["GNUPGHOME"],
+            home.join(".local/share/gnupg").to_string_lossy()
+        );
+    }
 }
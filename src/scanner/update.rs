@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+
+use super::App;
+use crate::config::remote;
+use crate::enclosure::fs::append_all;
+
+pub const DB_FILES: [&str; 2] = [
+    "hardcoded-applications.json",
+    "partial-support-applications.json",
+];
+
+/// The directory that updated copies of the app database are kept in.
+pub fn db_dir() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().ok_or_else(|| eyre!("could not find config directory"))?;
+    Ok(append_all(&config_dir, vec!["boxxy", "apps"]))
+}
+
+/// The apps in the updated copy of `file`, if there is one. A broken copy
+/// is ignored, so that the embedded database is used instead.
+pub fn load(file: &str) -> Option<Vec<App>> {
+    let path = db_dir().ok()?.join(file);
+    let contents = std::fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(apps) => Some(apps),
+        Err(err) => {
+            warn!("ignoring broken app database {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+/// Fetch the latest app database, check that it downloaded intact, and save
+/// it to [`db_dir`]. Returns how many apps it has.
+pub fn update() -> Result<usize> {
+    let contents = remote::get_repo_files(&DB_FILES)?;
+    let mut apps = 0;
+    for (file, contents) in DB_FILES.iter().zip(&contents) {
+        apps += serde_json::from_str::<Vec<App>>(contents)
+            .map_err(|err| eyre!("{file} isn't a valid app database: {err}"))?
+            .len();
+    }
+
+    // Only save once every file checks out, so that the copies are never
+    // from different versions.
+    let dir = db_dir()?;
+    for (file, contents) in DB_FILES.iter().zip(&contents) {
+        remote::write_atomically(&dir.join(file), contents)?;
+    }
+
+    Ok(apps)
+}
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use color_eyre::Result;
+
+use super::App;
+use crate::config::schema;
+use crate::enclosure::rule::BoxxyRules;
+use crate::enclosure::template::{self, TemplateContext};
+
+/// Where each XDG base directory is when its env var isn't set, and the
+/// template variable for it.
+const BASE_DIRS: [(&str, &str); 4] = [
+    ("~/.config", "xdg_config"),
+    ("~/.local/share", "xdg_data"),
+    ("~/.cache", "xdg_cache"),
+    ("~/.local/state", "xdg_state"),
+];
+
+/// A config moving every stray dotfile or dotdir in the home directory that
+/// `apps` knows an XDG location for into that location. Rewrites use the
+/// `xdg_*` templates, so that they follow `XDG_CONFIG_HOME` and friends;
+/// env vars are set to where those point now, since env vars aren't
+/// rendered.
+pub fn generate(apps: &[App]) -> Result<BoxxyRules> {
+    let ctx = TemplateContext::new();
+    let current = BASE_DIRS
+        .iter()
+        .map(|(dir, variable)| {
+            Ok((
+                *dir,
+                template::render(&format!("{{{{ {variable} }}}}"), &ctx)?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut targets = HashSet::new();
+    let mut rules = vec![];
+    for app in apps {
+        let app_rules = app.rules_with(templated, |value| {
+            rebase(value, &current).unwrap_or_else(|| shellexpand::tilde(value).to_string())
+        });
+        for rule in app_rules {
+            let target = shellexpand::tilde(rule.target.trim_end_matches('/')).to_string();
+            if Path::new(&target).exists() && targets.insert(target) {
+                rules.push(rule);
+            }
+        }
+    }
+
+    Ok(BoxxyRules {
+        version: Some(schema::CURRENT_VERSION),
+        rules,
+        ..Default::default()
+    })
+}
+
+/// `path` with its XDG base directory, if it's in one, replaced with the
+/// template for it.
+fn templated(path: &str) -> String {
+    let templates = BASE_DIRS.map(|(dir, variable)| (dir, format!("{{{{ {variable} }}}}")));
+    rebase(path, &templates).unwrap_or_else(|| path.to_string())
+}
+
+/// `path` with the first of `bases`' directories that it's in replaced
+/// with the replacement given for it.
+fn rebase(path: &str, bases: &[(&str, String)]) -> Option<String> {
+    bases.iter().find_map(|(dir, replacement)| {
+        let rest = path.strip_prefix(dir)?;
+        (rest.is_empty() || rest.starts_with('/')).then(|| format!("{replacement}{rest}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        assert_eq!(templated("~/.config/aws"), "{{ xdg_config }}/aws");
+        assert_eq!(templated("~/.local/share"), "{{ xdg_data }}");
+        assert_eq!(templated("~/.configs/aws"), "~/.configs/aws");
+
+        let home = Scratch::new("xdg")?;
+        fs::create_dir_all(home.join(".aws"))?;
+        let app = |name: &str, fix: &str| App {
+            name: name.into(),
+            paths: vec![],
+            fixes: vec![fix.replace("HOME", &home.to_string_lossy())],
+            env: BTreeMap::from([("AWS_CONFIG_FILE".into(), "~/.config/aws/config".into())]),
+        };
+        let config = generate(&[
+            app("aws", "HOME/.aws:~/.config/aws"),
+            app("aws-again", "HOME/.aws/:~/.local/share/aws"),
+            app("missing", "HOME/.missing:~/.config/missing"),
+        ])?;
+        assert_eq!(config.version, Some(schema::CURRENT_VERSION));
+        assert_eq!(config.rules.len(), 1);
+        assert_eq!(config.rules[0].name, "aws");
+        assert_eq!(config.rules[0].rewrite, "{{ xdg_config }}/aws");
+        assert_eq!(
+            config.rules[0].env["AWS_CONFIG_FILE"],
+            dirs::config_dir()
+                .unwrap()
+                .join("aws/config")
+                .to_string_lossy()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, IoSlice, IoSliceMut, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use serde::{Deserialize, Serialize};
+
+use crate::config::BoxxyConfig;
+use crate::enclosure::fs::append_all;
+
+/// Sent by `boxxy run-in` to ask a serving box to run a command. The client's
+/// stdin, stdout, and stderr are passed alongside it over the socket.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRequest {
+    pub argv: Vec<String>,
+    pub cwd: PathBuf,
+    pub env: Vec<(OsString, OsString)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunResponse {
+    pub exit_code: i32,
+    pub error: Option<String>,
+}
+
+/// The socket that the box called `name` serves on.
+pub fn socket_path(name: &str) -> Result<PathBuf> {
+    Ok(append_all(
+        &BoxxyConfig::runtime_dir()?,
+        vec![format!("{name}.sock")],
+    ))
+}
+
+/// Bind the socket for the box called `name`, replacing a stale socket left
+/// behind by a previous server.
+pub fn bind(name: &str) -> Result<UnixListener> {
+    let path = socket_path(name)?;
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(eyre!("a box called {name} is already being served"));
+        }
+        std::fs::remove_file(&path)?;
+    }
+    Ok(UnixListener::bind(&path)?)
+}
+
+/// Run commands sent by `boxxy run-in` until the listener fails, each client
+/// on its own thread, so that a long-running command doesn't hold up the
+/// others. `base_env` is applied on top of the client's environment, so that
+/// the env vars from rules and dotenv files apply to every command.
+pub fn serve(listener: UnixListener, base_env: &[(OsString, Option<OsString>)]) -> Result<()> {
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            scope.spawn(move || {
+                if let Err(err) = handle_client(stream, base_env) {
+                    warn!("failed to handle run-in client: {err}");
+                }
+            });
+        }
+
+        Ok(())
+    })
+}
+
+fn handle_client(stream: UnixStream, base_env: &[(OsString, Option<OsString>)]) -> Result<()> {
+    let fds = receive_fds(stream.as_raw_fd())?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let request: RunRequest = serde_json::from_str(&line)?;
+    debug!("run-in request: {:?}", request.argv);
+
+    let response = match run_request(&request, fds, base_env) {
+        Ok(exit_code) => RunResponse {
+            exit_code,
+            error: None,
+        },
+        Err(err) => RunResponse {
+            exit_code: 127,
+            error: Some(err.to_string()),
+        },
+    };
+
+    let mut stream = stream;
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+fn run_request(
+    request: &RunRequest,
+    fds: Vec<OwnedFd>,
+    base_env: &[(OsString, Option<OsString>)],
+) -> Result<i32> {
+    let (program, args) = request
+        .argv
+        .split_first()
+        .ok_or_else(|| eyre!("no command given"))?;
+    let [stdin, stdout, stderr]: [OwnedFd; 3] = fds
+        .try_into()
+        .map_err(|_| eyre!("expected stdin, stdout, and stderr from the client"))?;
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(&request.cwd)
+        .env_clear()
+        .envs(request.env.iter().cloned())
+        .stdin(Stdio::from(stdin))
+        .stdout(Stdio::from(stdout))
+        .stderr(Stdio::from(stderr));
+    for (key, value) in base_env {
+        match value {
+            Some(value) => command.env(key, value),
+            None => command.env_remove(key),
+        };
+    }
+
+    let status = command.spawn()?.wait()?;
+    Ok(status.code().unwrap_or_else(|| {
+        use std::os::unix::process::ExitStatusExt;
+        128 + status.signal().unwrap_or(0)
+    }))
+}
+
+fn receive_fds(socket: RawFd) -> Result<Vec<OwnedFd>> {
+    let mut byte = [0u8; 1];
+    let mut iov = [IoSliceMut::new(&mut byte)];
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; 3]);
+    let message = recvmsg::<()>(socket, &mut iov, Some(&mut cmsg_buffer), MsgFlags::empty())?;
+
+    let mut fds = vec![];
+    for cmsg in message.cmsgs() {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            // SAFETY: the kernel just handed us these fds, nothing else owns
+            // them.
+            fds.extend(
+                received
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+
+    Ok(fds)
+}
+
+/// Ask the box called `name` to run `argv` with our stdio, returning its
+/// exit code.
+pub fn run_in(name: &str, argv: Vec<String>) -> Result<i32> {
+    let path = socket_path(name)?;
+    let mut stream = UnixStream::connect(&path).map_err(|err| {
+        eyre!(
+            "could not connect to box {name} at {}: {err}",
+            path.display()
+        )
+    })?;
+
+    let fds = [0, 1, 2];
+    sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(&[0])],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )?;
+
+    let request = RunRequest {
+        argv,
+        cwd: std::env::current_dir()?,
+        env: std::env::vars_os().collect(),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: RunResponse = serde_json::from_str(&line)?;
+    if let Some(error) = response.error {
+        error!("{error}");
+    }
+
+    Ok(response.exit_code)
+}
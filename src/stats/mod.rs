@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::enclosure::fs::append_all;
+use crate::enclosure::rule::Rule;
+
+/// How often something was used, and when it was last used, in seconds since
+/// the epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub count: u64,
+    pub last_used: u64,
+}
+
+impl Usage {
+    fn record(&mut self, now: u64) {
+        self.count += 1;
+        self.last_used = now;
+    }
+}
+
+/// Local usage statistics. They're only recorded once `boxxy stats --enable`
+/// has created the stats file, and never leave this machine.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(default)]
+    pub binaries: BTreeMap<String, Usage>,
+    #[serde(default)]
+    pub rules: BTreeMap<String, Usage>,
+}
+
+impl Stats {
+    /// Count a run of `binary` that applied `rules`.
+    pub fn record(&mut self, binary: &str, rules: &[Rule], now: u64) {
+        self.binaries
+            .entry(binary.to_string())
+            .or_default()
+            .record(now);
+        for rule in rules {
+            self.rules.entry(rule.name.clone()).or_default().record(now);
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// `~/.local/share/boxxy/stats.json` by default.
+pub fn stats_path() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir().ok_or_else(|| eyre!("could not find data directory"))?;
+    Ok(append_all(&data_dir, vec!["boxxy", "stats.json"]))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// Record a run if stats are enabled. Failing to record is never an error for
+/// the run itself.
+pub fn record(binary: &str, rules: &[Rule]) {
+    if let Err(err) = try_record(binary, rules) {
+        debug!("could not record usage stats: {err}");
+    }
+}
+
+fn try_record(binary: &str, rules: &[Rule]) -> Result<()> {
+    let path = stats_path()?;
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut stats = Stats::load(&path)?;
+    stats.record(binary, rules, now());
+    fs::write(&path, serde_json::to_vec_pretty(&stats)?)?;
+    Ok(())
+}
+
+/// Turn recording on by creating the stats file, keeping any stats already in
+/// it.
+pub fn enable() -> Result<()> {
+    let path = stats_path()?;
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_vec_pretty(&Stats::default())?)?;
+    }
+    info!("recording usage stats in {}", path.display());
+    Ok(())
+}
+
+/// Turn recording off and forget everything recorded so far.
+pub fn disable() -> Result<()> {
+    let path = stats_path()?;
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    info!("not recording usage stats, removed {}", path.display());
+    Ok(())
+}
+
+/// Print the binaries and rules used most, most used first.
+pub fn print() -> Result<()> {
+    let path = stats_path()?;
+    if !path.exists() {
+        println!("usage stats are off, turn them on with `boxxy stats --enable`");
+        return Ok(());
+    }
+
+    let stats = Stats::load(&path)?;
+    let now = now();
+    for (title, usage) in [("binaries", &stats.binaries), ("rules", &stats.rules)] {
+        println!("{title}:");
+        if usage.is_empty() {
+            println!("  none yet");
+        }
+        for (name, usage) in by_count(usage) {
+            println!(
+                "  {:>6}  {name}  (last used {})",
+                usage.count,
+                ago(now, usage.last_used)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn by_count(usage: &BTreeMap<String, Usage>) -> Vec<(&String, &Usage)> {
+    let mut usage: Vec<_> = usage.iter().collect();
+    usage.sort_by_key(|(_, usage)| std::cmp::Reverse(usage.count));
+    usage
+}
+
+fn ago(now: u64, then: u64) -> String {
+    match now.saturating_sub(then) / 86400 {
+        0 => "today".to_string(),
+        1 => "yesterday".to_string(),
+        days => format!("{days} days ago"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let rules = [
+            Rule::builder("aws", "~/.aws", "~/.config/aws").build(),
+            Rule::builder("npm", "~/.npmrc", "~/.config/npm/npmrc").build(),
+        ];
+        let mut stats = Stats::default();
+        stats.record("aws", &rules[..1], 10);
+        stats.record("aws", &rules, 20);
+        stats.record("npm", &rules[1..], 30);
+
+        assert_eq!(
+            stats.binaries["aws"],
+            Usage {
+                count: 2,
+                last_used: 20
+            }
+        );
+        assert_eq!(stats.rules["npm"].count, 2);
+        assert_eq!(stats.rules["npm"].last_used, 30);
+        assert_eq!(
+            by_count(&stats.binaries)
+                .into_iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["aws", "npm"]
+        );
+
+        let json = serde_json::to_vec(&stats).unwrap();
+        assert_eq!(serde_json::from_slice::<Stats>(&json).unwrap(), stats);
+    }
+
+    #[test]
+    fn test_ago() {
+        assert_eq!(ago(100, 50), "today");
+        assert_eq!(ago(86400 * 3, 0), "3 days ago");
+        assert_eq!(ago(0, 100), "today");
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use log::*;
+
+use crate::enclosure::fs::{disk_usage, human_size, FsDriver};
+use crate::enclosure::rule::{BoxxyRules, Rule};
+
+/// A rewrite location and the rules that use it.
+struct Location {
+    path: PathBuf,
+    rules: Vec<String>,
+    size: u64,
+    largest_children: Vec<(String, u64)>,
+}
+
+/// Print how much disk space each rule's rewrite location uses, along with
+/// its largest children.
+pub fn du(rules: &BoxxyRules, fs: &FsDriver, children: usize) -> Result<()> {
+    let mut rules_by_path: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for rule in rules.rules.iter().filter(|rule| rule.has_rewrite()) {
+        for rewrite in rendered_rewrites(rule)? {
+            let path = fs.fully_expand_path(&rewrite)?;
+            rules_by_path
+                .entry(path)
+                .or_default()
+                .push(rule.name.clone());
+        }
+    }
+
+    let mut locations = vec![];
+    for (path, rules) in rules_by_path {
+        if !path.exists() {
+            debug!(
+                "skipping rewrite that doesn't exist yet: {}",
+                path.display()
+            );
+            continue;
+        }
+        locations.push(Location {
+            size: disk_usage(&path)?,
+            largest_children: largest_children(&path, children)?,
+            path,
+            rules,
+        });
+    }
+    locations.sort_by_key(|location| std::cmp::Reverse(location.size));
+
+    let mut total = 0;
+    for location in &locations {
+        total += location.size;
+        println!(
+            "{:>10}  {}  ({})",
+            human_size(location.size),
+            location.path.display(),
+            location.rules.join(", ")
+        );
+        for (name, size) in &location.largest_children {
+            println!("{:>10}    └ {name}", human_size(*size));
+        }
+    }
+    println!("{:>10}  total", human_size(total));
+
+    Ok(())
+}
+
+/// The rewrites of a rule with templates rendered. Rewrites that depend on
+/// the boxxed binary are rendered once for every binary in `only`.
+fn rendered_rewrites(rule: &Rule) -> Result<Vec<String>> {
+    match rule.render_templates(None) {
+        Ok(rule) => Ok(vec![rule.rewrite]),
+        Err(_) if !rule.only.is_empty() => rule
+            .only
+            .iter()
+            .map(|binary| {
+                rule.render_templates(Some(OsStr::new(binary)))
+                    .map(|rule| rule.rewrite)
+            })
+            .collect(),
+        Err(err) => {
+            warn!("{}: can't work out rewrite location: {err}", rule.name);
+            Ok(vec![])
+        }
+    }
+}
+
+fn largest_children(path: &std::path::Path, count: usize) -> Result<Vec<(String, u64)>> {
+    if count == 0 || !path.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut children = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        children.push((
+            entry.file_name().to_string_lossy().to_string(),
+            disk_usage(&entry.path())?,
+        ));
+    }
+    children.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    children.truncate(count);
+
+    Ok(children)
+}
@@ -0,0 +1,136 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+
+use crate::enclosure::fs::{placeholder_contents, FsDriver};
+use crate::enclosure::rule::{BoxxyRules, Rule, RuleMode};
+
+/// What's in the target of `rule` on the host, if the target has data in it
+/// but the rewrite doesn't. Boxing the program with the rule would hide that
+/// data behind the empty rewrite, so the program looks like it lost it.
+pub fn shadowed_data(rule: &Rule, fs: &FsDriver) -> Result<Option<String>> {
+    if !rule.has_rewrite() {
+        return Ok(None);
+    }
+
+    let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+    let rewrite = fs.fully_expand_path(&rule.rewrite)?;
+    if !target.exists() || (rewrite.exists() && placeholder_contents(&rewrite)?.is_some()) {
+        return Ok(None);
+    }
+
+    placeholder_contents(&target)
+}
+
+/// Copy what's in the target of the rule named `name` into its rewrite, so
+/// that the boxxed program keeps its data. `binary` is used to render
+/// templates in the rule, and defaults to the first program in `only`.
+pub fn migrate(rules: &BoxxyRules, fs: &FsDriver, name: &str, binary: Option<&str>) -> Result<()> {
+    let rule = rules
+        .rules
+        .iter()
+        .find(|rule| rule.name == name)
+        .ok_or_else(|| eyre!("no rule named {name}"))?;
+    let binary = binary.or(rule.only.first().map(String::as_str));
+    let rule = rule.render_templates(binary.map(OsStr::new))?;
+    if !rule.has_rewrite() {
+        return Err(eyre!("{name}: rule has no rewrite to migrate to"));
+    }
+
+    let target = fs.maybe_resolve_symlink(&fs.fully_expand_path(&rule.target)?)?;
+    let rewrite = fs.fully_expand_path(&rule.rewrite)?;
+    if rewrite.exists() {
+        if let Some(contents) = placeholder_contents(&rewrite)? {
+            return Err(eyre!(
+                "{name}: rewrite {} isn't empty ({contents}), not overwriting it",
+                rewrite.display()
+            ));
+        }
+    }
+    let Some(contents) = (if target.exists() {
+        placeholder_contents(&target)?
+    } else {
+        None
+    }) else {
+        info!("{name}: nothing to migrate, {} is empty", target.display());
+        return Ok(());
+    };
+
+    info!(
+        "{name}: copying {} ({contents}) to {}",
+        target.display(),
+        rewrite.display()
+    );
+    let directory = match rule.mode {
+        RuleMode::File => false,
+        RuleMode::Directory | RuleMode::Tmpfs => true,
+        RuleMode::Copy => target.is_dir(),
+        RuleMode::ExposeRo | RuleMode::Mask => {
+            unreachable!("{:?} rules have no rewrite", rule.mode)
+        }
+    };
+    if directory {
+        fs::create_dir_all(&rewrite)?;
+        copy(&target.join("."), &rewrite)
+    } else {
+        if let Some(parent) = rewrite.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        copy(&target, &rewrite)
+    }
+}
+
+/// `cp -a`, so that permissions, times, and symlinks are kept.
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    let status = Command::new("cp").arg("-a").arg(from).arg(to).status()?;
+    if !status.success() {
+        return Err(eyre!(
+            "could not copy {} to {}: cp exited with {status}",
+            from.display(),
+            to.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn test_migrate() -> Result<()> {
+        let dir = Scratch::new("migrate-test")?;
+        fs::create_dir_all(dir.join("target/nested"))?;
+        fs::write(dir.join("target/nested/config"), "hi")?;
+
+        let fs_driver = FsDriver::new();
+        let rule = Rule::builder(
+            "test",
+            dir.join("target").to_str().unwrap(),
+            dir.join("rewrite").to_str().unwrap(),
+        )
+        .build();
+        assert_eq!(
+            shadowed_data(&rule, &fs_driver)?.as_deref(),
+            Some("1 entry")
+        );
+
+        let rules = BoxxyRules {
+            rules: vec![rule.clone()],
+            ..Default::default()
+        };
+        migrate(&rules, &fs_driver, "test", None)?;
+        assert_eq!(fs::read_to_string(dir.join("rewrite/nested/config"))?, "hi");
+        assert_eq!(shadowed_data(&rule, &fs_driver)?, None);
+        assert!(migrate(&rules, &fs_driver, "test", None).is_err());
+        assert!(migrate(&rules, &fs_driver, "missing", None).is_err());
+
+        Ok(())
+    }
+}
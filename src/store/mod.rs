@@ -0,0 +1,300 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::enclosure::fs::{append_all, disk_usage, human_size};
+use crate::enclosure::rule::{Rule, RuleMode};
+use crate::enclosure::template::{self, TemplateContext};
+
+mod du;
+mod migrate;
+
+pub use du::du;
+pub use migrate::{migrate, shadowed_data};
+
+/// Name of the file in a project store entry that records which project
+/// directory the entry belongs to.
+const PROJECT_MARKER_FILE: &str = "project";
+/// Touched every time the entry is used, so that stale entries can be found.
+const LAST_USED_FILE: &str = "last-used";
+
+/// Kinds of projects that have global caches boxxy knows how to isolate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum ProjectKind {
+    Cargo,
+    Npm,
+    Pip,
+    Gradle,
+}
+
+impl ProjectKind {
+    /// Files that, when present in a directory, mark it as this kind of
+    /// project.
+    pub fn markers(&self) -> &'static [&'static str] {
+        match self {
+            ProjectKind::Cargo => &["Cargo.toml"],
+            ProjectKind::Npm => &["package.json"],
+            ProjectKind::Pip => &["requirements.txt", "pyproject.toml", "setup.py", "Pipfile"],
+            ProjectKind::Gradle => &[
+                "build.gradle",
+                "build.gradle.kts",
+                "settings.gradle",
+                "settings.gradle.kts",
+            ],
+        }
+    }
+
+    /// The global caches used by this kind of project, as (name, path) pairs.
+    pub fn caches(&self) -> Result<Vec<(&'static str, PathBuf)>> {
+        let home = dirs::home_dir().ok_or_else(|| eyre!("could not find home directory"))?;
+        let caches = match self {
+            ProjectKind::Cargo => {
+                let cargo_home = env_dir("CARGO_HOME").unwrap_or_else(|| home.join(".cargo"));
+                vec![
+                    ("cargo-registry", cargo_home.join("registry")),
+                    ("cargo-git", cargo_home.join("git")),
+                ]
+            }
+            ProjectKind::Npm => vec![("npm", home.join(".npm"))],
+            ProjectKind::Pip => {
+                let cache_dir = dirs::cache_dir().unwrap_or_else(|| home.join(".cache"));
+                vec![("pip", cache_dir.join("pip"))]
+            }
+            ProjectKind::Gradle => vec![(
+                "gradle",
+                env_dir("GRADLE_USER_HOME").unwrap_or_else(|| home.join(".gradle")),
+            )],
+        };
+
+        Ok(caches)
+    }
+}
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// A project directory detected from its marker files.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub kinds: Vec<ProjectKind>,
+}
+
+impl Project {
+    pub fn detect(dir: &Path) -> Option<Project> {
+        let kinds: Vec<ProjectKind> = ProjectKind::iter()
+            .filter(|kind| {
+                kind.markers()
+                    .iter()
+                    .any(|marker| dir.join(marker).exists())
+            })
+            .collect();
+
+        if kinds.is_empty() {
+            None
+        } else {
+            Some(Project {
+                root: dir.to_path_buf(),
+                kinds,
+            })
+        }
+    }
+
+    /// The directory in the store holding this project's isolated caches.
+    pub fn store_dir(&self) -> Result<PathBuf> {
+        self.store_dir_in(&projects_store_root()?)
+    }
+
+    /// The directory holding this project's isolated caches in the store at
+    /// `store_root`.
+    pub fn store_dir_in(&self, store_root: &Path) -> Result<PathBuf> {
+        let root = self.root.to_string_lossy();
+        let dir_name = self
+            .root
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "root".to_string());
+        let hash = template::render(
+            "{{ hash(target) }}",
+            &TemplateContext::new().with_target(root),
+        )?;
+
+        Ok(append_all(store_root, vec![format!("{dir_name}-{hash}")]))
+    }
+
+    /// Rules shadowing each global cache of this project with a directory in
+    /// the project's store entry, `store_dir`.
+    pub fn cache_rules(&self, store_dir: &Path) -> Result<Vec<Rule>> {
+        let mut rules = vec![];
+        for kind in &self.kinds {
+            for (name, cache) in kind.caches()? {
+                rules.push(
+                    Rule::builder(
+                        format!("isolate {kind} cache {name} for {}", self.root.display()),
+                        cache.to_string_lossy(),
+                        append_all(store_dir, vec![name]).to_string_lossy(),
+                    )
+                    .with_mode(RuleMode::Directory)
+                    .build(),
+                );
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Create this project's store entry, `store_dir`, if needed, and record
+    /// that it was just used.
+    pub fn mark_used(&self, store_dir: &Path) -> Result<()> {
+        fs::create_dir_all(store_dir)?;
+        fs::write(
+            append_all(store_dir, vec![PROJECT_MARKER_FILE]),
+            self.root.to_string_lossy().as_bytes(),
+        )?;
+        fs::write(append_all(store_dir, vec![LAST_USED_FILE]), b"")?;
+        debug!(
+            "marked project store {} as used by {}",
+            store_dir.display(),
+            self.root.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// The root of the per-project store, `~/.local/share/boxxy/projects` by
+/// default.
+pub fn projects_store_root() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir().ok_or_else(|| eyre!("could not find data directory"))?;
+    Ok(append_all(&data_dir, vec!["boxxy", "projects"]))
+}
+
+/// An entry in the per-project store.
+#[derive(Debug, Clone)]
+pub struct StoreEntry {
+    pub path: PathBuf,
+    /// The project directory that this entry belongs to, if it was recorded.
+    pub project: Option<PathBuf>,
+    pub last_used: Option<SystemTime>,
+    pub size: u64,
+}
+
+impl StoreEntry {
+    fn load(path: PathBuf) -> Result<Self> {
+        let project = fs::read_to_string(append_all(&path, vec![PROJECT_MARKER_FILE]))
+            .ok()
+            .map(|project| PathBuf::from(project.trim_end()));
+        let last_used = fs::metadata(append_all(&path, vec![LAST_USED_FILE]))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let size = disk_usage(&path)?;
+
+        Ok(Self {
+            path,
+            project,
+            last_used,
+            size,
+        })
+    }
+
+    /// Whether the project this entry belongs to no longer exists.
+    pub fn is_orphaned(&self) -> bool {
+        match &self.project {
+            Some(project) => !project.exists(),
+            None => true,
+        }
+    }
+
+    /// Whether this entry hasn't been used in at least `max_age`.
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        match self.last_used.map(|last_used| last_used.elapsed()) {
+            Some(Ok(age)) => age >= max_age,
+            // Clock skew; treat the entry as fresh.
+            Some(Err(_)) => false,
+            None => true,
+        }
+    }
+
+    fn days_since_use(&self) -> Option<u64> {
+        self.last_used
+            .and_then(|last_used| last_used.elapsed().ok())
+            .map(|age| age.as_secs() / 86400)
+    }
+}
+
+/// All entries currently in the per-project store.
+pub fn entries() -> Result<Vec<StoreEntry>> {
+    let root = projects_store_root()?;
+    if !root.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut entries = vec![];
+    for dir_entry in fs::read_dir(&root)? {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_type()?.is_dir() {
+            entries.push(StoreEntry::load(dir_entry.path())?);
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(entries)
+}
+
+/// Find orphaned or stale store entries, and offer to delete them.
+pub fn gc(max_age_days: u64, assume_yes: bool) -> Result<()> {
+    let max_age = Duration::from_secs(max_age_days * 86400);
+    let entries = entries()?;
+    let candidates: Vec<&StoreEntry> = entries
+        .iter()
+        .filter(|entry| entry.is_orphaned() || entry.is_stale(max_age))
+        .collect();
+
+    info!(
+        "{} store entries in {}, {} can be collected",
+        entries.len(),
+        projects_store_root()?.display(),
+        candidates.len()
+    );
+
+    let mut freed = 0;
+    for entry in candidates {
+        let reason = if entry.is_orphaned() {
+            "orphaned".to_string()
+        } else {
+            match entry.days_since_use() {
+                Some(days) => format!("unused for {days} days"),
+                None => "never used".to_string(),
+            }
+        };
+        let project = entry
+            .project
+            .as_ref()
+            .map(|project| project.display().to_string())
+            .unwrap_or_else(|| "unknown project".to_string());
+        println!(
+            "{:>10}  {}  ({project}, {reason})",
+            human_size(entry.size),
+            entry.path.display(),
+        );
+
+        if assume_yes || crate::cli::confirm(&format!("delete {}?", entry.path.display()))? {
+            fs::remove_dir_all(&entry.path)?;
+            freed += entry.size;
+            debug!("deleted store entry {}", entry.path.display());
+        }
+    }
+
+    info!("freed {}", human_size(freed));
+
+    Ok(())
+}
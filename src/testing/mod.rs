@@ -0,0 +1,38 @@
+//! Helpers for boxxy's own tests.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A scratch directory for a test, removed when it's dropped, so that it's
+/// cleaned up even when an assertion fails.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    /// An empty `boxxy-<name>-<pid>` directory in the temp directory.
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("boxxy-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
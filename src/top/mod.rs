@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::BoxxyConfig;
+
+/// How many open/close events to keep on screen.
+const MAX_EVENTS: usize = 10;
+
+/// What `boxxy top` needs to know about a running box. Written by the box
+/// when it starts, and removed when it exits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoxState {
+    pub name: String,
+    /// The process that set up the container.
+    pub pid: i32,
+    /// The box's mount namespace, ex. `mnt:[4026532206]`. Every process in
+    /// the box shares it, including ones that were daemonised.
+    pub namespace: String,
+    pub command: Vec<String>,
+    pub cwd: PathBuf,
+    /// Seconds since the epoch.
+    pub started: u64,
+    /// The targets of every rule that applies to the box.
+    pub targets: Vec<PathBuf>,
+}
+
+impl BoxState {
+    pub fn new(name: &str, pid: i32, command: Vec<String>, targets: Vec<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            pid,
+            namespace: fs::read_link(format!("/proc/{pid}/ns/mnt"))?
+                .to_string_lossy()
+                .to_string(),
+            command,
+            cwd: std::env::current_dir()?,
+            started: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            targets,
+        })
+    }
+
+    fn path(name: &str) -> Result<PathBuf> {
+        Ok(state_dir()?.join(format!("{name}.json")))
+    }
+
+    pub fn register(&self) -> Result<()> {
+        fs::write(Self::path(&self.name)?, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub fn unregister(name: &str) {
+        if let Ok(path) = Self::path(name) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn state_dir() -> Result<PathBuf> {
+    let dir = BoxxyConfig::runtime_dir()?.join("boxes");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Every box that's still running. State left behind by boxes that are gone
+/// is cleaned up.
+pub fn running() -> Result<Vec<BoxState>> {
+    let mut boxes = vec![];
+    for entry in fs::read_dir(state_dir()?)? {
+        let path = entry?.path();
+        let state: BoxState = match fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        {
+            Some(state) => state,
+            None => continue,
+        };
+        if processes_in(&state.namespace).is_empty() {
+            debug!("box {} is gone, removing {}", state.name, path.display());
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        boxes.push(state);
+    }
+    boxes.sort_by_key(|state| state.started);
+
+    Ok(boxes)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Process {
+    pid: i32,
+    ppid: i32,
+    command: String,
+}
+
+/// Every process in the mount namespace `namespace`.
+fn processes_in(namespace: &str) -> Vec<Process> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+
+    let mut processes = vec![];
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+            continue;
+        };
+        let in_namespace = fs::read_link(format!("/proc/{pid}/ns/mnt"))
+            .is_ok_and(|ns| ns.as_os_str() == namespace);
+        if !in_namespace {
+            continue;
+        }
+        if let Some(process) = read_process(pid) {
+            processes.push(process);
+        }
+    }
+    processes.sort_by_key(|process| process.pid);
+
+    processes
+}
+
+fn read_process(pid: i32) -> Option<Process> {
+    // The command name is in parens and may contain spaces, so the fields
+    // after it are found from the last `)`.
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (comm, rest) = stat.split_once(" (")?.1.rsplit_once(") ")?;
+    let ppid = rest.split_whitespace().nth(1)?.parse().ok()?;
+
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+    let command = cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let command = if command.is_empty() {
+        format!("[{comm}]")
+    } else {
+        command
+    };
+
+    Some(Process { pid, ppid, command })
+}
+
+/// The files that `processes` have open under any of `targets`.
+fn open_files(processes: &[Process], targets: &[PathBuf]) -> HashSet<(i32, PathBuf)> {
+    let mut files = HashSet::new();
+    for process in processes {
+        let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", process.pid)) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if let Ok(path) = fs::read_link(fd.path()) {
+                if targets.iter().any(|target| path.starts_with(target)) {
+                    files.insert((process.pid, path));
+                }
+            }
+        }
+    }
+
+    files
+}
+
+/// Show what the box called `name`, or the only running box, is doing,
+/// refreshing every `interval` until interrupted. With `once`, print it a
+/// single time instead.
+pub fn run(name: Option<&str>, once: bool, interval: Duration) -> Result<()> {
+    let boxes = running()?;
+    let state = match name {
+        Some(name) => boxes
+            .into_iter()
+            .find(|state| state.name == name)
+            .ok_or_else(|| eyre!("no running box called {name}"))?,
+        None => match boxes.len() {
+            0 => return Err(eyre!("no boxes are running")),
+            1 => boxes.into_iter().next().unwrap(),
+            _ => {
+                for state in &boxes {
+                    println!("{}\t{}", state.name, state.command.join(" "));
+                }
+                return Err(eyre!(
+                    "several boxes are running, pick one with `boxxy top <name>`"
+                ));
+            }
+        },
+    };
+
+    let clear = !once && std::io::stdout().is_terminal();
+    let watching_since = Instant::now();
+    let mut previous_files = None;
+    let mut events = VecDeque::new();
+    loop {
+        let processes = processes_in(&state.namespace);
+        if processes.is_empty() {
+            println!("box {} has exited", state.name);
+            return Ok(());
+        }
+
+        let files = open_files(&processes, &state.targets);
+        if let Some(previous_files) = &previous_files {
+            let elapsed = watching_since.elapsed().as_secs();
+            for (verb, changed) in [
+                ("opened", files.difference(previous_files)),
+                ("closed", previous_files.difference(&files)),
+            ] {
+                for (pid, path) in changed {
+                    events.push_back(format!("+{elapsed}s {verb} {} ({pid})", path.display()));
+                }
+            }
+            while events.len() > MAX_EVENTS {
+                events.pop_front();
+            }
+        }
+
+        if clear {
+            print!("\x1b[2J\x1b[H");
+        }
+        print!("{}", render(&state, &processes, &files, &events));
+        std::io::stdout().flush()?;
+        if once {
+            return Ok(());
+        }
+
+        previous_files = Some(files);
+        std::thread::sleep(interval);
+    }
+}
+
+fn render(
+    state: &BoxState,
+    processes: &[Process],
+    files: &HashSet<(i32, PathBuf)>,
+    events: &VecDeque<String>,
+) -> String {
+    let mut out = format!(
+        "box {}: {} (in {})\n\nprocesses:\n",
+        state.name,
+        state.command.join(" "),
+        state.cwd.display()
+    );
+
+    let pids: HashSet<i32> = processes.iter().map(|process| process.pid).collect();
+    let mut children: HashMap<i32, Vec<&Process>> = HashMap::new();
+    let mut roots = vec![];
+    for process in processes {
+        if pids.contains(&process.ppid) {
+            children.entry(process.ppid).or_default().push(process);
+        } else {
+            roots.push(process);
+        }
+    }
+    let mut stack: Vec<(&Process, usize)> = roots.into_iter().rev().map(|root| (root, 0)).collect();
+    while let Some((process, depth)) = stack.pop() {
+        out.push_str(&format!(
+            "  {:>7}  {}{}\n",
+            process.pid,
+            "  ".repeat(depth),
+            process.command
+        ));
+        for child in children.get(&process.pid).into_iter().flatten().rev() {
+            stack.push((child, depth + 1));
+        }
+    }
+
+    out.push_str("\nopen files under shadowed targets:\n");
+    let mut files: Vec<_> = files.iter().collect();
+    files.sort();
+    if files.is_empty() {
+        out.push_str("  none\n");
+    }
+    for (pid, path) in files {
+        out.push_str(&format!("  {pid:>7}  {}\n", path.display()));
+    }
+
+    out.push_str("\nrecent activity:\n");
+    if events.is_empty() {
+        out.push_str("  none\n");
+    }
+    for event in events {
+        out.push_str(&format!("  {event}\n"));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_processes_in_own_namespace() -> Result<()> {
+        let namespace = fs::read_link("/proc/self/ns/mnt")?;
+        let processes = processes_in(&namespace.to_string_lossy());
+        let me = processes
+            .iter()
+            .find(|process| process.pid == std::process::id() as i32)
+            .unwrap();
+        assert_eq!(me.ppid, nix::unistd::getppid().as_raw());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() {
+        let state = BoxState {
+            name: "test-box".into(),
+            pid: 10,
+            namespace: "mnt:[1]".into(),
+            command: vec!["aws".into(), "configure".into()],
+            cwd: PathBuf::from("/home/boxxy"),
+            started: 0,
+            targets: vec![PathBuf::from("/home/boxxy/.aws")],
+        };
+        let process = |pid, ppid, command: &str| Process {
+            pid,
+            ppid,
+            command: command.into(),
+        };
+        let processes = vec![
+            process(10, 1, "boxxy aws configure"),
+            process(11, 10, "aws configure"),
+            process(12, 11, "less"),
+            process(13, 10, "sh"),
+        ];
+        let files = HashSet::from([(11, PathBuf::from("/home/boxxy/.aws/config"))]);
+        let events = VecDeque::from(["+2s opened /home/boxxy/.aws/config (11)".to_string()]);
+
+        let expected = [
+            "box test-box: aws configure (in /home/boxxy)",
+            "",
+            "processes:",
+            "       10  boxxy aws configure",
+            "       11    aws configure",
+            "       12      less",
+            "       13    sh",
+            "",
+            "open files under shadowed targets:",
+            "       11  /home/boxxy/.aws/config",
+            "",
+            "recent activity:",
+            "  +2s opened /home/boxxy/.aws/config (11)",
+            "",
+        ];
+        assert_eq!(
+            render(&state, &processes, &files, &events),
+            expected.join("\n")
+        );
+    }
+}
@@ -0,0 +1,517 @@
+//! Tests that run the boxxy binary, with a scratch `$HOME` whose config has
+//! the rules under test, so that they see what a user would.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use color_eyre::Result;
+
+#[path = "../src/testing/mod.rs"]
+mod testing;
+use testing::Scratch;
+
+const BOXXY: &str = env!("CARGO_BIN_EXE_boxxy");
+
+/// A `$HOME` with `config` as the user's own config file.
+struct Home(Scratch);
+
+impl Home {
+    fn new(name: &str, config: &str) -> Result<Self> {
+        let home = Home(Scratch::new(&format!("cli-{name}"))?);
+        let config_dir = home.path(".config/boxxy");
+        std::fs::create_dir_all(&config_dir)?;
+        std::fs::write(config_dir.join(config_file_name()), config)?;
+        Ok(home)
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Run boxxy with `args` in this home.
+    fn boxxy(&self, args: &[&str]) -> Result<Output> {
+        self.boxxy_in(&self.0, args)
+    }
+
+    /// Run boxxy with `args` in this home, from `dir`.
+    fn boxxy_in(&self, dir: &Path, args: &[&str]) -> Result<Output> {
+        Ok(Command::new(BOXXY)
+            .args(["--no-community", "--log-level", "warn"])
+            .args(args)
+            .current_dir(dir)
+            .env("HOME", self.0.as_os_str())
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("XDG_CACHE_HOME")
+            .env_remove("XDG_DATA_HOME")
+            .env_remove("XDG_STATE_HOME")
+            .output()?)
+    }
+}
+
+/// The name of boxxy's config files. Debug builds of boxxy read their own, so
+/// that they don't clash with an installed boxxy.
+fn config_file_name() -> &'static str {
+    if BOXXY.contains("target/debug") {
+        "boxxy-dev.yaml"
+    } else {
+        "boxxy.yaml"
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn write(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+#[test]
+fn test_mask() -> Result<()> {
+    let home = Home::new(
+        "mask",
+        r#"
+rules:
+- name: "masked file"
+  target: "~/app/secret"
+  mode: "mask"
+- name: "masked dir"
+  target: "~/app/cache"
+  mode: "mask"
+- name: "scratch next to them"
+  target: "~/app/scratch"
+  mode: "tmpfs"
+"#,
+    )?;
+    write(&home.path("app/secret"), "secret")?;
+    write(&home.path("app/cache/entry"), "entry")?;
+    write(&home.path("app/sibling"), "sibling\n")?;
+    std::os::unix::fs::symlink("sibling", home.path("app/link"))?;
+
+    let output = home.boxxy(&[
+        "sh",
+        "-c",
+        "stat app/secret app/cache 2>&1 | grep -c 'No such file'; ls -A app; \
+         cat app/link; echo more >> app/sibling; touch app/scratch/new; \
+         touch app/new 2>/dev/null || echo read-only",
+    ])?;
+    assert!(output.status.success(), "{output:?}");
+    // The masked paths are gone, and the rest of their directory is bound
+    // back, with the other rules' mounts, but new entries can't be made.
+    assert_eq!(
+        stdout(&output),
+        "2\nlink\nscratch\nsibling\nsibling\nread-only\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(home.path("app/sibling"))?,
+        "sibling\nmore\n"
+    );
+    assert!(!home.path("app/scratch/new").exists());
+    assert!(!home.path("app/new").exists());
+    assert_eq!(std::fs::read_to_string(home.path("app/secret"))?, "secret");
+    assert!(home.path("app/cache/entry").exists());
+    Ok(())
+}
+
+#[test]
+fn test_dry_run() -> Result<()> {
+    let home = Home::new(
+        "dry-run",
+        r#"
+rules:
+- name: "npm"
+  target: "~/.npmrc"
+  rewrite: "~/.config/npm/npmrc"
+  mode: "file"
+  env:
+    NPM_CONFIG_USERCONFIG: "~/.config/npm/npmrc"
+"#,
+    )?;
+
+    let output = home.boxxy(&["--dry-run", "true"])?;
+    assert!(output.status.success(), "{output:?}");
+    let stdout = stdout(&output);
+    assert!(
+        stdout.starts_with("rules:\n  npm: ~/.npmrc\nmounts:\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!(
+            "would create:\n  file {}\n  file {}\n",
+            home.path(".config/npm/npmrc").display(),
+            home.path(".npmrc").display()
+        )),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with("env:\n  BOXXY_CONTAINER_NAME\n  NPM_CONFIG_USERCONFIG (rule 'npm')\n"),
+        "{stdout}"
+    );
+    // Nothing is created or run.
+    assert!(!home.path(".npmrc").exists());
+    assert!(!home.path(".config/npm").exists());
+    Ok(())
+}
+
+#[test]
+fn test_ro_home() -> Result<()> {
+    let home = Home::new(
+        "ro-home",
+        r#"
+rules:
+- name: "tool cache"
+  target: "~/.cache/tool"
+  rewrite: "~/.local/share/tool-cache"
+"#,
+    )?;
+
+    let output = home.boxxy(&[
+        "--ro-home",
+        "sh",
+        "-c",
+        "touch stray 2>/dev/null || echo read-only; touch .cache/tool/entry && echo written",
+    ])?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "read-only\nwritten\n");
+    assert!(!home.path("stray").exists());
+    // The rule's rewrite is the only place in `$HOME` it can write to.
+    assert!(home.path(".local/share/tool-cache/entry").exists());
+    Ok(())
+}
+
+#[test]
+fn test_private_tmpdir() -> Result<()> {
+    let home = Home::new("private-tmpdir", "rules: []\n")?;
+
+    let output = home.boxxy(&[
+        "--private-tmpdir",
+        "sh",
+        "-c",
+        "echo \"$TMPDIR\"; echo hi > \"$TMPDIR/file\" && ls -ld \"$TMPDIR\"",
+    ])?;
+    assert!(output.status.success(), "{output:?}");
+    let stdout = stdout(&output);
+    let mut lines = stdout.lines();
+    let tmpdir = Path::new(lines.next().unwrap_or_default());
+    // It's a directory of its own under the box's scratch directory, which
+    // AppImages also use.
+    assert_eq!(
+        tmpdir.file_name().and_then(|name| name.to_str()),
+        Some("tmp")
+    );
+    assert!(tmpdir
+        .parent()
+        .and_then(|parent| parent.to_str())
+        .is_some_and(|parent| parent.ends_with(".tmp")));
+    assert!(
+        lines
+            .next()
+            .is_some_and(|line| line.starts_with("drwx------")),
+        "{stdout}"
+    );
+    // It's deleted with the rest of the box.
+    assert!(!tmpdir.exists());
+    Ok(())
+}
+
+#[test]
+fn test_container_init() -> Result<()> {
+    let home = Home::new(
+        "container-init",
+        r#"
+container_init:
+- "mkdir -p ~/.cache/app/thumbnails"
+- "echo init > ~/.cache/app/log"
+rules:
+- name: "throwaway app cache"
+  target: "~/.cache/app"
+  mode: "tmpfs"
+"#,
+    )?;
+
+    let output = home.boxxy(&["sh", "-c", "ls .cache/app; cat .cache/app/log"])?;
+    assert!(output.status.success(), "{output:?}");
+    // They run in the box, after the rules are applied.
+    assert_eq!(stdout(&output), "log\nthumbnails\ninit\n");
+    assert!(!home.path(".cache/app/thumbnails").exists());
+    Ok(())
+}
+
+#[test]
+fn test_untrusted_container_init() -> Result<()> {
+    let home = Home::new("untrusted-container-init", "rules: []\n")?;
+    let project = home.path("project");
+    write(
+        &project.join(config_file_name()),
+        r#"
+container_init:
+- "touch init-ran"
+shell_init:
+- "touch shell-init-ran"
+rules: []
+"#,
+    )?;
+
+    let output = home.boxxy_in(&project, &["true"])?;
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ignoring its `container_init`"), "{stderr}");
+    assert!(stderr.contains("ignoring its `shell_init`"), "{stderr}");
+    assert!(!project.join("init-ran").exists());
+
+    let output = home.boxxy_in(&project, &["config", "trust"])?;
+    assert!(output.status.success(), "{output:?}");
+    let output = home.boxxy_in(&project, &["true"])?;
+    assert!(output.status.success(), "{output:?}");
+    assert!(project.join("init-ran").exists());
+    Ok(())
+}
+
+#[test]
+fn test_failing_container_init() -> Result<()> {
+    let home = Home::new(
+        "failing-container-init",
+        r#"
+container_init:
+- "true"
+- "exit 3"
+- "touch init-ran"
+rules: []
+"#,
+    )?;
+
+    let output = home.boxxy(&["touch", "program-ran"])?;
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("container_init command `exit 3` failed"),
+        "{output:?}"
+    );
+    // Nothing after the failing command runs, including the program.
+    assert!(!home.path("init-ran").exists());
+    assert!(!home.path("program-ran").exists());
+    Ok(())
+}
+
+#[test]
+fn test_env_file() -> Result<()> {
+    let config = r#"
+rules:
+- name: "aws"
+  target: "~/.aws"
+  rewrite: "~/.config/aws"
+  env_file: "~/.config/boxxy/secrets/aws.env"
+  env:
+    AWS_REGION: "eu-west-1"
+"#;
+    let home = Home::new("env-file", config)?;
+    write(
+        &home.path(".config/boxxy/secrets/aws.env"),
+        "# work account\nAWS_PROFILE=work\nAWS_TOKEN=\"s3cret value\"\nAWS_REGION=us-east-1\n",
+    )?;
+
+    let output = home.boxxy(&["sh", "-c", "echo \"$AWS_PROFILE|$AWS_TOKEN|$AWS_REGION\""])?;
+    assert!(output.status.success(), "{output:?}");
+    // `env` wins over the file.
+    assert_eq!(stdout(&output), "work|s3cret value|eu-west-1\n");
+
+    std::fs::remove_file(home.path(".config/boxxy/secrets/aws.env"))?;
+    let output = home.boxxy(&["true"])?;
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("aws: could not read env_file"),
+        "{output:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_protected_paths() -> Result<()> {
+    let home = Home::new("protected-paths", "rules: []\n")?;
+    write(&home.path(config_file_name()), "rules: []\n")?;
+    let write_config = &format!(
+        "echo 'rules: []' >> .config/boxxy/{name} 2>/dev/null || echo config read-only; \
+         touch .config/boxxy/new 2>/dev/null || echo dir read-only; \
+         echo 'rules: []' >> {name} 2>/dev/null || echo project config read-only; \
+         touch other && echo home writable",
+        name = config_file_name()
+    );
+
+    let output = home.boxxy(&["sh", "-c", write_config])?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        stdout(&output),
+        "config read-only\ndir read-only\nproject config read-only\nhome writable\n"
+    );
+    assert!(!home.path(".config/boxxy/new").exists());
+    assert!(home.path("other").exists());
+
+    let output = home.boxxy(&["--expose-boxxy-config", "sh", "-c", write_config])?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "home writable\n");
+    assert!(home.path(".config/boxxy/new").exists());
+    Ok(())
+}
+
+#[test]
+fn test_root_tmpfs() -> Result<()> {
+    let home = Home::new("root-tmpfs", "")?;
+    let containers = home.path("containers");
+    write(
+        &home.path(&format!(".config/boxxy/{}", config_file_name())),
+        &format!(
+            "containers_root: {}\nrules:\n- {{name: cache, target: ~/.cache/tool, rewrite: ~/tool-cache}}\n",
+            containers.display()
+        ),
+    )?;
+
+    let output = home.boxxy(&[
+        "--root-tmpfs",
+        "--private-tmpdir",
+        "sh",
+        "-c",
+        "stat -f -c %T \"$TMPDIR\"; touch \"$TMPDIR/scratch\" .cache/tool/entry",
+    ])?;
+    assert!(output.status.success(), "{output:?}");
+    // The box's own directories are on a tmpfs, and never on the disk...
+    assert_eq!(stdout(&output), "tmpfs\n");
+    assert!(std::fs::read_dir(&containers)?.next().is_none());
+    // ...but files written through rules still go where the rules send them.
+    assert!(home.path("tool-cache/entry").exists());
+    Ok(())
+}
+
+#[test]
+fn test_create_false() -> Result<()> {
+    let home = Home::new(
+        "create-false",
+        r#"
+rules:
+- name: "cache"
+  target: "~/.cache/tool"
+  rewrite: "~/tool-cache"
+- name: "usb"
+  target: "~/data"
+  rewrite: "~/usb/data"
+  create: false
+"#,
+    )?;
+    std::fs::create_dir_all(home.path("data"))?;
+    let missing = home.path("usb/data");
+
+    let output = home.boxxy(&["--dry-run", "true"])?;
+    assert!(output.status.success(), "{output:?}");
+    let stdout = stdout(&output);
+    assert!(
+        stdout.contains(&format!(
+            "would fail, missing with `create: false`:\n  {} (rule 'usb')\n",
+            missing.display()
+        )),
+        "{stdout}"
+    );
+
+    let output = home.boxxy(&["touch", "ran"])?;
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(&format!(
+            "usb: {} doesn't exist, and the rule has `create: false`",
+            missing.display()
+        )),
+        "{output:?}"
+    );
+    assert!(!home.path("ran").exists());
+    // It's checked before anything is created for the other rules.
+    assert!(!home.path("tool-cache").exists());
+    assert!(!missing.exists());
+
+    std::fs::create_dir_all(&missing)?;
+    let output = home.boxxy(&["touch", "data/ran"])?;
+    assert!(output.status.success(), "{output:?}");
+    assert!(missing.join("ran").exists());
+    Ok(())
+}
+
+#[test]
+fn test_check() -> Result<()> {
+    let home = Home::new(
+        "check",
+        r#"
+rules:
+- name: "git config"
+  target: "~/.gitconfig"
+  rewrite: "~/.config/git/config"
+  only: ["git"]
+"#,
+    )?;
+    let output = home.boxxy(&["check", "git", "status"])?;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert!(output.stdout.is_empty(), "{output:?}");
+    let output = home.boxxy(&["check", "make"])?;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(output.stdout.is_empty(), "{output:?}");
+
+    write(
+        &home.path(&format!(".config/boxxy/{}", config_file_name())),
+        "rules: [",
+    )?;
+    let output = home.boxxy(&["check", "git"])?;
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    Ok(())
+}
+
+#[test]
+fn test_trace_enforce_kill() -> Result<()> {
+    let home = Home::new("trace-enforce-kill", "rules: []\n")?;
+
+    // The first traced process is boxxy's own, in the box, which forks to
+    // run the command.
+    for syscalls in ["clone,clone3,fork,vfork", "wait4", "mkdir,mkdirat"] {
+        let output = home.boxxy(&[
+            "--trace",
+            "--trace-enforce",
+            syscalls,
+            "--trace-enforce-action",
+            "kill",
+            "mkdir",
+            "made",
+        ])?;
+        assert_eq!(output.status.code(), Some(137), "{syscalls}: {output:?}");
+        assert!(!home.path("made").exists());
+        assert!(home.path("boxxy-report.txt").exists());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_add_rule() -> Result<()> {
+    let home = Home::new(
+        "add-rule",
+        "# mine\nrules:\n- name: old\n  target: ~/.old\n",
+    )?;
+    let config = home.path(&format!(".config/boxxy/{}", config_file_name()));
+    let add =
+        |name: &str| home.boxxy(&["config", "add-rule", "--name", name, "--target", "~/.foo"]);
+
+    let output = add("foo")?;
+    assert!(output.status.success(), "{output:?}");
+    let contents = std::fs::read_to_string(&config)?;
+    assert!(contents.starts_with("# mine\n"), "{contents}");
+    assert!(contents.contains("name: old"), "{contents}");
+    assert!(contents.contains("name: foo"), "{contents}");
+
+    // A config that can't be read isn't written over.
+    let unreadable = b"# \xff\nrules:\n- name: old\n  target: ~/.old\n";
+    std::fs::write(&config, unreadable)?;
+    let output = add("bar")?;
+    assert!(!output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(&config)?, unreadable);
+
+    std::fs::remove_file(&config)?;
+    let output = add("baz")?;
+    assert!(output.status.success(), "{output:?}");
+    assert!(std::fs::read_to_string(&config)?.contains("name: baz"));
+    Ok(())
+}
@@ -0,0 +1,92 @@
+// Makes a fixed sequence of path-related syscalls on files under the
+// directory given as the only argument, for the golden trace tests in
+// tests/trace.rs. Syscalls that only exist on some architectures are guarded,
+// which is why there's a golden report per architecture.
+//
+// Changing this file changes its sha256, so the golden reports have to be
+// regenerated with `BOXXY_BLESS=1 cargo test --test trace`.
+
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <limits.h>
+#include <stdio.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+static char paths[8][PATH_MAX];
+
+static const char *path(int i, const char *dir, const char *name) {
+    snprintf(paths[i], PATH_MAX, "%s/%s", dir, name);
+    return paths[i];
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        return 2;
+    }
+    const char *root = argv[1];
+    const char *dir = path(0, root, "dir");
+    const char *sub = path(1, root, "dir/sub");
+    const char *file = path(2, root, "dir/file");
+    const char *link = path(3, root, "dir/link");
+    const char *moved = path(4, root, "dir/moved");
+    const char *moved2 = path(5, root, "dir/moved2");
+    char buffer[4096];
+    struct stat st;
+    int fd;
+
+#ifdef SYS_mkdir
+    syscall(SYS_mkdir, dir, 0755);
+#else
+    syscall(SYS_mkdirat, AT_FDCWD, dir, 0755);
+#endif
+    syscall(SYS_mkdirat, AT_FDCWD, sub, 0755);
+
+    // fd-based syscalls are resolved through /proc/<pid>/fd.
+#ifdef SYS_open
+    fd = syscall(SYS_open, file, O_CREAT | O_WRONLY, 0644);
+#else
+    fd = syscall(SYS_openat, AT_FDCWD, file, O_CREAT | O_WRONLY, 0644);
+#endif
+    syscall(SYS_write, fd, "boxxy", 5);
+    syscall(SYS_close, fd);
+
+    fd = syscall(SYS_openat, AT_FDCWD, file, O_RDONLY);
+    syscall(SYS_read, fd, buffer, sizeof(buffer));
+    syscall(SYS_fstat, fd, &st);
+    syscall(SYS_close, fd);
+
+#ifdef SYS_stat
+    syscall(SYS_stat, file, &st);
+#endif
+#ifdef SYS_newfstatat
+    syscall(SYS_newfstatat, AT_FDCWD, file, &st, 0);
+#endif
+    syscall(SYS_statx, AT_FDCWD, file, 0, 0x7ff, buffer);
+
+#ifdef SYS_access
+    syscall(SYS_access, file, R_OK);
+#endif
+    syscall(SYS_faccessat, AT_FDCWD, file, R_OK);
+
+#ifdef SYS_symlink
+    syscall(SYS_symlink, file, link);
+#else
+    syscall(SYS_symlinkat, file, AT_FDCWD, link);
+#endif
+    syscall(SYS_readlinkat, AT_FDCWD, link, buffer, sizeof(buffer));
+    syscall(SYS_utimensat, AT_FDCWD, file, NULL, 0);
+
+#ifdef SYS_rename
+    syscall(SYS_rename, link, moved);
+#else
+    syscall(SYS_renameat2, AT_FDCWD, link, AT_FDCWD, moved, 0);
+#endif
+    syscall(SYS_renameat2, AT_FDCWD, moved, AT_FDCWD, moved2, 0);
+
+    syscall(SYS_unlinkat, AT_FDCWD, moved2, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, sub, AT_REMOVEDIR);
+
+    return 0;
+}
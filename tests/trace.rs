@@ -0,0 +1,111 @@
+//! Golden tests for path extraction: trace a fixture that makes a known
+//! sequence of syscalls, and compare the paths the tracer reports with a
+//! golden report for the current architecture.
+//!
+//! Each golden report pins the sha256 of the fixture it was made from. After
+//! changing the fixture, or when the tracer is meant to report something
+//! different, regenerate the reports with `BOXXY_BLESS=1 cargo test --test
+//! trace`, and check the diff. A missing report fails the test, so a new
+//! architecture needs one generated the same way.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use boxxy::enclosure::TraceSession;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use sha2::{Digest, Sha256};
+
+#[path = "../src/testing/mod.rs"]
+mod testing;
+use testing::Scratch;
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/trace")
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Build `name.c` from the fixtures into `out`.
+fn compile(name: &str, out: &Path) -> Result<PathBuf> {
+    let binary = out.join(name);
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".into());
+    let status = Command::new(&cc)
+        .arg("-O0")
+        .arg("-o")
+        .arg(&binary)
+        .arg(fixtures().join(format!("{name}.c")))
+        .status()
+        .map_err(|err| eyre!("could not run {cc} to build the {name} fixture: {err}"))?;
+    if !status.success() {
+        return Err(eyre!("{cc} failed to build the {name} fixture"));
+    }
+
+    Ok(binary)
+}
+
+/// Trace `binary` with `root` as its argument, and list every syscall that
+/// touched a path under `root`, one per line, with `root` replaced by
+/// `$ROOT` so that the report is the same on every machine.
+fn trace(binary: &Path, root: &Path) -> Result<String> {
+    let mut session = TraceSession::spawn(Command::new(binary).arg(root))?;
+    let mut report = String::new();
+    for syscall in &mut session {
+        let syscall = syscall?;
+        if let Some(relative) = syscall
+            .path
+            .as_deref()
+            .and_then(|path| path.strip_prefix(root).ok())
+        {
+            report.push_str(&format!("{} $ROOT/{}\n", syscall.name, relative.display()));
+        }
+    }
+    session.wait()?;
+
+    Ok(report)
+}
+
+#[test]
+fn test_golden_trace() -> Result<()> {
+    let source = std::fs::read(fixtures().join("tracee.c"))?;
+    let header = format!("# tracee.c sha256: {}\n", sha256(&source));
+    let golden_path = fixtures().join(format!("golden-{}.txt", std::env::consts::ARCH));
+
+    let scratch = Scratch::new("trace-golden")?;
+    let binary = compile("tracee", &scratch)?;
+    let root = scratch.join("root");
+    std::fs::create_dir(&root)?;
+    let report = format!("{header}{}", trace(&binary, &root)?);
+
+    if std::env::var_os("BOXXY_BLESS").is_some() {
+        std::fs::write(&golden_path, &report)?;
+        eprintln!("wrote {}", golden_path.display());
+        return Ok(());
+    }
+
+    let golden = std::fs::read_to_string(&golden_path).map_err(|err| {
+        eyre!(
+            "could not read the golden report for {}, {}: {err}; generate it with BOXXY_BLESS=1",
+            std::env::consts::ARCH,
+            golden_path.display()
+        )
+    })?;
+    assert!(
+        golden.starts_with(&header),
+        "tracee.c changed since {} was generated, regenerate it with BOXXY_BLESS=1",
+        golden_path.display()
+    );
+    assert_eq!(
+        report,
+        golden,
+        "the trace doesn't match {}",
+        golden_path.display()
+    );
+
+    Ok(())
+}